@@ -1,8 +1,8 @@
 use argh::FromArgs;
 use pdf::file::{File, FileOptions};
-use pdf_render::{Cache, SceneBackend, render_page};
-use pathfinder_rasterize::Rasterizer;
+use pdf_render::{Cache, SceneBackend, Quality, render_page, raster};
 use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_color::ColorF;
 use std::error::Error;
 
 use std::path::PathBuf;
@@ -18,6 +18,18 @@ struct Options {
     #[argh(option, default="0")]
     page: u32,
 
+    /// disable anti-aliasing
+    #[argh(switch)]
+    no_antialias: bool,
+
+    /// sub-scanlines per pixel row when anti-aliasing
+    #[argh(option, default="16")]
+    samples: u32,
+
+    /// blend in linear light
+    #[argh(switch)]
+    gamma: bool,
+
     /// input PDF file
     #[argh(positional)]
     pdf: PathBuf,
@@ -37,10 +49,12 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let mut cache = Cache::new();
     let mut backend = SceneBackend::new(&mut cache);
+    backend.set_background(Some(ColorF::white()));
 
     render_page(&mut backend, &resolver, &page, Transform2F::from_scale(opt.dpi / 25.4))?;
 
-    let image = Rasterizer::new().rasterize(backend.finish(), None);
+    let quality = Quality { antialias: !opt.no_antialias, samples: opt.samples, gamma_correct: opt.gamma };
+    let image = raster::rasterize_with(&backend.finish(), None, &quality);
 
     image.save(opt.image)?;
 
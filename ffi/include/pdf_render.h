@@ -0,0 +1,52 @@
+#ifndef PDF_RENDER_H
+#define PDF_RENDER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Page numbers are 0 based. Functions returning int return -1 on error,
+ * functions returning pointers return NULL. The reason can be read with
+ * pdfr_last_error(). */
+
+typedef struct PdfrDocument PdfrDocument;
+
+typedef struct {
+    uint32_t width;
+    uint32_t height;
+    /* bytes per row */
+    uint32_t stride;
+    /* RGBA, 8 bit per channel, not premultiplied */
+    uint8_t *data;
+} PdfrImage;
+
+/* valid until the next call on the same thread */
+const char *pdfr_last_error(void);
+
+PdfrDocument *pdfr_open_file(const char *path);
+/* the data is copied */
+PdfrDocument *pdfr_open_memory(const uint8_t *data, size_t len);
+void pdfr_close(PdfrDocument *doc);
+
+int pdfr_page_count(const PdfrDocument *doc);
+/* media box size in points */
+int pdfr_page_size(const PdfrDocument *doc, uint32_t page_nr, float *width, float *height);
+
+#define PDFR_MAX_DPI 2400
+
+/* release image->data with pdfr_free_image, dpi has to be in (0, PDFR_MAX_DPI] */
+int pdfr_render_page(PdfrDocument *doc, uint32_t page_nr, float dpi, PdfrImage *image);
+void pdfr_free_image(PdfrImage *image);
+
+/* UTF-8, release with pdfr_free_string */
+char *pdfr_extract_text(const PdfrDocument *doc, uint32_t page_nr);
+void pdfr_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,238 @@
+//! C API for pdf_render.
+//!
+//! All functions are declared in `include/pdf_render.h`.
+//! Functions that can fail return a negative value or a null pointer and store
+//! a message that can be read with `pdfr_last_error`.
+//! Page numbers are 0 based.
+//!
+//! The functions that take pointers are `unsafe`: a pointer is either null
+//! or valid for what the function does with it, documents and strings are
+//! only passed to the functions that release them once.
+
+#[macro_use] extern crate log;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use pdf::error::PdfError;
+use pdf::file::{FileOptions, ObjectCache, StreamCache, NoLog};
+use pdf_render::{Document, RenderOptions, Unit, page_layout};
+use pathfinder_color::ColorF;
+use pathfinder_rasterize::Rasterizer;
+
+pub struct PdfrDocument {
+    doc: Document<Vec<u8>, ObjectCache, StreamCache, NoLog>,
+}
+
+#[repr(C)]
+pub struct PdfrImage {
+    pub width: u32,
+    pub height: u32,
+    /// bytes per row
+    pub stride: u32,
+    /// RGBA, 8 bit per channel, not premultiplied
+    pub data: *mut u8,
+}
+
+/// The highest resolution `pdfr_render_page` accepts, also `PDFR_MAX_DPI` in the header.
+pub const MAX_DPI: f32 = 2400.0;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_error(msg: String) {
+    warn!("{}", msg);
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// run `f`, turning errors and panics into `fail` and the error message
+fn guard<T>(fail: T, f: impl FnOnce() -> Result<T, PdfError>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(t)) => t,
+        Ok(Err(e)) => {
+            set_error(e.to_string());
+            fail
+        }
+        Err(_) => {
+            set_error("panic in pdf_render".into());
+            fail
+        }
+    }
+}
+
+// `doc` is null or an open document
+unsafe fn document<'a>(doc: *const PdfrDocument) -> Result<&'a PdfrDocument, PdfError> {
+    doc.as_ref().ok_or_else(|| PdfError::Other { msg: "document is null".into() })
+}
+unsafe fn document_mut<'a>(doc: *mut PdfrDocument) -> Result<&'a mut PdfrDocument, PdfError> {
+    doc.as_mut().ok_or_else(|| PdfError::Other { msg: "document is null".into() })
+}
+
+/// The message of the last error on this thread, or null.
+/// Valid until the next call into the library on the same thread.
+#[no_mangle]
+pub extern "C" fn pdfr_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()))
+}
+
+/// Open the file at `path` (UTF-8). Returns null on error.
+///
+/// # Safety
+/// `path` is null or a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_open_file(path: *const c_char) -> *mut PdfrDocument {
+    guard(ptr::null_mut(), || {
+        if path.is_null() {
+            return Err(PdfError::Other { msg: "path is null".into() });
+        }
+        let path = unsafe { CStr::from_ptr(path) }.to_str()
+            .map_err(|_| PdfError::Other { msg: "path is not UTF-8".into() })?;
+        let file = FileOptions::cached().open(path)?;
+        Ok(Box::into_raw(Box::new(PdfrDocument { doc: Document::new(file) })))
+    })
+}
+
+/// Open a document from `len` bytes at `data`. The data is copied.
+/// Returns null on error.
+///
+/// # Safety
+/// `data` is null or valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_open_memory(data: *const u8, len: usize) -> *mut PdfrDocument {
+    guard(ptr::null_mut(), || {
+        if data.is_null() {
+            return Err(PdfError::Other { msg: "data is null".into() });
+        }
+        let data = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+        let file = FileOptions::cached().load(data)?;
+        Ok(Box::into_raw(Box::new(PdfrDocument { doc: Document::new(file) })))
+    })
+}
+
+/// Close a document opened with `pdfr_open_file` or `pdfr_open_memory`. Null is ignored.
+///
+/// # Safety
+/// `doc` is null or a document of this library that was not closed yet.
+/// It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_close(doc: *mut PdfrDocument) {
+    if !doc.is_null() {
+        drop(unsafe { Box::from_raw(doc) });
+    }
+}
+
+/// Number of pages, or -1 on error.
+///
+/// # Safety
+/// `doc` is null or an open document.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_page_count(doc: *const PdfrDocument) -> c_int {
+    guard(-1, || Ok(document(doc)?.doc.num_pages() as c_int))
+}
+
+/// Size of the media box of the page in points. Returns 0 on success, -1 on error.
+///
+/// # Safety
+/// `doc` is null or an open document, `width` and `height` are null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_page_size(doc: *const PdfrDocument, page_nr: u32, width: *mut f32, height: *mut f32) -> c_int {
+    guard(-1, || {
+        let options = RenderOptions::new().unit(Unit::Points);
+        let size = document(doc)?.doc.page_bounds(page_nr, &options)?.size();
+        unsafe {
+            if let Some(w) = width.as_mut() {
+                *w = size.x();
+            }
+            if let Some(h) = height.as_mut() {
+                *h = size.y();
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Render the page at `dpi` on a white background into `image`.
+/// The pixels have to be released with `pdfr_free_image`.
+/// Returns 0 on success, -1 on error, also if `dpi` is not in `(0, MAX_DPI]`.
+///
+/// # Safety
+/// `doc` is null or an open document that is not used by another thread,
+/// `image` is null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_render_page(doc: *mut PdfrDocument, page_nr: u32, dpi: f32, image: *mut PdfrImage) -> c_int {
+    guard(-1, || {
+        let out = unsafe { image.as_mut() }.ok_or_else(|| PdfError::Other { msg: "image is null".into() })?;
+        // also false for NaN
+        if !(dpi > 0.0 && dpi <= MAX_DPI) {
+            return Err(PdfError::Other { msg: format!("dpi {} is not in (0, {}]", dpi, MAX_DPI) });
+        }
+        let background = ColorF::white();
+        let options = RenderOptions::new()
+            .dpi(dpi)
+            .background(Some(background));
+        let scene = document_mut(doc)?.doc.render_page(page_nr, &options)?;
+        let rgba = Rasterizer::new().rasterize(scene, Some(background));
+
+        let (width, height) = rgba.dimensions();
+        let mut data = rgba.into_raw().into_boxed_slice();
+        *out = PdfrImage {
+            width,
+            height,
+            stride: width * 4,
+            data: data.as_mut_ptr(),
+        };
+        std::mem::forget(data);
+        Ok(0)
+    })
+}
+
+/// Release the pixels of an image filled by `pdfr_render_page`.
+///
+/// # Safety
+/// `image` is null or an image that `pdfr_render_page` filled and that was not changed since.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_free_image(image: *mut PdfrImage) {
+    let image = match unsafe { image.as_mut() } {
+        Some(image) => image,
+        None => return
+    };
+    if !image.data.is_null() {
+        let len = image.stride as usize * image.height as usize;
+        drop(unsafe { Box::from_raw(std::slice::from_raw_parts_mut(image.data, len)) });
+        image.data = ptr::null_mut();
+    }
+}
+
+/// The text of the page as a UTF-8 string, with lines separated by `\n`
+/// and blocks by an empty line. Release it with `pdfr_free_string`.
+/// Returns null on error.
+///
+/// # Safety
+/// `doc` is null or an open document.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_extract_text(doc: *const PdfrDocument, page_nr: u32) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let doc = &document(doc)?.doc;
+        let options = RenderOptions::new();
+        let spans = doc.extract_text(page_nr, &options)?;
+        let bounds = doc.page_bounds(page_nr, &options)?;
+        let text = page_layout(&spans, bounds).text().replace('\0', "");
+        Ok(CString::new(text).unwrap_or_default().into_raw())
+    })
+}
+
+/// Release a string returned by this library. Null is ignored.
+///
+/// # Safety
+/// `s` is null or a string returned by this library that was not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn pdfr_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
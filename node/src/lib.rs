@@ -0,0 +1,121 @@
+//! Node.js bindings.
+//!
+//! ```js
+//! const { PdfDocument } = require("pdf-render");
+//! const doc = PdfDocument.open("file.pdf");
+//! const png = await doc.renderPage(0, { dpi: 150 });
+//! const text = await doc.extractText(0);
+//! ```
+//!
+//! Rendering and text extraction run on the libuv thread pool.
+
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use napi::bindgen_prelude::*;
+use napi::{Env, Task};
+use napi_derive::napi;
+
+use pdf::error::PdfError;
+use pdf::file::{FileOptions, ObjectCache, StreamCache, NoLog};
+use pdf_render::{Document, RenderOptions, page_layout};
+use pathfinder_color::ColorF;
+use pathfinder_rasterize::Rasterizer;
+
+type Doc = Document<Vec<u8>, ObjectCache, StreamCache, NoLog>;
+
+fn js_err(e: PdfError) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+fn lock(doc: &Mutex<Doc>) -> Result<std::sync::MutexGuard<'_, Doc>> {
+    doc.lock().map_err(|_| Error::from_reason("document is poisoned by an earlier panic"))
+}
+
+#[napi(object)]
+pub struct RenderPageOptions {
+    /// defaults to 150
+    pub dpi: Option<f64>,
+}
+
+pub struct RenderTask {
+    doc: Arc<Mutex<Doc>>,
+    page_nr: u32,
+    dpi: f32,
+}
+impl Task for RenderTask {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Vec<u8>> {
+        let background = ColorF::white();
+        let options = RenderOptions::new()
+            .dpi(self.dpi)
+            .background(Some(background));
+        let scene = lock(&self.doc)?.render_page(self.page_nr, &options).map_err(js_err)?;
+        let image = Rasterizer::new().rasterize(scene, Some(background));
+
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, image::ImageOutputFormat::Png)
+            .map_err(|e| Error::from_reason(e.to_string()))?;
+        Ok(png.into_inner())
+    }
+    fn resolve(&mut self, _env: Env, output: Vec<u8>) -> Result<Buffer> {
+        Ok(output.into())
+    }
+}
+
+pub struct TextTask {
+    doc: Arc<Mutex<Doc>>,
+    page_nr: u32,
+}
+impl Task for TextTask {
+    type Output = String;
+    type JsValue = String;
+
+    fn compute(&mut self) -> Result<String> {
+        let doc = lock(&self.doc)?;
+        let options = RenderOptions::new();
+        let spans = doc.extract_text(self.page_nr, &options).map_err(js_err)?;
+        let bounds = doc.page_bounds(self.page_nr, &options).map_err(js_err)?;
+        Ok(page_layout(&spans, bounds).text())
+    }
+    fn resolve(&mut self, _env: Env, output: String) -> Result<String> {
+        Ok(output)
+    }
+}
+
+#[napi]
+pub struct PdfDocument {
+    doc: Arc<Mutex<Doc>>,
+}
+
+#[napi]
+impl PdfDocument {
+    #[napi(factory)]
+    pub fn open(path: String) -> Result<PdfDocument> {
+        let file = FileOptions::cached().open(&path).map_err(js_err)?;
+        Ok(PdfDocument { doc: Arc::new(Mutex::new(Document::new(file))) })
+    }
+    /// the data is copied
+    #[napi(factory)]
+    pub fn from_buffer(data: Buffer) -> Result<PdfDocument> {
+        let file = FileOptions::cached().load(data.to_vec()).map_err(js_err)?;
+        Ok(PdfDocument { doc: Arc::new(Mutex::new(Document::new(file))) })
+    }
+    #[napi(getter)]
+    pub fn num_pages(&self) -> Result<u32> {
+        Ok(lock(&self.doc)?.num_pages())
+    }
+    /// Render page `page_nr` (0 based) to a PNG on a white background.
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn render_page(&self, page_nr: u32, options: Option<RenderPageOptions>) -> AsyncTask<RenderTask> {
+        let dpi = options.and_then(|o| o.dpi).unwrap_or(150.) as f32;
+        AsyncTask::new(RenderTask { doc: self.doc.clone(), page_nr, dpi })
+    }
+    /// The text of page `page_nr` (0 based), lines separated by `\n` and blocks by an empty line.
+    #[napi(ts_return_type = "Promise<string>")]
+    pub fn extract_text(&self, page_nr: u32) -> AsyncTask<TextTask> {
+        AsyncTask::new(TextTask { doc: self.doc.clone(), page_nr })
+    }
+}
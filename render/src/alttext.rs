@@ -0,0 +1,220 @@
+//! Alternate descriptions of images, from the /Figure elements of the structure tree,
+//! and captions that are found next to the images.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use pathfinder_geometry::rect::RectF;
+use pdf::object::{PlainRef, Resolve};
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::PdfError;
+use crate::{MarkedContent, TextSpan};
+use crate::tracer::DrawItem;
+
+// nesting of structure elements beyond this is not followed
+const MAX_DEPTH: usize = 64;
+// how far (in font sizes of the caption) a caption may be from the image
+const CAPTION_DISTANCE: f32 = 3.0;
+
+/// A /Figure element of the structure tree with the marked content of one page.
+#[derive(Debug, Clone)]
+pub struct Figure {
+    pub alt: Option<String>,
+    pub actual_text: Option<String>,
+    /// the marked content (MCIDs) of the figure on the page, including that of its children
+    pub mcids: Vec<i32>,
+}
+
+/// An image of a page and what describes it.
+#[derive(Debug, Clone)]
+pub struct ImageAlt {
+    /// index of the image in the traced items
+    pub item: usize,
+    pub bounds: RectF,
+    /// the /Alt of the figure the image is tagged with
+    pub alt: Option<String>,
+    pub actual_text: Option<String>,
+    /// text right below (or above) the image that looks like its caption
+    pub caption: Option<String>,
+}
+
+fn resolve_dict(p: &Primitive, resolve: &impl Resolve) -> Result<Option<Dictionary>, PdfError> {
+    let p = match *p {
+        Primitive::Reference(r) => resolve.resolve(r)?,
+        ref p => p.clone(),
+    };
+    match p {
+        Primitive::Dictionary(dict) => Ok(Some(dict)),
+        _ => Ok(None)
+    }
+}
+
+fn text(dict: &Dictionary, key: &str) -> Option<String> {
+    dict.get(key).and_then(|s| s.as_string().ok()).map(|s| s.to_string_lossy())
+}
+
+struct Walker<'r, R: Resolve> {
+    resolve: &'r R,
+    page: PlainRef,
+    role_map: Option<Dictionary>,
+    visited: HashSet<PlainRef>,
+    figures: Vec<Figure>,
+}
+impl<'r, R: Resolve> Walker<'r, R> {
+    // the standard type of a structure type, through /RoleMap
+    fn is_figure<'s>(&'s self, mut name: &'s str) -> bool {
+        for _ in 0 .. 8 {
+            if name == "Figure" {
+                return true;
+            }
+            match self.role_map.as_ref().and_then(|m| m.get(name)).and_then(|n| n.as_name().ok()) {
+                Some(mapped) if mapped != name => name = mapped,
+                _ => return false
+            }
+        }
+        false
+    }
+    // walks /K of an element on page `page` (from /Pg), collecting the MCIDs on this page into `mcids`
+    fn kids(&mut self, k: &Primitive, page: Option<PlainRef>, mcids: &mut Vec<i32>, depth: usize) -> Result<(), PdfError> {
+        match *k {
+            Primitive::Integer(mcid) => {
+                if page == Some(self.page) {
+                    mcids.push(mcid);
+                }
+            }
+            Primitive::Array(ref kids) => {
+                for kid in kids {
+                    self.kids(kid, page, mcids, depth)?;
+                }
+            }
+            Primitive::Reference(r) => {
+                if self.visited.insert(r) {
+                    if let Some(dict) = resolve_dict(k, self.resolve)? {
+                        self.element(&dict, page, mcids, depth + 1)?;
+                    }
+                }
+            }
+            Primitive::Dictionary(ref dict) => self.element(dict, page, mcids, depth + 1)?,
+            _ => {}
+        }
+        Ok(())
+    }
+    // a structure element, or a marked content or object reference
+    fn element(&mut self, dict: &Dictionary, page: Option<PlainRef>, mcids: &mut Vec<i32>, depth: usize) -> Result<(), PdfError> {
+        if depth > MAX_DEPTH {
+            return Ok(());
+        }
+        let page = match dict.get("Pg") {
+            Some(&Primitive::Reference(r)) => Some(r),
+            _ => page
+        };
+        match dict.get("Type").and_then(|t| t.as_name().ok()) {
+            Some("MCR") => {
+                if let (Some(mcid), true) = (dict.get("MCID").and_then(|n| n.as_integer().ok()), page == Some(self.page)) {
+                    mcids.push(mcid);
+                }
+                return Ok(());
+            }
+            Some("OBJR") => return Ok(()),
+            _ => {}
+        }
+
+        let figure = dict.get("S").and_then(|s| s.as_name().ok()).map_or(false, |s| self.is_figure(s));
+        if !figure {
+            if let Some(k) = dict.get("K") {
+                self.kids(k, page, mcids, depth)?;
+            }
+            return Ok(());
+        }
+
+        // nested figures are part of this one
+        let mut own = vec![];
+        if let Some(k) = dict.get("K") {
+            self.kids(k, page, &mut own, depth)?;
+        }
+        mcids.extend_from_slice(&own);
+        if !own.is_empty() {
+            self.figures.push(Figure {
+                alt: text(dict, "Alt"),
+                actual_text: text(dict, "ActualText"),
+                mcids: own,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The figures of the structure tree with content on the page `page`.
+/// `struct_tree_root` is the /StructTreeRoot of the catalog.
+pub fn page_figures(struct_tree_root: &Primitive, page: PlainRef, resolve: &impl Resolve) -> Result<Vec<Figure>, PdfError> {
+    let root = match resolve_dict(struct_tree_root, resolve)? {
+        Some(root) => root,
+        None => return Ok(vec![])
+    };
+    let role_map = match root.get("RoleMap") {
+        Some(p) => resolve_dict(p, resolve)?,
+        None => None
+    };
+    let mut walker = Walker { resolve, page, role_map, visited: HashSet::new(), figures: vec![] };
+    if let Some(k) = root.get("K") {
+        walker.kids(k, None, &mut vec![], 0)?;
+    }
+    Ok(walker.figures)
+}
+
+// the text of the line closest to `bounds`, below or above it
+fn find_caption(bounds: RectF, spans: &[&TextSpan]) -> Option<String> {
+    let overlaps = |r: RectF| r.min_x() < bounds.max_x() && r.max_x() > bounds.min_x();
+    let distance = |r: RectF| if r.min_y() >= bounds.max_y() {
+        r.min_y() - bounds.max_y()
+    } else if r.max_y() <= bounds.min_y() {
+        bounds.min_y() - r.max_y()
+    } else {
+        f32::INFINITY
+    };
+    // captions that say what they are win over plain text that is closer
+    let labeled = |span: &TextSpan| {
+        let text = span.text.trim_start();
+        ["Fig", "Figure", "Abb", "Table", "Image"].iter().any(|l| text.starts_with(l))
+    };
+    let candidates = spans.iter()
+        .filter(|s| !s.text.trim().is_empty() && overlaps(s.rect))
+        .map(|s| (s, distance(s.rect)))
+        .filter(|&(s, d)| d <= CAPTION_DISTANCE * s.effective_font_size.max(1.0));
+    let (best, _) = candidates.min_by(|(a, da), (b, db)| {
+        labeled(b).cmp(&labeled(a)).then(da.total_cmp(db))
+    })?;
+
+    // the rest of its line
+    let mut line: Vec<&TextSpan> = spans.iter().cloned()
+        .filter(|s| (s.rect.center().y() - best.rect.center().y()).abs() < 0.5 * best.rect.height().abs())
+        .collect();
+    line.sort_by(|a, b| a.rect.min_x().total_cmp(&b.rect.min_x()));
+    let text = line.iter().map(|s| s.text.trim()).filter(|t| !t.is_empty()).collect::<Vec<_>>().join(" ");
+    Some(text)
+}
+
+/// Pair the images of a page traced with `Tracer::finish_with_tags` with the figures they are tagged with
+/// and with captions next to them. Images that are not in any figure are included, without alt text.
+pub fn image_alt_texts(items: &[(DrawItem, Arc<[MarkedContent]>)], figures: &[Figure]) -> Vec<ImageAlt> {
+    let spans: Vec<&TextSpan> = items.iter().filter_map(|(item, _)| match item {
+        DrawItem::Text(span, _) => Some(span),
+        _ => None
+    }).collect();
+
+    items.iter().enumerate().filter_map(|(i, (item, tags))| {
+        if !matches!(item, DrawItem::Image(_) | DrawItem::InlineImage(_)) {
+            return None;
+        }
+        let figure = tags.iter().rev()
+            .filter_map(|mc| mc.mcid)
+            .find_map(|mcid| figures.iter().find(|f| f.mcids.contains(&mcid)));
+        let bounds = item.bounds();
+        Some(ImageAlt {
+            item: i,
+            bounds,
+            alt: figure.and_then(|f| f.alt.clone()),
+            actual_text: figure.and_then(|f| f.actual_text.clone()),
+            caption: find_caption(bounds, &spans),
+        })
+    }).collect()
+}
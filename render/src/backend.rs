@@ -1,23 +1,29 @@
 use pathfinder_geometry::{
     transform2d::Transform2F,
     rect::RectF,
+    vector::Vector2F,
 };
 use pathfinder_content::{
     fill::FillRule,
     stroke::{StrokeStyle},
-    outline::Outline,
+    outline::{Outline, Contour},
+    pattern::Image,
 };
 
-use pdf::{object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef}, content::Op};
+use pdf::{object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, Pattern, Page}, content::Op};
 use pdf::error::PdfError;
-use font::Glyph;
-use super::{FontEntry, TextSpan, Fill};
+use font::{Glyph, GlyphId};
+use super::{FontEntry, FunctionCache, RenderOptions, Budget, TextSpan, Fill, TextOptions, ColorGlyph, SoftMask, Transfer, Gradient, PagePlacement, shading};
 use pdf::font::Font as PdfFont;
 use std::sync::Arc;
+use std::borrow::Cow;
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BlendMode {
+    /// normal painting, the source over the backdrop (not the Overlay blend mode of PDF)
     Overlay,
+    /// overprinting, approximated by multiplying with the backdrop
     Darken
 }
 
@@ -25,15 +31,103 @@ pub trait Backend {
     type ClipPathId: Copy;
 
     fn create_clip_path(&mut self, path: Outline, fill_rule: FillRule, parent: Option<Self::ClipPathId>) -> Self::ClipPathId;
-    fn draw(&mut self, outline: &Outline, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<Self::ClipPathId>);
+    /// `outline` is owned when the caller doesn't need it anymore, so that backends can keep it without a copy.
+    fn draw(&mut self, outline: Cow<Outline>, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<Self::ClipPathId>);
     fn set_view_box(&mut self, r: RectF);
-    fn draw_image(&mut self, xref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>, resolve: &impl Resolve);
-    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>, resolve: &impl Resolve);
+    /// Called after `set_view_box` with where the page goes, before anything of it is drawn.
+    fn begin_page(&mut self, placement: &PagePlacement) {}
+    /// Errors mean the image could not be decoded, they end up in the `RenderDiagnostics`.
+    fn draw_image(&mut self, xref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError>;
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError>;
+    /// Draw decoded pixels into the unit square of `transform`, like `draw_image` does.
+    /// Used to replay a `DisplayList`, by default nothing is drawn.
+    fn draw_image_pixels(&mut self, image: &Image, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>) {}
     fn draw_glyph(&mut self, glyph: &Glyph, mode: &DrawMode, transform: Transform2F, clip: Option<Self::ClipPathId>) {
-        self.draw(&glyph.path, mode, FillRule::Winding, transform, clip);
+        self.draw(Cow::Borrowed(&glyph.path), mode, FillRule::Winding, transform, clip);
+    }
+    /// Draw the glyphs of one text showing operator that share `mode` and `clip`.
+    /// Glyph `g` is placed with `base_transform * g.transform`.
+    /// By default each glyph is drawn with `draw_glyph`.
+    fn draw_glyph_run(&mut self, font: &FontEntry, glyphs: &[PositionedGlyph], mode: &DrawMode, base_transform: Transform2F, clip: Option<Self::ClipPathId>) {
+        for g in glyphs {
+            self.draw_glyph(&g.glyph, mode, base_transform * g.transform, clip);
+        }
+    }
+    /// Draw a glyph of a color font.
+    /// By default the layers are filled with their color, bitmaps are not drawn.
+    fn draw_color_glyph(&mut self, glyph: &ColorGlyph, mode: &DrawMode, transform: Transform2F, clip: Option<Self::ClipPathId>) {
+        draw_color_layers(self, glyph, mode, transform, clip);
+    }
+    /// Start rendering a soft mask group. Until `end_soft_mask` everything is drawn into the mask instead of the output.
+    /// Backends that can't render masks return false and the mask group is skipped.
+    fn begin_soft_mask(&mut self) -> bool {
+        false
+    }
+    /// Finish the mask group. `backdrop` is the RGB color (from /BC) the group is composited over.
+    fn end_soft_mask(&mut self, backdrop: (f32, f32, f32)) -> Option<Arc<SoftMask>> {
+        None
+    }
+    /// The soft mask of everything that is drawn from now on, None when the mask ends.
+    fn set_soft_mask(&mut self, mask: Option<Arc<SoftMask>>) {}
+    /// A fill or stroke with `Fill::Pattern(pattern)` and `pattern_transform` may follow.
+    /// `draw` has no access to the document, so backends that paint tiling patterns load them here.
+    /// The content of the pattern is rendered with `options` and uses up `budget`, see `render_pattern_in`.
+    fn prepare_pattern(&mut self, pattern: Ref<Pattern>, pattern_transform: Transform2F, resolve: &impl Resolve, options: &RenderOptions, budget: &mut Budget) {}
+    /// A rendered tile of the tiling pattern for `pattern_transform`, in place of `prepare_pattern`.
+    /// `transform` maps its pixels to the output, the tile repeats in both directions.
+    /// Used to replay a `DisplayList`, by default it is ignored.
+    fn set_pattern_tile(&mut self, pattern: Ref<Pattern>, pattern_transform: Transform2F, tile: &Image, transform: Transform2F) {}
+    /// The transfer function for images drawn from now on.
+    /// Colors passed to `draw` have it applied already.
+    fn set_transfer(&mut self, transfer: Option<Arc<Transfer>>) {}
+    /// A marked content sequence (BMC or BDC) starts, what is drawn until the matching
+    /// `end_marked_content` is part of it. Sequences of a form are nested in those around the form.
+    fn begin_marked_content(&mut self, marked_content: &MarkedContent) {}
+    fn end_marked_content(&mut self) {}
+    /// Draw a triangle of a mesh shading. The colors at the corners are meant to be interpolated linearly,
+    /// by default the triangle is filled with their mean.
+    fn draw_shaded_triangle(&mut self, points: [Vector2F; 3], colors: [(f32, f32, f32); 3], alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<Self::ClipPathId>) {
+        let mut contour = Contour::new();
+        for p in points {
+            contour.push_endpoint(p);
+        }
+        contour.close();
+        let mut outline = Outline::new();
+        outline.push_contour(contour);
+
+        let (r, g, b) = colors.iter().fold((0., 0., 0.), |(r, g, b), &(r2, g2, b2)| (r + r2, g + g2, b + b2));
+        let fill = FillMode { color: Fill::Solid(r / 3., g / 3., b / 3.), alpha, mode, pattern_transform: Transform2F::default() };
+        self.draw(Cow::Owned(outline), &DrawMode::Fill { fill }, FillRule::Winding, transform, clip);
+    }
+    /// Draw an axial or radial shading (`gradient` in the space of `transform`), covering `area` of the output.
+    /// By default it is split into triangles for `draw_shaded_triangle`.
+    fn draw_gradient(&mut self, gradient: &Gradient, area: RectF, alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<Self::ClipPathId>) {
+        shading::gradient_triangles(gradient, transform.inverse() * area, &mut |points, colors| {
+            self.draw_shaded_triangle(points, colors, alpha, mode, transform, clip);
+        });
     }
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError>;
     fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>);
+    /// How the unicode of glyphs is post-processed before it is passed to `add_text`.
+    fn text_options(&self) -> TextOptions {
+        TextOptions::default()
+    }
+    /// The ops of the content stream of `page`. `page_nr` is `RenderOptions::page_nr`,
+    /// backends with a `Cache` keep the ops of numbered pages so that they are only parsed once,
+    /// if a file was selected with `Cache::set_file`.
+    fn page_ops(&mut self, page: &Page, page_nr: Option<u32>, resolve: &impl Resolve) -> Result<Arc<[Op]>, PdfError> {
+        crate::page_ops(page, resolve)
+    }
+    /// Where the sampled functions of color spaces and shadings are kept, see `Cache::functions`.
+    /// Without it, functions are sampled again for every page.
+    fn function_cache(&mut self) -> Option<FunctionCache> {
+        None
+    }
+    /// If false, `get_font` is not called and no glyphs are drawn.
+    /// Text is still passed to `add_text`, measured with the /Widths of the font.
+    fn load_fonts(&self) -> bool {
+        true
+    }
 
     /// The following functions are for debugging PDF files and not relevant for rendering them.
     fn bug_text_no_font(&mut self, data: &[u8]) {}
@@ -42,20 +136,72 @@ pub trait Backend {
     fn bug_op(&mut self, op_nr: usize) {}
     fn inspect_op(&mut self, op: &Op) {}
 }
-#[derive(Clone, Debug)]
+/// Fill the layers of a COLR glyph with `draw`, the default of `Backend::draw_color_glyph`.
+pub fn draw_color_layers<B: Backend>(backend: &mut B, glyph: &ColorGlyph, mode: &DrawMode, transform: Transform2F, clip: Option<B::ClipPathId>) {
+    let layers = match *glyph {
+        ColorGlyph::Layers(ref layers) => layers,
+        ColorGlyph::Bitmap { .. } => return
+    };
+    let fill = match *mode {
+        DrawMode::Fill { ref fill } | DrawMode::FillStroke { ref fill, .. } => fill,
+        DrawMode::Stroke { .. } => {
+            for (outline, _) in layers {
+                backend.draw(Cow::Borrowed(outline), mode, FillRule::Winding, transform, clip);
+            }
+            return;
+        }
+    };
+    for &(ref outline, color) in layers {
+        let fill = match color {
+            Some(c) => {
+                let c = c.to_f32();
+                FillMode { color: Fill::Solid(c.r(), c.g(), c.b()), alpha: c.a() * fill.alpha, mode: fill.mode, pattern_transform: fill.pattern_transform }
+            }
+            None => fill.clone()
+        };
+        backend.draw(Cow::Borrowed(outline), &DrawMode::Fill { fill }, FillRule::Winding, transform, clip);
+    }
+}
 
+/// An open marked content sequence.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarkedContent {
+    pub tag: String,
+    /// the /MCID of the properties, which links the content to the structure tree
+    pub mcid: Option<i32>,
+    /// the /Name of the optional content group, for the tag OC
+    pub layer: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FillMode {
     pub color: Fill,
     pub alpha: f32,
     pub mode: BlendMode,
+    /// Maps pattern space to the output, for `Fill::Pattern`.
+    /// Pattern space is the default space of the page or form the pattern is used in,
+    /// so it does not change with the CTM. The /Matrix of the pattern comes on top of it.
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::transform"))]
+    pub pattern_transform: Transform2F,
 }
+/// A glyph of a `draw_glyph_run`, `transform` is relative to the start of the run.
+pub struct PositionedGlyph {
+    pub gid: GlyphId,
+    pub glyph: Glyph,
+    pub transform: Transform2F,
+}
+#[derive(Clone, Debug)]
 pub enum DrawMode {
     Fill { fill: FillMode },
     Stroke { stroke: FillMode, stroke_mode: Stroke },
     FillStroke { fill: FillMode, stroke: FillMode, stroke_mode: Stroke },
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stroke {
     pub dash_pattern: Option<(Vec<f32>, f32)>,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::stroke_style"))]
     pub style: StrokeStyle,
 }
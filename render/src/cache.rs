@@ -1,10 +1,15 @@
 use std::path::{PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 use pdf::object::*;
-use pdf::primitive::Name;
+use pdf::primitive::{Name, Primitive, Dictionary};
 use pdf::font::{Font as PdfFont};
-use pdf::error::{Result};
+use pdf::content::Op;
+use pdf::error::{Result, PdfError};
 
 use pathfinder_geometry::{
     vector::{Vector2I},
@@ -13,11 +18,11 @@ use pathfinder_content::{
     pattern::{Image},
 };
 
-use crate::BlendMode;
+use crate::{BlendMode, CacheOptions, FunctionCache};
 
 use super::{fontentry::FontEntry};
 use super::image::load_image;
-use super::font::{load_font, StandardCache};
+use super::font::{load_font, font_key, StandardCache, FontResolver, FontDebugSink, EmbeddedFonts};
 use globalcache::{sync::SyncCache, ValueSize};
 
 #[derive(Clone)]
@@ -31,27 +36,116 @@ impl ValueSize for ImageResult {
     }
 }
 
-pub struct Cache {
-    // shared mapping of fontname -> font
-    fonts: Arc<SyncCache<usize, Option<Arc<FontEntry>>>>,
+/// The parsed content stream of a page.
+#[derive(Clone)]
+pub struct OpsResult(pub Result<Arc<[Op]>, Arc<PdfError>>);
+impl ValueSize for OpsResult {
+    // only the ops themselves, not the strings and arrays they own
+    fn size(&self) -> usize {
+        match self.0 {
+            Ok(ref ops) => ops.len() * std::mem::size_of::<Op>(),
+            Err(_) => 1,
+        }
+    }
+}
+
+/// Identifies the document that cached entries belong to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FileId(u64);
+impl FileId {
+    /// a new, unique id
+    pub fn new() -> FileId {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        FileId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+impl Default for FileId {
+    fn default() -> Self {
+        FileId(0)
+    }
+}
+
+struct FileCache {
+    fonts: Arc<SyncCache<u64, Option<Arc<FontEntry>>>>,
+    // the font programs of `fonts`, shared by fonts that embed the same data
+    embedded_fonts: Arc<EmbeddedFonts>,
     images: Arc<SyncCache<(Ref<XObject>, BlendMode), ImageResult>>,
+    // by page number
+    ops: Arc<SyncCache<u32, OpsResult>>,
+    // inline images have no reference, they are keyed by a hash of their dictionary and data
+    inline_images: Arc<SyncCache<(u64, BlendMode), ImageResult>>,
+    functions: FunctionCache,
+}
+impl FileCache {
+    fn new() -> Self {
+        FileCache {
+            fonts: SyncCache::new(),
+            embedded_fonts: SyncCache::new(),
+            images: SyncCache::new(),
+            ops: SyncCache::new(),
+            inline_images: SyncCache::new(),
+            functions: FunctionCache::new(),
+        }
+    }
+}
+
+pub struct Cache {
+    // per document caches, keyed by the file they were loaded from
+    files: HashMap<FileId, FileCache>,
+    current: FileId,
     std: StandardCache,
     missing_fonts: Vec<Name>,
 }
 
 impl Cache {
     pub fn new() -> Cache {
+        Cache::with_standard_cache(StandardCache::new())
+    }
+    /// Load substitutes for non-embedded fonts from `resolver` instead of STANDARD_FONTS.
+    pub fn with_font_resolver(resolver: impl FontResolver + 'static) -> Cache {
+        Cache::with_standard_cache(StandardCache::with_resolver(resolver))
+    }
+    pub fn with_options(options: CacheOptions) -> Cache {
+        let mut std = StandardCache::new();
+        std.set_options(&options);
+        Cache::with_standard_cache(std)
+    }
+    /// Change how fonts are loaded. Fonts that are already cached are not reloaded.
+    pub fn set_options(&mut self, options: &CacheOptions) {
+        self.std.set_options(options);
+    }
+    fn with_standard_cache(std: StandardCache) -> Cache {
         Cache {
-            fonts: SyncCache::new(),
-            images: SyncCache::new(),
-            std: StandardCache::new(),
+            files: HashMap::new(),
+            current: FileId::default(),
+            std,
             missing_fonts: Vec::new(),
         }
     }
+    /// Pass embedded fonts to `sink` when they are parsed.
+    pub fn set_font_debug_sink(&mut self, sink: Option<Arc<dyn FontDebugSink>>) {
+        self.std.set_debug_sink(sink);
+    }
+    /// Select the document that following lookups belong to.
+    pub fn set_file(&mut self, file: FileId) {
+        self.current = file;
+    }
+    pub fn current_file(&self) -> FileId {
+        self.current
+    }
+    /// Drop all fonts, images and ops that were loaded for `file`.
+    pub fn clear_file(&mut self, file: FileId) {
+        self.files.remove(&file);
+    }
+    fn file(&mut self) -> &FileCache {
+        self.files.entry(self.current).or_insert_with(FileCache::new)
+    }
     pub fn get_font(&mut self, pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, > {
         let mut error = None;
-        let val = self.fonts.get(&**pdf_font as *const PdfFont as usize, |_| 
-            match load_font(pdf_font, resolve, &mut self.std) {
+        let file = self.file();
+        let (fonts, embedded) = (file.fonts.clone(), file.embedded_fonts.clone());
+        let mut load = || {
+            match load_font(pdf_font, resolve, &self.std, &embedded) {
                 Ok(Some(f)) => Some(Arc::new(f)),
                 Ok(None) => {
                     if let Some(ref name) = pdf_font.name {
@@ -64,21 +158,90 @@ impl Cache {
                     None
                 }
             }
-        );
+        };
+        let val = match font_key(pdf_font) {
+            Some(key) => fonts.get(key, |_| load()),
+            None => load()
+        };
         match error {
             None => Ok(val),
             Some(e) => Err(e)
         }
     }
 
+    /// The ops of page `page_nr` of the current file, parsed the first time they are needed.
+    ///
+    /// Page numbers only identify a page together with the file, so the ops are only kept
+    /// after `set_file` selected one. Without it every call parses the ops again.
+    pub fn get_ops(&mut self, page_nr: u32, page: &Page, resolve: &impl Resolve) -> Result<Arc<[Op]>> {
+        if self.current == FileId::default() {
+            return crate::page_ops(page, resolve);
+        }
+        let result = self.file().ops.get(page_nr, |_| OpsResult(crate::page_ops(page, resolve).map_err(Arc::new)));
+        result.0.map_err(|source| PdfError::Shared { source })
+    }
+
+    /// The sampled functions of the current file.
+    pub fn functions(&mut self) -> FunctionCache {
+        self.file().functions.clone()
+    }
+
     pub fn get_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> ImageResult {
-        self.images.get((xobject_ref, mode), |_|
-            ImageResult(Arc::new(load_image(im, resources, resolve, mode).map(|image|
-                Image::new(Vector2I::new(im.width as i32, im.height as i32), Arc::new(image.into_data().into()))
-            )))
-        )
+        self.file().images.get((xobject_ref, mode), |_| decode_image(im, resources, resolve, mode))
+    }
+    /// Like `get_image`, for the image of an inline image op.
+    /// The key is a hash of the dictionary and the data as it is in the content stream, before decoding.
+    pub fn get_inline_image(&mut self, im: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> Result<ImageResult> {
+        let key = inline_image_key(im, resolve)?;
+        Ok(self.file().inline_images.get((key, mode), |_| decode_image(im, resources, resolve, mode)))
+    }
+}
+fn inline_image_key(im: &ImageXObject, resolve: &impl Resolve) -> Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    hash_primitive(&im.inner.info.info.to_primitive(&mut NoUpdate)?, &mut hasher);
+    // the filters are plain values (no maps), their debug output doesn't change between calls
+    for filter in &im.inner.info.filters {
+        format!("{:?}", filter).hash(&mut hasher);
+    }
+    im.inner.raw_data(resolve)?.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+// hash the contents of `p`, dictionaries in the order of their keys
+fn hash_primitive(p: &Primitive, state: &mut impl Hasher) {
+    std::mem::discriminant(p).hash(state);
+    match *p {
+        Primitive::Null => {}
+        Primitive::Integer(i) => i.hash(state),
+        Primitive::Number(n) => n.to_bits().hash(state),
+        Primitive::Boolean(b) => b.hash(state),
+        Primitive::String(ref s) => s.as_bytes().hash(state),
+        Primitive::Name(ref n) => n.as_str().hash(state),
+        Primitive::Reference(r) => (r.id, r.gen).hash(state),
+        Primitive::Array(ref items) => {
+            items.len().hash(state);
+            for item in items {
+                hash_primitive(item, state);
+            }
+        }
+        Primitive::Dictionary(ref dict) => hash_dictionary(dict, state),
+        Primitive::Stream(ref stream) => hash_dictionary(&stream.info, state),
+    }
+}
+fn hash_dictionary(dict: &Dictionary, state: &mut impl Hasher) {
+    let mut entries: Vec<_> = dict.iter().collect();
+    entries.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    entries.len().hash(state);
+    for (key, value) in entries {
+        key.as_str().hash(state);
+        hash_primitive(value, state);
     }
 }
+fn decode_image(im: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> ImageResult {
+    ImageResult(Arc::new(load_image(im, resources, resolve, mode).map(|image|
+        Image::new(Vector2I::new(im.width as i32, im.height as i32), Arc::new(image.into_data().into()))
+    )))
+}
+
 impl Drop for Cache {
     fn drop(&mut self) {
         info!("missing fonts:");
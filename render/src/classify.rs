@@ -0,0 +1,77 @@
+//! Cheap classification of pages, for example to decide which pages need OCR.
+
+use pathfinder_geometry::rect::RectF;
+use pdf::content::TextMode;
+use pdf::error::PdfError;
+use pdf::object::{Page, Resolve};
+use crate::{Fill, RenderOptions, render_page_with};
+use crate::tracer::{DrawItem, TraceCache, Tracer};
+
+/// An image that covers more than this fraction of the page makes it a scan.
+const SCAN_COVERAGE: f32 = 0.9;
+
+/// What was drawn on a page.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PageStats {
+    /// text spans that can be seen
+    pub text: usize,
+    /// text spans in invisible mode, like the OCR layer of a scan
+    pub invisible_text: usize,
+    /// paths and shadings, except paths that are filled white and not stroked
+    pub vector: usize,
+    /// images, inline ones included
+    pub images: usize,
+    /// fraction of the page covered by the largest image
+    pub largest_image_coverage: f32,
+}
+impl PageStats {
+    /// Count the items of a page traced with `Tracer`, `view_box` is the page in the same space.
+    pub fn from_items(items: &[DrawItem], view_box: RectF) -> PageStats {
+        let page_area = area(view_box);
+        let mut stats = PageStats::default();
+        for item in items {
+            match item {
+                DrawItem::Text(span, _) if span.text.trim().is_empty() => {}
+                DrawItem::Text(span, _) if matches!(span.mode, TextMode::Invisible) => stats.invisible_text += 1,
+                DrawItem::Text(..) => stats.text += 1,
+                DrawItem::Vector(path) | DrawItem::PatternFill(path) => {
+                    let white = matches!(path.fill, Some(ref f) if f.color == Fill::Solid(1.0, 1.0, 1.0));
+                    if !white || path.stroke.is_some() {
+                        stats.vector += 1;
+                    }
+                }
+                DrawItem::Shading(_) => stats.vector += 1,
+                DrawItem::Image(_) | DrawItem::InlineImage(_) => {
+                    stats.images += 1;
+                    if page_area > 0.0 {
+                        let covered = item.bounds().intersection(view_box).map_or(0.0, area);
+                        stats.largest_image_coverage = stats.largest_image_coverage.max(covered / page_area);
+                    }
+                }
+            }
+        }
+        stats
+    }
+    /// nothing visible is drawn
+    pub fn is_blank(&self) -> bool {
+        self.text == 0 && self.vector == 0 && self.images == 0
+    }
+    /// One image covers (almost) the whole page and there is no visible text.
+    /// Invisible text is allowed, scans often come with an OCR layer.
+    pub fn is_scan(&self) -> bool {
+        self.text == 0 && self.largest_image_coverage > SCAN_COVERAGE
+    }
+}
+
+fn area(r: RectF) -> f32 {
+    r.width().abs() * r.height().abs()
+}
+
+/// Trace `page` and count what is drawn on it.
+pub fn classify_page(cache: &TraceCache, resolve: &impl Resolve, page: &Page, options: &RenderOptions) -> Result<PageStats, PdfError> {
+    let mut clip_paths = vec![];
+    let mut tracer = Tracer::new(cache, &mut clip_paths);
+    render_page_with(&mut tracer, resolve, page, options)?;
+    let view_box = tracer.view_box();
+    Ok(PageStats::from_items(&tracer.finish(), view_box))
+}
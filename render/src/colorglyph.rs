@@ -0,0 +1,264 @@
+//! Color glyphs of emoji fonts, read directly from the OpenType tables.
+//!
+//! Supported are COLR version 0 layers with CPAL palette 0 and PNG bitmaps in sbix.
+//! CBDT bitmaps are not read yet.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use pathfinder_color::ColorU;
+use pathfinder_content::outline::Outline;
+use pathfinder_geometry::{rect::RectF, vector::Vector2F};
+use font::GlyphId;
+use crate::font::FontRc;
+
+/// One layer of a COLR glyph.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorLayer {
+    pub gid: GlyphId,
+    /// None means the current text color
+    pub color: Option<ColorU>,
+}
+
+#[derive(Debug, Clone)]
+struct Bitmap {
+    range: Range<usize>,
+    /// in font units, y up
+    rect: RectF,
+}
+
+/// The color glyphs of a font.
+pub struct ColorGlyphs {
+    data: Arc<[u8]>,
+    layers: HashMap<u32, Vec<ColorLayer>>,
+    bitmaps: HashMap<u32, Bitmap>,
+}
+
+/// What a backend gets to draw in place of the outline of a glyph.
+pub enum ColorGlyph<'a> {
+    /// outlines in glyph space, bottom first, with their color or None for the text color
+    Layers(Vec<(Outline, Option<ColorU>)>),
+    /// a PNG image covering `rect` in glyph space
+    Bitmap { png: &'a [u8], rect: RectF },
+}
+impl<'a> ColorGlyph<'a> {
+    /// bounds in glyph space
+    pub fn bounds(&self) -> RectF {
+        match *self {
+            ColorGlyph::Layers(ref layers) => layers.iter()
+                .map(|(outline, _)| outline.bounds())
+                .reduce(|a, b| a.union_rect(b))
+                .unwrap_or_else(|| RectF::new(Vector2F::zero(), Vector2F::zero())),
+            ColorGlyph::Bitmap { rect, .. } => rect,
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+impl<'a> Reader<'a> {
+    fn u16(&self, pos: usize) -> Option<u16> {
+        self.0.get(pos .. pos.checked_add(2)?).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn i16(&self, pos: usize) -> Option<i16> {
+        self.u16(pos).map(|n| n as i16)
+    }
+    fn u32(&self, pos: usize) -> Option<u32> {
+        self.0.get(pos .. pos.checked_add(4)?).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn table(&self, tag: &[u8; 4]) -> Option<Range<usize>> {
+        let num_tables = self.u16(4)? as usize;
+        for i in 0 .. num_tables {
+            let record = 12 + 16 * i;
+            if self.0.get(record .. record + 4)? == tag {
+                let offset = self.u32(record + 8)? as usize;
+                let end = offset.checked_add(self.u32(record + 12)? as usize)?;
+                return (end <= self.0.len()).then(|| offset .. end);
+            }
+        }
+        None
+    }
+}
+// position of entry `index` of an array of `size` byte entries at `base`, None if it overflows
+fn entry(base: usize, index: usize, size: usize) -> Option<usize> {
+    index.checked_mul(size)?.checked_add(base)
+}
+
+impl ColorGlyphs {
+    /// None if the font has no color glyphs that can be read.
+    pub fn parse(data: &[u8]) -> Option<ColorGlyphs> {
+        let r = Reader(data);
+        let layers = r.table(b"COLR").and_then(|colr| {
+            let palette = r.table(b"CPAL").and_then(|cpal| parse_cpal(&r, cpal.start)).unwrap_or_default();
+            parse_colr(&r, colr.start, &palette)
+        }).unwrap_or_default();
+        let bitmaps = r.table(b"sbix").and_then(|sbix| parse_sbix(&r, sbix)).unwrap_or_default();
+
+        if layers.is_empty() && bitmaps.is_empty() {
+            return None;
+        }
+        Some(ColorGlyphs {
+            data: data.into(),
+            layers,
+            bitmaps,
+        })
+    }
+
+    /// The color version of glyph `gid`, layers are looked up in `font`.
+    pub fn glyph(&self, font: &FontRc, gid: GlyphId) -> Option<ColorGlyph> {
+        if let Some(layers) = self.layers(gid) {
+            let layers = layers.iter()
+                .filter_map(|l| font.glyph(l.gid).map(|g| (g.path, l.color)))
+                .collect();
+            return Some(ColorGlyph::Layers(layers));
+        }
+        self.bitmap(gid).map(|(png, rect)| ColorGlyph::Bitmap { png, rect })
+    }
+    pub fn layers(&self, gid: GlyphId) -> Option<&[ColorLayer]> {
+        self.layers.get(&gid.0).map(|l| l.as_slice())
+    }
+    /// PNG data and the rect it covers in font units
+    pub fn bitmap(&self, gid: GlyphId) -> Option<(&[u8], RectF)> {
+        self.bitmaps.get(&gid.0).map(|b| (&self.data[b.range.clone()], b.rect))
+    }
+}
+
+fn parse_cpal(r: &Reader, start: usize) -> Option<Vec<ColorU>> {
+    let num_entries = r.u16(start + 2)? as usize;
+    let records = start.checked_add(r.u32(start + 8)? as usize)?;
+    let first = r.u16(start + 12)? as usize;
+    (0 .. num_entries).map(|i| {
+        let pos = entry(records, first + i, 4)?;
+        let bgra = r.0.get(pos .. pos.checked_add(4)?)?;
+        Some(ColorU::new(bgra[2], bgra[1], bgra[0], bgra[3]))
+    }).collect()
+}
+
+fn parse_colr(r: &Reader, start: usize, palette: &[ColorU]) -> Option<HashMap<u32, Vec<ColorLayer>>> {
+    let num_base = r.u16(start + 2)? as usize;
+    let base_records = start.checked_add(r.u32(start + 4)? as usize)?;
+    let layer_records = start.checked_add(r.u32(start + 8)? as usize)?;
+
+    let mut map = HashMap::with_capacity(num_base);
+    for i in 0 .. num_base {
+        let pos = entry(base_records, i, 6)?;
+        let gid = r.u16(pos)? as u32;
+        let first = r.u16(pos + 2)? as usize;
+        let count = r.u16(pos + 4)? as usize;
+        let layers = (first .. first + count).map(|l| {
+            let pos = entry(layer_records, l, 4)?;
+            let gid = GlyphId(r.u16(pos)? as u32);
+            let palette_index = r.u16(pos + 2)?;
+            Some(ColorLayer {
+                gid,
+                color: match palette_index {
+                    0xFFFF => None,
+                    n => palette.get(n as usize).cloned(),
+                }
+            })
+        }).collect::<Option<Vec<_>>>()?;
+        map.insert(gid, layers);
+    }
+    Some(map)
+}
+
+fn parse_sbix(r: &Reader, sbix: Range<usize>) -> Option<HashMap<u32, Bitmap>> {
+    let units_per_em = r.table(b"head").and_then(|head| r.u16(head.start + 18))? as f32;
+    let num_glyphs = r.table(b"maxp").and_then(|maxp| r.u16(maxp.start + 4))? as usize;
+
+    // use the strike with the highest resolution
+    let num_strikes = r.u32(sbix.start + 4)? as usize;
+    let strike = (0 .. num_strikes)
+        .filter_map(|i| sbix.start.checked_add(r.u32(entry(sbix.start + 8, i, 4)?)? as usize))
+        .max_by_key(|&strike| r.u16(strike).unwrap_or(0))?;
+    let ppem = r.u16(strike)? as f32;
+    if ppem == 0.0 {
+        return None;
+    }
+    let scale = units_per_em / ppem;
+
+    let mut map = HashMap::new();
+    for gid in 0 .. num_glyphs {
+        let start = strike.checked_add(r.u32(entry(strike + 4, gid, 4)?)? as usize)?;
+        let end = strike.checked_add(r.u32(entry(strike + 8, gid, 4)?)? as usize)?;
+        if end <= start.saturating_add(8) || end > sbix.end {
+            continue;
+        }
+        if r.0.get(start + 4 .. start + 8)? != b"png " {
+            continue;
+        }
+        let png = start + 8 .. end;
+        // width and height from the IHDR chunk
+        let (width, height) = match (r.u32(png.start + 16), r.u32(png.start + 20)) {
+            (Some(w), Some(h)) => (w as f32, h as f32),
+            _ => continue
+        };
+        let origin = Vector2F::new(r.i16(start)? as f32, r.i16(start + 2)? as f32);
+        map.insert(gid as u32, Bitmap {
+            range: png,
+            rect: RectF::new(origin * scale, Vector2F::new(width, height) * scale),
+        });
+    }
+    Some(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a font with only a COLR and a CPAL table.
+    // glyph 5 is drawn with glyph 10 in palette entry 1 and glyph 11 in the text color.
+    fn font(color_records: u32) -> Vec<u8> {
+        let mut colr = vec![];
+        colr.extend_from_slice(&0u16.to_be_bytes()); // version
+        colr.extend_from_slice(&1u16.to_be_bytes()); // base glyph records
+        colr.extend_from_slice(&14u32.to_be_bytes());
+        colr.extend_from_slice(&20u32.to_be_bytes()); // layer records
+        colr.extend_from_slice(&2u16.to_be_bytes());
+        for n in [5u16, 0, 2, 10, 1, 11, 0xFFFF] {
+            colr.extend_from_slice(&n.to_be_bytes());
+        }
+
+        let mut cpal = vec![];
+        for n in [0u16, 2, 1, 2] { // version, entries, palettes, color records
+            cpal.extend_from_slice(&n.to_be_bytes());
+        }
+        cpal.extend_from_slice(&color_records.to_be_bytes());
+        cpal.extend_from_slice(&0u16.to_be_bytes()); // first entry of palette 0
+        cpal.extend_from_slice(&[0, 0, 255, 255, 255, 0, 0, 128]); // BGRA
+
+        let mut data = vec![0, 1, 0, 0];
+        data.extend_from_slice(&2u16.to_be_bytes());
+        data.extend_from_slice(&[0; 6]);
+        let mut offset = 12 + 2 * 16;
+        for (tag, table) in [(b"COLR", &colr), (b"CPAL", &cpal)] {
+            data.extend_from_slice(tag);
+            data.extend_from_slice(&[0; 4]); // checksum
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        data.extend_from_slice(&colr);
+        data.extend_from_slice(&cpal);
+        data
+    }
+
+    #[test]
+    fn colr_layers() {
+        let glyphs = ColorGlyphs::parse(&font(14)).unwrap();
+        let layers = glyphs.layers(GlyphId(5)).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].gid.0, 10);
+        assert_eq!(layers[0].color, Some(ColorU::new(0, 0, 255, 128)));
+        assert_eq!(layers[1].gid.0, 11);
+        assert_eq!(layers[1].color, None);
+        assert!(glyphs.layers(GlyphId(10)).is_none());
+    }
+
+    #[test]
+    fn cpal_out_of_range() {
+        // the color records can't be read, the layers are still there without their colors
+        let glyphs = ColorGlyphs::parse(&font(u32::MAX)).unwrap();
+        let layers = glyphs.layers(GlyphId(5)).unwrap();
+        assert_eq!(layers[0].color, None);
+    }
+}
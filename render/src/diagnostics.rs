@@ -0,0 +1,276 @@
+//! Problems that did not stop a page from rendering, but may have changed what it looks like.
+
+use std::fmt;
+use pdf::content::Op;
+use pdf::error::{PdfError, Context};
+
+/// Parts of PDF that are not (fully) implemented.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Feature {
+    ColorSpace,
+    Image,
+    Shading,
+    SoftMask,
+    Other,
+}
+
+/// What went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// a font that is neither embedded nor available as a substitute, its text is not drawn
+    MissingFont { name: String },
+    /// a font that is drawn with a substitute because the embedded font is broken
+    SubstitutedFont { name: String },
+    /// a color space that is not in the resources, treated as DeviceGray
+    MissingColorSpace { name: String },
+    /// a color that could not be converted, drawn black
+    ColorConversion { error: String },
+    /// something this crate can't draw yet, `detail` says what exactly
+    Unsupported { feature: Feature, detail: String },
+    /// an image that could not be decoded
+    ImageDecode { error: String },
+    /// an image or path that was skipped because it exceeds one of `RenderOptions::limits`
+    TooLarge { what: &'static str, size: usize, limit: usize },
+    /// a content stream that ended with this many q that were not closed by Q
+    UnclosedSave { depth: usize },
+    /// a Q without q, the state from the start of the content stream was restored
+    ExtraRestore,
+    /// a BT without ET, either before another BT or at the end of the content stream
+    UnclosedText,
+    /// an ET without BT
+    ExtraEndText,
+    /// an op that failed and was skipped because of `Strictness::RecoverAll`
+    SkippedOp { error: String },
+    /// The document has an XFA form, which is not drawn. With `needs_rendering` the page content is
+    /// most likely only a placeholder, see `FormInfo`. Reported by `Document`, with op 0.
+    XfaForm { needs_rendering: bool },
+}
+
+impl DiagnosticKind {
+    /// `Unsupported` if `e` comes from a part of the renderer that is not implemented, `other(e)` otherwise.
+    pub(crate) fn from_error(e: &PdfError, feature: Feature, other: impl FnOnce(String) -> DiagnosticKind) -> DiagnosticKind {
+        // see the unimplemented! macro
+        if let PdfError::Other { ref msg } = *e {
+            if let Some(detail) = msg.strip_prefix("Unimplemented: ") {
+                return DiagnosticKind::Unsupported { feature, detail: detail.into() };
+            }
+        }
+        other(format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// number of the op in the content stream of the page.
+    /// For problems inside of forms, patterns and soft masks it is the op that used them.
+    pub op_nr: usize,
+    pub kind: DiagnosticKind,
+}
+
+/// The diagnostics of one page, in the order they occurred.
+#[derive(Debug, Clone, Default)]
+pub struct RenderDiagnostics {
+    pub items: Vec<Diagnostic>,
+}
+impl RenderDiagnostics {
+    pub fn new() -> Self {
+        RenderDiagnostics::default()
+    }
+    /// Fonts are only reported the first time they are used.
+    pub fn push(&mut self, op_nr: usize, kind: DiagnosticKind) {
+        let per_font = matches!(kind, DiagnosticKind::MissingFont { .. } | DiagnosticKind::SubstitutedFont { .. });
+        if per_font && self.items.iter().any(|d| d.kind == kind) {
+            return;
+        }
+        self.items.push(Diagnostic { op_nr, kind });
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item=&Diagnostic> {
+        self.items.iter()
+    }
+    /// names of the fonts that could not be drawn, without duplicates
+    pub fn missing_fonts(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = vec![];
+        for d in &self.items {
+            if let DiagnosticKind::MissingFont { ref name } = d.kind {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+    /// Whether anything was not drawn because it is not supported.
+    pub fn has_unsupported(&self) -> bool {
+        self.items.iter().any(|d| matches!(d.kind, DiagnosticKind::Unsupported { .. }))
+    }
+    /// How often each unsupported feature was encountered, in the order they first appeared.
+    pub fn unsupported_summary(&self) -> Vec<(Feature, usize)> {
+        let mut summary: Vec<(Feature, usize)> = vec![];
+        for d in &self.items {
+            if let DiagnosticKind::Unsupported { feature, .. } = d.kind {
+                match summary.iter_mut().find(|(f, _)| *f == feature) {
+                    Some((_, count)) => *count += 1,
+                    None => summary.push((feature, 1)),
+                }
+            }
+        }
+        summary
+    }
+    /// Add the diagnostics of a nested content stream, all attributed to the op `op_nr` that drew it.
+    pub(crate) fn append_nested(&mut self, nested: RenderDiagnostics, op_nr: usize) {
+        for d in nested.items {
+            self.push(op_nr, d.kind);
+        }
+    }
+}
+
+/// An error that stopped rendering a page, see `render_page_with`.
+#[derive(Debug)]
+pub struct RenderError {
+    /// `RenderOptions::page_nr` of the page
+    pub page_nr: Option<u32>,
+    /// the op the error came from, None if the page could not be read at all (like its resources or page boxes)
+    pub context: Option<RenderErrorContext>,
+    pub error: PdfError,
+}
+impl RenderError {
+    pub fn new(page_nr: Option<u32>, error: PdfError) -> Self {
+        RenderError { page_nr, context: None, error }
+    }
+}
+impl From<PdfError> for RenderError {
+    fn from(error: PdfError) -> Self {
+        RenderError::new(None, error)
+    }
+}
+/// For functions that return a `PdfError`, the page and op become its context.
+impl From<RenderError> for PdfError {
+    fn from(e: RenderError) -> Self {
+        let mut context = vec![];
+        if let Some(page_nr) = e.page_nr {
+            context.push(("page_nr", page_nr.to_string()));
+        }
+        if let Some(c) = e.context {
+            context.push(("op_nr", c.op_nr.to_string()));
+            context.push(("operator", c.operator));
+            if let Some(resource) = c.resource {
+                context.push(("resource", resource));
+            }
+        }
+        if context.is_empty() {
+            return e.error;
+        }
+        PdfError::Try { file: file!(), line: line!(), column: column!(), context: Context(context), source: Box::new(e.error) }
+    }
+}
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(page_nr) = self.page_nr {
+            write!(f, "page {}: ", page_nr)?;
+        }
+        if let Some(ref context) = self.context {
+            write!(f, "{}: ", context)?;
+        }
+        write!(f, "{}", self.error)
+    }
+}
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The op of the content stream of a page that an error came from.
+/// For errors inside of forms it is the op that drew the form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderErrorContext {
+    /// number of the op in its content stream
+    pub op_nr: usize,
+    /// the PDF operator, for ops that stand for several operators (like `sc`, `g` and `rg`) one of them
+    pub operator: String,
+    /// name of the resource the op uses (font, XObject, ExtGState, shading or color space)
+    pub resource: Option<String>,
+}
+impl RenderErrorContext {
+    pub fn new(op: &Op, op_nr: usize) -> Self {
+        RenderErrorContext {
+            op_nr,
+            operator: operator_name(op).into(),
+            resource: resource_name(op).map(|n| n.into()),
+        }
+    }
+}
+impl fmt::Display for RenderErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "op {} ({}", self.op_nr, self.operator)?;
+        if let Some(ref resource) = self.resource {
+            write!(f, " /{}", resource)?;
+        }
+        write!(f, ")")
+    }
+}
+
+fn operator_name(op: &Op) -> &'static str {
+    match *op {
+        Op::BeginMarkedContent { .. } => "BDC",
+        Op::EndMarkedContent { .. } => "EMC",
+        Op::MarkedContentPoint { .. } => "DP",
+        Op::Close => "h",
+        Op::MoveTo { .. } => "m",
+        Op::LineTo { .. } => "l",
+        Op::CurveTo { .. } => "c",
+        Op::Rect { .. } => "re",
+        Op::EndPath => "n",
+        Op::Stroke => "S",
+        Op::FillAndStroke { .. } => "B",
+        Op::Fill { .. } => "f",
+        Op::Shade { .. } => "sh",
+        Op::Clip { .. } => "W",
+        Op::Save => "q",
+        Op::Restore => "Q",
+        Op::Transform { .. } => "cm",
+        Op::LineWidth { .. } => "w",
+        Op::Dash { .. } => "d",
+        Op::LineJoin { .. } => "j",
+        Op::LineCap { .. } => "J",
+        Op::MiterLimit { .. } => "M",
+        Op::Flatness { .. } => "i",
+        Op::GraphicsState { .. } => "gs",
+        Op::StrokeColor { .. } => "SC",
+        Op::FillColor { .. } => "sc",
+        Op::FillColorSpace { .. } => "cs",
+        Op::StrokeColorSpace { .. } => "CS",
+        Op::RenderingIntent { .. } => "ri",
+        Op::BeginText => "BT",
+        Op::EndText => "ET",
+        Op::CharSpacing { .. } => "Tc",
+        Op::WordSpacing { .. } => "Tw",
+        Op::TextScaling { .. } => "Tz",
+        Op::Leading { .. } => "TL",
+        Op::TextFont { .. } => "Tf",
+        Op::TextRenderMode { .. } => "Tr",
+        Op::TextRise { .. } => "Ts",
+        Op::MoveTextPosition { .. } => "Td",
+        Op::SetTextMatrix { .. } => "Tm",
+        Op::TextNewline => "T*",
+        Op::TextDraw { .. } => "Tj",
+        Op::TextDrawAdjusted { .. } => "TJ",
+        Op::XObject { .. } => "Do",
+        Op::InlineImage { .. } => "BI",
+    }
+}
+
+fn resource_name(op: &Op) -> Option<&str> {
+    match *op {
+        Op::TextFont { ref name, .. } |
+        Op::XObject { ref name } |
+        Op::GraphicsState { ref name } |
+        Op::Shade { ref name } |
+        Op::FillColorSpace { ref name } |
+        Op::StrokeColorSpace { ref name } => Some(name.as_str()),
+        _ => None
+    }
+}
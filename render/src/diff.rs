@@ -0,0 +1,110 @@
+//! Comparing the text and vector content of the same page in two documents or versions.
+
+use std::collections::HashMap;
+use pathfinder_geometry::vector::Vector2F;
+use pdf::object::{Page, Resolve};
+use pdf::error::PdfError;
+use crate::{RenderOptions, render_page_with};
+use crate::tracer::{DrawItem, TraceCache, Tracer};
+
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// items that moved less than this (in output units) are the same
+    pub tolerance: f32,
+}
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions { tolerance: 1.0 }
+    }
+}
+
+/// A difference between two traces, with indices into the old and new items.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Change {
+    Added { new: usize },
+    Removed { old: usize },
+    /// the same content at a different place, `offset` is how far the bounds moved
+    Moved { old: usize, new: usize, offset: Vector2F },
+}
+
+/// The traces of both pages and how they differ.
+#[derive(Debug)]
+pub struct TraceDiff {
+    pub old: Vec<DrawItem>,
+    pub new: Vec<DrawItem>,
+    pub changes: Vec<Change>,
+}
+
+// what has to be equal for two items to be the same content
+fn content_key(item: &DrawItem, tolerance: f32) -> Option<String> {
+    match item {
+        DrawItem::Text(span, _) if !span.text.trim().is_empty() => Some(format!("t:{}", span.text)),
+        DrawItem::Vector(path) | DrawItem::PatternFill(path) => {
+            // the size of the shape, but not where it is
+            let size = (path.transform * path.outline.bounds()).size() * (1.0 / tolerance.max(1e-3));
+            Some(format!("v:{}x{}:{}:{:?}:{:?}",
+                size.x().round(), size.y().round(), path.outline.contours().len(),
+                path.fill.as_ref().map(|f| f.color), path.stroke.as_ref().map(|(s, _)| s.color)
+            ))
+        }
+        _ => None
+    }
+}
+
+/// Compare the text and vector items of two traces of the same page.
+///
+/// Items with the same content (the same text, or paths of the same size and color) are paired in op order,
+/// pairs that are further apart than `options.tolerance` are `Moved`.
+/// The changes are ordered by the old items, followed by the added ones.
+pub fn diff_items(old: &[DrawItem], new: &[DrawItem], options: &DiffOptions) -> Vec<Change> {
+    let mut unmatched: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, item) in new.iter().enumerate().rev() {
+        if let Some(key) = content_key(item, options.tolerance) {
+            unmatched.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut changes = vec![];
+    for (i, item) in old.iter().enumerate() {
+        let key = match content_key(item, options.tolerance) {
+            Some(key) => key,
+            None => continue
+        };
+        // the indices are reversed, so the first unmatched one is last
+        match unmatched.get_mut(&key).and_then(|indices| indices.pop()) {
+            Some(j) => {
+                let offset = new[j].bounds().origin() - item.bounds().origin();
+                if offset.x().abs() > options.tolerance || offset.y().abs() > options.tolerance {
+                    changes.push(Change::Moved { old: i, new: j, offset });
+                }
+            }
+            None => changes.push(Change::Removed { old: i }),
+        }
+    }
+
+    let mut added: Vec<usize> = unmatched.into_values().flatten().collect();
+    added.sort_unstable();
+    changes.extend(added.into_iter().map(|new| Change::Added { new }));
+    changes
+}
+
+fn trace(cache: &TraceCache, resolve: &impl Resolve, page: &Page, options: &RenderOptions) -> Result<Vec<DrawItem>, PdfError> {
+    let mut clip_paths = vec![];
+    let mut tracer = Tracer::new(cache, &mut clip_paths);
+    render_page_with(&mut tracer, resolve, page, options)?;
+    Ok(tracer.finish())
+}
+
+/// Trace `old_page` and `new_page`, which may come from different files, and compare them.
+/// A `TraceCache` belongs to one file (see `font_key`), pass the same one twice only if both pages are from the same file.
+#[allow(clippy::too_many_arguments)]
+pub fn diff_pages(
+    old_cache: &TraceCache, old_resolve: &impl Resolve, old_page: &Page,
+    new_cache: &TraceCache, new_resolve: &impl Resolve, new_page: &Page,
+    render_options: &RenderOptions, options: &DiffOptions
+) -> Result<TraceDiff, PdfError> {
+    let old = trace(old_cache, old_resolve, old_page, render_options)?;
+    let new = trace(new_cache, new_resolve, new_page, render_options)?;
+    let changes = diff_items(&old, &new, options);
+    Ok(TraceDiff { old, new, changes })
+}
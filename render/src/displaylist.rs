@@ -0,0 +1,923 @@
+//! A backend that records the resolved draw commands of a page, so they can be stored
+//! and replayed into another backend later, possibly in another process.
+//!
+//! Images are decoded and kept as pixels, every image XObject only once.
+//! Text spans keep everything but the font. Tiling patterns are recorded as the tiles that
+//! `SceneBackend` renders for them (which needs a rasterizer), soft masks are not recorded.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::borrow::Cow;
+use pathfinder_color::ColorU;
+use pathfinder_content::{
+    fill::FillRule,
+    outline::{Outline, Contour, ContourIterFlags},
+    pattern::{Image, PatternSource},
+    segment::SegmentKind,
+    stroke::{StrokeStyle, LineCap, LineJoin},
+};
+use pathfinder_geometry::{
+    vector::{Vector2F, Vector2I},
+    rect::RectF, transform2d::Transform2F,
+};
+use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, PlainRef, Pattern as PdfPattern};
+use pdf::content::TextMode;
+use pdf::font::Font as PdfFont;
+use pdf::error::PdfError;
+use crate::{
+    Backend, BlendMode, Cache, FunctionCache, DrawMode, Fill, FillMode, FontEntry, TextSpan, TextChar, Transfer,
+    Script, Gradient, GradientShape, RenderOptions, Budget, SceneBackend, backend::Stroke,
+    scene::{PatternKey, pattern_key},
+};
+
+const MAGIC: &[u8; 4] = b"PDL\0";
+const VERSION: u8 = 2;
+
+#[derive(Debug)]
+enum Command {
+    ViewBox(RectF),
+    // the id of a clip path is the number of clip paths before it
+    ClipPath { outline: Outline, fill_rule: FillRule, parent: Option<usize> },
+    Draw { outline: Outline, mode: DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<usize> },
+    ShadedTriangle { points: [Vector2F; 3], colors: [(f32, f32, f32); 3], alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<usize> },
+    Gradient { gradient: Gradient, area: RectF, alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<usize> },
+    // index into `DisplayList::images`
+    Image { image: usize, transform: Transform2F, mode: BlendMode, clip: Option<usize> },
+    Text { span: TextSpan, clip: Option<usize> },
+    // the tile of a tiling pattern, `image` is an index into `DisplayList::images`
+    PatternTile { pattern: Ref<PdfPattern>, pattern_transform: Transform2F, image: usize, transform: Transform2F },
+}
+
+/// The draw commands of a page, see `DisplayListBackend`.
+#[derive(Debug, Default)]
+pub struct DisplayList {
+    commands: Vec<Command>,
+    images: Vec<Image>,
+}
+impl DisplayList {
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+    /// Draw the commands into `backend`, in the order they were recorded.
+    /// Images are passed to `Backend::draw_image_pixels`, pattern tiles to `Backend::set_pattern_tile`.
+    pub fn replay<B: Backend>(&self, backend: &mut B) {
+        let mut clip_paths: Vec<B::ClipPathId> = vec![];
+        for command in &self.commands {
+            let clip_id = |clip: Option<usize>| clip.and_then(|i| clip_paths.get(i).copied());
+            match *command {
+                Command::ViewBox(r) => backend.set_view_box(r),
+                Command::ClipPath { ref outline, fill_rule, parent } => {
+                    let id = backend.create_clip_path(outline.clone(), fill_rule, clip_id(parent));
+                    clip_paths.push(id);
+                }
+                Command::Draw { ref outline, ref mode, fill_rule, transform, clip } => {
+                    backend.draw(Cow::Borrowed(outline), mode, fill_rule, transform, clip_id(clip));
+                }
+                Command::ShadedTriangle { points, colors, alpha, mode, transform, clip } => {
+                    backend.draw_shaded_triangle(points, colors, alpha, mode, transform, clip_id(clip));
+                }
+                Command::Gradient { ref gradient, area, alpha, mode, transform, clip } => {
+                    backend.draw_gradient(gradient, area, alpha, mode, transform, clip_id(clip));
+                }
+                Command::Image { image, transform, mode, clip } => {
+                    backend.draw_image_pixels(&self.images[image], transform, mode, clip_id(clip));
+                }
+                Command::Text { ref span, clip } => backend.add_text(copy_span(span), clip_id(clip)),
+                Command::PatternTile { pattern, pattern_transform, image, transform } => {
+                    backend.set_pattern_tile(pattern, pattern_transform, &self.images[image], transform);
+                }
+            }
+        }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer { data: MAGIC.to_vec() };
+        w.u8(VERSION);
+        w.usize(self.images.len());
+        for image in &self.images {
+            w.usize(image.size().x() as usize);
+            w.usize(image.size().y() as usize);
+            for c in image.pixels().iter() {
+                w.data.extend_from_slice(&[c.r, c.g, c.b, c.a]);
+            }
+        }
+        w.usize(self.commands.len());
+        for command in &self.commands {
+            w.command(command);
+        }
+        w.data
+    }
+    pub fn from_bytes(data: &[u8]) -> Result<DisplayList, PdfError> {
+        let mut r = Reader { data, pos: 0 };
+        if r.bytes(MAGIC.len())? != MAGIC {
+            return Err(PdfError::Other { msg: "not a display list".into() });
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(PdfError::Other { msg: format!("display list version {} is not supported", version) });
+        }
+        let mut list = DisplayList::default();
+        for _ in 0 .. r.usize()? {
+            let (width, height) = (r.usize()?, r.usize()?);
+            let len = width.checked_mul(height).and_then(|n| n.checked_mul(4)).ok_or_else(|| r.error())?;
+            let pixels = r.bytes(len)?.chunks(4).map(|c| ColorU::new(c[0], c[1], c[2], c[3])).collect();
+            list.images.push(Image::new(Vector2I::new(width as i32, height as i32), Arc::new(pixels)));
+        }
+        for _ in 0 .. r.usize()? {
+            let command = r.command()?;
+            if let Command::Image { image, .. } | Command::PatternTile { image, .. } = command {
+                if image >= list.images.len() {
+                    return Err(r.error());
+                }
+            }
+            list.commands.push(command);
+        }
+        Ok(list)
+    }
+}
+
+fn copy_span(span: &TextSpan) -> TextSpan {
+    TextSpan {
+        rect: span.rect,
+        width: span.width,
+        bbox: span.bbox,
+        font_size: span.font_size,
+        effective_font_size: span.effective_font_size,
+        font: None,
+        text: span.text.clone(),
+        chars: span.chars.clone(),
+        color: span.color,
+        alpha: span.alpha,
+        transform: span.transform,
+        mode: span.mode,
+        op_nr: span.op_nr,
+        rise: span.rise,
+        script: span.script,
+    }
+}
+
+/// Records what is drawn into a `DisplayList`.
+pub struct DisplayListBackend<'a> {
+    cache: &'a mut Cache,
+    list: DisplayList,
+    transfer: Option<Arc<Transfer>>,
+    // image XObjects that are in the list already
+    image_ids: HashMap<(Ref<XObject>, BlendMode), usize>,
+    // patterns that have a tile in the list already, or can't be drawn
+    patterns: HashSet<PatternKey>,
+    clip_paths: usize,
+}
+impl<'a> DisplayListBackend<'a> {
+    pub fn new(cache: &'a mut Cache) -> Self {
+        DisplayListBackend {
+            cache,
+            list: DisplayList::default(),
+            transfer: None,
+            image_ids: HashMap::new(),
+            patterns: HashSet::new(),
+            clip_paths: 0,
+        }
+    }
+    pub fn finish(self) -> DisplayList {
+        self.list
+    }
+    fn push_image(&mut self, image: Image, transform: Transform2F, mode: BlendMode, clip: Option<usize>) -> usize {
+        let image = match self.transfer {
+            Some(ref t) => t.apply_image(&image),
+            None => image
+        };
+        self.list.images.push(image);
+        let index = self.list.images.len() - 1;
+        self.list.commands.push(Command::Image { image: index, transform, mode, clip });
+        index
+    }
+}
+impl<'a> Backend for DisplayListBackend<'a> {
+    type ClipPathId = usize;
+    fn create_clip_path(&mut self, path: Outline, fill_rule: FillRule, parent: Option<usize>) -> usize {
+        self.list.commands.push(Command::ClipPath { outline: path, fill_rule, parent });
+        self.clip_paths += 1;
+        self.clip_paths - 1
+    }
+    fn draw(&mut self, outline: Cow<Outline>, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<usize>) {
+        self.list.commands.push(Command::Draw { outline: outline.into_owned(), mode: mode.clone(), fill_rule, transform, clip });
+    }
+    fn set_view_box(&mut self, r: RectF) {
+        self.list.commands.push(Command::ViewBox(r));
+    }
+    fn draw_image(&mut self, xref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<usize>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        // the transfer function is the same for all uses of an image in practice
+        if self.transfer.is_none() {
+            if let Some(&image) = self.image_ids.get(&(xref, mode)) {
+                self.list.commands.push(Command::Image { image, transform, mode, clip });
+                return Ok(());
+            }
+        }
+        let image = match *self.cache.get_image(xref, im, resources, resolve, mode).0 {
+            Ok(ref image) => image.clone(),
+            Err(ref e) => return Err(PdfError::Other { msg: e.to_string() })
+        };
+        let index = self.push_image(image, transform, mode, clip);
+        if self.transfer.is_none() {
+            self.image_ids.insert((xref, mode), index);
+        }
+        Ok(())
+    }
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<usize>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        let image = match *self.cache.get_inline_image(im, resources, resolve, mode)?.0 {
+            Ok(ref image) => image.clone(),
+            Err(ref e) => return Err(PdfError::Other { msg: e.to_string() })
+        };
+        self.push_image(image, transform, mode, clip);
+        Ok(())
+    }
+    fn set_transfer(&mut self, transfer: Option<Arc<Transfer>>) {
+        self.transfer = transfer;
+    }
+    fn prepare_pattern(&mut self, pattern: Ref<PdfPattern>, pattern_transform: Transform2F, resolve: &impl Resolve, options: &RenderOptions, budget: &mut Budget) {
+        if !self.patterns.insert(pattern_key(pattern, pattern_transform)) {
+            return;
+        }
+        let mut scene = SceneBackend::new(&mut *self.cache);
+        scene.prepare_pattern(pattern, pattern_transform, resolve, options, budget);
+        let tile = match scene.take_pattern_tile(pattern, pattern_transform) {
+            Some(tile) => tile,
+            None => return
+        };
+        if let PatternSource::Image(ref image) = *tile.source() {
+            self.list.images.push(image.clone());
+            let image = self.list.images.len() - 1;
+            self.list.commands.push(Command::PatternTile { pattern, pattern_transform, image, transform: tile.transform() });
+        }
+    }
+    fn draw_shaded_triangle(&mut self, points: [Vector2F; 3], colors: [(f32, f32, f32); 3], alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<usize>) {
+        self.list.commands.push(Command::ShadedTriangle { points, colors, alpha, mode, transform, clip });
+    }
+    fn draw_gradient(&mut self, gradient: &Gradient, area: RectF, alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<usize>) {
+        self.list.commands.push(Command::Gradient { gradient: gradient.clone(), area, alpha, mode, transform, clip });
+    }
+    fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
+        self.cache.get_font(font_ref, resolve)
+    }
+    fn function_cache(&mut self) -> Option<FunctionCache> {
+        Some(self.cache.functions())
+    }
+    fn add_text(&mut self, mut span: TextSpan, clip: Option<usize>) {
+        span.font = None;
+        self.list.commands.push(Command::Text { span, clip });
+    }
+}
+
+struct Writer {
+    data: Vec<u8>,
+}
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+    // LEB128
+    fn usize(&mut self, mut v: usize) {
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.data.push(byte);
+                return;
+            }
+            self.data.push(byte | 0x80);
+        }
+    }
+    fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+    fn f32(&mut self, v: f32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.data.extend_from_slice(s.as_bytes());
+    }
+    fn vec2(&mut self, v: Vector2F) {
+        self.f32(v.x());
+        self.f32(v.y());
+    }
+    fn rect(&mut self, r: RectF) {
+        self.vec2(r.origin());
+        self.vec2(r.lower_right());
+    }
+    fn transform(&mut self, t: Transform2F) {
+        for v in [t.matrix.m11(), t.matrix.m12(), t.matrix.m21(), t.matrix.m22(), t.vector.x(), t.vector.y()] {
+            self.f32(v);
+        }
+    }
+    fn rgb(&mut self, (r, g, b): (f32, f32, f32)) {
+        self.f32(r);
+        self.f32(g);
+        self.f32(b);
+    }
+    fn option<T>(&mut self, v: Option<T>, f: impl FnOnce(&mut Self, T)) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                f(self, v);
+            }
+            None => self.u8(0),
+        }
+    }
+    fn clip(&mut self, clip: Option<usize>) {
+        self.option(clip, Self::usize);
+    }
+    fn fill_rule(&mut self, rule: FillRule) {
+        self.u8(match rule {
+            FillRule::Winding => 0,
+            FillRule::EvenOdd => 1,
+        });
+    }
+    fn blend_mode(&mut self, mode: BlendMode) {
+        self.u8(match mode {
+            BlendMode::Overlay => 0,
+            BlendMode::Darken => 1,
+        });
+    }
+    fn outline(&mut self, outline: &Outline) {
+        self.usize(outline.contours().len());
+        for contour in outline.contours() {
+            let segments: Vec<_> = contour.iter(ContourIterFlags::IGNORE_CLOSE_SEGMENT).collect();
+            self.usize(segments.len());
+            self.bool(contour.is_closed());
+            if let Some(first) = segments.first() {
+                self.vec2(first.baseline.from());
+            }
+            for segment in &segments {
+                match segment.kind {
+                    SegmentKind::Quadratic => {
+                        self.u8(2);
+                        self.vec2(segment.ctrl.from());
+                    }
+                    SegmentKind::Cubic => {
+                        self.u8(3);
+                        self.vec2(segment.ctrl.from());
+                        self.vec2(segment.ctrl.to());
+                    }
+                    _ => self.u8(1),
+                }
+                self.vec2(segment.baseline.to());
+            }
+        }
+    }
+    fn fill(&mut self, fill: Fill) {
+        match fill {
+            Fill::Solid(r, g, b) => {
+                self.u8(0);
+                self.rgb((r, g, b));
+            }
+            Fill::Pattern(p) => {
+                self.u8(1);
+                self.pattern(p);
+            }
+        }
+    }
+    fn pattern(&mut self, pattern: Ref<PdfPattern>) {
+        let PlainRef { id, gen } = pattern.get_inner();
+        self.usize(id as usize);
+        self.usize(gen as usize);
+    }
+    fn fill_mode(&mut self, fill: &FillMode) {
+        self.fill(fill.color);
+        self.f32(fill.alpha);
+        self.blend_mode(fill.mode);
+        self.transform(fill.pattern_transform);
+    }
+    fn stroke(&mut self, stroke: &Stroke) {
+        self.option(stroke.dash_pattern.as_ref(), |w, (pattern, phase)| {
+            w.usize(pattern.len());
+            for &v in pattern {
+                w.f32(v);
+            }
+            w.f32(*phase);
+        });
+        let style = stroke.style;
+        self.f32(style.line_width);
+        self.u8(match style.line_cap {
+            LineCap::Butt => 0,
+            LineCap::Square => 1,
+            LineCap::Round => 2,
+        });
+        match style.line_join {
+            LineJoin::Miter(limit) => {
+                self.u8(0);
+                self.f32(limit);
+            }
+            LineJoin::Bevel => self.u8(1),
+            LineJoin::Round => self.u8(2),
+        }
+    }
+    fn draw_mode(&mut self, mode: &DrawMode) {
+        match *mode {
+            DrawMode::Fill { ref fill } => {
+                self.u8(0);
+                self.fill_mode(fill);
+            }
+            DrawMode::Stroke { ref stroke, ref stroke_mode } => {
+                self.u8(1);
+                self.fill_mode(stroke);
+                self.stroke(stroke_mode);
+            }
+            DrawMode::FillStroke { ref fill, ref stroke, ref stroke_mode } => {
+                self.u8(2);
+                self.fill_mode(fill);
+                self.fill_mode(stroke);
+                self.stroke(stroke_mode);
+            }
+        }
+    }
+    fn gradient(&mut self, gradient: &Gradient) {
+        match gradient.shape {
+            GradientShape::Axial { start, end } => {
+                self.u8(0);
+                self.vec2(start);
+                self.vec2(end);
+            }
+            GradientShape::Radial { start, r0, end, r1 } => {
+                self.u8(1);
+                self.vec2(start);
+                self.f32(r0);
+                self.vec2(end);
+                self.f32(r1);
+            }
+        }
+        self.usize(gradient.stops.len());
+        for &c in &gradient.stops {
+            self.rgb(c);
+        }
+        self.bool(gradient.extend.0);
+        self.bool(gradient.extend.1);
+    }
+    fn span(&mut self, span: &TextSpan) {
+        self.rect(span.rect);
+        self.f32(span.width);
+        self.option(span.bbox, Self::rect);
+        self.f32(span.font_size);
+        self.f32(span.effective_font_size);
+        self.str(&span.text);
+        self.usize(span.chars.len());
+        for c in &span.chars {
+            self.usize(c.offset);
+            self.f32(c.pos);
+            self.f32(c.width);
+            self.option(c.bbox, Self::rect);
+        }
+        self.fill(span.color);
+        self.f32(span.alpha);
+        self.transform(span.transform);
+        self.u8(match span.mode {
+            TextMode::Fill => 0,
+            TextMode::Stroke => 1,
+            TextMode::FillThenStroke => 2,
+            TextMode::Invisible => 3,
+            TextMode::FillAndClip => 4,
+            TextMode::StrokeAndClip => 5,
+        });
+        self.usize(span.op_nr);
+        self.f32(span.rise);
+        self.u8(match span.script {
+            Script::Normal => 0,
+            Script::Superscript => 1,
+            Script::Subscript => 2,
+        });
+    }
+    fn command(&mut self, command: &Command) {
+        match *command {
+            Command::ViewBox(r) => {
+                self.u8(0);
+                self.rect(r);
+            }
+            Command::ClipPath { ref outline, fill_rule, parent } => {
+                self.u8(1);
+                self.outline(outline);
+                self.fill_rule(fill_rule);
+                self.clip(parent);
+            }
+            Command::Draw { ref outline, ref mode, fill_rule, transform, clip } => {
+                self.u8(2);
+                self.outline(outline);
+                self.draw_mode(mode);
+                self.fill_rule(fill_rule);
+                self.transform(transform);
+                self.clip(clip);
+            }
+            Command::ShadedTriangle { points, colors, alpha, mode, transform, clip } => {
+                self.u8(3);
+                for p in points {
+                    self.vec2(p);
+                }
+                for c in colors {
+                    self.rgb(c);
+                }
+                self.f32(alpha);
+                self.blend_mode(mode);
+                self.transform(transform);
+                self.clip(clip);
+            }
+            Command::Gradient { ref gradient, area, alpha, mode, transform, clip } => {
+                self.u8(4);
+                self.gradient(gradient);
+                self.rect(area);
+                self.f32(alpha);
+                self.blend_mode(mode);
+                self.transform(transform);
+                self.clip(clip);
+            }
+            Command::Image { image, transform, mode, clip } => {
+                self.u8(5);
+                self.usize(image);
+                self.transform(transform);
+                self.blend_mode(mode);
+                self.clip(clip);
+            }
+            Command::Text { ref span, clip } => {
+                self.u8(6);
+                self.span(span);
+                self.clip(clip);
+            }
+            Command::PatternTile { pattern, pattern_transform, image, transform } => {
+                self.u8(7);
+                self.pattern(pattern);
+                self.transform(pattern_transform);
+                self.usize(image);
+                self.transform(transform);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn error(&self) -> PdfError {
+        PdfError::Other { msg: format!("invalid display list at byte {}", self.pos) }
+    }
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], PdfError> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.data.len()).ok_or_else(|| self.error())?;
+        let bytes = &self.data[self.pos .. end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, PdfError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn usize(&mut self) -> Result<usize, PdfError> {
+        let mut v = 0usize;
+        for shift in (0 .. usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            v |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(self.error())
+    }
+    // a count of items that take at least `min_size` bytes each, checked against the remaining data
+    fn count(&mut self, min_size: usize) -> Result<usize, PdfError> {
+        let n = self.usize()?;
+        if n.saturating_mul(min_size) > self.data.len() - self.pos {
+            return Err(self.error());
+        }
+        Ok(n)
+    }
+    fn bool(&mut self) -> Result<bool, PdfError> {
+        Ok(self.u8()? != 0)
+    }
+    fn f32(&mut self) -> Result<f32, PdfError> {
+        let b = self.bytes(4)?;
+        Ok(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn str(&mut self) -> Result<String, PdfError> {
+        let n = self.usize()?;
+        let bytes = self.bytes(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error())
+    }
+    fn vec2(&mut self) -> Result<Vector2F, PdfError> {
+        Ok(Vector2F::new(self.f32()?, self.f32()?))
+    }
+    fn rect(&mut self) -> Result<RectF, PdfError> {
+        Ok(RectF::from_points(self.vec2()?, self.vec2()?))
+    }
+    fn transform(&mut self) -> Result<Transform2F, PdfError> {
+        let [m11, m12, m21, m22, x, y] = [self.f32()?, self.f32()?, self.f32()?, self.f32()?, self.f32()?, self.f32()?];
+        Ok(Transform2F::row_major(m11, m12, x, m21, m22, y))
+    }
+    fn rgb(&mut self) -> Result<(f32, f32, f32), PdfError> {
+        Ok((self.f32()?, self.f32()?, self.f32()?))
+    }
+    fn option<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, PdfError>) -> Result<Option<T>, PdfError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => f(self).map(Some),
+            _ => Err(self.error()),
+        }
+    }
+    fn clip(&mut self) -> Result<Option<usize>, PdfError> {
+        self.option(Self::usize)
+    }
+    fn fill_rule(&mut self) -> Result<FillRule, PdfError> {
+        match self.u8()? {
+            0 => Ok(FillRule::Winding),
+            1 => Ok(FillRule::EvenOdd),
+            _ => Err(self.error()),
+        }
+    }
+    fn blend_mode(&mut self) -> Result<BlendMode, PdfError> {
+        match self.u8()? {
+            0 => Ok(BlendMode::Overlay),
+            1 => Ok(BlendMode::Darken),
+            _ => Err(self.error()),
+        }
+    }
+    fn outline(&mut self) -> Result<Outline, PdfError> {
+        let mut outline = Outline::new();
+        for _ in 0 .. self.count(2)? {
+            let segments = self.count(9)?;
+            let closed = self.bool()?;
+            let mut contour = Contour::new();
+            if segments > 0 {
+                contour.push_endpoint(self.vec2()?);
+            }
+            for _ in 0 .. segments {
+                match self.u8()? {
+                    1 => contour.push_endpoint(self.vec2()?),
+                    2 => {
+                        let c = self.vec2()?;
+                        contour.push_quadratic(c, self.vec2()?);
+                    }
+                    3 => {
+                        let (c1, c2) = (self.vec2()?, self.vec2()?);
+                        contour.push_cubic(c1, c2, self.vec2()?);
+                    }
+                    _ => return Err(self.error()),
+                }
+            }
+            if closed {
+                contour.close();
+            }
+            outline.push_contour(contour);
+        }
+        Ok(outline)
+    }
+    fn fill(&mut self) -> Result<Fill, PdfError> {
+        match self.u8()? {
+            0 => {
+                let (r, g, b) = self.rgb()?;
+                Ok(Fill::Solid(r, g, b))
+            }
+            1 => Ok(Fill::Pattern(self.pattern()?)),
+            _ => Err(self.error()),
+        }
+    }
+    fn pattern(&mut self) -> Result<Ref<PdfPattern>, PdfError> {
+        let (id, gen) = (self.usize()? as u64, self.usize()? as u64);
+        Ok(Ref::new(PlainRef { id, gen }))
+    }
+    fn fill_mode(&mut self) -> Result<FillMode, PdfError> {
+        Ok(FillMode {
+            color: self.fill()?,
+            alpha: self.f32()?,
+            mode: self.blend_mode()?,
+            pattern_transform: self.transform()?,
+        })
+    }
+    fn stroke(&mut self) -> Result<Stroke, PdfError> {
+        let dash_pattern = self.option(|r| {
+            let pattern = (0 .. r.count(4)?).map(|_| r.f32()).collect::<Result<Vec<f32>, _>>()?;
+            Ok((pattern, r.f32()?))
+        })?;
+        let line_width = self.f32()?;
+        let line_cap = match self.u8()? {
+            0 => LineCap::Butt,
+            1 => LineCap::Square,
+            2 => LineCap::Round,
+            _ => return Err(self.error()),
+        };
+        let line_join = match self.u8()? {
+            0 => LineJoin::Miter(self.f32()?),
+            1 => LineJoin::Bevel,
+            2 => LineJoin::Round,
+            _ => return Err(self.error()),
+        };
+        Ok(Stroke { dash_pattern, style: StrokeStyle { line_width, line_cap, line_join } })
+    }
+    fn draw_mode(&mut self) -> Result<DrawMode, PdfError> {
+        match self.u8()? {
+            0 => Ok(DrawMode::Fill { fill: self.fill_mode()? }),
+            1 => Ok(DrawMode::Stroke { stroke: self.fill_mode()?, stroke_mode: self.stroke()? }),
+            2 => Ok(DrawMode::FillStroke { fill: self.fill_mode()?, stroke: self.fill_mode()?, stroke_mode: self.stroke()? }),
+            _ => Err(self.error()),
+        }
+    }
+    fn gradient(&mut self) -> Result<Gradient, PdfError> {
+        let shape = match self.u8()? {
+            0 => GradientShape::Axial { start: self.vec2()?, end: self.vec2()? },
+            1 => GradientShape::Radial { start: self.vec2()?, r0: self.f32()?, end: self.vec2()?, r1: self.f32()? },
+            _ => return Err(self.error()),
+        };
+        let stops = (0 .. self.count(12)?).map(|_| self.rgb()).collect::<Result<Vec<_>, _>>()?;
+        let extend = (self.bool()?, self.bool()?);
+        Ok(Gradient { shape, stops, extend })
+    }
+    fn span(&mut self) -> Result<TextSpan, PdfError> {
+        let rect = self.rect()?;
+        let width = self.f32()?;
+        let bbox = self.option(Self::rect)?;
+        let font_size = self.f32()?;
+        let effective_font_size = self.f32()?;
+        let text = self.str()?;
+        let chars = (0 .. self.count(10)?).map(|_| Ok(TextChar {
+            offset: self.usize()?,
+            pos: self.f32()?,
+            width: self.f32()?,
+            bbox: self.option(Self::rect)?,
+        })).collect::<Result<Vec<_>, PdfError>>()?;
+        let color = self.fill()?;
+        let alpha = self.f32()?;
+        let transform = self.transform()?;
+        let mode = match self.u8()? {
+            0 => TextMode::Fill,
+            1 => TextMode::Stroke,
+            2 => TextMode::FillThenStroke,
+            3 => TextMode::Invisible,
+            4 => TextMode::FillAndClip,
+            5 => TextMode::StrokeAndClip,
+            _ => return Err(self.error()),
+        };
+        let op_nr = self.usize()?;
+        let rise = self.f32()?;
+        let script = match self.u8()? {
+            0 => Script::Normal,
+            1 => Script::Superscript,
+            2 => Script::Subscript,
+            _ => return Err(self.error()),
+        };
+        Ok(TextSpan {
+            rect, width, bbox, font_size, effective_font_size, font: None, text, chars,
+            color, alpha, transform, mode, op_nr, rise, script,
+        })
+    }
+    fn command(&mut self) -> Result<Command, PdfError> {
+        Ok(match self.u8()? {
+            0 => Command::ViewBox(self.rect()?),
+            1 => Command::ClipPath { outline: self.outline()?, fill_rule: self.fill_rule()?, parent: self.clip()? },
+            2 => Command::Draw {
+                outline: self.outline()?,
+                mode: self.draw_mode()?,
+                fill_rule: self.fill_rule()?,
+                transform: self.transform()?,
+                clip: self.clip()?,
+            },
+            3 => Command::ShadedTriangle {
+                points: [self.vec2()?, self.vec2()?, self.vec2()?],
+                colors: [self.rgb()?, self.rgb()?, self.rgb()?],
+                alpha: self.f32()?,
+                mode: self.blend_mode()?,
+                transform: self.transform()?,
+                clip: self.clip()?,
+            },
+            4 => Command::Gradient {
+                gradient: self.gradient()?,
+                area: self.rect()?,
+                alpha: self.f32()?,
+                mode: self.blend_mode()?,
+                transform: self.transform()?,
+                clip: self.clip()?,
+            },
+            5 => Command::Image {
+                image: self.usize()?,
+                transform: self.transform()?,
+                mode: self.blend_mode()?,
+                clip: self.clip()?,
+            },
+            6 => Command::Text { span: self.span()?, clip: self.clip()? },
+            7 => Command::PatternTile {
+                pattern: self.pattern()?,
+                pattern_transform: self.transform()?,
+                image: self.usize()?,
+                transform: self.transform()?,
+            },
+            _ => return Err(self.error()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathfinder_renderer::scene::DrawPathId;
+
+    fn fill(color: Fill) -> FillMode {
+        FillMode { color, alpha: 0.5, mode: BlendMode::Overlay, pattern_transform: Transform2F::from_scale(Vector2F::splat(2.0)) }
+    }
+
+    fn pattern() -> Ref<PdfPattern> {
+        Ref::new(PlainRef { id: 7, gen: 0 })
+    }
+
+    // one command of each kind
+    fn sample() -> DisplayList {
+        let mut cache = Cache::new();
+        let mut backend = DisplayListBackend::new(&mut cache);
+        let rect = RectF::new(Vector2F::zero(), Vector2F::new(10.0, 20.0));
+        backend.set_view_box(rect);
+        let mut outline = Outline::from_rect(rect);
+        let mut contour = Contour::new();
+        contour.push_endpoint(Vector2F::new(1.0, 1.0));
+        contour.push_quadratic(Vector2F::new(2.0, 3.0), Vector2F::new(4.0, 1.0));
+        contour.push_cubic(Vector2F::new(5.0, 0.0), Vector2F::new(6.0, 2.0), Vector2F::new(7.0, 1.0));
+        outline.push_contour(contour);
+        let clip = backend.create_clip_path(outline.clone(), FillRule::EvenOdd, None);
+
+        let stroke_mode = Stroke {
+            dash_pattern: Some((vec![1.0, 2.0], 0.5)),
+            style: StrokeStyle { line_width: 1.5, line_cap: LineCap::Round, line_join: LineJoin::Miter(4.0) },
+        };
+        let mode = DrawMode::FillStroke { fill: fill(Fill::Solid(1.0, 0.0, 0.5)), stroke: fill(Fill::Pattern(pattern())), stroke_mode };
+        backend.draw(Cow::Owned(outline), &mode, FillRule::Winding, Transform2F::from_translation(Vector2F::new(1.0, 2.0)), Some(clip));
+        backend.draw_shaded_triangle([Vector2F::zero(), Vector2F::new(1.0, 0.0), Vector2F::new(0.0, 1.0)], [(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)], 1.0, BlendMode::Darken, Transform2F::default(), None);
+        let gradient = Gradient {
+            shape: GradientShape::Radial { start: Vector2F::zero(), r0: 0.0, end: Vector2F::new(1.0, 1.0), r1: 5.0 },
+            stops: vec![(0.0, 0.0, 0.0), (1.0, 1.0, 1.0)],
+            extend: (true, false),
+        };
+        backend.draw_gradient(&gradient, rect, 0.8, BlendMode::Overlay, Transform2F::default(), Some(clip));
+        backend.add_text(TextSpan {
+            rect,
+            width: 2.0,
+            bbox: Some(rect),
+            font_size: 10.0,
+            effective_font_size: 12.0,
+            font: None,
+            text: "fi".into(),
+            chars: vec![TextChar { offset: 0, pos: 0.0, width: 2.0, bbox: None }],
+            color: Fill::Solid(0.0, 0.0, 1.0),
+            alpha: 1.0,
+            transform: Transform2F::default(),
+            mode: TextMode::FillThenStroke,
+            op_nr: 3,
+            rise: 2.0,
+            script: Script::Superscript,
+        }, None);
+
+        let mut list = backend.finish();
+        let pixels = vec![ColorU::new(1, 2, 3, 4), ColorU::new(5, 6, 7, 8)];
+        list.images.push(Image::new(Vector2I::new(2, 1), Arc::new(pixels)));
+        list.commands.push(Command::Image { image: 0, transform: Transform2F::from_scale(Vector2F::splat(3.0)), mode: BlendMode::Overlay, clip: Some(clip) });
+        list.commands.push(Command::PatternTile { pattern: pattern(), pattern_transform: Transform2F::from_scale(Vector2F::splat(2.0)), image: 0, transform: Transform2F::default() });
+        list
+    }
+
+    #[test]
+    fn round_trip() {
+        let list = sample();
+        let bytes = list.to_bytes();
+        let read = DisplayList::from_bytes(&bytes).unwrap();
+        assert_eq!(format!("{:?}", read.commands), format!("{:?}", list.commands));
+        assert_eq!(read.images.len(), 1);
+        assert_eq!(read.images[0].size(), Vector2I::new(2, 1));
+        assert_eq!(read.images[0].pixels()[1], ColorU::new(5, 6, 7, 8));
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn invalid() {
+        let bytes = sample().to_bytes();
+        for len in [0, 4, bytes.len() / 2, bytes.len() - 1] {
+            assert!(DisplayList::from_bytes(&bytes[.. len]).is_err());
+        }
+        let mut other_version = bytes.clone();
+        other_version[MAGIC.len()] = VERSION + 1;
+        assert!(DisplayList::from_bytes(&other_version).is_err());
+    }
+
+    #[test]
+    fn pattern_tile_replay() {
+        let mut list = DisplayList::default();
+        list.images.push(Image::new(Vector2I::splat(1), Arc::new(vec![ColorU::new(255, 0, 0, 255)])));
+        let pattern_transform = Transform2F::from_scale(Vector2F::splat(2.0));
+        list.commands.push(Command::PatternTile { pattern: pattern(), pattern_transform, image: 0, transform: Transform2F::default() });
+        let mut fill = fill(Fill::Pattern(pattern()));
+        fill.pattern_transform = pattern_transform;
+        let outline = Outline::from_rect(RectF::new(Vector2F::zero(), Vector2F::splat(4.0)));
+        list.commands.push(Command::Draw { outline, mode: DrawMode::Fill { fill }, fill_rule: FillRule::Winding, transform: Transform2F::default(), clip: None });
+
+        let mut cache = Cache::new();
+        let mut backend = SceneBackend::new(&mut cache);
+        list.replay(&mut backend);
+        let scene = backend.finish();
+        assert_eq!(scene.draw_path_count(), 1);
+        // the tile, not the black that stands in for patterns that can't be drawn
+        let paint = scene.get_paint(scene.get_draw_path(DrawPathId(0)).paint());
+        assert!(paint.overlay().is_some());
+    }
+}
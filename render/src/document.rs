@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use std::path::Path;
+use pdf::file::{File as PdfFile, Cache as PdfCache, Log, FileOptions, ObjectCache, StreamCache, NoLog};
+use pdf::any::AnySync;
+use pdf::backend::Backend as PdfBackend;
+use pdf::object::{Resolve, Annot};
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::PdfError;
+use pathfinder_geometry::{rect::RectF, vector::Vector2F};
+use pathfinder_renderer::scene::Scene;
+use crate::{
+    Backend, Cache, FileId, SceneBackend, RenderOptions, RenderOutput, RenderError, TextSpan,
+    render_page_with, page_transform, Imposition, render_sheet,
+    ImageAlt, page_figures, image_alt_texts, FormInfo, form_info, DiagnosticKind,
+    tracer::{TraceCache, Tracer, DrawItem},
+};
+
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// rect of the link annotation in output space
+    pub rect: RectF,
+    pub uri: Option<String>,
+    /// the raw /Dest or /A /D entry of internal links
+    pub dest: Option<Primitive>,
+}
+
+/// An open document together with the caches needed to render it.
+pub struct Document<B: PdfBackend, OC, SC, L> {
+    file: PdfFile<B, OC, SC, L>,
+    file_id: FileId,
+    cache: Cache,
+    trace_cache: TraceCache,
+    form_info: FormInfo,
+}
+impl<B, OC, SC, L> Document<B, OC, SC, L>
+where
+    B: PdfBackend,
+    OC: PdfCache<Result<AnySync, Arc<PdfError>>>,
+    SC: PdfCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log
+{
+    pub fn new(file: PdfFile<B, OC, SC, L>) -> Self {
+        let file_id = FileId::new();
+        let mut cache = Cache::new();
+        cache.set_file(file_id);
+        let form_info = match read_form_info(&file) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("can't read the form of the document: {:?}", e);
+                FormInfo::default()
+            }
+        };
+        Document {
+            file,
+            file_id,
+            cache,
+            trace_cache: TraceCache::new(),
+            form_info,
+        }
+    }
+    /// Whether the document has an AcroForm or XFA form.
+    pub fn form_info(&self) -> FormInfo {
+        self.form_info
+    }
+
+    pub fn file(&self) -> &PdfFile<B, OC, SC, L> {
+        &self.file
+    }
+    pub fn file_id(&self) -> FileId {
+        self.file_id
+    }
+    pub fn num_pages(&self) -> u32 {
+        self.file.num_pages()
+    }
+    /// media box of page `page_nr` (0 based) in the unit of `options`
+    pub fn page_bounds(&self, page_nr: u32, options: &RenderOptions) -> Result<RectF, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        crate::page_bounds_in(&page, options.unit)
+    }
+    pub fn render_page(&mut self, page_nr: u32, options: &RenderOptions) -> Result<Scene, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        let resolver = self.file.resolver();
+        self.cache.set_file(self.file_id);
+        let options = page_options(self.form_info, page_nr, options);
+        let mut backend = SceneBackend::new(&mut self.cache);
+        render_page_with(&mut backend, &resolver, &page, &options)?;
+        Ok(backend.finish())
+    }
+    /// render into a caller provided backend
+    pub fn render_page_into(&self, backend: &mut impl Backend, page_nr: u32, options: &RenderOptions) -> Result<RenderOutput, RenderError> {
+        let page = self.file.get_page(page_nr).map_err(|e| RenderError::new(Some(page_nr), e))?;
+        let resolver = self.file.resolver();
+        let mut output = render_page_with(backend, &resolver, &page, &page_options(self.form_info, page_nr, options))?;
+        if self.form_info.xfa {
+            output.diagnostics.push(0, DiagnosticKind::XfaForm { needs_rendering: self.form_info.needs_rendering });
+        }
+        Ok(output)
+    }
+    /// Render one side of a sheet with the pages `page_nrs` in the cells of `imposition`,
+    /// see `n_up_order` and `booklet_order`.
+    pub fn render_sheet(&mut self, page_nrs: &[Option<u32>], imposition: &Imposition, options: &RenderOptions) -> Result<Scene, PdfError> {
+        let pages = page_nrs.iter()
+            .map(|&page_nr| page_nr.map(|page_nr| Ok((page_nr, self.file.get_page(page_nr)?))).transpose())
+            .collect::<Result<Vec<_>, PdfError>>()?;
+        let resolver = self.file.resolver();
+        self.cache.set_file(self.file_id);
+        let mut backend = SceneBackend::new(&mut self.cache);
+        let pages = pages.iter().map(|page| page.as_ref().map(|(page_nr, page)| (*page_nr, &**page)));
+        render_sheet(&mut backend, &resolver, pages, imposition, &form_options(self.form_info, options))?;
+        Ok(backend.finish())
+    }
+    pub fn extract_text(&self, page_nr: u32, options: &RenderOptions) -> Result<Vec<TextSpan>, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        let resolver = self.file.resolver();
+        let mut clip_paths = vec![];
+        let mut tracer = Tracer::new(&self.trace_cache, &mut clip_paths);
+        render_page_with(&mut tracer, &resolver, &page, options)?;
+        Ok(tracer.finish().into_iter().filter_map(|item| match item {
+            DrawItem::Text(span, _) => Some(span),
+            _ => None
+        }).collect())
+    }
+    /// The images of page `page_nr` with the alt text of the figures of the structure tree they belong to
+    /// and the captions found next to them.
+    pub fn image_alt_texts(&self, page_nr: u32, options: &RenderOptions) -> Result<Vec<ImageAlt>, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        let resolver = self.file.resolver();
+        let catalog = resolver.resolve(self.file.trailer.root.get_ref().get_inner())?.into_dictionary()?;
+        let figures = match catalog.get("StructTreeRoot") {
+            Some(root) => page_figures(root, page.get_ref().get_inner(), &resolver)?,
+            None => vec![]
+        };
+        let mut clip_paths = vec![];
+        let mut tracer = Tracer::new(&self.trace_cache, &mut clip_paths);
+        render_page_with(&mut tracer, &resolver, &page, options)?;
+        Ok(image_alt_texts(&tracer.finish_with_tags(), &figures))
+    }
+    pub fn links(&self, page_nr: u32, options: &RenderOptions) -> Result<Vec<Link>, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        let resolver = self.file.resolver();
+        let transform = page_transform(&page, options)?;
+
+        let mut links = vec![];
+        for annot in page.annotations.load(&resolver)?.iter() {
+            let annot: &Annot = &annot;
+            if annot.subtype.as_str() != "Link" {
+                continue;
+            }
+            let rect = match annot.rect {
+                Some(r) => transform * RectF::from_points(Vector2F::new(r.left, r.bottom), Vector2F::new(r.right, r.top)),
+                None => continue
+            };
+            let action = match annot.other.get("A") {
+                Some(a) => Some(resolve_dict(a, &resolver)?),
+                None => None
+            };
+            let uri = action.as_ref()
+                .and_then(|a| a.get("URI"))
+                .and_then(|u| u.as_string().ok())
+                .map(|s| s.to_string_lossy());
+            let dest = annot.other.get("Dest").cloned()
+                .or_else(|| action.as_ref().and_then(|a| a.get("D")).cloned());
+
+            links.push(Link { rect, uri, dest });
+        }
+        Ok(links)
+    }
+    /// render into a caller owned pixel buffer, see `render_into_buffer`
+    #[cfg(feature="rasterize")]
+    pub fn render_page_into_buffer(&mut self, page_nr: u32, options: &RenderOptions, buffer: &mut crate::PixelBuffer, damage: &[pathfinder_geometry::rect::RectI]) -> Result<Vec<pathfinder_geometry::rect::RectI>, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        let resolver = self.file.resolver();
+        self.cache.set_file(self.file_id);
+        let options = page_options(self.form_info, page_nr, options);
+        crate::render_into_buffer(&mut self.cache, &resolver, &page, &options, buffer, damage)
+    }
+    /// drop everything that was cached for this document
+    pub fn clear_cache(&mut self) {
+        self.cache.clear_file(self.file_id);
+    }
+}
+
+impl Document<Vec<u8>, ObjectCache, StreamCache, NoLog> {
+    /// Open an encrypted file. The password can be the user or the owner password,
+    /// a wrong one fails with `PdfError::InvalidPassword`.
+    pub fn open_with_password(path: impl AsRef<Path>, password: &str) -> Result<Self, PdfError> {
+        let file = FileOptions::cached().password(password.as_bytes()).open(path)?;
+        Ok(Document::new(file))
+    }
+}
+
+// the options of page `page_nr`
+fn page_options(form_info: FormInfo, page_nr: u32, options: &RenderOptions) -> RenderOptions {
+    form_options(form_info, options).page_nr(page_nr)
+}
+
+// XFA forms are shown by their widgets where they have appearance streams
+fn form_options(form_info: FormInfo, options: &RenderOptions) -> RenderOptions {
+    match form_info {
+        FormInfo { xfa: true, acro_form: true, .. } => options.clone().render_widgets(true),
+        _ => options.clone()
+    }
+}
+
+fn read_form_info<B: PdfBackend, OC, SC, L>(file: &PdfFile<B, OC, SC, L>) -> Result<FormInfo, PdfError>
+where
+    OC: PdfCache<Result<AnySync, Arc<PdfError>>>,
+    SC: PdfCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log
+{
+    let resolver = file.resolver();
+    let catalog = resolver.resolve(file.trailer.root.get_ref().get_inner())?.into_dictionary()?;
+    form_info(&catalog, &resolver)
+}
+
+fn resolve_dict(p: &Primitive, resolve: &impl Resolve) -> Result<Dictionary, PdfError> {
+    match *p {
+        Primitive::Reference(r) => resolve.resolve(r)?.into_dictionary(),
+        ref p => p.clone().into_dictionary(),
+    }
+}
@@ -4,14 +4,17 @@ use std::ops::Deref;
 use std::collections::HashMap;
 use glyphmatcher::FontDb;
 use pdf::object::*;
-use pdf::font::{Font as PdfFont};
+use pdf::font::{Font as PdfFont, FontDescriptor, Flags};
 use pdf::error::{Result, PdfError};
 
 use font::{self};
 use std::sync::Arc;
-use super::FontEntry;
+use super::{FontEntry, CacheOptions};
+use super::fontentry::{font_descriptor, has_font_file};
+use super::colorglyph::ColorGlyphs;
 use globalcache::{sync::SyncCache, ValueSize};
 use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
 
 #[derive(Clone)]
 pub struct FontRc(Arc<dyn font::Font + Send + Sync + 'static>);
@@ -47,54 +50,142 @@ impl Hash for FontRc {
         Arc::as_ptr(&self.0).hash(state)
     }
 }
-pub struct StandardCache {
-    inner: Arc<SyncCache<String, Option<FontRc>>>,
-
-    #[cfg(not(feature="embed"))]
-    dir: PathBuf,
+/// A parsed embedded font and the color glyphs of its tables.
+#[derive(Clone)]
+pub struct EmbeddedFont {
+    pub font: FontRc,
+    pub color: Option<Arc<ColorGlyphs>>,
+}
+impl ValueSize for EmbeddedFont {
+    #[inline]
+    fn size(&self) -> usize {
+        1 // TODO
+    }
+}
+/// The embedded fonts of one document by content hash and length, None if they failed to parse.
+/// Documents written page by page embed the same subset many times, each one is parsed once.
+pub type EmbeddedFonts = SyncCache<(u64, usize), Option<EmbeddedFont>>;
 
-    #[cfg(feature="embed")]
-    dir: EmbeddedStandardFonts,
+/// Supplies font data for fonts that are not embedded in the document.
+pub trait FontResolver: Send + Sync {
+    /// Data of a font to draw in place of `name`, the /BaseFont of the PDF font.
+    fn resolve(&self, name: &str, descriptor: Option<&FontDescriptor>) -> Option<Cow<'static, [u8]>>;
+}
 
+/// Resolves fonts through the `fonts.json` of a directory, like https://github.com/s3bk/pdf_fonts.
+/// Unknown fonts are replaced with Arial.
+pub struct DirFontResolver<D> {
+    dir: D,
     fonts: HashMap<String, String>,
-    dump: Dump,
+}
+impl<D: DirRead> DirFontResolver<D> {
+    pub fn new(dir: D) -> Result<Self> {
+        let data = dir.read_file("fonts.json")?;
+        let fonts = serde_json::from_slice(&data).map_err(|e| PdfError::Other { msg: format!("fonts.json is invalid: {:?}", e) })?;
+        Ok(DirFontResolver { dir, fonts })
+    }
+}
+impl DirFontResolver<PathBuf> {
+    /// the directory in STANDARD_FONTS, if set
+    pub fn from_env() -> Option<Self> {
+        let dir = PathBuf::from(std::env::var_os("STANDARD_FONTS")?);
+        match DirFontResolver::new(dir) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                warn!("can't use STANDARD_FONTS: {:?}", e);
+                None
+            }
+        }
+    }
+}
+impl<D: DirRead + Send + Sync> FontResolver for DirFontResolver<D> {
+    fn resolve(&self, name: &str, descriptor: Option<&FontDescriptor>) -> Option<Cow<'static, [u8]>> {
+        let ps_name = name.split('+').nth(1).unwrap_or(name);
+        let file_name = self.fonts.get(name)
+            .or_else(|| self.fonts.get(ps_name))
+            .or_else(|| descriptor.and_then(|d| self.fonts.get(similar_standard_font(d))))
+            .or_else(|| self.fonts.get("Arial"))?;
+        match self.dir.read_file(file_name) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                warn!("can't open {} for {} {:?}", file_name, name, e);
+                None
+            }
+        }
+    }
+}
+
+/// The standard font that looks most like the described font.
+fn similar_standard_font(desc: &FontDescriptor) -> &'static str {
+    let bold = desc.font_weight.map(|w| w >= 600.).unwrap_or(false) || desc.flags.contains(Flags::FORCE_BOLD);
+    let italic = desc.flags.contains(Flags::ITALIC) || desc.italic_angle != 0.0;
+    if desc.flags.contains(Flags::FIXED_PITCH) {
+        match (bold, italic) {
+            (false, false) => "Courier",
+            (true, false) => "Courier-Bold",
+            (false, true) => "Courier-Oblique",
+            (true, true) => "Courier-BoldOblique",
+        }
+    } else if desc.flags.contains(Flags::SERIF) {
+        match (bold, italic) {
+            (false, false) => "Times-Roman",
+            (true, false) => "Times-Bold",
+            (false, true) => "Times-Italic",
+            (true, true) => "Times-BoldItalic",
+        }
+    } else {
+        match (bold, italic) {
+            (false, false) => "Helvetica",
+            (true, false) => "Helvetica-Bold",
+            (false, true) => "Helvetica-Oblique",
+            (true, true) => "Helvetica-BoldOblique",
+        }
+    }
+}
+
+pub struct StandardCache {
+    // loaded substitutes by font name
+    inner: Arc<SyncCache<String, Option<FontRc>>>,
+    resolver: Option<Box<dyn FontResolver>>,
+    debug_sink: Option<Arc<dyn FontDebugSink>>,
+    font_db: Option<Arc<FontDb>>,
     require_unique_unicode: bool,
 }
 impl StandardCache {
     #[cfg(not(feature="embed"))]
     pub fn new() -> StandardCache {
-        let standard_fonts = PathBuf::from(std::env::var_os("STANDARD_FONTS").expect("STANDARD_FONTS is not set. Please check https://github.com/pdf-rs/pdf_render/#fonts for instructions."));
-
-        let data = standard_fonts.read_file("fonts.json").expect("can't read fonts.json");
-        let fonts: HashMap<String, String> = serde_json::from_slice(&data).expect("fonts.json is invalid");
-
-        let dump = match std::env::var("DUMP_FONT").as_deref() {
-            Err(_) => Dump::Never,
-            Ok("always") => Dump::Always,
-            Ok("error") => Dump::OnError,
-            Ok(_) => Dump::Never
-        };
-        StandardCache {
-            inner: SyncCache::new(),
-            dir: standard_fonts,
-            fonts,
-            dump,
-            require_unique_unicode: false,
+        // without substitutes, text in non-embedded standard fonts is measured with the built-in metrics
+        let resolver = DirFontResolver::from_env();
+        if resolver.is_none() {
+            warn!("STANDARD_FONTS is not set, non-embedded fonts will not be drawn. Please check https://github.com/pdf-rs/pdf_render/#fonts for instructions.");
         }
+        StandardCache::from_resolver(resolver.map(|r| Box::new(r) as _))
     }
     #[cfg(feature="embed")]
     pub fn new() -> StandardCache {
-        let ref data = EmbeddedStandardFonts::get("fonts.json").unwrap().data;
-        let fonts: HashMap<String, String> = serde_json::from_slice(&data).expect("fonts.json is invalid");
-
+        let resolver = DirFontResolver::new(EmbeddedStandardFonts).expect("fonts.json is invalid");
+        StandardCache::with_resolver(resolver)
+    }
+    pub fn with_resolver(resolver: impl FontResolver + 'static) -> StandardCache {
+        StandardCache::from_resolver(Some(Box::new(resolver)))
+    }
+    fn from_resolver(resolver: Option<Box<dyn FontResolver>>) -> StandardCache {
         StandardCache {
             inner: SyncCache::new(),
-            fonts,
-            dir: EmbeddedStandardFonts,
-            dump: Dump::Never,
+            resolver,
+            debug_sink: None,
+            font_db: None,
             require_unique_unicode: false,
         }
     }
+    pub fn set_options(&mut self, options: &CacheOptions) {
+        self.require_unique_unicode = options.require_unique_unicode;
+        self.font_db = options.font_db.clone();
+        self.debug_sink = options.font_debug_sink.clone();
+    }
+    pub fn set_debug_sink(&mut self, sink: Option<Arc<dyn FontDebugSink>>) {
+        self.debug_sink = sink;
+    }
 
     pub fn require_unique_unicode(&mut self, r: bool) {
         self.require_unique_unicode = r;
@@ -135,70 +226,113 @@ impl DirRead for EmbeddedStandardFonts {
     }
 }
 
-#[derive(Debug)]
-enum Dump {
-    Never,
-    OnError,
-    Always
+/// Cache key of a font: the object number for indirect fonts.
+/// Direct fonts have no identity (the address of a freed font is reused by the next one),
+/// they are not cached. Their font programs still are, see `EmbeddedFonts`.
+pub fn font_key(font_ref: &MaybeRef<PdfFont>) -> Option<u64> {
+    match font_ref {
+        MaybeRef::Direct(_) => None,
+        MaybeRef::Indirect(re) => Some(re.get_ref().get_inner().id as _)
+    }
+}
+
+/// Receives every embedded font that is parsed, to capture problem fonts.
+pub trait FontDebugSink: Send + Sync {
+    /// `error` is the parse error, if the font could not be parsed.
+    fn font(&self, name: Option<&str>, data: &[u8], error: Option<&str>);
 }
 
-pub fn load_font(font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve, cache: &StandardCache) -> Result<Option<FontEntry>> {
+/// Writes fonts to files named `font_<name>` in a directory.
+pub struct DumpFonts {
+    pub dir: PathBuf,
+    /// only write fonts that failed to parse
+    pub only_errors: bool,
+}
+impl FontDebugSink for DumpFonts {
+    fn font(&self, name: Option<&str>, data: &[u8], error: Option<&str>) {
+        if self.only_errors && error.is_none() {
+            return;
+        }
+        let path = self.dir.join(format!("font_{}", name.unwrap_or("unnamed")));
+        match std::fs::write(&path, data) {
+            Ok(()) => info!("font dumped in {:?}", path),
+            Err(e) => warn!("can't dump font to {:?}: {:?}", path, e)
+        }
+    }
+}
+
+/// Load the font `font_ref` of a document, `embedded` are the embedded fonts of that document.
+pub fn load_font(font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve, cache: &StandardCache, embedded: &EmbeddedFonts) -> Result<Option<FontEntry>> {
     let pdf_font = font_ref.clone();
     debug!("loading {:?}", pdf_font);
     
-    let font: FontRc = match pdf_font.embedded_data(resolve) {
+    let embedded: Option<EmbeddedFont> = match pdf_font.embedded_data(resolve) {
         Some(Ok(data)) => {
-            debug!("loading embedded font");
-            let font = font::parse(&data).map_err(|e| {
-                PdfError::Other { msg: format!("Font Error: {:?}", e) }
-            });
-            if matches!(cache.dump, Dump::Always) || (matches!(cache.dump, Dump::OnError) && font.is_err()) {
-                let name = format!("font_{}", pdf_font.name.as_ref().map(|s| s.as_str()).unwrap_or("unnamed"));
-                std::fs::write(&name, &data).unwrap();
-                println!("font dumped in {}", name);
-            }
-            FontRc::from(font?)
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+            let key = (hasher.finish(), data.len());
+
+            embedded.get(key, |_| {
+                debug!("loading embedded font");
+                let font = font::parse(&data);
+                if let Some(ref sink) = cache.debug_sink {
+                    let error = font.as_ref().err().map(|e| format!("{:?}", e));
+                    sink.font(pdf_font.name.as_ref().map(|s| s.as_str()), &data, error.as_deref());
+                }
+                match font {
+                    Ok(f) => Some(EmbeddedFont {
+                        font: f.into(),
+                        color: ColorGlyphs::parse(&data).map(Arc::new),
+                    }),
+                    Err(e) => {
+                        warn!("broken embedded font {:?}, substituting: {:?}", pdf_font.name, e);
+                        None
+                    }
+                }
+            })
+        }
+        Some(Err(e)) => {
+            warn!("can't read embedded font {:?}, substituting: {:?}", pdf_font.name, e);
+            None
         }
-        Some(Err(e)) => return Err(e),
         None => {
             debug!("no embedded font.");
-            let name = match pdf_font.name {
-                Some(ref name) => name.as_str(),
-                None => return Ok(None)
-            };
-            debug!("loading {name} instead");
-            match cache.fonts.get(name).or_else(|| cache.fonts.get("Arial")) {
-                Some(file_name) => {
-                    let val = cache.inner.get(file_name.clone(), |_| {
-                        let data = match cache.dir.read_file(file_name) {
-                            Ok(data) => data,
-                            Err(e) => {
-                                warn!("can't open {} for {:?} {:?}", file_name, pdf_font.name, e);
-                                return None;
-                            }
-                        };
-                        match font::parse(&data) {
-                            Ok(f) => Some(f.into()),
-                            Err(e) => {
-                                warn!("Font Error: {:?}", e);
-                                return None;
-                            }
-                        }
-                    });
-                    match val {
-                        Some(f) => f,
-                        None => {
-                            return Ok(None);
-                        }
-                    }
-                }
-                None => {
-                    warn!("no font for {:?}", pdf_font.name);
-                    return Ok(None);
-                }
-            }
+            None
         }
     };
+    let substituted = embedded.is_none() && has_font_file(&pdf_font);
 
-    Ok(Some(FontEntry::build(font, pdf_font, None, resolve, cache.require_unique_unicode)?))
+    let (font, color) = match embedded {
+        Some(EmbeddedFont { font, color }) => (font, color),
+        None => match load_substitute(&pdf_font, cache) {
+            Some(f) => (f, None),
+            None => return Ok(None)
+        }
+    };
+
+    // the /Widths of the PDF font keep the spacing of a substitute right
+    let mut entry = FontEntry::build(font, pdf_font, cache.font_db.as_deref(), resolve, cache.require_unique_unicode)?;
+    entry.substituted = substituted;
+    entry.color = color;
+    Ok(Some(entry))
+}
+
+fn load_substitute(pdf_font: &PdfFont, cache: &StandardCache) -> Option<FontRc> {
+    let name = pdf_font.name.as_ref()?.as_str();
+    debug!("loading {name} instead");
+    let resolver = cache.resolver.as_ref()?;
+    let val = cache.inner.get(name.to_string(), |_| {
+        let data = resolver.resolve(name, font_descriptor(pdf_font))?;
+        match font::parse(&data) {
+            Ok(f) => Some(f.into()),
+            Err(e) => {
+                warn!("Font Error: {:?}", e);
+                None
+            }
+        }
+    });
+    if val.is_none() {
+        warn!("no font for {:?}", pdf_font.name);
+    }
+    val
 }
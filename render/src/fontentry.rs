@@ -3,12 +3,14 @@ use font::{self, GlyphId, TrueTypeFont, CffFont, Type1Font, OpenTypeFont};
 use glyphmatcher::FontDb;
 use itertools::Itertools;
 use pdf::encoding::BaseEncoding;
-use pdf::font::{Font as PdfFont, Widths, CidToGidMap};
+use pdf::font::{Font as PdfFont, FontData, FontDescriptor, Flags, Widths, CidToGidMap};
 use pdf::object::{Resolve, MaybeRef};
 use pdf::error::PdfError;
 use pdf_encoding::{Encoding, glyphname_to_unicode};
 use istring::SmallString;
 use crate::font::FontRc;
+use crate::colorglyph::ColorGlyphs;
+use std::sync::Arc;
 
 pub struct FontEntry {
     pub font: FontRc,
@@ -17,6 +19,66 @@ pub struct FontEntry {
     pub widths: Option<Widths>,
     pub is_cid: bool,
     pub name: String,
+    pub style: FontStyle,
+    /// the embedded font was broken and another font is drawn instead
+    pub substituted: bool,
+    pub color: Option<Arc<ColorGlyphs>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FontStyle {
+    /// /FontWeight from the descriptor (100 - 900)
+    pub weight: Option<f32>,
+    pub bold: bool,
+    pub italic: bool,
+    pub small_caps: bool,
+}
+impl FontStyle {
+    fn from_font(pdf_font: &PdfFont, name: &str) -> FontStyle {
+        let ps_name = name.split("+").nth(1).unwrap_or(name);
+        let has = |s: &str| ps_name.contains(s);
+
+        let (weight, flags, italic_angle) = match font_descriptor(pdf_font) {
+            Some(desc) => (desc.font_weight, desc.flags, desc.italic_angle),
+            None => (None, Flags::empty(), 0.0)
+        };
+        FontStyle {
+            weight,
+            bold: weight.map(|w| w >= 600.).unwrap_or(false) || flags.contains(Flags::FORCE_BOLD) || has("Bold") || has("Black") || has("Heavy"),
+            italic: flags.contains(Flags::ITALIC) || italic_angle != 0.0 || has("Italic") || has("Oblique"),
+            small_caps: flags.contains(Flags::SMALL_CAP) || has("SmallCaps") || ps_name.ends_with("-SC"),
+        }
+    }
+}
+
+/// The built-in encoding of the symbolic standard fonts, when they are not embedded.
+fn builtin_encoding(pdf_font: &PdfFont) -> Option<Encoding> {
+    if has_font_file(pdf_font) {
+        return None;
+    }
+    let name = pdf_font.name.as_ref()?.as_str();
+    let ps_name = name.split("+").nth(1).unwrap_or(name);
+    match ps_name {
+        "Symbol" | "SymbolMT" => Some(Encoding::AdobeSymbol),
+        "ZapfDingbats" | "ZapfDingbatsITC" | "Dingbats" => Some(Encoding::AdobeZdingbat),
+        _ => None
+    }
+}
+
+/// whether the descriptor points to an embedded font program
+pub(crate) fn has_font_file(pdf_font: &PdfFont) -> bool {
+    font_descriptor(pdf_font)
+        .map(|d| d.font_file.is_some() || d.font_file2.is_some() || d.font_file3.is_some())
+        .unwrap_or(false)
+}
+
+pub(crate) fn font_descriptor(pdf_font: &PdfFont) -> Option<&FontDescriptor> {
+    match pdf_font.data {
+        FontData::Type1(ref info) | FontData::TrueType(ref info) => info.font_descriptor.as_ref(),
+        FontData::CIDFontType0(ref cid) | FontData::CIDFontType2(ref cid) => Some(&cid.font_descriptor),
+        FontData::Type0(ref t0) => t0.descendant_fonts.get(0).and_then(|f| font_descriptor(f)),
+        _ => None
+    }
 }
 
 
@@ -25,7 +87,7 @@ impl FontEntry {
         let mut is_cid = pdf_font.is_cid();
 
         let name = match pdf_font.data {
-            pdf::font::FontData::Type0(ref t0) => t0.descendant_fonts[0].name.as_ref(),
+            pdf::font::FontData::Type0(ref t0) => t0.descendant_fonts.get(0).and_then(|f| f.name.as_ref()),
             _ => pdf_font.name.as_ref()
         };
 
@@ -124,7 +186,10 @@ impl FontEntry {
         } else {
             let mut cmap = HashMap::<u16, (GlyphId, Option<SmallString>)>::new();
             
+            let builtin = builtin_encoding(&pdf_font);
             let source_encoding = match base_encoding {
+                // Symbol and ZapfDingbats ignore the standard encoding, their own applies
+                None | Some(BaseEncoding::StandardEncoding) if builtin.is_some() => builtin,
                 Some(BaseEncoding::StandardEncoding) => Some(Encoding::AdobeStandard),
                 Some(BaseEncoding::SymbolEncoding) => Some(Encoding::AdobeSymbol),
                 Some(BaseEncoding::WinAnsiEncoding) => Some(Encoding::WinAnsiEncoding),
@@ -141,8 +206,7 @@ impl FontEntry {
 
             match (source_encoding, font_encoding) {
                 (Some(source), Some(dest)) => {
-                    if let Some(transcoder) = source.to(dest) {
-                        let forward = source.forward_map().unwrap();
+                    if let (Some(transcoder), Some(forward)) = (source.to(dest), source.forward_map()) {
                         for b in 0 .. 256 {
                             if let Some(gid) = transcoder.translate(b).and_then(|cp| font.gid_for_codepoint(cp)) {
                                 cmap.insert(b as u16, (gid, forward.get(b as u8).map(|c| c.into())));
@@ -258,7 +322,8 @@ impl FontEntry {
         }
 
         let widths = pdf_font.widths(resolve)?;
-        let name = pdf_font.name.as_ref().ok_or_else(|| PdfError::Other { msg: "font has no name".into() })?.as_str().into();
+        let name: String = pdf_font.name.as_ref().ok_or_else(|| PdfError::Other { msg: "font has no name".into() })?.as_str().into();
+        let style = FontStyle::from_font(&pdf_font, &name);
 
         if require_unique_unicode {
             let mut next_code = 0xE000;
@@ -316,6 +381,9 @@ impl FontEntry {
             is_cid,
             widths,
             name,
+            style,
+            substituted: false,
+            color: None,
         })
     }
 }
@@ -0,0 +1,105 @@
+//! Interactive forms: detecting XFA and drawing the appearance streams of form fields.
+
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F, vector::Vector2F};
+use pdf::object::{Page, Resolve, Resources, Annot, AppearanceStreamEntry};
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::PdfError;
+use crate::{RenderOptions, BBox, RenderDiagnostics, Backend};
+use crate::renderstate::RenderState;
+
+// annotation flags
+const HIDDEN: u32 = 1 << 1;
+const NO_VIEW: u32 = 1 << 5;
+
+/// What kind of form a document has.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FormInfo {
+    /// there is an /AcroForm with fields, their widgets usually have appearance streams
+    pub acro_form: bool,
+    /// the /AcroForm has an /XFA entry
+    pub xfa: bool,
+    /// /NeedsRendering of the catalog: the form is laid out from the XFA and the pages
+    /// only hold a placeholder ("please wait… your reader can't display this document")
+    pub needs_rendering: bool,
+}
+
+fn resolve_dict(p: &Primitive, resolve: &impl Resolve) -> Result<Dictionary, PdfError> {
+    match *p {
+        Primitive::Reference(r) => resolve.resolve(r)?.into_dictionary(),
+        ref p => p.clone().into_dictionary(),
+    }
+}
+
+/// Find out which kind of form the document with the catalog `catalog` has.
+pub fn form_info(catalog: &Dictionary, resolve: &impl Resolve) -> Result<FormInfo, PdfError> {
+    let mut info = FormInfo {
+        needs_rendering: matches!(catalog.get("NeedsRendering"), Some(Primitive::Boolean(true))),
+        .. FormInfo::default()
+    };
+    if let Some(acro_form) = catalog.get("AcroForm") {
+        let acro_form = resolve_dict(acro_form, resolve)?;
+        info.xfa = acro_form.get("XFA").is_some();
+        info.acro_form = match acro_form.get("Fields") {
+            Some(&Primitive::Array(ref fields)) => !fields.is_empty(),
+            Some(&Primitive::Reference(r)) => resolve.resolve(r)?.as_array().map_or(false, |f| !f.is_empty()),
+            _ => false
+        };
+    }
+    Ok(info)
+}
+
+/// Draw the normal appearance of the visible widget annotations of `page`, like a flattened form.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_widgets<B: Backend>(
+    backend: &mut B, resolve: &impl Resolve, page: &Page, resources: &Resources, root_transformation: Transform2F,
+    options: &RenderOptions, clip: Option<(B::ClipPathId, RectF)>, redactions: &[RectF],
+) -> Result<(BBox, RenderDiagnostics), PdfError> {
+    let mut renderstate = RenderState::new(backend, resolve, resources, root_transformation, options);
+    renderstate.set_redactions(redactions);
+    if let Some((id, rect)) = clip {
+        renderstate.set_clip(id, rect);
+    }
+    for annot in page.annotations.load(resolve)?.iter() {
+        let annot: &Annot = &annot;
+        if annot.subtype.as_str() != "Widget" || annot.annot_flags & (HIDDEN | NO_VIEW) != 0 {
+            continue;
+        }
+        let (rect, streams) = match (annot.rect, annot.appearance_streams.as_ref()) {
+            (Some(rect), Some(streams)) => (rect, streams),
+            _ => continue
+        };
+        let normal = resolve.get(streams.normal)?;
+        // checkboxes and radio buttons have one appearance per state, /AS picks one
+        let form = match *normal {
+            AppearanceStreamEntry::Single(ref form) => form,
+            AppearanceStreamEntry::Dict(ref states) => match annot.appearance_state.as_ref().and_then(|state| states.get(state)) {
+                Some(AppearanceStreamEntry::Single(ref form)) => form,
+                _ => continue
+            }
+        };
+
+        // the bbox of the form, transformed by its matrix, is mapped onto /Rect
+        let dict = form.dict();
+        let matrix = match dict.matrix {
+            Some(m) => Transform2F::row_major(m.a, m.c, m.e, m.b, m.d, m.f),
+            None => Transform2F::default()
+        };
+        let bbox = matrix * RectF::from_points(
+            Vector2F::new(dict.bbox.left.min(dict.bbox.right), dict.bbox.bottom.min(dict.bbox.top)),
+            Vector2F::new(dict.bbox.left.max(dict.bbox.right), dict.bbox.bottom.max(dict.bbox.top)),
+        );
+        let rect = RectF::from_points(
+            Vector2F::new(rect.left.min(rect.right), rect.bottom.min(rect.top)),
+            Vector2F::new(rect.left.max(rect.right), rect.bottom.max(rect.top)),
+        );
+        if bbox.width() <= 0.0 || bbox.height() <= 0.0 {
+            continue;
+        }
+        let to_rect = Transform2F::from_translation(rect.origin())
+            * Transform2F::from_scale(rect.size() / bbox.size())
+            * Transform2F::from_translation(-bbox.origin());
+        renderstate.draw_appearance(form, to_rect * matrix)?;
+    }
+    renderstate.finish();
+    Ok((renderstate.bbox(), renderstate.take_diagnostics()))
+}
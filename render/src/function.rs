@@ -0,0 +1,282 @@
+//! Sampled versions of PDF functions.
+//!
+//! Tint transforms of Separation and DeviceN color spaces and the functions of
+//! shadings are evaluated for every color operator or shading sample, which is
+//! slow for PostScript calculator and stitching functions. They are sampled once
+//! per document on a grid and interpolated from then on.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fmt::{self, Write};
+use std::sync::Arc;
+use pdf::function::Function;
+use pdf::error::Result;
+use globalcache::{sync::SyncCache, ValueSize};
+
+/// A function sampled on a regular grid over its domain, interpolated (multi)linearly.
+pub struct SampledFunction {
+    domain: Vec<(f32, f32)>,
+    outputs: usize,
+    // samples per input
+    size: usize,
+    // `outputs` values per grid point, the first input varies fastest
+    values: Vec<f32>,
+}
+
+impl SampledFunction {
+    /// Sample `f` over `domain` (one range per input).
+    /// None if the function has too many inputs to be sampled.
+    pub fn new(f: &Function, domain: &[(f32, f32)]) -> Result<Option<SampledFunction>> {
+        let size = match domain.len() {
+            1 => 256,
+            2 => 33,
+            3 => 17,
+            _ => return Ok(None)
+        };
+        let outputs = f.output_dim();
+        let points = size.pow(domain.len() as u32);
+        let mut values = Vec::with_capacity(points * outputs);
+        let mut x = vec![0.0; domain.len()];
+        let mut out = vec![0.0; outputs];
+        for n in 0 .. points {
+            let mut rest = n;
+            for (x, &(a, b)) in x.iter_mut().zip(domain) {
+                *x = a + (b - a) * (rest % size) as f32 / (size - 1) as f32;
+                rest /= size;
+            }
+            f.apply(&x, &mut out)?;
+            values.extend_from_slice(&out);
+        }
+        Ok(Some(SampledFunction { domain: domain.into(), outputs, size, values }))
+    }
+
+    pub fn apply(&self, x: &[f32], out: &mut [f32]) {
+        let mut base = 0;
+        let mut stride = 1;
+        // (offset of the upper neighbor, weight of the upper neighbor) per input
+        let mut cell = [(0, 0.0); 3];
+        for (i, (&x, &(a, b))) in x.iter().zip(&self.domain).enumerate() {
+            let pos = if b > a { ((x - a) / (b - a)).clamp(0.0, 1.0) } else { 0.0 } * (self.size - 1) as f32;
+            let idx = (pos as usize).min(self.size - 2);
+            base += idx * stride;
+            cell[i] = (stride, pos - idx as f32);
+            stride *= self.size;
+        }
+        let cell = &cell[.. self.domain.len()];
+
+        out.iter_mut().for_each(|o| *o = 0.0);
+        for corner in 0 .. 1usize << cell.len() {
+            let mut index = base;
+            let mut weight = 1.0;
+            for (i, &(offset, t)) in cell.iter().enumerate() {
+                if corner & (1 << i) != 0 {
+                    index += offset;
+                    weight *= t;
+                } else {
+                    weight *= 1.0 - t;
+                }
+            }
+            if weight == 0.0 {
+                continue;
+            }
+            let values = &self.values[index * self.outputs .. (index + 1) * self.outputs];
+            for (o, &v) in out.iter_mut().zip(values) {
+                *o += weight * v;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SampledResult(pub Option<Arc<SampledFunction>>);
+impl ValueSize for SampledResult {
+    fn size(&self) -> usize {
+        match self.0 {
+            Some(ref f) => f.values.len() * 4,
+            None => 1,
+        }
+    }
+}
+
+/// Sampled functions by a hash of the function and the domain it is sampled over.
+///
+/// Kept in `Cache` (see `Cache::functions`), so that the tables are shared by all content streams
+/// of a document instead of being sampled again for every form that sets a color.
+#[derive(Clone)]
+pub struct FunctionCache {
+    sampled: Arc<SyncCache<u64, SampledResult>>,
+}
+impl Default for FunctionCache {
+    fn default() -> Self {
+        FunctionCache { sampled: SyncCache::new() }
+    }
+}
+impl FunctionCache {
+    pub fn new() -> Self {
+        FunctionCache::default()
+    }
+}
+
+// feeds the debug output of a function into a hasher, without building the string
+struct HashWriter<'h>(&'h mut DefaultHasher);
+impl fmt::Write for HashWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+// the contents of `f` (the derived debug output has all of its fields) and the domain
+fn function_key(f: &Function, domain: &[(f32, f32)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let _ = write!(HashWriter(&mut hasher), "{:?}", f);
+    for &(a, b) in domain {
+        a.to_bits().hash(&mut hasher);
+        b.to_bits().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// The lookup tables used by one content stream.
+///
+/// Functions are found by their address first, which is only stable while the resources
+/// that hold them are borrowed, so they are hashed once per content stream and not for every color.
+pub struct FunctionLuts {
+    cache: FunctionCache,
+    by_address: HashMap<(usize, usize), Option<Arc<SampledFunction>>>,
+}
+
+impl FunctionLuts {
+    pub fn new(cache: FunctionCache) -> Self {
+        FunctionLuts { cache, by_address: HashMap::new() }
+    }
+    /// The shared cache, for the lookup tables of nested content streams.
+    pub fn cache(&self) -> FunctionCache {
+        self.cache.clone()
+    }
+    /// Evaluate `f` with inputs in the unit range, like the tints of Separation and DeviceN color spaces.
+    pub fn apply(&mut self, f: &Function, x: &[f32], out: &mut [f32]) -> Result<()> {
+        let domain = [(0.0, 1.0); 3];
+        self.apply_in(f, &domain[.. x.len().min(3)], x, out)
+    }
+    /// Evaluate `f` with the inputs in `domain`, which has to be the same for every call with `f`.
+    pub fn apply_in(&mut self, f: &Function, domain: &[(f32, f32)], x: &[f32], out: &mut [f32]) -> Result<()> {
+        if x.len() != domain.len() || out.len() > f.output_dim() {
+            return f.apply(x, out);
+        }
+        let address = (f as *const Function as usize, domain.len());
+        let sampled = match self.by_address.get(&address) {
+            Some(s) => s.clone(),
+            None => {
+                // functions that fail somewhere on the grid are always evaluated directly
+                let SampledResult(s) = self.cache.sampled.get(function_key(f, domain), |_|
+                    SampledResult(SampledFunction::new(f, domain).ok().flatten().map(Arc::new))
+                );
+                self.by_address.insert(address, s.clone());
+                s
+            }
+        };
+        match sampled {
+            Some(s) => {
+                s.apply(x, out);
+                Ok(())
+            }
+            None => f.apply(x, out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf::object::NoResolve;
+    use pdf::primitive::{Dictionary, Primitive};
+
+    fn numbers(n: &[f32]) -> Primitive {
+        Primitive::Array(n.iter().map(|&n| Primitive::Number(n)).collect())
+    }
+
+    // an exponential (type 2) function from c0 to c1 over [0, 1]
+    fn exponential(c0: &[f32], c1: &[f32], n: f32) -> Function {
+        let mut dict = Dictionary::new();
+        dict.insert("FunctionType", Primitive::Integer(2));
+        dict.insert("Domain", numbers(&[0., 1.]));
+        dict.insert("C0", numbers(c0));
+        dict.insert("C1", numbers(c1));
+        dict.insert("N", Primitive::Number(n));
+        Function::from_primitive(Primitive::Dictionary(dict), &NoResolve).unwrap()
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn one_input() {
+        let f = exponential(&[0., 0.], &[1., 2.], 2.0);
+        let sampled = SampledFunction::new(&f, &[(0.0, 1.0)]).unwrap().unwrap();
+        let mut out = [0.0; 2];
+        for &x in &[0.0, 0.3, 0.5, 0.77, 1.0] {
+            sampled.apply(&[x], &mut out);
+            assert!(close(&out, &[x * x, 2.0 * x * x]), "{} -> {:?}", x, out);
+        }
+        // inputs are clamped to the domain
+        sampled.apply(&[1.5], &mut out);
+        assert_eq!(out, [1.0, 2.0]);
+        sampled.apply(&[-1.0], &mut out);
+        assert_eq!(out, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn domain() {
+        let f = exponential(&[0.], &[1.], 1.0);
+        let sampled = SampledFunction::new(&f, &[(0.5, 1.0)]).unwrap().unwrap();
+        let mut out = [0.0];
+        sampled.apply(&[0.75], &mut out);
+        assert!(close(&out, &[0.75]));
+        sampled.apply(&[0.0], &mut out);
+        assert!(close(&out, &[0.5]));
+    }
+
+    #[test]
+    fn bilinear() {
+        // f(x, y) = x + 2y on a 2×2 grid, x varies fastest
+        let sampled = SampledFunction {
+            domain: vec![(0.0, 1.0), (0.0, 1.0)],
+            outputs: 1,
+            size: 2,
+            values: vec![0.0, 1.0, 2.0, 3.0],
+        };
+        let mut out = [0.0];
+        sampled.apply(&[0.5, 0.25], &mut out);
+        assert_eq!(out, [1.0]);
+        sampled.apply(&[1.0, 1.0], &mut out);
+        assert_eq!(out, [3.0]);
+        sampled.apply(&[0.0, 0.5], &mut out);
+        assert_eq!(out, [1.0]);
+    }
+
+    #[test]
+    fn too_many_inputs() {
+        let f = exponential(&[0.], &[1.], 1.0);
+        assert!(SampledFunction::new(&f, &[(0.0, 1.0); 4]).unwrap().is_none());
+    }
+
+    #[test]
+    fn shared_cache() {
+        let f = exponential(&[0.], &[1.], 1.0);
+        let cache = FunctionCache::new();
+        let mut a = FunctionLuts::new(cache.clone());
+        let mut b = FunctionLuts::new(cache.clone());
+        let mut out = [0.0];
+        a.apply(&f, &[0.25], &mut out).unwrap();
+        assert!(close(&out, &[0.25]));
+        // the same function with the same domain has the same key
+        let g = exponential(&[0.], &[1.], 1.0);
+        assert_eq!(function_key(&f, &[(0.0, 1.0)]), function_key(&g, &[(0.0, 1.0)]));
+        assert_ne!(function_key(&f, &[(0.0, 1.0)]), function_key(&f, &[(0.0, 0.5)]));
+        b.apply(&g, &[0.5], &mut out).unwrap();
+        assert!(close(&out, &[0.5]));
+    }
+}
@@ -3,11 +3,17 @@ use pathfinder_renderer::{paint::PaintId, scene::ClipPath};
 use pdf::object::ColorSpace;
 
 use pathfinder_geometry::{transform2d::Transform2F, rect::RectF};
-use crate::{Fill, backend::Stroke, Backend};
+use std::sync::Arc;
+use std::borrow::Cow;
+use crate::{Fill, backend::{Stroke, FillMode, BlendMode}, Backend, SoftMask, Transfer};
 
 pub struct GraphicsState<'a, B: Backend> {
     pub transform: Transform2F,
+    /// maps pattern space to the output: the CTM at the start of the page or form
+    pub pattern_transform: Transform2F,
     pub stroke_style: StrokeStyle,
+    /// kept separately, as it only shows in `stroke_style` while the line join is miter
+    pub miter_limit: f32,
 
     pub fill_color: Fill,
     pub fill_color_alpha: f32,
@@ -20,7 +26,8 @@ pub struct GraphicsState<'a, B: Backend> {
     pub clip_path_rect: Option<RectF>,
     pub fill_color_space: &'a ColorSpace,
     pub stroke_color_space: &'a ColorSpace,
-    pub dash_pattern: Option<(&'a [f32], f32)>,
+    /// borrowed from a `d` op, owned if it comes from an ExtGState
+    pub dash_pattern: Option<(Cow<'a, [f32]>, f32)>,
 
     pub stroke_alpha: f32,
     pub fill_alpha: f32,
@@ -28,12 +35,18 @@ pub struct GraphicsState<'a, B: Backend> {
     pub overprint_fill: bool,
     pub overprint_stroke: bool,
     pub overprint_mode: i32,
+
+    pub soft_mask: Option<Arc<SoftMask>>,
+    pub transfer: Option<Arc<Transfer>>,
 }
 
 impl<'a, B: Backend> Clone for GraphicsState<'a, B> {
     fn clone(&self) -> Self {
         GraphicsState {
             clip_path: self.clip_path.clone(),
+            dash_pattern: self.dash_pattern.clone(),
+            soft_mask: self.soft_mask.clone(),
+            transfer: self.transfer.clone(),
             .. *self
         }
     }
@@ -67,10 +80,24 @@ impl<'a, B: Backend> GraphicsState<'a, B> {
             self.stroke_paint = None;
         }
     }
+    /// the fill color as it is drawn, after the transfer function
+    pub fn fill_mode(&self, mode: BlendMode) -> FillMode {
+        FillMode { color: self.transfer(self.fill_color), alpha: self.fill_color_alpha, mode, pattern_transform: self.pattern_transform }
+    }
+    /// the stroke color as it is drawn, after the transfer function
+    pub fn stroke_fill_mode(&self, mode: BlendMode) -> FillMode {
+        FillMode { color: self.transfer(self.stroke_color), alpha: self.stroke_color_alpha, mode, pattern_transform: self.pattern_transform }
+    }
+    fn transfer(&self, color: Fill) -> Fill {
+        match self.transfer {
+            Some(ref t) => t.apply_fill(color),
+            None => color
+        }
+    }
     pub fn stroke(&self) -> Stroke {
         Stroke {
             style: self.stroke_style,
-            dash_pattern: self.dash_pattern.map(|(a, p)| (a.into(), p))
+            dash_pattern: self.dash_pattern.as_ref().map(|(a, p)| (a.to_vec(), *p))
         }
     }
 }
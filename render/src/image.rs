@@ -91,9 +91,11 @@ impl<'a> ImageData<'a> {
         }
     }
 
-    pub fn safe(&self, path: &Path) {
+    pub fn safe(&self, path: &Path) -> Result<(), PdfError> {
         let data = self.rgba_data();
-        ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(self.width, self.height, data).unwrap().save(path).unwrap()
+        let image = ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(self.width, self.height, data)
+            .ok_or_else(|| PdfError::Other { msg: "image size mismatch".into() })?;
+        image.save(path).map_err(|e| PdfError::Other { msg: format!("can't save image: {:?}", e) })
     }
 }
 
@@ -110,6 +112,9 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
     let raw_data = image.image_data(resolve)?;
 
     let pixel_count = image.width as usize * image.height as usize;
+    if pixel_count == 0 {
+        return Err(PdfError::Other { msg: "image has no pixels".into() });
+    }
 
     if raw_data.len() % pixel_count != 0 {
         warn!("invalid data length {} bytes for {} pixels", raw_data.len(), pixel_count);
@@ -146,7 +151,7 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
             let mask_height = mask.height as usize;
             let bits_per_component = mask.bits_per_component.ok_or_else(|| PdfError::Other { msg: format!("no bits per component")})?;
             let bits = mask_width * mask_height * bits_per_component as usize;
-            assert_eq!(data.len(), (bits + 7) / 8);
+            check_eq!(data.len(), (bits + 7) / 8);
 
             let mut alpha: Data = match bits_per_component {
                 1 => data.iter().flat_map(|&b| (0..8).map(move |i| ex(b >> i, 1))).collect::<Vec<u8>>().into(),
@@ -158,7 +163,9 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
                 n => return Err(PdfError::Other { msg: format!("invalid bits per component {}", n)})
             };
             if mask.width != image.width || mask.height != image.height {
-                alpha = resize_alpha(&*alpha, mask.width, mask.height, image.width, image.height).unwrap().into();
+                alpha = resize_alpha(&*alpha, mask.width, mask.height, image.width, image.height)
+                    .ok_or_else(|| PdfError::Other { msg: "invalid soft mask size".into() })?
+                    .into();
             }
             alpha
         }
@@ -207,7 +214,7 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
             // dbg!(&cs);
             match cs {
                 Some(&ColorSpace::DeviceGray) => {
-                    assert_eq!(pixel_data.len(), pixel_count);
+                    check_eq!(pixel_data.len(), pixel_count);
                     pixel_data.iter().zip(alpha).map(|(&g, a)| ColorU { r: g, g: g, b: g, a }).collect()
                 }
                 Some(&ColorSpace::Indexed(ref base, hival, ref lookup)) => {
@@ -263,7 +270,7 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
                 }
                 None => {
                     info!("image has data/pixel ratio of 1, but no colorspace");
-                    assert_eq!(pixel_data.len(), pixel_count);
+                    check_eq!(pixel_data.len(), pixel_count);
                     pixel_data.iter().zip(alpha).map(|(&g, a)| ColorU { r: g, g: g, b: g, a }).collect()
                 }
                 _ => unimplemented!("cs={:?}", cs),
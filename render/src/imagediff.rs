@@ -0,0 +1,170 @@
+//! Comparing the rendered pixels of two pages, the visual counterpart of `diff_pages`.
+
+use image::{RgbaImage, Rgba};
+use pdf::error::PdfError;
+use pathfinder_color::ColorF;
+use pathfinder_geometry::{rect::RectI, vector::{Vector2I, vec2i}};
+use crate::{RenderOptions, Source, render_to_image};
+
+// how far (in pixels of the downsampled images) page b may be shifted against page a
+const MAX_SHIFT: i32 = 8;
+const DOWNSAMPLE: u32 = 4;
+// changed pixels are grouped into cells of this size before they are merged into regions
+const CELL_SIZE: u32 = 16;
+// difference of the gray value below which pixels are the same
+const THRESHOLD: u8 = 32;
+
+#[derive(Debug)]
+pub struct DiffResult {
+    /// Page a faded out, with what is only on page a in red and what is only on page b in green.
+    /// It has the size of page a.
+    pub image: RgbaImage,
+    /// where page b was placed on page a to align them, in pixels
+    pub offset: Vector2I,
+    /// Bounds of the changed areas in pixels of `image`, changes close to each other (16 pixels) are merged.
+    pub regions: Vec<RectI>,
+    pub changed_pixels: usize,
+}
+
+fn gray(p: &Rgba<u8>) -> u8 {
+    ((p[0] as u32 * 3 + p[1] as u32 * 6 + p[2] as u32) / 10) as u8
+}
+
+// mean gray value of blocks of DOWNSAMPLE x DOWNSAMPLE pixels
+fn downsample(image: &RgbaImage) -> (Vec<u8>, u32, u32) {
+    let (w, h) = (image.width() / DOWNSAMPLE, image.height() / DOWNSAMPLE);
+    let mut out = Vec::with_capacity((w * h) as usize);
+    for y in 0 .. h {
+        for x in 0 .. w {
+            let mut sum = 0u32;
+            for dy in 0 .. DOWNSAMPLE {
+                for dx in 0 .. DOWNSAMPLE {
+                    sum += gray(image.get_pixel(x * DOWNSAMPLE + dx, y * DOWNSAMPLE + dy)) as u32;
+                }
+            }
+            out.push((sum / (DOWNSAMPLE * DOWNSAMPLE)) as u8);
+        }
+    }
+    (out, w, h)
+}
+
+// the shift of b that matches a best, by the mean difference of the downsampled images
+fn align(a: &RgbaImage, b: &RgbaImage) -> Vector2I {
+    let (a, aw, ah) = downsample(a);
+    let (b, bw, bh) = downsample(b);
+    let mut best = (u64::MAX, Vector2I::zero());
+    for sy in -MAX_SHIFT ..= MAX_SHIFT {
+        for sx in -MAX_SHIFT ..= MAX_SHIFT {
+            let (mut sum, mut count) = (0u64, 0u64);
+            for y in 0 .. ah as i32 {
+                let by = y - sy;
+                if by < 0 || by >= bh as i32 {
+                    continue;
+                }
+                for x in 0 .. aw as i32 {
+                    let bx = x - sx;
+                    if bx < 0 || bx >= bw as i32 {
+                        continue;
+                    }
+                    let pa = a[(y * aw as i32 + x) as usize];
+                    let pb = b[(by * bw as i32 + bx) as usize];
+                    sum += pa.abs_diff(pb) as u64;
+                    count += 1;
+                }
+            }
+            if count == 0 {
+                continue;
+            }
+            // mean difference, scaled to stay an integer
+            let score = sum * 1024 / count;
+            if score < best.0 {
+                best = (score, vec2i(sx, sy));
+            }
+        }
+    }
+    best.1 * DOWNSAMPLE as i32
+}
+
+// join the changed cells into the bounds of 8-connected groups
+fn regions(changed: &[bool], columns: u32, rows: u32, bounds: RectI) -> Vec<RectI> {
+    let mut seen = vec![false; changed.len()];
+    let mut regions = vec![];
+    for (start, &is_changed) in changed.iter().enumerate() {
+        if !is_changed || seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![start];
+        let (mut min, mut max) = (vec2i(i32::MAX, i32::MAX), vec2i(i32::MIN, i32::MIN));
+        while let Some(i) = stack.pop() {
+            let p = vec2i((i as u32 % columns) as i32, (i as u32 / columns) as i32);
+            min = min.min(p);
+            max = max.max(p);
+            for dy in -1 ..= 1 {
+                for dx in -1 ..= 1 {
+                    let q = p + vec2i(dx, dy);
+                    if q.x() < 0 || q.y() < 0 || q.x() >= columns as i32 || q.y() >= rows as i32 {
+                        continue;
+                    }
+                    let j = (q.y() as u32 * columns + q.x() as u32) as usize;
+                    if changed[j] && !seen[j] {
+                        seen[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+        let rect = RectI::from_points(min * CELL_SIZE as i32, (max + vec2i(1, 1)) * CELL_SIZE as i32);
+        if let Some(rect) = rect.intersection(bounds) {
+            regions.push(rect);
+        }
+    }
+    regions
+}
+
+/// Render page `page_a` of `file_a` and `page_b` of `file_b` at `dpi` on white, align them and compare the pixels.
+///
+/// Page b may be shifted by a few pixels against page a, like a page where everything moved a bit, see `DiffResult::offset`.
+pub fn diff_page_images<'a, 'b>(file_a: impl Into<Source<'a>>, page_a: u32, file_b: impl Into<Source<'b>>, page_b: u32, dpi: f32) -> Result<DiffResult, PdfError> {
+    let options = RenderOptions::new().dpi(dpi).background(Some(ColorF::white()));
+    let a = render_to_image(file_a, page_a, &options)?;
+    let b = render_to_image(file_b, page_b, &options)?;
+    let offset = align(&a, &b);
+
+    let (width, height) = a.dimensions();
+    let (columns, rows) = ((width + CELL_SIZE - 1) / CELL_SIZE, (height + CELL_SIZE - 1) / CELL_SIZE);
+    let mut changed = vec![false; (columns * rows) as usize];
+    let mut changed_pixels = 0;
+    let mut image = RgbaImage::new(width, height);
+    for (x, y, pa) in a.enumerate_pixels() {
+        let (bx, by) = (x as i32 - offset.x(), y as i32 - offset.y());
+        let gb = if bx >= 0 && by >= 0 && (bx as u32) < b.width() && (by as u32) < b.height() {
+            gray(b.get_pixel(bx as u32, by as u32))
+        } else {
+            255
+        };
+        let ga = gray(pa);
+        let out = if ga.abs_diff(gb) < THRESHOLD {
+            // unchanged, faded towards white
+            let g = 192 + ga / 4;
+            Rgba([g, g, g, 255])
+        } else {
+            changed_pixels += 1;
+            changed[(y / CELL_SIZE * columns + x / CELL_SIZE) as usize] = true;
+            if ga < gb {
+                Rgba([220, 0, 0, 255])
+            } else {
+                Rgba([0, 160, 0, 255])
+            }
+        };
+        image.put_pixel(x, y, out);
+    }
+
+    let bounds = RectI::new(Vector2I::zero(), vec2i(width as i32, height as i32));
+    Ok(DiffResult {
+        image,
+        offset,
+        regions: regions(&changed, columns, rows, bounds),
+        changed_pixels,
+    })
+}
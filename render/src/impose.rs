@@ -0,0 +1,152 @@
+//! Placing several pages on one sheet, for printing n-up and booklets.
+
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F, vector::Vector2F};
+use pathfinder_content::{fill::FillRule, outline::Outline};
+use pdf::object::{Page, Resolve};
+use pdf::error::PdfError;
+use crate::{Backend, RenderOptions, RenderOutput, RenderError, FitMode, page_geometry, render_page_clipped};
+
+/// A grid of cells on a sheet, one page goes into each cell.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Imposition {
+    /// size of the sheet in output units
+    pub sheet: Vector2F,
+    pub columns: u32,
+    pub rows: u32,
+    /// space around the grid
+    pub margin: f32,
+    /// space between the cells
+    pub gap: f32,
+}
+impl Imposition {
+    pub fn new(sheet: Vector2F, columns: u32, rows: u32) -> Self {
+        Imposition { sheet, columns, rows, margin: 0.0, gap: 0.0 }
+    }
+    /// two pages side by side, for landscape sheets and booklets
+    pub fn two_up(sheet: Vector2F) -> Self {
+        Imposition::new(sheet, 2, 1)
+    }
+    pub fn four_up(sheet: Vector2F) -> Self {
+        Imposition::new(sheet, 2, 2)
+    }
+    pub fn margin(mut self, margin: f32) -> Self {
+        self.margin = margin;
+        self
+    }
+    pub fn gap(mut self, gap: f32) -> Self {
+        self.gap = gap;
+        self
+    }
+    pub fn per_sheet(&self) -> usize {
+        self.columns as usize * self.rows as usize
+    }
+    /// The cells, row by row from the top left.
+    pub fn cells(&self) -> Vec<RectF> {
+        let (columns, rows) = (self.columns.max(1), self.rows.max(1));
+        let inner = self.sheet - Vector2F::splat(2.0 * self.margin);
+        let size = Vector2F::new(
+            (inner.x() - self.gap * (columns - 1) as f32) / columns as f32,
+            (inner.y() - self.gap * (rows - 1) as f32) / rows as f32,
+        ).max(Vector2F::zero());
+        (0 .. rows).flat_map(|row| (0 .. columns).map(move |column| {
+            let origin = Vector2F::splat(self.margin) + (size + Vector2F::splat(self.gap)) * Vector2F::new(column as f32, row as f32);
+            RectF::new(origin, size)
+        })).collect()
+    }
+}
+
+/// The pages (0 based) of each sheet when they are printed in order, the last sheet is filled up with None.
+pub fn n_up_order(num_pages: u32, per_sheet: usize) -> Vec<Vec<Option<u32>>> {
+    let per_sheet = per_sheet.max(1);
+    let pages: Vec<u32> = (0 .. num_pages).collect();
+    pages.chunks(per_sheet).map(|chunk| {
+        let mut sheet: Vec<Option<u32>> = chunk.iter().cloned().map(Some).collect();
+        sheet.resize(per_sheet, None);
+        sheet
+    }).collect()
+}
+
+/// The two pages of each side of a folded booklet with `Imposition::two_up`, in print order:
+/// front and back of the first sheet, then those of the second and so on.
+/// The page count is padded to a multiple of four with blank pages (None).
+pub fn booklet_order(num_pages: u32) -> Vec<Vec<Option<u32>>> {
+    let padded = (num_pages + 3) / 4 * 4;
+    let page = |nr: u32| Some(nr).filter(|&nr| nr < num_pages);
+    (0 .. padded / 4).flat_map(|sheet| {
+        let (outer, inner) = (2 * sheet, padded - 1 - 2 * sheet);
+        [
+            vec![page(inner), page(outer)],
+            vec![page(outer + 1), page(inner - 1)],
+        ]
+    }).collect()
+}
+
+/// Render `page` scaled to fit into `cell` and centered in it. Nothing of the page is drawn outside of the cell.
+/// `options.fit` and `options.transform` are replaced, the rest of the options apply.
+pub fn render_page_in_cell<B: Backend>(backend: &mut B, resolve: &impl Resolve, page: &Page, cell: RectF, options: &RenderOptions) -> Result<RenderOutput, RenderError> {
+    let options = options.clone().transform(Transform2F::default()).fit(FitMode::FitPage(cell.size()));
+    let size = page_geometry(page, &options).map_err(|e| RenderError::new(options.page_nr, e))?.view_box.size();
+    let offset = cell.origin() + (cell.size() - size) * 0.5;
+    let options = options.transform(Transform2F::from_translation(offset));
+
+    let clip = backend.create_clip_path(Outline::from_rect(cell), FillRule::Winding, None);
+    render_page_clipped(backend, resolve, page, &options, Some((clip, cell)), |_, _| {})
+}
+
+/// Render one side of a sheet, with the pages and their numbers in the order of `Imposition::cells`.
+/// Cells without a page stay blank. The number of each page replaces `options.page_nr`.
+pub fn render_sheet<'p, B: Backend>(backend: &mut B, resolve: &impl Resolve, pages: impl IntoIterator<Item=Option<(u32, &'p Page)>>, imposition: &Imposition, options: &RenderOptions) -> Result<(), PdfError> {
+    backend.set_view_box(RectF::new(Vector2F::zero(), imposition.sheet));
+    for (page, cell) in pages.into_iter().zip(imposition.cells()) {
+        if let Some((page_nr, page)) = page {
+            render_page_in_cell(backend, resolve, page, cell, &options.clone().page_nr(page_nr))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn n_up() {
+        assert_eq!(n_up_order(5, 4), [
+            vec![Some(0), Some(1), Some(2), Some(3)],
+            vec![Some(4), None, None, None],
+        ]);
+        assert_eq!(n_up_order(2, 2), [vec![Some(0), Some(1)]]);
+        assert!(n_up_order(0, 4).is_empty());
+    }
+
+    #[test]
+    fn booklet() {
+        // 5 pages are padded to 8, the sides of two sheets
+        assert_eq!(booklet_order(5), [
+            vec![None, Some(0)],
+            vec![Some(1), None],
+            vec![None, Some(2)],
+            vec![Some(3), Some(4)],
+        ]);
+        assert_eq!(booklet_order(4), [
+            vec![Some(3), Some(0)],
+            vec![Some(1), Some(2)],
+        ]);
+        assert!(booklet_order(0).is_empty());
+    }
+
+    #[test]
+    fn cells() {
+        let imposition = Imposition::two_up(Vector2F::new(100.0, 50.0)).margin(5.0).gap(10.0);
+        assert_eq!(imposition.per_sheet(), 2);
+        assert_eq!(imposition.cells(), [
+            RectF::new(Vector2F::new(5.0, 5.0), Vector2F::new(40.0, 40.0)),
+            RectF::new(Vector2F::new(55.0, 5.0), Vector2F::new(40.0, 40.0)),
+        ]);
+
+        // row by row from the top left
+        let cells = Imposition::four_up(Vector2F::new(20.0, 20.0)).cells();
+        let origins: Vec<_> = cells.iter().map(|c| (c.min_x(), c.min_y())).collect();
+        assert_eq!(origins, [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0), (10.0, 10.0)]);
+    }
+}
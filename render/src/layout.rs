@@ -0,0 +1,202 @@
+use pathfinder_geometry::rect::RectF;
+use crate::TextSpan;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Script {
+    Normal,
+    Superscript,
+    Subscript,
+}
+impl Script {
+    /// The script of text that is raised by `rise` (Ts, negative to lower it) and has a size of `font_size`,
+    /// in a line of text with `line_font_size`. All three have to be in the same units.
+    ///
+    /// Super- and subscripts are smaller than the line, and the rise has to be a noticeable part of
+    /// their size, so a small rise that only adjusts the baseline is not taken for one.
+    pub fn from_rise(rise: f32, font_size: f32, line_font_size: f32) -> Script {
+        if !(font_size <= 0.9 * line_font_size) || rise.abs() < 0.15 * font_size {
+            Script::Normal
+        } else if rise > 0.0 {
+            Script::Superscript
+        } else {
+            Script::Subscript
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// indices into the span slice, in content order
+    pub spans: Vec<usize>,
+    pub rect: RectF,
+}
+
+fn vertical_overlap(a: RectF, b: RectF) -> f32 {
+    let overlap = a.max_y().min(b.max_y()) - a.min_y().max(b.min_y());
+    let height = a.height().min(b.height());
+    if height <= 0.0 {
+        return 0.0;
+    }
+    overlap.max(0.0) / height
+}
+
+/// Group spans into lines of horizontal text.
+pub fn group_lines(spans: &[TextSpan]) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    for (i, span) in spans.iter().enumerate() {
+        match lines.iter_mut().rev().find(|line| vertical_overlap(line.rect, span.rect) > 0.5) {
+            Some(line) => {
+                line.spans.push(i);
+                line.rect = line.rect.union_rect(span.rect);
+            }
+            None => lines.push(Line { spans: vec![i], rect: span.rect })
+        }
+    }
+    lines
+}
+
+/// Refine the `script` of spans that have no text rise by comparing
+/// their size and baseline to the dominant span of their line.
+pub fn classify_scripts(spans: &mut [TextSpan]) {
+    for line in group_lines(spans) {
+        let dominant = match line.spans.iter().max_by_key(|&&i| spans[i].text.len()) {
+            Some(&i) => i,
+            None => continue
+        };
+        let size = spans[dominant].effective_font_size;
+        // the rect spans from the baseline to 1em above, and y points down
+        let baseline = spans[dominant].rect.max_y();
+
+        for &i in line.spans.iter() {
+            let span = &mut spans[i];
+            if span.script != Script::Normal || span.effective_font_size > 0.85 * size {
+                continue;
+            }
+            let raised = baseline - span.rect.max_y();
+            if raised > 0.15 * size {
+                span.script = Script::Superscript;
+            } else if raised < -0.05 * size {
+                span.script = Script::Subscript;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub text: String,
+    pub rect: RectF,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextLine {
+    pub words: Vec<Word>,
+    pub rect: RectF,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub lines: Vec<TextLine>,
+    pub rect: RectF,
+}
+
+#[derive(Debug, Clone)]
+pub struct PageLayout {
+    pub bounds: RectF,
+    pub blocks: Vec<Block>,
+}
+impl PageLayout {
+    /// Plain text with words separated by spaces, lines by `\n` and blocks by an empty line.
+    pub fn text(&self) -> String {
+        let blocks: Vec<String> = self.blocks.iter().map(|block| {
+            block.lines.iter().map(|line| {
+                line.words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(" ")
+            }).collect::<Vec<_>>().join("\n")
+        }).collect();
+        blocks.join("\n\n")
+    }
+}
+
+fn line_words(spans: &[TextSpan], line: &Line) -> Vec<Word> {
+    let mut order = line.spans.clone();
+    order.sort_by(|&a, &b| spans[a].rect.min_x().partial_cmp(&spans[b].rect.min_x()).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut words = vec![];
+    let mut current: Option<Word> = None;
+    let mut last_x: Option<f32> = None;
+    for &i in order.iter() {
+        let span = &spans[i];
+        // a visible gap between two spans separates words
+        if let Some(x) = last_x {
+            if span.rect.min_x() - x > 0.25 * span.effective_font_size {
+                words.extend(current.take());
+            }
+        }
+        for part in span.parts() {
+            let rect = span.char_rect(part.pos, part.width);
+            for c in part.text.chars() {
+                if c.is_whitespace() {
+                    words.extend(current.take());
+                    continue;
+                }
+                match current {
+                    Some(ref mut w) => {
+                        w.text.push(c);
+                        w.rect = w.rect.union_rect(rect);
+                    }
+                    None => current = Some(Word { text: c.into(), rect })
+                }
+            }
+        }
+        last_x = Some(span.rect.max_x());
+    }
+    words.extend(current);
+    words
+}
+
+/// Build a words / lines / blocks hierarchy from the spans of one page.
+pub fn page_layout(spans: &[TextSpan], bounds: RectF) -> PageLayout {
+    let mut blocks: Vec<Block> = vec![];
+    for line in group_lines(spans) {
+        let words = line_words(spans, &line);
+        if words.is_empty() {
+            continue;
+        }
+        let rect = words.iter().skip(1).fold(words[0].rect, |r, w| r.union_rect(w.rect));
+        let text_line = TextLine { words, rect };
+
+        let joins = blocks.last().map(|block| {
+            let prev = block.rect;
+            let gap = rect.min_y() - prev.max_y();
+            let overlaps = rect.min_x() < prev.max_x() && rect.max_x() > prev.min_x();
+            overlaps && gap < rect.height()
+        }).unwrap_or(false);
+
+        match blocks.last_mut() {
+            Some(block) if joins => {
+                block.rect = block.rect.union_rect(rect);
+                block.lines.push(text_line);
+            }
+            _ => blocks.push(Block { lines: vec![text_line], rect })
+        }
+    }
+    PageLayout { bounds, blocks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_from_rise() {
+        assert_eq!(Script::from_rise(4.0, 7.0, 10.0), Script::Superscript);
+        assert_eq!(Script::from_rise(-2.0, 7.0, 10.0), Script::Subscript);
+        // a small rise in a smaller font
+        assert_eq!(Script::from_rise(0.5, 7.0, 10.0), Script::Normal);
+        // raised text of the same size as the line
+        assert_eq!(Script::from_rise(4.0, 10.0, 10.0), Script::Normal);
+        // nothing to compare to
+        assert_eq!(Script::from_rise(4.0, 7.0, 0.0), Script::Normal);
+    }
+}
@@ -1,7 +1,8 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate pdf;
 
-macro_rules! assert_eq {
+// like `assert_eq!`, but returns an error instead of panicking
+macro_rules! check_eq {
     ($a:expr, $b:expr) => {
         if $a != $b {
             return Err(pdf::error::PdfError::Other { msg: format!("{} ({}) != {} ({})", stringify!($a), $a, stringify!($b), $b)});
@@ -26,24 +27,79 @@ pub mod tracer;
 mod image;
 mod scene;
 mod font;
+mod metrics;
+mod colorglyph;
+mod softmask;
+mod transfer;
+mod diagnostics;
+mod function;
+mod shading;
+mod search;
+mod layout;
+mod classify;
+mod diff;
+mod redact;
+mod impose;
+mod alttext;
+mod forms;
+mod svg;
+mod options;
+mod document;
+mod displaylist;
+#[cfg(feature="serde")]
+mod serde_impls;
+#[cfg(any(feature="rasterize", feature="raster"))]
+mod to_image;
+#[cfg(any(feature="rasterize", feature="raster"))]
+mod imagediff;
+#[cfg(feature="raster")]
+pub mod raster;
+pub mod ocr;
 
-pub use cache::{Cache};
-pub use fontentry::{FontEntry};
-pub use backend::{DrawMode, Backend, BlendMode, FillMode};
-pub use scene::SceneBackend;
+pub use cache::{Cache, FileId};
+pub use fontentry::{FontEntry, FontStyle};
+pub use metrics::{StandardMetrics, standard_metrics};
+pub use font::{FontResolver, DirFontResolver, DirRead, FontDebugSink, DumpFonts};
+pub use colorglyph::{ColorGlyphs, ColorGlyph, ColorLayer};
+pub use softmask::SoftMask;
+pub use shading::{Gradient, GradientShape};
+pub use transfer::Transfer;
+pub use renderstate::Budget;
+pub use function::FunctionCache;
+pub use diagnostics::{RenderDiagnostics, Diagnostic, DiagnosticKind, Feature, RenderError, RenderErrorContext};
+pub use backend::{DrawMode, Backend, BlendMode, FillMode, MarkedContent, PositionedGlyph};
+use pathfinder_content::{fill::FillRule, outline::Outline};
+pub use scene::{SceneBackend, UserPath};
+pub use textstate::TextOptions;
+pub use options::{RenderOptions, PageBox, ColorFilter, FitMode, Unit, CacheOptions, Strictness, Limits, Quality};
 pub use crate::image::{load_image, ImageData};
+#[cfg(any(feature="rasterize", feature="raster"))]
+pub use to_image::{render_to_image, render_into_buffer, PixelBuffer, Source};
+#[cfg(any(feature="rasterize", feature="raster"))]
+pub use imagediff::{DiffResult, diff_page_images};
+pub use document::{Document, Link};
+pub use displaylist::{DisplayList, DisplayListBackend};
+pub use svg::{outline_to_svg_path, outline_from_svg_path, trace_to_svg, page_trace_svg};
+pub use tracer::{Tracer, TraceCache, DrawItem, ItemMap};
+pub use search::{find_text, find_in_spans, FindOptions, Match};
+pub use classify::{PageStats, classify_page};
+pub use forms::{FormInfo, form_info};
+pub use alttext::{Figure, ImageAlt, page_figures, image_alt_texts};
+pub use impose::{Imposition, n_up_order, booklet_order, render_sheet, render_page_in_cell};
+pub use diff::{DiffOptions, Change, TraceDiff, diff_items, diff_pages};
+pub use layout::{Script, Line, group_lines, classify_scripts, Word, TextLine, Block, PageLayout, page_layout};
 use custom_debug_derive::Debug;
 
-use pdf::{object::*, content::TextMode};
+use pdf::{object::*, content::{Op, TextMode, Matrix}};
 use pdf::error::PdfError;
 use pathfinder_geometry::{
-    vector::{Vector2F},
+    vector::{Vector2F, Vector2I},
     rect::RectF, transform2d::Transform2F,
 };
 use renderstate::RenderState;
 use std::sync::Arc;
+use std::borrow::Cow;
 use itertools::Itertools;
-const SCALE: f32 = 25.4 / 72.;
 
 
 #[derive(Copy, Clone, Default)]
@@ -74,58 +130,258 @@ impl From<RectF> for BBox {
 }
 
 
-pub fn page_bounds(page: &Page) -> RectF {
-    let Rect { left, right, top, bottom } = page.media_box().expect("no media box");
-    RectF::from_points(Vector2F::new(left, bottom), Vector2F::new(right, top)) * SCALE
+/// Media box of the page in millimeters.
+#[deprecated(note = "page space depends on RenderOptions::unit, use page_bounds_in")]
+pub fn page_bounds(page: &Page) -> Result<RectF, PdfError> {
+    page_bounds_in(page, Unit::Millimeters)
 }
+/// Media box of the page in the given unit.
+pub fn page_bounds_in(page: &Page, unit: Unit) -> Result<RectF, PdfError> {
+    page_box_bounds(page, PageBox::MediaBox, unit)
+}
+fn page_box_bounds(page: &Page, page_box: PageBox, unit: Unit) -> Result<RectF, PdfError> {
+    let Rect { left, right, top, bottom } = match page_box {
+        PageBox::MediaBox => page.media_box()?,
+        PageBox::CropBox => page.crop_box()?,
+        PageBox::TrimBox => match page.trim_box {
+            Some(r) => r,
+            None => page.crop_box()?
+        }
+    };
+    Ok(RectF::from_points(Vector2F::new(left, bottom), Vector2F::new(right, top)) * unit.scale())
+}
+
+/// The limit of `RenderOptions::limits` that stopped rendering.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Ops,
+    FormDepth,
+    Time,
+    ClipPaths,
+}
+
+/// Rendering stopped early, everything before `op_nr` was drawn.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PartialRender {
+    pub limit: Limit,
+    /// number of the op in the content stream of the page, or the op that drew the form in which the limit was reached
+    pub op_nr: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOutput {
+    /// maps page space to the output
+    pub transform: Transform2F,
+    /// size of the output in pixels, rounded up
+    pub size: Vector2I,
+    /// union of the bounds of everything that was drawn, in output space.
+    /// None if the page is blank.
+    pub bbox: Option<RectF>,
+    /// problems that did not stop rendering
+    pub diagnostics: RenderDiagnostics,
+    /// Some if a limit was reached and only part of the page was drawn
+    pub partial: Option<PartialRender>,
+}
+
+/// Where a page was drawn in the output, see `Backend::begin_page`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PagePlacement {
+    /// `RenderOptions::page_nr`
+    pub page_nr: Option<u32>,
+    /// maps page space to the output
+    pub transform: Transform2F,
+    /// the page box in the output
+    pub view_box: RectF,
+}
+impl PagePlacement {
+    pub fn contains(&self, p: Vector2F) -> bool {
+        self.view_box.contains_point(p)
+    }
+    /// map a point of the output back to page space
+    pub fn to_page(&self, p: Vector2F) -> Vector2F {
+        self.transform.inverse() * p
+    }
+}
+
 pub fn render_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, transform: Transform2F) -> Result<Transform2F, PdfError> {
-    let bounds = page_bounds(page);
+    Ok(render_page_with(backend, resolve, page, &RenderOptions::new().transform(transform))?.transform)
+}
+
+struct PageGeometry {
+    view_box: RectF,
+    root_transformation: Transform2F,
+}
+fn page_geometry(page: &Page, options: &RenderOptions) -> Result<PageGeometry, PdfError> {
+    let bounds = page_box_bounds(page, options.page_box, options.unit)?;
+    let scale = options.unit.scale();
     let rotate = Transform2F::from_rotation(page.rotate as f32 * std::f32::consts::PI / 180.);
     let br = rotate * RectF::new(Vector2F::zero(), bounds.size());
+    let transform = match options.fit {
+        Some(fit) => options.transform * Transform2F::from_scale(Vector2F::splat(fit.scale(br.size(), options.unit))),
+        None => options.transform
+    };
     let translate = Transform2F::from_translation(Vector2F::new(
         -br.min_x().min(br.max_x()),
         -br.min_y().min(br.max_y()),
     ));
     let view_box = transform * translate * br;
-    backend.set_view_box(view_box);
-    
     let root_transformation = transform
         * translate
         * rotate
-        * Transform2F::row_major(SCALE, 0.0, -bounds.min_x(), 0.0, -SCALE, bounds.max_y());
+        * Transform2F::row_major(scale, 0.0, -bounds.min_x(), 0.0, -scale, bounds.max_y());
+
+    Ok(PageGeometry { view_box, root_transformation })
+}
+
+/// The transformation from PDF user space of the page to the output, without rendering anything.
+pub fn page_transform(page: &Page, options: &RenderOptions) -> Result<Transform2F, PdfError> {
+    page_geometry(page, options).map(|g| g.root_transformation)
+}
+
+/// Render `page` into `backend`. When an error stops rendering, the `RenderError` tells which op of the page it came from,
+/// unless `options.strictness` recovers from it.
+pub fn render_page_with(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, options: &RenderOptions) -> Result<RenderOutput, RenderError> {
+    render_page_with_overlay(backend, resolve, page, options, |_, _| {})
+}
+/// Like `render_page_with`, and once the content of the page is drawn, `overlay` is called with the backend
+/// and the transformation from PDF user space of the page to the output.
+/// What it draws ends up on top of the page content, below form fields (`RenderOptions::render_widgets`) and redactions,
+/// like watermarks, grids or debugging output.
+pub fn render_page_with_overlay<B: Backend>(backend: &mut B, resolve: &impl Resolve, page: &Page, options: &RenderOptions, overlay: impl FnOnce(&mut B, Transform2F)) -> Result<RenderOutput, RenderError> {
+    let PageGeometry { view_box, .. } = page_geometry(page, options).map_err(|e| RenderError::new(options.page_nr, e))?;
+    backend.set_view_box(view_box);
+    render_page_clipped(backend, resolve, page, options, None, overlay)
+}
+// everything of `render_page_with_overlay` but `set_view_box`, with all of the page clipped to `clip`
+pub(crate) fn render_page_clipped<B: Backend>(backend: &mut B, resolve: &impl Resolve, page: &Page, options: &RenderOptions, clip: Option<(B::ClipPathId, RectF)>, overlay: impl FnOnce(&mut B, Transform2F)) -> Result<RenderOutput, RenderError> {
+    draw_page(backend, resolve, page, options, clip, overlay).map_err(|e| RenderError { page_nr: options.page_nr, ..e })
+}
+fn draw_page<B: Backend>(backend: &mut B, resolve: &impl Resolve, page: &Page, options: &RenderOptions, clip: Option<(B::ClipPathId, RectF)>, overlay: impl FnOnce(&mut B, Transform2F)) -> Result<RenderOutput, RenderError> {
+    let PageGeometry { view_box, root_transformation } = page_geometry(page, options)?;
+    backend.begin_page(&PagePlacement { page_nr: options.page_nr, transform: root_transformation, view_box });
+
+    if let Some(color) = options.background {
+        let fill = FillMode {
+            color: Fill::Solid(color.r(), color.g(), color.b()),
+            alpha: color.a(),
+            mode: BlendMode::Overlay,
+            pattern_transform: Transform2F::default(),
+        };
+        backend.draw(Cow::Owned(Outline::from_rect(view_box)), &DrawMode::Fill { fill }, FillRule::Winding, Transform2F::default(), clip.map(|(id, _)| id));
+    }
     
-    let resources = t!(page.resources());
+    let resources = page.resources()?;
+    let redactions = match options.apply_redactions {
+        true => redact::page_redactions(page, resolve, root_transformation)?,
+        false => vec![]
+    };
+    let redacted: Vec<RectF> = redactions.iter().map(|r| r.rect).collect();
 
-    let contents = try_opt!(page.contents.as_ref());
-    let ops = contents.operations(resolve)?;
-    let mut renderstate = RenderState::new(backend, resolve, &resources, root_transformation);
-    for (i, op) in ops.iter().enumerate() {
-        debug!("op {}: {:?}", i, op);
-        renderstate.draw_op(op, i)?;
+    let mut bbox = BBox::empty();
+    let mut diagnostics = RenderDiagnostics::new();
+    let mut partial = None;
+    if page.contents.is_some() {
+        let ops = backend.page_ops(page, options.page_nr, resolve)?;
+        let mut renderstate = RenderState::new(backend, resolve, &resources, root_transformation, options);
+        renderstate.set_redactions(&redacted);
+        if let Some((id, rect)) = clip {
+            renderstate.set_clip(id, rect);
+        }
+        for (i, op) in ops.iter().enumerate() {
+            debug!("op {}: {:?}", i, op);
+            if let Err(error) = renderstate.draw_op(op, i) {
+                return Err(RenderError { page_nr: None, context: Some(RenderErrorContext::new(op, i)), error });
+            }
+            if renderstate.partial().is_some() {
+                break;
+            }
+        }
+        renderstate.finish();
+        bbox = renderstate.bbox();
+        diagnostics = renderstate.take_diagnostics();
+        partial = renderstate.partial();
+    }
+    overlay(backend, root_transformation);
+    if options.render_widgets {
+        let (widget_bbox, widget_diagnostics) = forms::draw_widgets(backend, resolve, page, &resources, root_transformation, options, clip, &redacted)?;
+        bbox.add_bbox(widget_bbox);
+        diagnostics.items.extend(widget_diagnostics.items);
+    }
+    for redaction in &redactions {
+        let fill = FillMode {
+            color: redaction.color,
+            alpha: 1.0,
+            mode: BlendMode::Overlay,
+            pattern_transform: Transform2F::default(),
+        };
+        backend.draw(Cow::Owned(Outline::from_rect(redaction.rect)), &DrawMode::Fill { fill }, FillRule::Winding, Transform2F::default(), clip.map(|(id, _)| id));
+        bbox.add(redaction.rect);
     }
 
-    Ok(root_transformation)
+    Ok(RenderOutput {
+        transform: root_transformation,
+        size: view_box.size().ceil().to_i32(),
+        bbox: bbox.rect(),
+        diagnostics,
+        partial,
+    })
+}
+/// Parse the content stream of `page`, see `Backend::page_ops`.
+pub fn page_ops(page: &Page, resolve: &impl Resolve) -> Result<Arc<[Op]>, PdfError> {
+    match page.contents {
+        Some(ref contents) => Ok(contents.operations(resolve)?.into()),
+        None => Ok(Vec::new().into())
+    }
 }
 pub fn render_pattern(backend: &mut impl Backend, pattern: &Pattern, resolve: &impl Resolve) -> Result<(), PdfError> {
+    let options = RenderOptions::default();
+    render_pattern_in(backend, pattern, Transform2F::default(), resolve, &options, &mut Budget::new(&options))
+}
+/// Render one cell of a tiling pattern, or the shading of a shading pattern.
+/// `pattern_transform` is `FillMode::pattern_transform` of the fill that uses the pattern,
+/// the /Matrix of the pattern is applied on top of it.
+///
+/// `options` are those of the page the pattern is used on, its limits apply to the pattern too:
+/// the pattern counts as one more level of nesting and its ops are taken from `budget`.
+pub fn render_pattern_in(backend: &mut impl Backend, pattern: &Pattern, pattern_transform: Transform2F, resolve: &impl Resolve, options: &RenderOptions, budget: &mut Budget) -> Result<(), PdfError> {
     match pattern {
         Pattern::Stream(ref dict, ref ops) => {
             let resources = resolve.get(dict.resources)?;
-            let mut renderstate = RenderState::new(backend, resolve, &*resources, Transform2F::default());
-            for (i, op) in ops.iter().enumerate() {
+            let Matrix { a, b, c, d, e, f } = dict.matrix;
+            let transform = pattern_transform * Transform2F::row_major(a, c, e, b, d, f);
+            let mut renderstate = RenderState::new(backend, resolve, &*resources, transform, options);
+            renderstate.nest_in(*budget);
+            let result = ops.iter().enumerate().try_for_each(|(i, op)| {
                 debug!("op {}: {:?}", i, op);
-                renderstate.draw_op(op, i)?;
-            }
+                match renderstate.partial() {
+                    None => renderstate.draw_op(op, i),
+                    Some(_) => Ok(())
+                }
+            });
+            renderstate.finish();
+            renderstate.return_budget(budget);
+            result?;
+        }
+        Pattern::Dict(ref dict) => {
+            // shadings don't use resources, they only have to be passed on
+            let resources = Resources::default();
+            let mut renderstate = RenderState::new(backend, resolve, &resources, pattern_transform, options);
+            renderstate.nest_in(*budget);
+            let result = renderstate.paint_shading_pattern(dict);
+            renderstate.finish();
+            renderstate.return_budget(budget);
+            result?;
         }
-        Pattern::Dict(_) => {}
     }
     Ok(())
 }
 
 
 #[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Fill {
     Solid(f32, f32, f32),
-    Pattern(Ref<Pattern>),
+    Pattern(#[cfg_attr(feature="serde", serde(with="crate::serde_impls::pdf_ref"))] Ref<Pattern>),
 }
 impl Fill {
     pub fn black() -> Self {
@@ -134,16 +390,22 @@ impl Fill {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TextSpan {
     // A rect with the origin at the baseline, a height of 1em and width that corresponds to the advance width.
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::rect"))]
     pub rect: RectF,
 
     // width in textspace units (before applying transform)
     pub width: f32,
-    // Bounding box of the rendered outline
+    // Bounding box of the rendered glyph outlines in output space, None if no glyph has an outline
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::rect_opt"))]
     pub bbox: Option<RectF>,
     pub font_size: f32,
+    // font size after applying the transform
+    pub effective_font_size: f32,
     #[debug(skip)]
+    #[cfg_attr(feature="serde", serde(skip))]
     pub font: Option<Arc<FontEntry>>,
     pub text: String,
     pub chars: Vec<TextChar>,
@@ -151,14 +413,27 @@ pub struct TextSpan {
     pub alpha: f32,
 
     // apply this transform to a text draw in at the origin with the given width and font-size
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::transform"))]
     pub transform: Transform2F,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::text_mode"))]
     pub mode: TextMode,
     pub op_nr: usize,
+
+    // text rise (Ts) in unscaled text space units
+    pub rise: f32,
+    pub script: Script,
 }
 impl TextSpan {
+    /// Output-space rect of the text between `pos` and `pos + width` (in text space), one em high above the baseline.
+    pub fn char_rect(&self, pos: f32, width: f32) -> RectF {
+        self.transform * RectF::from_points(
+            Vector2F::new(pos, -self.font_size),
+            Vector2F::new(pos + width, 0.0)
+        )
+    }
     pub fn parts(&self) -> impl Iterator<Item=Part> + '_ {
         self.chars.iter().cloned()
-            .chain(std::iter::once(TextChar { offset: self.text.len(), pos: self.width, width: 0.0 }))
+            .chain(std::iter::once(TextChar { offset: self.text.len(), pos: self.width, width: 0.0, bbox: None }))
             .tuple_windows()
             .map(|(a, b)| Part {
                 text: &self.text[a.offset..b.offset],
@@ -169,7 +444,7 @@ impl TextSpan {
     }
     pub fn rparts(&self) -> impl Iterator<Item=Part> + '_ {
         self.chars.iter().cloned()
-            .chain(std::iter::once(TextChar { offset: self.text.len(), pos: self.width, width: 0.0 })).rev()
+            .chain(std::iter::once(TextChar { offset: self.text.len(), pos: self.width, width: 0.0, bbox: None })).rev()
             .tuple_windows()
             .map(|(b, a)| Part {
                 text: &self.text[a.offset..b.offset],
@@ -186,8 +461,12 @@ pub struct Part<'a> {
     pub offset: usize,
 }
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TextChar {
     pub offset: usize,
     pub pos: f32,
     pub width: f32,
+    // transformed outline bounds of the glyph, None for spaces and empty glyphs
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::rect_opt"))]
+    pub bbox: Option<RectF>,
 }
\ No newline at end of file
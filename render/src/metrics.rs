@@ -0,0 +1,318 @@
+//! Advance widths of the standard 14 fonts, from the Adobe AFM files.
+//!
+//! The widths are keyed by the codes of WinAnsiEncoding, which has the Latin-1 letters and
+//! the typographic punctuation, and by those of the built-in encodings of Symbol and ZapfDingbats.
+//! They are used when a non-embedded font comes without /Widths, which is allowed for the
+//! standard fonts, and when no substitute font could be loaded at all.
+
+/// Widths in 1/1000 em.
+pub struct StandardMetrics {
+    pub name: &'static str,
+    /// widths of the codes 0x20 ..= 0xFF, 0 where the encoding has no glyph
+    widths: Option<&'static [u16; 224]>,
+    /// whether `widths` are in the built-in encoding of the font instead of WinAnsiEncoding
+    symbolic: bool,
+    /// width of everything else (and of all glyphs in fixed pitch fonts)
+    default: u16,
+}
+impl StandardMetrics {
+    /// The width of the glyph for `c`, the default width of the font if it has none.
+    pub fn width(&self, c: char) -> f32 {
+        let w = match (self.widths, win_ansi_code(c)) {
+            (Some(widths), Some(code)) if !self.symbolic => widths[code as usize - 0x20],
+            _ => 0
+        };
+        let w = if w != 0 { w } else { self.default };
+        w as f32
+    }
+    /// The width of the glyph for `code`, which maps to `unicode`.
+    /// Symbol and ZapfDingbats are looked up by the code, None if their encoding has no glyph for it.
+    pub fn glyph_width(&self, code: u16, unicode: Option<char>) -> Option<f32> {
+        match self.widths {
+            Some(widths) if self.symbolic => match code {
+                0x20 ..= 0xFF => Some(widths[code as usize - 0x20]).filter(|&w| w != 0).map(|w| w as f32),
+                _ => None
+            },
+            _ => unicode.map(|c| self.width(c))
+        }
+    }
+}
+
+// the characters of the codes 0x80 ..= 0x9F of WinAnsiEncoding, NUL where it has none
+static WIN_ANSI_80: [char; 32] = [
+    '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0',
+    '\0', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ',
+];
+
+// the code of `c` in WinAnsiEncoding, for the printable characters
+fn win_ansi_code(c: char) -> Option<u8> {
+    match c {
+        ' ' ..= '~' | '\u{A0}' ..= '\u{FF}' => Some(c as u8),
+        '\0' => None,
+        _ => WIN_ANSI_80.iter().position(|&w| w == c).map(|i| 0x80 + i as u8)
+    }
+}
+
+/// The character of `code` in WinAnsiEncoding, for the printable codes.
+pub fn win_ansi_char(code: u8) -> Option<char> {
+    match code {
+        0x20 ..= 0x7E | 0xA0 ..= 0xFF => Some(code as char),
+        0x80 ..= 0x9F => Some(WIN_ANSI_80[code as usize - 0x80]).filter(|&c| c != '\0'),
+        _ => None
+    }
+}
+
+/// Look up the metrics for a font name like `Helvetica-BoldOblique`, `ABCDEF+Arial,Bold` or `TimesNewRomanPS-ItalicMT`.
+pub fn standard_metrics(name: &str) -> Option<&'static StandardMetrics> {
+    let name = name.split('+').nth(1).unwrap_or(name);
+    match name {
+        "Symbol" | "SymbolMT" => return Some(&SYMBOL),
+        "ZapfDingbats" | "ZapfDingbatsITC" | "Dingbats" => return Some(&ZAPF_DINGBATS),
+        _ => {}
+    }
+    let bold = name.contains("Bold") || name.contains("Black");
+    let italic = name.contains("Italic") || name.contains("Oblique");
+
+    let family = if name.starts_with("Helvetica") || name.starts_with("Arial") {
+        0
+    } else if name.starts_with("Times") {
+        1
+    } else if name.starts_with("Courier") {
+        2
+    } else {
+        return None;
+    };
+
+    Some(match (family, bold, italic) {
+        (0, false, _) => &HELVETICA,
+        (0, true, _) => &HELVETICA_BOLD,
+        (1, false, false) => &TIMES_ROMAN,
+        (1, true, false) => &TIMES_BOLD,
+        (1, false, true) => &TIMES_ITALIC,
+        (1, true, true) => &TIMES_BOLD_ITALIC,
+        _ => &COURIER,
+    })
+}
+
+// the oblique variants of Helvetica share the widths of the upright ones
+static HELVETICA: StandardMetrics = StandardMetrics {
+    name: "Helvetica",
+    widths: Some(&[
+        278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+        556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+        1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+        667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+        333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+        556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, 0,
+        556, 0, 222, 556, 333, 1000, 556, 556, 333, 1000, 667, 333, 1000, 0, 611, 0,
+        0, 222, 222, 333, 333, 350, 556, 1000, 333, 1000, 500, 333, 944, 0, 500, 667,
+        278, 333, 556, 556, 556, 556, 260, 556, 333, 737, 370, 556, 584, 333, 737, 333,
+        400, 584, 333, 333, 333, 556, 537, 278, 333, 333, 365, 556, 834, 834, 834, 611,
+        667, 667, 667, 667, 667, 667, 1000, 722, 667, 667, 667, 667, 278, 278, 278, 278,
+        722, 722, 778, 778, 778, 778, 778, 584, 778, 722, 722, 722, 722, 667, 667, 611,
+        556, 556, 556, 556, 556, 556, 889, 500, 556, 556, 556, 556, 278, 278, 278, 278,
+        556, 556, 556, 556, 556, 556, 556, 584, 611, 556, 556, 556, 556, 500, 556, 500,
+    ]),
+    symbolic: false,
+    default: 556,
+};
+static HELVETICA_BOLD: StandardMetrics = StandardMetrics {
+    name: "Helvetica-Bold",
+    widths: Some(&[
+        278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+        556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+        975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+        667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+        333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+        611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584, 0,
+        556, 0, 278, 556, 500, 1000, 556, 556, 333, 1000, 667, 333, 1000, 0, 611, 0,
+        0, 278, 278, 500, 500, 350, 556, 1000, 333, 1000, 556, 333, 944, 0, 500, 667,
+        278, 333, 556, 556, 556, 556, 280, 556, 333, 737, 370, 556, 584, 333, 737, 333,
+        400, 584, 333, 333, 333, 611, 556, 278, 333, 333, 365, 556, 834, 834, 834, 611,
+        722, 722, 722, 722, 722, 722, 1000, 722, 667, 667, 667, 667, 278, 278, 278, 278,
+        722, 722, 778, 778, 778, 778, 778, 584, 778, 722, 722, 722, 722, 667, 667, 611,
+        556, 556, 556, 556, 556, 556, 889, 556, 556, 556, 556, 556, 278, 278, 278, 278,
+        611, 611, 611, 611, 611, 611, 611, 584, 611, 611, 611, 611, 611, 556, 611, 556,
+    ]),
+    symbolic: false,
+    default: 611,
+};
+static TIMES_ROMAN: StandardMetrics = StandardMetrics {
+    name: "Times-Roman",
+    widths: Some(&[
+        250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444,
+        921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722,
+        556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500,
+        333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500,
+        500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541, 0,
+        500, 0, 333, 500, 444, 1000, 500, 500, 333, 1000, 556, 333, 889, 0, 611, 0,
+        0, 333, 333, 444, 444, 350, 500, 1000, 333, 980, 389, 333, 722, 0, 444, 722,
+        250, 333, 500, 500, 500, 500, 200, 500, 333, 760, 276, 500, 564, 333, 760, 333,
+        400, 564, 300, 300, 333, 500, 453, 250, 333, 300, 310, 500, 750, 750, 750, 444,
+        722, 722, 722, 722, 722, 722, 889, 667, 611, 611, 611, 611, 333, 333, 333, 333,
+        722, 722, 722, 722, 722, 722, 722, 564, 722, 722, 722, 722, 722, 722, 556, 500,
+        444, 444, 444, 444, 444, 444, 667, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+        500, 500, 500, 500, 500, 500, 500, 564, 500, 500, 500, 500, 500, 500, 500, 500,
+    ]),
+    symbolic: false,
+    default: 500,
+};
+static TIMES_BOLD: StandardMetrics = StandardMetrics {
+    name: "Times-Bold",
+    widths: Some(&[
+        250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+        930, 722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778,
+        611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667, 333, 278, 333, 581, 500,
+        333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500,
+        556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520, 0,
+        500, 0, 333, 500, 500, 1000, 500, 500, 333, 1000, 556, 333, 1000, 0, 667, 0,
+        0, 333, 333, 500, 500, 350, 500, 1000, 333, 1000, 389, 333, 722, 0, 444, 722,
+        250, 333, 500, 500, 500, 500, 220, 500, 333, 747, 300, 500, 570, 333, 747, 333,
+        400, 570, 300, 300, 333, 556, 540, 250, 333, 300, 330, 500, 750, 750, 750, 500,
+        722, 722, 722, 722, 722, 722, 1000, 722, 667, 667, 667, 667, 389, 389, 389, 389,
+        722, 722, 778, 778, 778, 778, 778, 570, 778, 722, 722, 722, 722, 722, 611, 556,
+        500, 500, 500, 500, 500, 500, 722, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+        500, 556, 500, 500, 500, 500, 500, 570, 500, 556, 556, 556, 556, 500, 556, 500,
+    ]),
+    symbolic: false,
+    default: 500,
+};
+static TIMES_ITALIC: StandardMetrics = StandardMetrics {
+    name: "Times-Italic",
+    widths: Some(&[
+        250, 333, 420, 500, 500, 833, 778, 214, 333, 333, 500, 675, 250, 333, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 675, 675, 675, 500,
+        920, 611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833, 667, 722,
+        611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556, 389, 278, 389, 422, 500,
+        333, 500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722, 500, 500,
+        500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389, 400, 275, 400, 541, 0,
+        500, 0, 333, 500, 556, 889, 500, 500, 333, 1000, 500, 333, 944, 0, 556, 0,
+        0, 333, 333, 556, 556, 350, 500, 889, 333, 980, 389, 333, 667, 0, 389, 556,
+        250, 389, 500, 500, 500, 500, 275, 500, 333, 760, 276, 500, 675, 333, 760, 333,
+        400, 675, 300, 300, 333, 500, 523, 250, 333, 300, 310, 500, 750, 750, 750, 500,
+        611, 611, 611, 611, 611, 611, 889, 667, 611, 611, 611, 611, 333, 333, 333, 333,
+        722, 667, 722, 722, 722, 722, 722, 675, 722, 722, 722, 722, 722, 556, 611, 500,
+        500, 500, 500, 500, 500, 500, 667, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+        500, 500, 500, 500, 500, 500, 500, 675, 500, 500, 500, 500, 500, 444, 500, 444,
+    ]),
+    symbolic: false,
+    default: 500,
+};
+static TIMES_BOLD_ITALIC: StandardMetrics = StandardMetrics {
+    name: "Times-BoldItalic",
+    widths: Some(&[
+        250, 389, 555, 500, 500, 833, 778, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+        832, 667, 667, 667, 722, 667, 667, 722, 778, 389, 500, 667, 611, 889, 722, 722,
+        611, 722, 667, 556, 611, 722, 667, 889, 667, 611, 611, 333, 278, 333, 570, 500,
+        333, 500, 500, 444, 500, 444, 333, 500, 556, 278, 278, 500, 278, 778, 556, 500,
+        500, 500, 389, 389, 278, 556, 444, 667, 500, 444, 389, 348, 220, 348, 570, 0,
+        500, 0, 333, 500, 500, 1000, 500, 500, 333, 1000, 556, 333, 944, 0, 611, 0,
+        0, 333, 333, 500, 500, 350, 500, 1000, 333, 1000, 389, 333, 722, 0, 389, 611,
+        250, 389, 500, 500, 500, 500, 220, 500, 333, 747, 266, 500, 606, 333, 747, 333,
+        400, 570, 300, 300, 333, 576, 500, 250, 333, 300, 300, 500, 750, 750, 750, 500,
+        667, 667, 667, 667, 667, 667, 944, 667, 667, 667, 667, 667, 389, 389, 389, 389,
+        722, 722, 722, 722, 722, 722, 722, 570, 722, 722, 722, 722, 722, 611, 611, 500,
+        500, 500, 500, 500, 500, 500, 722, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+        500, 556, 500, 500, 500, 500, 500, 570, 500, 556, 556, 556, 556, 444, 500, 444,
+    ]),
+    symbolic: false,
+    default: 500,
+};
+static COURIER: StandardMetrics = StandardMetrics {
+    name: "Courier",
+    widths: None,
+    symbolic: false,
+    default: 600,
+};
+
+// widths by code in the built-in encoding
+static SYMBOL: StandardMetrics = StandardMetrics {
+    name: "Symbol",
+    widths: Some(&[
+        250, 333, 713, 500, 549, 833, 778, 439, 333, 333, 500, 549, 250, 549, 250, 278,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 549, 549, 549, 444,
+        549, 722, 667, 722, 612, 611, 763, 603, 722, 333, 631, 722, 686, 889, 722, 722,
+        768, 741, 556, 592, 611, 690, 439, 768, 645, 795, 611, 333, 863, 333, 658, 500,
+        500, 631, 549, 549, 494, 439, 521, 411, 603, 329, 603, 549, 549, 576, 521, 549,
+        549, 521, 549, 603, 439, 576, 713, 686, 493, 686, 494, 480, 200, 480, 549, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        750, 620, 247, 549, 167, 713, 500, 753, 753, 753, 753, 1042, 987, 603, 987, 603,
+        400, 549, 411, 549, 549, 713, 494, 460, 549, 549, 549, 549, 1000, 603, 1000, 658,
+        823, 686, 795, 987, 768, 768, 823, 768, 768, 713, 713, 713, 713, 713, 713, 713,
+        768, 713, 790, 790, 890, 823, 549, 250, 713, 603, 603, 1042, 987, 603, 987, 603,
+        494, 329, 790, 790, 786, 713, 384, 384, 384, 384, 384, 384, 494, 494, 494, 494,
+        0, 329, 274, 686, 686, 686, 384, 384, 384, 384, 384, 384, 494, 494, 494, 0,
+    ]),
+    symbolic: true,
+    default: 0,
+};
+static ZAPF_DINGBATS: StandardMetrics = StandardMetrics {
+    name: "ZapfDingbats",
+    widths: Some(&[
+        278, 974, 961, 974, 980, 719, 789, 790, 791, 690, 960, 939, 549, 855, 911, 933,
+        911, 945, 974, 755, 846, 762, 761, 571, 677, 763, 760, 759, 754, 494, 552, 537,
+        577, 692, 786, 788, 788, 790, 793, 794, 816, 823, 789, 841, 823, 833, 816, 831,
+        923, 744, 723, 749, 790, 792, 695, 776, 768, 792, 759, 707, 708, 682, 701, 826,
+        815, 789, 789, 707, 687, 696, 689, 786, 787, 713, 791, 785, 791, 873, 761, 762,
+        762, 759, 759, 892, 892, 788, 784, 438, 138, 277, 415, 392, 392, 668, 668, 0,
+        390, 390, 317, 317, 276, 276, 509, 509, 410, 410, 234, 234, 334, 334, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 732, 544, 544, 910, 667, 760, 760, 776, 595, 694, 626, 788, 788, 788, 788,
+        788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788,
+        788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788,
+        788, 788, 788, 788, 894, 838, 1016, 458, 748, 924, 748, 918, 927, 928, 928, 834,
+        873, 828, 924, 924, 917, 930, 931, 463, 883, 836, 836, 867, 867, 696, 696, 874,
+        0, 874, 760, 946, 771, 865, 771, 888, 967, 888, 831, 873, 927, 970, 918, 0,
+    ]),
+    symbolic: true,
+    default: 0,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn win_ansi() {
+        let helvetica = standard_metrics("Helvetica").unwrap();
+        assert_eq!(helvetica.width('a'), 556.);
+        // accented letters have the width of their base letter, the dotless i is wider than i
+        assert_eq!(helvetica.width('é'), helvetica.width('e'));
+        assert_eq!(helvetica.width('í'), 278.);
+        assert_eq!(helvetica.width('–'), 556.);
+        assert_eq!(helvetica.width('—'), 1000.);
+        assert_eq!(helvetica.width('“'), 333.);
+        // not in WinAnsiEncoding
+        assert_eq!(helvetica.width('π'), 556.);
+
+        let times = standard_metrics("ABCDEF+Times-Italic").unwrap();
+        assert_eq!(times.width('Œ'), 944.);
+        assert_eq!(times.width('ß'), 500.);
+        assert_eq!(standard_metrics("Courier-Bold").unwrap().width('ü'), 600.);
+    }
+
+    #[test]
+    fn symbolic() {
+        let symbol = standard_metrics("Symbol").unwrap();
+        // alpha is at the code of 'a'
+        assert_eq!(symbol.glyph_width(0x61, Some('α')), Some(631.));
+        assert_eq!(symbol.glyph_width(0x7F, None), None);
+        let dingbats = standard_metrics("ZapfDingbats").unwrap();
+        assert_eq!(dingbats.glyph_width(0x20, Some(' ')), Some(278.));
+    }
+
+    #[test]
+    fn codes() {
+        for code in 0x20 ..= 0xFF {
+            if let Some(c) = win_ansi_char(code) {
+                assert_eq!(win_ansi_code(c), Some(code));
+            }
+        }
+        assert_eq!(win_ansi_char(0x96), Some('–'));
+        assert_eq!(win_ansi_char(0x81), None);
+    }
+}
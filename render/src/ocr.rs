@@ -0,0 +1,147 @@
+//! hOCR and ALTO serialization of the text layout.
+//!
+//! Coordinates are written as found in the layout (rounded to integers),
+//! so build it from spans rendered with a pixel transform.
+
+use std::fmt::Write;
+use pathfinder_geometry::rect::RectF;
+use crate::layout::PageLayout;
+
+/// Escape `s` for XML text and attribute values.
+/// Characters XML does not allow (controls other than tab, LF and CR, U+FFFE and U+FFFF) are left out.
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\t' | '\n' | '\r' => out.push(c),
+            '\u{0}' ..= '\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => {}
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c)
+        }
+    }
+    out
+}
+
+fn bbox(r: RectF) -> String {
+    format!("bbox {} {} {} {}", r.min_x().round() as i32, r.min_y().round() as i32, r.max_x().round() as i32, r.max_y().round() as i32)
+}
+
+pub fn to_hocr(pages: &[PageLayout]) -> String {
+    let mut out = String::new();
+    out.push_str(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd\">\n",
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\">\n",
+        "<head>\n",
+        "<meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\" />\n",
+        "<meta name=\"ocr-system\" content=\"pdf_render\" />\n",
+        "<meta name=\"ocr-capabilities\" content=\"ocr_page ocr_carea ocr_line ocrx_word\" />\n",
+        "</head>\n<body>\n"
+    ));
+    let (mut block_nr, mut line_nr, mut word_nr) = (0, 0, 0);
+    for (page_nr, page) in pages.iter().enumerate() {
+        writeln!(out, "<div class=\"ocr_page\" id=\"page_{}\" title=\"{}; ppageno {}\">", page_nr + 1, bbox(page.bounds), page_nr).unwrap();
+        for block in page.blocks.iter() {
+            block_nr += 1;
+            writeln!(out, " <div class=\"ocr_carea\" id=\"block_{}\" title=\"{}\">", block_nr, bbox(block.rect)).unwrap();
+            for line in block.lines.iter() {
+                line_nr += 1;
+                writeln!(out, "  <span class=\"ocr_line\" id=\"line_{}\" title=\"{}\">", line_nr, bbox(line.rect)).unwrap();
+                for word in line.words.iter() {
+                    word_nr += 1;
+                    writeln!(out, "   <span class=\"ocrx_word\" id=\"word_{}\" title=\"{}; x_wconf 100\">{}</span>", word_nr, bbox(word.rect), escape(&word.text)).unwrap();
+                }
+                out.push_str("  </span>\n");
+            }
+            out.push_str(" </div>\n");
+        }
+        out.push_str("</div>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn alto_pos(r: RectF) -> String {
+    format!("HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\"", r.min_x().round() as i32, r.min_y().round() as i32, r.width().round() as i32, r.height().round() as i32)
+}
+
+pub fn to_alto(pages: &[PageLayout]) -> String {
+    let mut out = String::new();
+    out.push_str(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<alto xmlns=\"http://www.loc.gov/standards/alto/ns-v4#\">\n",
+        "<Description>\n<MeasurementUnit>pixel</MeasurementUnit>\n</Description>\n",
+        "<Layout>\n"
+    ));
+    let (mut block_nr, mut line_nr, mut word_nr) = (0, 0, 0);
+    for (page_nr, page) in pages.iter().enumerate() {
+        writeln!(out, "<Page ID=\"page_{}\" PHYSICAL_IMG_NR=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\">", page_nr + 1, page_nr + 1, page.bounds.width().round() as i32, page.bounds.height().round() as i32).unwrap();
+        writeln!(out, " <PrintSpace {}>", alto_pos(page.bounds)).unwrap();
+        for block in page.blocks.iter() {
+            block_nr += 1;
+            writeln!(out, "  <TextBlock ID=\"block_{}\" {}>", block_nr, alto_pos(block.rect)).unwrap();
+            for line in block.lines.iter() {
+                line_nr += 1;
+                writeln!(out, "   <TextLine ID=\"line_{}\" {}>", line_nr, alto_pos(line.rect)).unwrap();
+                for (i, word) in line.words.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str("    <SP/>\n");
+                    }
+                    word_nr += 1;
+                    writeln!(out, "    <String ID=\"string_{}\" {} WC=\"1.0\" CONTENT=\"{}\"/>", word_nr, alto_pos(word.rect), escape(&word.text)).unwrap();
+                }
+                out.push_str("   </TextLine>\n");
+            }
+            out.push_str("  </TextBlock>\n");
+        }
+        out.push_str(" </PrintSpace>\n</Page>\n");
+    }
+    out.push_str("</Layout>\n</alto>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathfinder_geometry::vector::Vector2F;
+    use crate::layout::{Block, TextLine, Word};
+
+    fn page(words: &[&str]) -> PageLayout {
+        let rect = RectF::new(Vector2F::new(10.0, 20.0), Vector2F::new(100.0, 12.0));
+        let words = words.iter().map(|&text| Word { text: text.into(), rect }).collect();
+        PageLayout {
+            bounds: RectF::new(Vector2F::zero(), Vector2F::new(200.0, 300.0)),
+            blocks: vec![Block { lines: vec![TextLine { words, rect }], rect }],
+        }
+    }
+
+    #[test]
+    fn escape_xml() {
+        assert_eq!(escape("a<b> & \"c\" 'd'"), "a&lt;b&gt; &amp; &quot;c&quot; &apos;d&apos;");
+        assert_eq!(escape("a\u{0}b\u{1}\u{1F}c\u{FFFF}"), "abc");
+        assert_eq!(escape("a\tb\nc\rd"), "a\tb\nc\rd");
+    }
+
+    #[test]
+    fn hocr() {
+        let hocr = to_hocr(&[page(&["a&b", "c\u{2}"])]);
+        assert!(hocr.contains("<div class=\"ocr_page\" id=\"page_1\" title=\"bbox 0 0 200 300; ppageno 0\">\n"));
+        assert!(hocr.contains("<span class=\"ocr_line\" id=\"line_1\" title=\"bbox 10 20 110 32\">\n"));
+        assert!(hocr.contains("<span class=\"ocrx_word\" id=\"word_1\" title=\"bbox 10 20 110 32; x_wconf 100\">a&amp;b</span>\n"));
+        assert!(hocr.contains(">c</span>\n"));
+        assert!(hocr.ends_with("</div>\n</body>\n</html>\n"));
+    }
+
+    #[test]
+    fn alto() {
+        let alto = to_alto(&[page(&["a<b", "c"])]);
+        assert!(alto.contains("<Page ID=\"page_1\" PHYSICAL_IMG_NR=\"1\" WIDTH=\"200\" HEIGHT=\"300\">\n"));
+        assert!(alto.contains("<String ID=\"string_1\" HPOS=\"10\" VPOS=\"20\" WIDTH=\"100\" HEIGHT=\"12\" WC=\"1.0\" CONTENT=\"a&lt;b\"/>\n    <SP/>\n"));
+        assert!(alto.contains("CONTENT=\"c\"/>\n   </TextLine>\n"));
+        assert!(alto.ends_with("</Layout>\n</alto>\n"));
+    }
+}
@@ -0,0 +1,330 @@
+use pathfinder_color::ColorF;
+use pathfinder_geometry::{
+    vector::Vector2F,
+    transform2d::Transform2F,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use glyphmatcher::FontDb;
+use crate::{Fill, FontDebugSink};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PageBox {
+    MediaBox,
+    CropBox,
+    /// falls back to the crop box if the page has no trim box
+    TrimBox,
+}
+
+/// The unit of page space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Unit {
+    Points,
+    Millimeters,
+    /// pixels at the given resolution
+    Pixels(f32),
+}
+impl Unit {
+    pub fn per_inch(self) -> f32 {
+        match self {
+            Unit::Points => 72.,
+            Unit::Millimeters => 25.4,
+            Unit::Pixels(dpi) => dpi,
+        }
+    }
+    /// page space units per PDF point
+    pub fn scale(self) -> f32 {
+        self.per_inch() / 72.
+    }
+}
+impl Default for Unit {
+    fn default() -> Self {
+        Unit::Millimeters
+    }
+}
+
+/// How the page is scaled to the output.
+///
+/// Sizes are in output pixels and apply to the rotated page.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FitMode {
+    FitWidth(f32),
+    FitHeight(f32),
+    /// the largest scale at which the whole page fits into the given size
+    FitPage(Vector2F),
+    Dpi(f32),
+    /// pixels per page space unit
+    Scale(f32),
+}
+impl FitMode {
+    /// `page_size` is the size of the (rotated) page in page space
+    pub fn scale(self, page_size: Vector2F, unit: Unit) -> f32 {
+        match self {
+            FitMode::FitWidth(w) => w / page_size.x(),
+            FitMode::FitHeight(h) => h / page_size.y(),
+            FitMode::FitPage(size) => (size.x() / page_size.x()).min(size.y() / page_size.y()),
+            FitMode::Dpi(dpi) => dpi / unit.per_inch(),
+            FitMode::Scale(s) => s,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorFilter {
+    Grayscale,
+    Invert,
+}
+impl ColorFilter {
+    pub fn apply(self, fill: Fill) -> Fill {
+        match (self, fill) {
+            (ColorFilter::Grayscale, Fill::Solid(r, g, b)) => {
+                let l = 0.3 * r + 0.59 * g + 0.11 * b;
+                Fill::Solid(l, l, l)
+            }
+            (ColorFilter::Invert, Fill::Solid(r, g, b)) => Fill::Solid(1.0 - r, 1.0 - g, 1.0 - b),
+            (_, fill) => fill
+        }
+    }
+}
+
+/// How errors in the content of a page are handled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Strictness {
+    /// every error aborts rendering
+    Strict,
+    /// colors that can't be converted are drawn black and unknown color spaces are treated as gray,
+    /// everything else aborts
+    RecoverColors,
+    /// like `RecoverColors`, and ops that refer to missing resources or fail otherwise are skipped
+    RecoverAll,
+}
+impl Strictness {
+    pub fn recover_colors(self) -> bool {
+        matches!(self, Strictness::RecoverColors | Strictness::RecoverAll)
+    }
+    pub fn recover_all(self) -> bool {
+        self == Strictness::RecoverAll
+    }
+}
+impl Default for Strictness {
+    fn default() -> Self {
+        Strictness::RecoverColors
+    }
+}
+
+/// Limits that protect against pathological and hostile pages.
+///
+/// When a limit on the page as a whole is reached, rendering stops and `RenderOutput::partial` tells which one it was.
+/// Images and paths that are too large are skipped and reported in the diagnostics.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Limits {
+    /// ops in total, including those of forms, patterns and soft masks
+    pub max_ops: Option<usize>,
+    /// how deeply forms may be nested, 32 by default so that forms that draw themselves don't overflow the stack
+    pub max_form_depth: Option<usize>,
+    /// wall-clock time for one page
+    pub time_budget: Option<Duration>,
+    /// Images (and their soft masks) with more pixels are not drawn.
+    pub max_image_pixels: Option<usize>,
+    /// Paths with more points are not drawn.
+    pub max_outline_points: Option<usize>,
+    /// clip paths in total, rendering stops at the first one beyond that
+    pub max_clip_paths: Option<usize>,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_ops: None,
+            max_form_depth: Some(32),
+            time_budget: None,
+            max_image_pixels: None,
+            max_outline_points: None,
+            max_clip_paths: None,
+        }
+    }
+}
+
+/// How the software rasterizer (feature `raster`) turns the scene into pixels.
+/// Other rasterizers ignore it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quality {
+    /// Without anti-aliasing pixels are either covered or not, which is faster and gives hard edges.
+    pub antialias: bool,
+    /// sub-scanlines per row of pixels when anti-aliasing, 1 to 64
+    pub samples: u32,
+    /// Blend and anti-alias in linear light instead of sRGB. Thin dark text on light backgrounds looks lighter.
+    pub gamma_correct: bool,
+}
+impl Default for Quality {
+    fn default() -> Self {
+        Quality {
+            antialias: true,
+            samples: 16,
+            gamma_correct: false,
+        }
+    }
+}
+impl Quality {
+    /// no anti-aliasing
+    pub fn fast() -> Self {
+        Quality { antialias: false, samples: 1, gamma_correct: false }
+    }
+    /// more samples and gamma correct blending
+    pub fn best() -> Self {
+        Quality { antialias: true, samples: 64, gamma_correct: true }
+    }
+}
+
+/// Everything that controls how a page is rendered.
+///
+/// ```ignore
+/// let options = RenderOptions::new().dpi(150.).page_box(PageBox::CropBox);
+/// render_page_with(&mut backend, &resolver, &page, &options)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub transform: Transform2F,
+    /// applied before `transform`
+    pub fit: Option<FitMode>,
+    /// unit of page space
+    pub unit: Unit,
+    pub page_box: PageBox,
+    pub background: Option<ColorF>,
+    pub strictness: Strictness,
+    /// names of optional content groups (layers) that are not drawn
+    pub hidden_layers: Vec<String>,
+    pub color_filter: Option<ColorFilter>,
+    /// Strokes are drawn at least this wide, in output units (pixels when rendering with a `FitMode`).
+    /// Without it, lines of width 0 are not drawn at all.
+    pub min_stroke_width: Option<f32>,
+    pub limits: Limits,
+    pub quality: Quality,
+    /// number of the page that is rendered, only passed on to `Backend::begin_page`
+    pub page_nr: Option<u32>,
+    /// Fill the regions of /Redact annotations with their /IC (or black) and leave the text in them out of `Backend::add_text`.
+    /// Glyph outlines and images below the regions are still drawn, just covered.
+    pub apply_redactions: bool,
+    /// Draw the normal appearance (/AP /N) of form field widgets on top of the page, like a flattened form.
+    /// `Document` turns it on for XFA forms, which often only have a placeholder as page content.
+    pub render_widgets: bool,
+}
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            transform: Transform2F::default(),
+            fit: None,
+            unit: Unit::Millimeters,
+            page_box: PageBox::MediaBox,
+            background: None,
+            strictness: Strictness::default(),
+            hidden_layers: vec![],
+            color_filter: None,
+            min_stroke_width: None,
+            limits: Limits::default(),
+            quality: Quality::default(),
+            page_nr: None,
+            apply_redactions: false,
+            render_widgets: false,
+        }
+    }
+}
+impl RenderOptions {
+    pub fn new() -> Self {
+        RenderOptions::default()
+    }
+    pub fn transform(mut self, transform: Transform2F) -> Self {
+        self.transform = transform;
+        self
+    }
+    pub fn fit(mut self, fit: FitMode) -> Self {
+        self.fit = Some(fit);
+        self
+    }
+    /// scale the page to pixels at the given resolution
+    pub fn dpi(self, dpi: f32) -> Self {
+        self.fit(FitMode::Dpi(dpi))
+    }
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+    pub fn page_box(mut self, page_box: PageBox) -> Self {
+        self.page_box = page_box;
+        self
+    }
+    pub fn background(mut self, color: Option<ColorF>) -> Self {
+        self.background = color;
+        self
+    }
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+    /// `Strictness::RecoverAll` if true, `Strictness::Strict` otherwise
+    pub fn lenient(self, lenient: bool) -> Self {
+        self.strictness(if lenient { Strictness::RecoverAll } else { Strictness::Strict })
+    }
+    pub fn hide_layer(mut self, name: impl Into<String>) -> Self {
+        self.hidden_layers.push(name.into());
+        self
+    }
+    pub fn color_filter(mut self, filter: Option<ColorFilter>) -> Self {
+        self.color_filter = filter;
+        self
+    }
+    pub fn min_stroke_width(mut self, width: Option<f32>) -> Self {
+        self.min_stroke_width = width;
+        self
+    }
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+    pub fn quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+    pub fn page_nr(mut self, page_nr: u32) -> Self {
+        self.page_nr = Some(page_nr);
+        self
+    }
+    pub fn apply_redactions(mut self, apply: bool) -> Self {
+        self.apply_redactions = apply;
+        self
+    }
+    pub fn render_widgets(mut self, render: bool) -> Self {
+        self.render_widgets = render;
+        self
+    }
+}
+
+/// How fonts are loaded by `Cache` and `TraceCache`.
+///
+/// ```ignore
+/// let cache = TraceCache::with_options(CacheOptions::new().require_unique_unicode(true));
+/// ```
+#[derive(Clone, Default)]
+pub struct CacheOptions {
+    /// give glyphs without (or with duplicate) unicode a code point in the private use area
+    pub require_unique_unicode: bool,
+    /// fix up the unicode of known fonts by matching their glyphs
+    pub font_db: Option<Arc<FontDb>>,
+    pub font_debug_sink: Option<Arc<dyn FontDebugSink>>,
+}
+impl CacheOptions {
+    pub fn new() -> Self {
+        CacheOptions::default()
+    }
+    pub fn require_unique_unicode(mut self, r: bool) -> Self {
+        self.require_unique_unicode = r;
+        self
+    }
+    pub fn font_db(mut self, font_db: Arc<FontDb>) -> Self {
+        self.font_db = Some(font_db);
+        self
+    }
+    pub fn font_debug_sink(mut self, sink: Arc<dyn FontDebugSink>) -> Self {
+        self.font_debug_sink = Some(sink);
+        self
+    }
+}
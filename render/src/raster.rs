@@ -0,0 +1,566 @@
+//! A software rasterizer for the scenes built by `SceneBackend`, so images can be rendered
+//! without a GPU or an external rasterizer. Enabled with the `raster` feature.
+//!
+//! Paths are filled with analytic coverage along x and `Quality::samples` sub-scanlines per row.
+//! Solid colors, linear and radial gradients and image patterns are supported, as are
+//! clip paths and the separable blend modes. Render targets are not used by `SceneBackend`
+//! and are ignored; soft masks are already multiplied into the pixels of the masked drawings,
+//! which `SceneBackend` rasterizes with this module when the `raster` feature is enabled.
+
+use std::collections::HashMap;
+use image::RgbaImage;
+use pathfinder_color::{ColorF, ColorU};
+use pathfinder_content::{
+    effects::BlendMode,
+    fill::FillRule,
+    gradient::{Gradient, GradientGeometry, GradientWrap},
+    outline::{Outline, ContourIterFlags},
+    pattern::{Pattern, PatternSource},
+    segment::SegmentKind,
+};
+use pathfinder_geometry::{
+    vector::{Vector2F, Vector2I},
+    rect::RectI,
+};
+use pathfinder_renderer::{
+    paint::{Paint, PaintContents},
+    scene::{Scene, DrawPathId, ClipPathId},
+};
+use crate::Quality;
+
+// largest distance of a flattened curve from the real one, in pixels
+const FLATTEN_TOLERANCE: f32 = 0.1;
+const MAX_CURVE_STEPS: usize = 256;
+
+/// Rasterize `scene` into an image the size of its view box, on top of `background`.
+pub fn rasterize(scene: &Scene, background: Option<ColorF>) -> RgbaImage {
+    rasterize_with(scene, background, &Quality::default())
+}
+
+/// Like `rasterize`, with the anti-aliasing and blending of `quality`.
+pub fn rasterize_with(scene: &Scene, background: Option<ColorF>, quality: &Quality) -> RgbaImage {
+    let view_box = scene.view_box();
+    let size = view_box.size().ceil().to_i32().max(Vector2I::splat(0));
+    let mut canvas = Canvas::new(size, background, quality.gamma_correct);
+    let sampling = Sampling::new(quality);
+    let mut clips = ClipCache::default();
+    let offset = -view_box.origin();
+
+    for i in 0 .. scene.draw_path_count() {
+        let draw_path = scene.get_draw_path(DrawPathId(i));
+        let mut mask = match fill_mask(draw_path.outline(), draw_path.fill_rule(), offset, canvas.bounds(), sampling) {
+            Some(m) => m,
+            None => continue
+        };
+        if let Some(clip) = draw_path.clip_path() {
+            match clips.get(scene, clip, offset, canvas.bounds(), sampling) {
+                Some(clip) => mask.intersect(clip),
+                None => continue
+            }
+        }
+        let shader = Shader::new(scene.get_paint(draw_path.paint()));
+        canvas.composite(&mask, &shader, view_box.origin(), draw_path.blend_mode());
+    }
+    canvas.into_image()
+}
+
+/// Coverage of the pixels in `rect`, row by row.
+struct Mask {
+    rect: RectI,
+    cover: Vec<f32>,
+}
+impl Mask {
+    fn get(&self, p: Vector2I) -> f32 {
+        if !self.rect.contains_point(p) {
+            return 0.0;
+        }
+        let q = p - self.rect.origin();
+        self.cover[q.y() as usize * self.rect.width() as usize + q.x() as usize]
+    }
+    fn intersect(&mut self, other: &Mask) {
+        let origin = self.rect.origin();
+        let width = self.rect.width();
+        for (i, c) in self.cover.iter_mut().enumerate() {
+            let p = origin + Vector2I::new(i as i32 % width, i as i32 / width);
+            *c *= other.get(p);
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Edge {
+    // y0 < y1
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    // +1 if the edge goes down, -1 if it goes up
+    dir: i32,
+}
+impl Edge {
+    fn x_at(&self, y: f32) -> f32 {
+        self.x0 + (y - self.y0) * (self.x1 - self.x0) / (self.y1 - self.y0)
+    }
+}
+
+fn push_edge(edges: &mut Vec<Edge>, a: Vector2F, b: Vector2F) {
+    if a.y() == b.y() {
+        return;
+    }
+    let (p, q, dir) = if a.y() < b.y() { (a, b, 1) } else { (b, a, -1) };
+    edges.push(Edge { x0: p.x(), y0: p.y(), x1: q.x(), y1: q.y(), dir });
+}
+
+// number of lines for a curve with this control polygon
+fn curve_steps(points: &[Vector2F]) -> usize {
+    let len: f32 = points.windows(2).map(|w| (w[1] - w[0]).length()).sum();
+    ((len / FLATTEN_TOLERANCE).sqrt() * 0.5).ceil().max(1.0).min(MAX_CURVE_STEPS as f32) as usize
+}
+
+fn edges(outline: &Outline, offset: Vector2F) -> Vec<Edge> {
+    let mut edges = vec![];
+    for contour in outline.contours() {
+        let mut first = None;
+        let mut last = Vector2F::zero();
+        for segment in contour.iter(ContourIterFlags::IGNORE_CLOSE_SEGMENT) {
+            let from = segment.baseline.from() + offset;
+            let to = segment.baseline.to() + offset;
+            if first.is_none() {
+                first = Some(from);
+            }
+            match segment.kind {
+                SegmentKind::Quadratic => {
+                    let c = segment.ctrl.from() + offset;
+                    let n = curve_steps(&[from, c, to]);
+                    let mut prev = from;
+                    for i in 1 ..= n {
+                        let t = i as f32 / n as f32;
+                        let p = from.lerp(c, t).lerp(c.lerp(to, t), t);
+                        push_edge(&mut edges, prev, p);
+                        prev = p;
+                    }
+                }
+                SegmentKind::Cubic => {
+                    let (c1, c2) = (segment.ctrl.from() + offset, segment.ctrl.to() + offset);
+                    let n = curve_steps(&[from, c1, c2, to]);
+                    let mut prev = from;
+                    for i in 1 ..= n {
+                        let t = i as f32 / n as f32;
+                        let (a, b, c) = (from.lerp(c1, t), c1.lerp(c2, t), c2.lerp(to, t));
+                        let p = a.lerp(b, t).lerp(b.lerp(c, t), t);
+                        push_edge(&mut edges, prev, p);
+                        prev = p;
+                    }
+                }
+                _ => push_edge(&mut edges, from, to),
+            }
+            last = to;
+        }
+        // every contour is filled as if it was closed
+        if let Some(first) = first {
+            push_edge(&mut edges, last, first);
+        }
+    }
+    edges
+}
+
+#[derive(Copy, Clone)]
+struct Sampling {
+    // sub-scanlines per row
+    samples: usize,
+    antialias: bool,
+}
+impl Sampling {
+    fn new(quality: &Quality) -> Sampling {
+        match quality.antialias {
+            true => Sampling { samples: quality.samples.max(1).min(64) as usize, antialias: true },
+            false => Sampling { samples: 1, antialias: false },
+        }
+    }
+}
+
+// add `weight` times the part of the pixels in `row` between `a` and `b`
+fn add_span(row: &mut [f32], a: f32, b: f32, weight: f32) {
+    let width = row.len() as f32;
+    let (a, b) = (a.max(0.0).min(width), b.max(0.0).min(width));
+    if a >= b {
+        return;
+    }
+    let (ia, ib) = (a as usize, b as usize);
+    if ia == ib {
+        row[ia] += (b - a) * weight;
+        return;
+    }
+    row[ia] += (ia as f32 + 1.0 - a) * weight;
+    for c in &mut row[ia + 1 .. ib] {
+        *c += weight;
+    }
+    if ib < row.len() {
+        row[ib] += (b - ib as f32) * weight;
+    }
+}
+
+// set the pixels in `row` whose center is between `a` and `b`
+fn add_span_aliased(row: &mut [f32], a: f32, b: f32) {
+    let width = row.len() as f32;
+    let (ia, ib) = ((a - 0.5).ceil().max(0.0).min(width), (b - 0.5).ceil().max(0.0).min(width));
+    for c in &mut row[ia as usize .. (ib as usize).max(ia as usize)] {
+        *c = 1.0;
+    }
+}
+
+/// The coverage of `outline` (moved by `offset`) inside of `bounds`, None if nothing is covered.
+fn fill_mask(outline: &Outline, fill_rule: FillRule, offset: Vector2F, bounds: RectI, sampling: Sampling) -> Option<Mask> {
+    let rect = (outline.bounds() + offset).round_out().to_i32().intersection(bounds)?;
+    if rect.width() <= 0 || rect.height() <= 0 {
+        return None;
+    }
+    let mut edges = edges(outline, offset);
+    edges.sort_by(|a, b| a.y0.total_cmp(&b.y0));
+
+    let width = rect.width() as usize;
+    let mut cover = vec![0.0; width * rect.height() as usize];
+    let mut active: Vec<Edge> = vec![];
+    let mut next = 0;
+    let mut crossings: Vec<(f32, i32)> = vec![];
+    let weight = 1.0 / sampling.samples as f32;
+    let x_origin = rect.min_x() as f32;
+
+    for (row_nr, row) in cover.chunks_mut(width).enumerate() {
+        let y = (rect.min_y() + row_nr as i32) as f32;
+        active.retain(|e| e.y1 > y);
+        while next < edges.len() && edges[next].y0 < y + 1.0 {
+            if edges[next].y1 > y {
+                active.push(edges[next]);
+            }
+            next += 1;
+        }
+        for k in 0 .. sampling.samples {
+            let ys = y + (k as f32 + 0.5) * weight;
+            crossings.clear();
+            crossings.extend(active.iter().filter(|e| e.y0 <= ys && ys < e.y1).map(|e| (e.x_at(ys) - x_origin, e.dir)));
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = match fill_rule {
+                    FillRule::Winding => winding != 0,
+                    FillRule::EvenOdd => winding % 2 != 0,
+                };
+                if inside && sampling.antialias {
+                    add_span(row, pair[0].0, pair[1].0, weight);
+                } else if inside {
+                    add_span_aliased(row, pair[0].0, pair[1].0);
+                }
+            }
+        }
+    }
+    for c in &mut cover {
+        *c = c.min(1.0);
+    }
+    Some(Mask { rect, cover })
+}
+
+// clip paths are shared by many draw paths, so their masks are kept
+#[derive(Default)]
+struct ClipCache {
+    masks: HashMap<u32, Option<Mask>>,
+}
+impl ClipCache {
+    fn get(&mut self, scene: &Scene, id: ClipPathId, offset: Vector2F, bounds: RectI, sampling: Sampling) -> Option<&Mask> {
+        if !self.masks.contains_key(&id.0) {
+            let clip = scene.get_clip_path(id);
+            let mut mask = fill_mask(clip.outline(), clip.fill_rule(), offset, bounds, sampling);
+            if let (Some(m), Some(parent)) = (mask.as_mut(), clip.clip_path()) {
+                match self.get(scene, parent, offset, bounds, sampling) {
+                    Some(parent) => m.intersect(parent),
+                    None => mask = None,
+                }
+            }
+            self.masks.insert(id.0, mask);
+        }
+        self.masks[&id.0].as_ref()
+    }
+}
+
+// premultiplied RGBA
+type Rgba = [f32; 4];
+
+fn premultiplied(c: ColorU) -> Rgba {
+    let c = c.to_f32();
+    [c.r() * c.a(), c.g() * c.a(), c.b() * c.a(), c.a()]
+}
+fn scale(c: Rgba, f: f32) -> Rgba {
+    c.map(|v| v * f)
+}
+fn modulate(c: Rgba, base: Rgba) -> Rgba {
+    [c[0] * base[0], c[1] * base[1], c[2] * base[2], c[3] * base[3]]
+}
+fn lerp(a: Rgba, b: Rgba, t: f32) -> Rgba {
+    [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+/// The color of a paint at each point.
+enum Shader<'a> {
+    Solid(Rgba),
+    Gradient(&'a Gradient, Rgba),
+    Pattern(&'a Pattern, Rgba),
+}
+impl<'a> Shader<'a> {
+    fn new(paint: &'a Paint) -> Self {
+        // gradients and patterns are multiplied with the base color, which carries the opacity
+        let base = premultiplied(paint.base_color());
+        match paint.overlay().as_ref().map(|o| o.contents()) {
+            Some(PaintContents::Gradient(g)) => Shader::Gradient(g, base),
+            Some(PaintContents::Pattern(p)) => Shader::Pattern(p, base),
+            None => Shader::Solid(base),
+        }
+    }
+    fn color_at(&self, p: Vector2F) -> Rgba {
+        match *self {
+            Shader::Solid(c) => c,
+            Shader::Gradient(g, base) => match gradient_t(g, p) {
+                Some(t) => modulate(sample_gradient(g, t), base),
+                None => [0.0; 4],
+            }
+            Shader::Pattern(pattern, base) => modulate(sample_pattern(pattern, p), base),
+        }
+    }
+}
+
+fn gradient_t(g: &Gradient, p: Vector2F) -> Option<f32> {
+    let t = match g.geometry {
+        GradientGeometry::Linear(line) => {
+            let d = line.vector();
+            let len2 = d.square_length();
+            if len2 == 0.0 {
+                return None;
+            }
+            (p - line.from()).dot(d) / len2
+        }
+        GradientGeometry::Radial { line, radii, transform } => {
+            // the largest t with |q - c(t)| = r(t) and r(t) >= 0, where the circles go from
+            // (line.from(), radii.x()) to (line.to(), radii.y())
+            let q = transform.inverse() * p;
+            let (r0, dr) = (radii.x(), radii.y() - radii.x());
+            let dc = line.vector();
+            let pd = q - line.from();
+            let a = dc.square_length() - dr * dr;
+            let b = pd.dot(dc) + r0 * dr;
+            let c = pd.square_length() - r0 * r0;
+            let valid = |t: f32| r0 + t * dr >= 0.0;
+            if a.abs() < 1e-6 {
+                if b == 0.0 {
+                    return None;
+                }
+                let t = c / (2.0 * b);
+                if !valid(t) {
+                    return None;
+                }
+                t
+            } else {
+                let disc = b * b - a * c;
+                if disc < 0.0 {
+                    return None;
+                }
+                let s = disc.sqrt();
+                let (t1, t2) = ((b + s) / a, (b - s) / a);
+                let (hi, lo) = (t1.max(t2), t1.min(t2));
+                if valid(hi) {
+                    hi
+                } else if valid(lo) {
+                    lo
+                } else {
+                    return None;
+                }
+            }
+        }
+    };
+    Some(match g.wrap {
+        GradientWrap::Clamp => t.max(0.0).min(1.0),
+        GradientWrap::Repeat => t - t.floor(),
+    })
+}
+
+fn sample_gradient(g: &Gradient, t: f32) -> Rgba {
+    let stops = g.stops();
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(f), Some(l)) => (f, l),
+        _ => return [0.0; 4]
+    };
+    if t <= first.offset {
+        return premultiplied(first.color);
+    }
+    for w in stops.windows(2) {
+        if t <= w[1].offset {
+            let span = w[1].offset - w[0].offset;
+            let f = if span > 0.0 { (t - w[0].offset) / span } else { 1.0 };
+            return lerp(premultiplied(w[0].color), premultiplied(w[1].color), f);
+        }
+    }
+    premultiplied(last.color)
+}
+
+fn sample_pattern(pattern: &Pattern, p: Vector2F) -> Rgba {
+    let image = match *pattern.source() {
+        PatternSource::Image(ref image) => image,
+        PatternSource::RenderTarget { .. } => return [0.0; 4],
+    };
+    let size = image.size();
+    if size.x() <= 0 || size.y() <= 0 {
+        return [0.0; 4];
+    }
+    let pixels = image.pixels();
+    let q = pattern.transform().inverse() * p;
+    // None outside of a pattern that does not repeat
+    let wrap = |v: i32, len: i32, repeat: bool| if repeat {
+        Some(v.rem_euclid(len))
+    } else if (0 .. len).contains(&v) {
+        Some(v)
+    } else {
+        None
+    };
+    let texel = |x: i32, y: i32| -> Rgba {
+        match (wrap(x, size.x(), pattern.repeat_x()), wrap(y, size.y(), pattern.repeat_y())) {
+            (Some(x), Some(y)) => premultiplied(pixels[(y * size.x() + x) as usize]),
+            _ => [0.0; 4]
+        }
+    };
+    if !pattern.smoothing_enabled() {
+        return texel(q.x().floor() as i32, q.y().floor() as i32);
+    }
+    // bilinear, between the centers of the texels
+    let (x, y) = (q.x() - 0.5, q.y() - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let top = lerp(texel(x0, y0), texel(x0 + 1, y0), fx);
+    let bottom = lerp(texel(x0, y0 + 1), texel(x0 + 1, y0 + 1), fx);
+    lerp(top, bottom, fy)
+}
+
+// the separable blend function B(backdrop, source) of unpremultiplied channels
+fn blend_channel(mode: BlendMode, b: f32, s: f32) -> f32 {
+    match mode {
+        BlendMode::Multiply => b * s,
+        BlendMode::Screen => b + s - b * s,
+        BlendMode::Darken => b.min(s),
+        BlendMode::Lighten => b.max(s),
+        BlendMode::Difference => (b - s).abs(),
+        BlendMode::Exclusion => b + s - 2.0 * b * s,
+        // HardLight with backdrop and source swapped
+        BlendMode::Overlay => if b <= 0.5 { s * 2.0 * b } else { s + (2.0 * b - 1.0) - s * (2.0 * b - 1.0) },
+        BlendMode::HardLight => if s <= 0.5 { b * 2.0 * s } else { b + (2.0 * s - 1.0) - b * (2.0 * s - 1.0) },
+        BlendMode::ColorDodge => if b == 0.0 { 0.0 } else if s >= 1.0 { 1.0 } else { (b / (1.0 - s)).min(1.0) },
+        BlendMode::ColorBurn => if b >= 1.0 { 1.0 } else if s <= 0.0 { 0.0 } else { 1.0 - ((1.0 - b) / s).min(1.0) },
+        _ => s,
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+// apply `f` to the color channels of a premultiplied color
+fn map_channels(c: Rgba, f: impl Fn(f32) -> f32) -> Rgba {
+    let a = c[3];
+    if a <= 0.0 {
+        return [0.0; 4];
+    }
+    [f(c[0] / a) * a, f(c[1] / a) * a, f(c[2] / a) * a, a]
+}
+
+struct Canvas {
+    size: Vector2I,
+    pixels: Vec<Rgba>,
+    // the pixels are in linear light, not sRGB
+    linear: bool,
+}
+impl Canvas {
+    fn new(size: Vector2I, background: Option<ColorF>, linear: bool) -> Canvas {
+        let mut fill = match background {
+            Some(c) => [c.r() * c.a(), c.g() * c.a(), c.b() * c.a(), c.a()],
+            None => [0.0; 4],
+        };
+        if linear {
+            fill = map_channels(fill, srgb_to_linear);
+        }
+        Canvas { size, pixels: vec![fill; size.x() as usize * size.y() as usize], linear }
+    }
+    fn bounds(&self) -> RectI {
+        RectI::new(Vector2I::zero(), self.size)
+    }
+    // `origin` is the origin of the view box in scene space
+    fn composite(&mut self, mask: &Mask, shader: &Shader, origin: Vector2F, mode: BlendMode) {
+        let rect = mask.rect;
+        let width = rect.width() as usize;
+        for (i, &coverage) in mask.cover.iter().enumerate() {
+            if coverage <= 0.0 {
+                continue;
+            }
+            let p = rect.origin() + Vector2I::new((i % width) as i32, (i / width) as i32);
+            let center = p.to_f32() + Vector2F::splat(0.5) + origin;
+            let mut color = shader.color_at(center);
+            if self.linear {
+                color = map_channels(color, srgb_to_linear);
+            }
+            let src = scale(color, coverage);
+            let dst = &mut self.pixels[p.y() as usize * self.size.x() as usize + p.x() as usize];
+            *dst = blend(mode, src, *dst);
+        }
+    }
+    fn into_image(self) -> RgbaImage {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        let linear = self.linear;
+        for mut pixel in self.pixels {
+            if linear {
+                pixel = map_channels(pixel, linear_to_srgb);
+            }
+            let [r, g, b, a] = pixel;
+            let unmul = |v: f32| if a > 0.0 { v / a } else { 0.0 };
+            let c = ColorF::new(unmul(r), unmul(g), unmul(b), a).to_u8();
+            data.extend_from_slice(&[c.r, c.g, c.b, c.a]);
+        }
+        RgbaImage::from_raw(self.size.x() as u32, self.size.y() as u32, data).unwrap()
+    }
+}
+
+fn blend(mode: BlendMode, src: Rgba, dst: Rgba) -> Rgba {
+    let (sa, da) = (src[3], dst[3]);
+    match mode {
+        BlendMode::SrcOver => [0, 1, 2, 3].map(|i| src[i] + dst[i] * (1.0 - sa)),
+        _ => {
+            let mut out = [0.0; 4];
+            for i in 0 .. 3 {
+                let (s, d) = (src[i], dst[i]);
+                let b = if sa > 0.0 && da > 0.0 { blend_channel(mode, d / da, s / sa) } else { 0.0 };
+                out[i] = (1.0 - da) * s + (1.0 - sa) * d + sa * da * b;
+            }
+            out[3] = sa + da - sa * da;
+            out
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay() {
+        // multiplies dark backdrops, screens light ones, and a source of 0.5 changes nothing
+        assert_eq!(blend_channel(BlendMode::Overlay, 0.25, 1.0), 0.5);
+        assert_eq!(blend_channel(BlendMode::Overlay, 0.75, 0.0), 0.5);
+        assert_eq!(blend_channel(BlendMode::Overlay, 0.25, 0.5), 0.25);
+        assert_eq!(blend_channel(BlendMode::Overlay, 0.75, 0.5), 0.75);
+
+        let dst = [0.25, 0.75, 0.0, 1.0];
+        let src = [1.0, 0.0, 0.5, 1.0];
+        assert_eq!(blend(BlendMode::Overlay, src, dst), [0.5, 0.5, 0.0, 1.0]);
+        assert_eq!(blend(BlendMode::SrcOver, src, dst), src);
+    }
+}
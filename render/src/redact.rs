@@ -0,0 +1,131 @@
+//! Applying /Redact annotations while rendering, see `RenderOptions::apply_redactions`.
+
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F, vector::Vector2F};
+use pdf::object::{Page, Resolve, Annot};
+use pdf::primitive::Primitive;
+use pdf::error::PdfError;
+use crate::{BBox, Fill, TextChar};
+use crate::textstate::Span;
+
+/// A region of a /Redact annotation in output space.
+pub struct Redaction {
+    pub rect: RectF,
+    /// the /IC of the annotation, black if it has none
+    pub color: Fill,
+}
+
+fn numbers(p: &Primitive, resolve: &impl Resolve) -> Result<Vec<f32>, PdfError> {
+    p.clone().resolve(resolve)?.as_array()?.iter().map(|n| n.as_number()).collect()
+}
+
+/// The regions of all /Redact annotations on the page, one for each quad of /QuadPoints, or the /Rect.
+pub fn page_redactions(page: &Page, resolve: &impl Resolve, transform: Transform2F) -> Result<Vec<Redaction>, PdfError> {
+    let mut redactions = vec![];
+    for annot in page.annotations.load(resolve)?.iter() {
+        let annot: &Annot = &annot;
+        if annot.subtype.as_str() != "Redact" {
+            continue;
+        }
+        let color = match annot.other.get("IC").map(|p| numbers(p, resolve)).transpose()?.as_deref() {
+            Some(&[g]) => Fill::Solid(g, g, g),
+            Some(&[r, g, b]) => Fill::Solid(r, g, b),
+            _ => Fill::black(),
+        };
+        let quads = match annot.other.get("QuadPoints") {
+            Some(p) => numbers(p, resolve)?,
+            None => vec![]
+        };
+        if quads.len() >= 8 {
+            for q in quads.chunks_exact(8) {
+                let points = q.chunks_exact(2).map(|p| Vector2F::new(p[0], p[1]));
+                let (min, max) = points.fold((Vector2F::splat(f32::INFINITY), Vector2F::splat(f32::NEG_INFINITY)), |(min, max), p| (min.min(p), max.max(p)));
+                redactions.push(Redaction { rect: transform * RectF::from_points(min, max), color });
+            }
+        } else if let Some(r) = annot.rect {
+            let rect = RectF::from_points(Vector2F::new(r.left, r.bottom), Vector2F::new(r.right, r.top));
+            redactions.push(Redaction { rect: transform * rect, color });
+        }
+    }
+    Ok(redactions)
+}
+
+/// Remove the characters of `span` that touch one of `rects`.
+/// `transform` and `font_size` are those of the `TextSpan` the span becomes.
+/// Returns true if anything was removed.
+pub fn redact_span(span: &mut Span, transform: Transform2F, font_size: f32, rects: &[RectF]) -> bool {
+    let hit = |r: RectF| rects.iter().any(|&redacted| redacted.intersects(r));
+    let ends = span.chars.iter().skip(1).map(|c| c.offset).chain(std::iter::once(span.text.len()));
+
+    let mut text = String::new();
+    let mut chars = vec![];
+    let mut bbox = BBox::empty();
+    let mut removed = false;
+    for (c, end) in span.chars.iter().zip(ends) {
+        let (a, b) = (Vector2F::new(c.pos, -font_size), Vector2F::new(c.pos + c.width, 0.0));
+        let rect = transform * RectF::from_points(a.min(b), a.max(b));
+        if hit(rect) || c.bbox.map_or(false, hit) {
+            removed = true;
+            continue;
+        }
+        chars.push(TextChar { offset: text.len(), ..*c });
+        text.push_str(&span.text[c.offset..end]);
+        if let Some(r) = c.bbox {
+            bbox.add(r);
+        }
+    }
+    if removed {
+        span.text = text;
+        span.chars = chars;
+        span.bbox = bbox;
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // chars 10 units wide, starting at 0
+    fn span(text: &str) -> Span {
+        let chars: Vec<TextChar> = text.char_indices().enumerate()
+            .map(|(i, (offset, _))| TextChar { offset, pos: 10.0 * i as f32, width: 10.0, bbox: None })
+            .collect();
+        Span { text: text.into(), width: 10.0 * chars.len() as f32, chars, bbox: BBox::empty() }
+    }
+
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> RectF {
+        RectF::new(Vector2F::new(x, y), Vector2F::new(w, h))
+    }
+
+    #[test]
+    fn removes_hit_chars() {
+        // "é" takes two bytes, the offsets of the remaining chars have to be updated
+        let mut s = span("aébc");
+        assert!(redact_span(&mut s, Transform2F::default(), 10.0, &[rect(12.0, -5.0, 15.0, 2.0)]));
+        assert_eq!(s.text, "ac");
+        let chars: Vec<(usize, f32)> = s.chars.iter().map(|c| (c.offset, c.pos)).collect();
+        assert_eq!(chars, [(0, 0.0), (1, 30.0)]);
+    }
+
+    #[test]
+    fn transform() {
+        // the span is 100 units to the right and the chars are below the baseline in output space
+        let transform = Transform2F::from_translation(Vector2F::new(100.0, 0.0));
+        let mut s = span("abc");
+        assert!(!redact_span(&mut s, transform, 10.0, &[rect(12.0, -5.0, 5.0, 2.0)]));
+        assert_eq!(s.text, "abc");
+        assert!(!redact_span(&mut s, transform, 10.0, &[rect(112.0, 1.0, 5.0, 2.0)]));
+        assert!(redact_span(&mut s, transform, 10.0, &[rect(112.0, -5.0, 5.0, 2.0)]));
+        assert_eq!(s.text, "ac");
+    }
+
+    #[test]
+    fn glyph_bbox() {
+        // a glyph that extends far beyond its advance
+        let mut s = span("ab");
+        s.chars[1].bbox = Some(rect(10.0, -50.0, 10.0, 50.0));
+        assert!(redact_span(&mut s, Transform2F::default(), 10.0, &[rect(12.0, -40.0, 2.0, 2.0)]));
+        assert_eq!(s.text, "a");
+        assert_eq!(s.bbox.rect(), None);
+    }
+}
@@ -2,10 +2,12 @@ use pathfinder_content::outline::ContourIterFlags;
 use pathfinder_renderer::scene::ClipPath;
 use pdf::object::*;
 use pdf::primitive::{Primitive, Dictionary};
-use pdf::content::{Op, Matrix, Point, Rect, Color, Rgb, Cmyk, Winding, FormXObject};
+use pdf::content::{self, Op, Matrix, Point, Rect, Color, Rgb, Cmyk, Winding, FormXObject, TextMode};
 use pdf::error::{PdfError, Result};
+use pdf::function::Function;
+use pdf::font::Font as PdfFont;
 use pdf::content::TextDrawAdjusted;
-use crate::backend::{Backend, BlendMode, Stroke, FillMode};
+use crate::backend::{Backend, BlendMode, Stroke, MarkedContent};
 
 use pathfinder_geometry::{
     vector::Vector2F,
@@ -18,11 +20,29 @@ use pathfinder_content::{
 };
 use super::{
     graphicsstate::GraphicsState,
-    textstate::{TextState, Span},
+    textstate::{TextState, Span, KnockoutGroup},
     DrawMode,
     TextSpan,
     Fill,
+    Script,
+    RenderOptions,
+    BBox,
+    SoftMask,
+    Transfer,
+    RenderDiagnostics,
+    DiagnosticKind,
+    Feature,
+    FontEntry,
+    Limit,
+    PartialRender,
+    Strictness,
+    function::FunctionLuts,
+    redact::redact_span,
+    shading,
 };
+use std::sync::Arc;
+use std::borrow::Cow;
+use instant::Instant;
 
 trait Cvt {
     type Out;
@@ -83,17 +103,82 @@ pub struct RenderState<'a, R: Resolve, B: Backend> {
     resolve: &'a R,
     resources: &'a Resources,
     backend: &'a mut B,
+    options: &'a RenderOptions,
+    // one entry per open marked content sequence, true if it hides its content
+    marked_content: Vec<bool>,
+    // union of everything drawn so far
+    bbox: BBox,
+    // tint transforms of the color spaces in `resources`
+    functions: FunctionLuts,
+    // glyphs of the current text object that are drawn together at its end
+    knockout: KnockoutGroup<B>,
+    diagnostics: RenderDiagnostics,
+    // the op that is being drawn
+    op_nr: usize,
+    budget: Budget,
+    // set when a limit is reached, no more ops are drawn after that
+    partial: Option<PartialRender>,
+    // points in the current path, and whether it has too many of them
+    path_points: usize,
+    path_overflow: bool,
+    // the state at the start of the content stream, restored by a Q without q
+    root: (GraphicsState<'a, B>, TextState),
+    // between BT and ET
+    in_text: bool,
+    // effective font size of the last text without rise, which raised and lowered text is compared to
+    line_font_size: f32,
+    // output space regions of /Redact annotations, text in them is not passed to `add_text`
+    redactions: &'a [RectF],
+}
+
+/// How much of `RenderOptions::limits` is used up, shared by nested content streams
+/// and the content of the patterns they use.
+#[derive(Debug, Copy, Clone)]
+pub struct Budget {
+    ops: usize,
+    form_depth: usize,
+    clip_paths: usize,
+    // only read when there is a time budget
+    start: Option<Instant>,
+}
+impl Budget {
+    pub fn new(options: &RenderOptions) -> Budget {
+        Budget {
+            ops: 0,
+            form_depth: 0,
+            clip_paths: 0,
+            start: options.limits.time_budget.map(|_| Instant::now()),
+        }
+    }
+    fn exceeded(&self, options: &RenderOptions) -> Option<Limit> {
+        let limits = &options.limits;
+        if limits.max_ops.map_or(false, |max| self.ops >= max) {
+            return Some(Limit::Ops);
+        }
+        // reading the clock for every op would be too slow
+        if self.ops % 64 == 0 {
+            if let (Some(budget), Some(start)) = (limits.time_budget, self.start) {
+                if start.elapsed() > budget {
+                    return Some(Limit::Time);
+                }
+            }
+        }
+        None
+    }
 }
 
 impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
-    pub fn new(backend: &'a mut B, resolve: &'a R, resources: &'a Resources, root_transformation: Transform2F) -> Self {
+    pub fn new(backend: &'a mut B, resolve: &'a R, resources: &'a Resources, root_transformation: Transform2F, options: &'a RenderOptions) -> Self {
+        let black = filter_color(options, Fill::black());
+        let function_cache = backend.function_cache().unwrap_or_default();
         let graphics_state = GraphicsState {
             transform: root_transformation,
-            fill_color: Fill::black(),
+            pattern_transform: root_transformation,
+            fill_color: black,
             fill_color_alpha: 1.0,
             fill_paint: None,
             fill_alpha: 1.0,
-            stroke_color: Fill::black(),
+            stroke_color: black,
             stroke_color_alpha: 1.0,
             stroke_paint: None,
             stroke_alpha: 1.0,
@@ -104,15 +189,19 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             stroke_color_space: &ColorSpace::DeviceRGB,
             stroke_style: StrokeStyle {
                 line_cap: LineCap::Butt,
-                line_join: LineJoin::Miter(1.0),
+                line_join: LineJoin::Miter(10.0),
                 line_width: 1.0,
             },
+            miter_limit: 10.0,
             dash_pattern: None,
             overprint_fill: false,
             overprint_stroke: false,
             overprint_mode: 0,
+            soft_mask: None,
+            transfer: None,
         };
         let text_state = TextState::new();
+        let root = (graphics_state.clone(), text_state.clone());
         let stack = vec![];
         let current_outline = Outline::new();
         let current_contour = Contour::new();
@@ -126,79 +215,284 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             resources,
             resolve,
             backend,
+            options,
+            marked_content: vec![],
+            bbox: BBox::empty(),
+            functions: FunctionLuts::new(function_cache),
+            knockout: KnockoutGroup::new(),
+            diagnostics: RenderDiagnostics::new(),
+            op_nr: 0,
+            budget: Budget::new(options),
+            partial: None,
+            path_points: 0,
+            path_overflow: false,
+            root,
+            in_text: false,
+            line_font_size: 0.0,
+            redactions: &[],
         }
     }
+    /// Clip everything to `rect` (in output space), which `id` is the clip path of.
+    /// A Q without q restores this clip.
+    pub fn set_clip(&mut self, id: B::ClipPathId, rect: RectF) {
+        self.graphics_state.clip_path_id = Some(id);
+        self.graphics_state.clip_path = Some(ClipPath::new(Outline::from_rect(rect)));
+        self.graphics_state.clip_path_rect = Some(rect);
+        self.root.0 = self.graphics_state.clone();
+    }
+    /// Text in these regions (in output space) is left out of `Backend::add_text`, including that of forms.
+    pub fn set_redactions(&mut self, rects: &'a [RectF]) {
+        self.redactions = rects;
+    }
+    /// End the content stream: closes an open text object and reports unbalanced q.
+    pub fn finish(&mut self) {
+        if self.in_text {
+            self.report(DiagnosticKind::UnclosedText);
+            self.in_text = false;
+        }
+        self.knockout.flush(self.backend);
+        if !self.stack.is_empty() {
+            self.report(DiagnosticKind::UnclosedSave { depth: self.stack.len() });
+        }
+        for _ in self.marked_content.drain(..) {
+            self.backend.end_marked_content();
+        }
+    }
+    /// bounds of everything that was drawn, in output space
+    pub fn bbox(&self) -> BBox {
+        self.bbox
+    }
+    /// Some once a limit of `RenderOptions::limits` was reached. Further ops are ignored then.
+    pub fn partial(&self) -> Option<PartialRender> {
+        self.partial
+    }
+    /// Continue with the budget of the content stream this one is nested in, one level deeper.
+    pub fn nest_in(&mut self, budget: Budget) {
+        self.budget = Budget { form_depth: budget.form_depth + 1, .. budget };
+    }
+    /// The budget after what was drawn so far, `budget` at the depth this state was nested in.
+    pub fn return_budget(&self, budget: &mut Budget) {
+        *budget = Budget { form_depth: budget.form_depth, .. self.budget };
+    }
+    /// Take the diagnostics collected so far.
+    pub fn take_diagnostics(&mut self) -> RenderDiagnostics {
+        std::mem::take(&mut self.diagnostics)
+    }
+    fn report(&mut self, kind: DiagnosticKind) {
+        self.diagnostics.push(self.op_nr, kind);
+    }
+    fn report_font(&mut self, name: &str, entry: Option<&FontEntry>) {
+        match entry {
+            None => self.report(DiagnosticKind::MissingFont { name: name.into() }),
+            Some(e) if e.substituted => self.report(DiagnosticKind::SubstitutedFont { name: name.into() }),
+            Some(_) => {}
+        }
+    }
+    // Load the font with the backend, if it wants fonts.
+    // Without a font, text is only measured with the /Widths of the PDF font.
+    fn set_font(&mut self, font: Option<&MaybeRef<PdfFont>>, name: &str, size: f32) -> Result<()> {
+        let load = self.backend.load_fonts();
+        let entry = match font {
+            Some(font) if load => self.backend.get_font(font, self.resolve)?,
+            _ => None
+        };
+        match entry {
+            Some(ref e) => debug!("new font: {} (is_cid={:?}) at size {}", e.name, e.is_cid, size),
+            None => info!("no font {}", name),
+        }
+        if load {
+            self.report_font(name, entry.as_deref());
+        }
+        let widths = match font {
+            Some(font) if entry.is_none() => match font.widths(self.resolve) {
+                Ok(widths) => widths.map(Arc::new),
+                Err(e) => {
+                    debug!("can't read the widths of {}: {:?}", name, e);
+                    None
+                }
+            },
+            _ => None
+        };
+        self.text_state.set_font(font.map(|f| &**f), entry, widths, size);
+        Ok(())
+    }
+    // counts the points of the current path, false once there are more than `Limits::max_outline_points`
+    fn grow_path(&mut self, points: usize) -> bool {
+        self.path_points += points;
+        match self.options.limits.max_outline_points {
+            Some(max) if self.path_points > max => {
+                if !self.path_overflow {
+                    self.report(DiagnosticKind::TooLarge { what: "path", size: self.path_points, limit: max });
+                    self.path_overflow = true;
+                    self.current_contour.clear();
+                    self.current_outline.clear();
+                }
+                false
+            }
+            _ => true
+        }
+    }
+    fn reset_path_points(&mut self) {
+        self.path_points = 0;
+        self.path_overflow = false;
+    }
+    // checks the size of an image and its soft mask against `Limits::max_image_pixels`
+    fn image_too_large(&mut self, im: &ImageXObject) -> bool {
+        let max = match self.options.limits.max_image_pixels {
+            Some(max) => max,
+            None => return false
+        };
+        let mut pixels = im.width as usize * im.height as usize;
+        if let Some(Ok(mask)) = im.smask.map(|r| self.resolve.get(r)) {
+            pixels = pixels.max(mask.width as usize * mask.height as usize);
+        }
+        if pixels > max {
+            self.report(DiagnosticKind::TooLarge { what: "image", size: pixels, limit: max });
+            return true;
+        }
+        false
+    }
+    fn hidden(&self) -> bool {
+        self.marked_content.iter().any(|&hidden| hidden)
+    }
+    fn begin_marked_content(&mut self, tag: &str, properties: Option<&Primitive>) {
+        let dict = match properties.map(|p| self.get_properties(p)) {
+            Some(Ok(dict)) => Some(dict),
+            Some(Err(e)) => {
+                debug!("can't resolve marked content properties: {:?}", e);
+                None
+            }
+            None => None
+        };
+        let mcid = dict.and_then(|d| d.get("MCID")).and_then(|n| n.as_integer().ok());
+        let layer = match dict {
+            Some(d) if tag == "OC" => d.get("Name")
+                .and_then(|n| n.as_string().ok())
+                .map(|s| s.to_string_lossy()),
+            _ => None
+        };
+        let hidden = layer.as_ref().map_or(false, |name| self.options.hidden_layers.iter().any(|l| l == name));
+        self.backend.begin_marked_content(&MarkedContent { tag: tag.into(), mcid, layer });
+        self.marked_content.push(hidden);
+    }
     fn draw(&mut self, mode: &DrawMode, fill_rule: FillRule) {
         self.flush();
-        self.backend.draw(&self.current_outline, mode, fill_rule, self.graphics_state.transform, self.graphics_state.clip_path_id);
-        self.current_outline.clear();
+        let overflow = self.path_overflow;
+        self.reset_path_points();
+        if self.hidden() || overflow {
+            self.current_outline.clear();
+            return;
+        }
+        if !self.current_outline.is_empty() {
+            let bounds = match mode {
+                DrawMode::Fill { .. } => self.current_outline.bounds(),
+                DrawMode::Stroke { stroke_mode, .. } | DrawMode::FillStroke { stroke_mode, .. } => self.current_outline.bounds().dilate(0.5 * stroke_mode.style.line_width),
+            };
+            self.bbox.add(self.graphics_state.transform * bounds);
+        }
+        let outline = std::mem::replace(&mut self.current_outline, Outline::new());
+        self.backend.draw(Cow::Owned(outline), mode, fill_rule, self.graphics_state.transform, self.graphics_state.clip_path_id);
     }
-    #[allow(unused_variables)]
     pub fn draw_op(&mut self, op: &'a Op, op_nr: usize) -> Result<()> {
         self.backend.inspect_op(op);
         self.backend.bug_op(op_nr);
+        self.op_nr = op_nr;
+        if self.partial.is_some() {
+            return Ok(());
+        }
+        if let Some(limit) = self.budget.exceeded(self.options) {
+            warn!("{:?} limit reached at op {}, stopping", limit, op_nr);
+            self.partial = Some(PartialRender { limit, op_nr });
+            return Ok(());
+        }
+        self.budget.ops += 1;
+        match self.draw_op_inner(op, op_nr) {
+            Err(e) if self.options.strictness.recover_all() => {
+                warn!("skipping op {} ({:?}): {:?}", op_nr, op, e);
+                self.report(DiagnosticKind::from_error(&e, Feature::Other, |error| DiagnosticKind::SkippedOp { error }));
+                Ok(())
+            }
+            result => result
+        }
+    }
+    #[allow(unused_variables)]
+    fn draw_op_inner(&mut self, op: &'a Op, op_nr: usize) -> Result<()> {
         match *op {
-            Op::BeginMarkedContent { .. } => {}
-            Op::EndMarkedContent { .. } => {}
+            Op::BeginMarkedContent { ref tag, ref properties } => {
+                self.begin_marked_content(tag.as_str(), properties.as_ref());
+            }
+            Op::EndMarkedContent { .. } => {
+                // an EMC without BMC must not end the sequences around a form
+                if self.marked_content.pop().is_some() {
+                    self.backend.end_marked_content();
+                }
+            }
             Op::MarkedContentPoint { .. } => {}
             Op::Close => {
                 self.current_contour.close();
             }
-            Op::MoveTo { p } => {
+            Op::MoveTo { p } => if self.grow_path(1) {
                 self.flush();
                 self.current_contour.push_endpoint(p.cvt());
             },
-            Op::LineTo { p } => {
+            Op::LineTo { p } => if self.grow_path(1) {
                 self.current_contour.push_endpoint(p.cvt());
             },
-            Op::CurveTo { c1, c2, p } => {
+            Op::CurveTo { c1, c2, p } => if self.grow_path(3) {
                 self.current_contour.push_cubic(c1.cvt(), c2.cvt(), p.cvt());
             },
-            Op::Rect { rect } => {
+            Op::Rect { rect } => if self.grow_path(4) {
                 self.flush();
                 self.current_outline.push_contour(Contour::from_rect(rect.cvt()));
             },
             Op::EndPath => {
                 self.current_contour.clear();
                 self.current_outline.clear();
+                self.reset_path_points();
             }
             Op::Stroke => {
                 self.draw(&DrawMode::Stroke { 
-                    stroke: FillMode {
-                        color: self.graphics_state.stroke_color,
-                        alpha: self.graphics_state.stroke_color_alpha,
-                        mode: self.blend_mode_stroke(),
-                    },
-                    stroke_mode: self.graphics_state.stroke()},
+                    stroke: self.graphics_state.stroke_fill_mode(self.blend_mode_stroke()),
+                    stroke_mode: self.stroke()},
                     FillRule::Winding
                 );
             },
-            Op::FillAndStroke { winding } => {
+            Op::FillAndStroke { winding } => if let Some(pattern) = self.shading_pattern(self.graphics_state.fill_color)? {
+                // the stroke needs the path again
+                self.flush();
+                let (outline, overflow) = (self.current_outline.clone(), self.path_overflow);
+                self.fill_shading(&pattern, winding.cvt())?;
+                self.current_outline = outline;
+                self.path_overflow = overflow;
+                self.draw(&DrawMode::Stroke {
+                    stroke: self.graphics_state.stroke_fill_mode(self.blend_mode_stroke()),
+                    stroke_mode: self.stroke()},
+                    FillRule::Winding
+                );
+            } else {
                 self.draw(&DrawMode::FillStroke {
-                    fill: FillMode {
-                        color: self.graphics_state.fill_color,
-                        alpha: self.graphics_state.fill_color_alpha,
-                        mode: self.blend_mode_fill(),
-                    },
-                    stroke: FillMode {
-                        color: self.graphics_state.stroke_color,
-                        alpha: self.graphics_state.stroke_color_alpha,
-                        mode: self.blend_mode_stroke()
-                    },
-                    stroke_mode: self.graphics_state.stroke()
+                    fill: self.graphics_state.fill_mode(self.blend_mode_fill()),
+                    stroke: self.graphics_state.stroke_fill_mode(self.blend_mode_stroke()),
+                    stroke_mode: self.stroke()
                 }, winding.cvt());
             }
-            Op::Fill { winding } => {
+            Op::Fill { winding } => if let Some(pattern) = self.shading_pattern(self.graphics_state.fill_color)? {
+                self.fill_shading(&pattern, winding.cvt())?;
+            } else {
                 self.draw(&DrawMode::Fill {
-                    fill: FillMode {
-                        color: self.graphics_state.fill_color,
-                        alpha: self.graphics_state.fill_color_alpha,
-                        mode: self.blend_mode_fill(),
-                    },
+                    fill: self.graphics_state.fill_mode(self.blend_mode_fill()),
             }, winding.cvt());
             }
-            Op::Shade { ref name } => {},
+            Op::Shade { .. } if self.hidden() => {}
+            Op::Shade { ref name } => self.draw_shading(name.as_str())?,
+            Op::Clip { .. } if self.path_overflow => {}
             Op::Clip { winding } => {
+                if self.options.limits.max_clip_paths.map_or(false, |max| self.budget.clip_paths >= max) {
+                    warn!("too many clip paths at op {}, stopping", op_nr);
+                    self.partial = Some(PartialRender { limit: Limit::ClipPaths, op_nr });
+                    return Ok(());
+                }
+                self.budget.clip_paths += 1;
                 self.flush();
                 let mut path = self.current_outline.clone().transformed(&self.graphics_state.transform);
                 let clip_path_rect = to_rect(&path);
@@ -213,7 +507,7 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                         (path, None, None)
                     }
                     (None, Some(r), Some(p)) => {
-                        let mut path = self.graphics_state.clip_path.as_ref().unwrap().outline.clone();
+                        let mut path = self.graphics_state.clip_path.as_ref().map(|c| c.outline.clone()).unwrap_or_else(Outline::new);
                         path.clip_against_polygon(&[r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]);
                         (path, None, None)
                     }
@@ -237,7 +531,19 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 self.stack.push((self.graphics_state.clone(), self.text_state.clone()));
             },
             Op::Restore => {
-                let (g, t) = self.stack.pop().ok_or_else(|| pdf::error::PdfError::Other { msg: "graphcs stack is empty".into() })?;
+                let (g, t) = match self.stack.pop() {
+                    Some(state) => state,
+                    None if self.options.strictness == Strictness::Strict => {
+                        return Err(PdfError::Other { msg: "graphics stack is empty".into() });
+                    }
+                    None => {
+                        warn!("Q without q at op {}, restoring the initial state", op_nr);
+                        self.report(DiagnosticKind::ExtraRestore);
+                        self.root.clone()
+                    }
+                };
+                self.set_soft_mask(g.soft_mask.clone());
+                self.set_transfer(g.transfer.clone());
                 self.graphics_state = g;
                 self.text_state = t;
             },
@@ -246,10 +552,16 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 self.graphics_state.transform = self.graphics_state.transform * matrix.cvt();
             }
             Op::LineWidth { width } => self.graphics_state.stroke_style.line_width = width,
-            Op::Dash { ref pattern, phase } => self.graphics_state.dash_pattern = Some((&*pattern, phase)),
-            Op::LineJoin { join } => {},
-            Op::LineCap { cap } => {},
-            Op::MiterLimit { limit } => {},
+            Op::Dash { ref pattern, phase } => self.set_dash(Cow::Borrowed(&pattern[..]), phase),
+            Op::LineJoin { join } => self.set_line_join(join),
+            Op::LineCap { cap } => {
+                self.graphics_state.stroke_style.line_cap = match cap {
+                    content::LineCap::Butt => LineCap::Butt,
+                    content::LineCap::Round => LineCap::Round,
+                    content::LineCap::Square => LineCap::Square,
+                };
+            }
+            Op::MiterLimit { limit } => self.set_miter_limit(limit),
             Op::Flatness { tolerance } => {},
             Op::GraphicsState { ref name } => {
                 let gs = try_opt!(self.resources.graphics_states.get(name));
@@ -261,14 +573,9 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 self.graphics_state.set_stroke_alpha(gs.stroke_alpha.unwrap_or(1.0));
                 
                 if let Some((font_ref, size)) = gs.font {
-                    let font = self.resolve.get(font_ref)?;
-                    if let Some(e) = self.backend.get_font(&MaybeRef::Indirect(font), self.resolve)? {
-                        debug!("new font: {} at size {}", e.name, size);
-                        self.text_state.font_entry = Some(e);
-                        self.text_state.font_size = size;
-                    } else {
-                        self.text_state.font_entry = None;
-                    }
+                    let font = MaybeRef::Indirect(self.resolve.get(font_ref)?);
+                    let name = font.name.as_ref().map(|n| n.as_str()).unwrap_or("?");
+                    self.set_font(Some(&font), name, size)?;
                 }
                 if let Some(op) = gs.overprint {
                     self.graphics_state.overprint_fill = op;
@@ -280,47 +587,94 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 if let Some(m) = gs.overprint_mode {
                     self.graphics_state.overprint_mode = m;
                 }
+                // TR, TR2 and TK are not parsed by the pdf crate and only remain in the dictionary
+                if let Ok(Primitive::Dictionary(dict)) = gs.to_primitive(&mut NoUpdate) {
+                    // the line style, like the ops M, j, J and d. The miter limit comes first, so a miter join gets it.
+                    if let Some(Ok(limit)) = dict.get("ML").map(|p| p.as_number()) {
+                        self.set_miter_limit(limit);
+                    }
+                    match dict.get("LJ").map(|p| p.as_integer().ok().and_then(line_join)) {
+                        Some(Some(join)) => self.set_line_join(join),
+                        Some(None) => warn!("invalid line join {:?}", dict.get("LJ")),
+                        None => {}
+                    }
+                    match dict.get("LC").map(|p| p.as_integer().ok().and_then(line_cap)) {
+                        Some(Some(cap)) => self.graphics_state.stroke_style.line_cap = cap,
+                        Some(None) => warn!("invalid line cap {:?}", dict.get("LC")),
+                        None => {}
+                    }
+                    if let Some(d) = dict.get("D") {
+                        match dash_pattern(d) {
+                            Ok((pattern, phase)) => self.set_dash(Cow::Owned(pattern), phase),
+                            Err(e) => warn!("invalid dash pattern {:?}: {:?}", d, e),
+                        }
+                    }
+                    if let Some(tr) = dict.get("TR2").or_else(|| dict.get("TR")) {
+                        match Transfer::from_primitive(tr, self.resolve) {
+                            Ok(transfer) => self.set_transfer(transfer.map(Arc::new)),
+                            Err(e) => warn!("invalid transfer function: {:?}", e),
+                        }
+                    }
+                    if let Some(Ok(tk)) = dict.get("TK").map(|p| p.as_bool()) {
+                        self.text_state.knockout = tk;
+                    }
+                }
+                if let Some(ref smask) = gs.smask {
+                    match self.render_soft_mask(smask) {
+                        Ok(mask) => self.set_soft_mask(mask),
+                        Err(e) => {
+                            warn!("can't render soft mask: {:?}", e);
+                            self.set_soft_mask(None);
+                        }
+                    }
+                }
             },
             Op::StrokeColor { ref color } => {
                 let mode = self.blend_mode_stroke();
-                let color = t!(convert_color(&mut self.graphics_state.stroke_color_space, color, &self.resources, self.resolve, mode));
-                self.graphics_state.set_stroke_color(color);
+                let color = convert_color2(&mut self.graphics_state.stroke_color_space, color, &self.resources, mode, &mut self.functions);
+                let color = t!(self.recover_color(color));
+                self.prepare_pattern(color);
+                self.graphics_state.set_stroke_color(filter_color(self.options, color));
             },
             Op::FillColor { ref color } => {
                 let mode = self.blend_mode_fill();
-                let color = t!(convert_color(&mut self.graphics_state.fill_color_space, color, &self.resources, self.resolve, mode));
-                self.graphics_state.set_fill_color(color);
+                let color = convert_color2(&mut self.graphics_state.fill_color_space, color, &self.resources, mode, &mut self.functions);
+                let color = t!(self.recover_color(color));
+                self.prepare_pattern(color);
+                self.graphics_state.set_fill_color(filter_color(self.options, color));
             },
             Op::FillColorSpace { ref name } => {
                 self.graphics_state.fill_color_space = self.color_space(name)?;
-                self.graphics_state.set_fill_color(Fill::black());
+                self.graphics_state.set_fill_color(filter_color(self.options, Fill::black()));
             },
             Op::StrokeColorSpace { ref name } => {
                 self.graphics_state.stroke_color_space = self.color_space(name)?;
-                self.graphics_state.set_stroke_color(Fill::black());
+                self.graphics_state.set_stroke_color(filter_color(self.options, Fill::black()));
             },
             Op::RenderingIntent { intent } => {},
-            Op::BeginText => self.text_state.reset_matrix(),
-            Op::EndText => {},
+            Op::BeginText => {
+                if self.in_text {
+                    self.report(DiagnosticKind::UnclosedText);
+                }
+                self.in_text = true;
+                self.knockout.flush(self.backend);
+                self.text_state.reset_matrix();
+            }
+            Op::EndText => {
+                if !self.in_text {
+                    self.report(DiagnosticKind::ExtraEndText);
+                }
+                self.in_text = false;
+                self.knockout.flush(self.backend);
+            }
             Op::CharSpacing { char_space } => self.text_state.char_space = char_space,
             Op::WordSpacing { word_space } => self.text_state.word_space = word_space,
             Op::TextScaling { horiz_scale } => self.text_state.horiz_scale = 0.01 * horiz_scale,
             Op::Leading { leading } => self.text_state.leading = leading,
             Op::TextFont { ref name, size } => {
-                let font = match self.resources.fonts.get(name) {
-                    Some(font_ref) => {
-                        self.backend.get_font(font_ref, self.resolve)?
-                    },
-                    None => None
-                };
-                if let Some(e) = font {
-                    debug!("new font: {} (is_cid={:?})", e.name, e.is_cid);
-                    self.text_state.font_entry = Some(e);
-                    self.text_state.font_size = size;
-                } else {
-                    info!("no font {}", name);
-                    self.text_state.font_entry = None;
-                }
+                let font_ref = self.resources.fonts.get(name);
+                let font_name = font_ref.and_then(|f| f.name.as_ref()).unwrap_or(name);
+                self.set_font(font_ref, font_name.as_str(), size)?;
             },
             Op::TextRenderMode { mode } => self.text_state.mode = mode,
             Op::TextRise { rise } => self.text_state.rise = rise,
@@ -330,18 +684,18 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             Op::TextDraw { ref text } => {
                 let fill_mode = self.blend_mode_fill();
                 let stroke_mode = self.blend_mode_stroke();
-                self.text(|backend, text_state, graphics_state, span| {
-                    text_state.draw_text(backend, graphics_state, &text.data, span, fill_mode, stroke_mode);
+                self.text(|backend, text_state, graphics_state, span, knockout| {
+                    text_state.draw_text(backend, graphics_state, &text.data, span, fill_mode, stroke_mode, knockout);
                 }, op_nr);
             },
             Op::TextDrawAdjusted { ref array } => {
                 let fill_mode = self.blend_mode_fill();
                 let stroke_mode = self.blend_mode_stroke();
-                self.text(|backend, text_state, graphics_state, span| {
+                self.text(|backend, text_state, graphics_state, span, knockout| {
                     for arg in array {
                         match *arg {
                             TextDrawAdjusted::Text(ref data) => {
-                                text_state.draw_text(backend, graphics_state, data.as_bytes(), span, fill_mode, stroke_mode);
+                                text_state.draw_text(backend, graphics_state, data.as_bytes(), span, fill_mode, stroke_mode, knockout);
                             },
                             TextDrawAdjusted::Spacing(offset) => {
                                 // because why not PDF…
@@ -352,13 +706,18 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                     }
                 }, op_nr);
             },
+            Op::XObject { .. } | Op::InlineImage { .. } if self.hidden() => {}
             Op::XObject { ref name } => {
                 let &xobject_ref = self.resources.xobjects.get(name).ok_or(PdfError::NotFound { word: name.as_str().into()})?;
                 let xobject = self.resolve.get(xobject_ref)?;
                 let mode = self.blend_mode_fill();
                 match *xobject {
+                    XObject::Image(ref im) if self.image_too_large(im) => {}
                     XObject::Image(ref im) => {
-                        self.backend.draw_image(xobject_ref, im, self.resources, self.graphics_state.transform, mode, self.graphics_state.clip_path_id, self.resolve);
+                        self.bbox.add(self.graphics_state.transform * RectF::new(Vector2F::zero(), Vector2F::splat(1.0)));
+                        if let Err(e) = self.backend.draw_image(xobject_ref, im, self.resources, self.graphics_state.transform, mode, self.graphics_state.clip_path_id, self.resolve) {
+                            self.report(DiagnosticKind::from_error(&e, Feature::Image, |error| DiagnosticKind::ImageDecode { error }));
+                        }
                     }
                     XObject::Form(ref content) => {
                         self.draw_form(content)?;
@@ -370,15 +729,31 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                     }
                 }
             },
+            Op::InlineImage { ref image } if self.image_too_large(image) => {}
             Op::InlineImage { ref image } => {
                 let mode = self.blend_mode_fill();
-                self.backend.draw_inline_image(image, &self.resources, self.graphics_state.transform, mode, self.graphics_state.clip_path_id, self.resolve);
+                self.bbox.add(self.graphics_state.transform * RectF::new(Vector2F::zero(), Vector2F::splat(1.0)));
+                if let Err(e) = self.backend.draw_inline_image(image, &self.resources, self.graphics_state.transform, mode, self.graphics_state.clip_path_id, self.resolve) {
+                    self.report(DiagnosticKind::from_error(&e, Feature::Image, |error| DiagnosticKind::ImageDecode { error }));
+                }
             }
         }
 
         Ok(())
     }
 
+    // the stroke of the graphics state, widened to `RenderOptions::min_stroke_width`
+    fn stroke(&self) -> Stroke {
+        let mut stroke = self.graphics_state.stroke();
+        if let Some(min_width) = self.options.min_stroke_width {
+            // mean scale of the CTM
+            let scale = self.graphics_state.transform.matrix.det().abs().sqrt();
+            if scale > 0.0 {
+                stroke.style.line_width = stroke.style.line_width.max(min_width / scale);
+            }
+        }
+        stroke
+    }
     fn blend_mode_fill(&self) -> BlendMode {
         if self.graphics_state.overprint_fill {
             BlendMode::Darken
@@ -394,36 +769,81 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
         }
     }
 
-    fn text(&mut self, inner: impl FnOnce(&mut B, &mut TextState, &mut GraphicsState<B>, &mut Span), op_nr: usize) {
+    fn text(&mut self, inner: impl FnOnce(&mut B, &mut TextState, &mut GraphicsState<B>, &mut Span, &mut KnockoutGroup<B>), op_nr: usize) {
         let mut span = Span::default();
         let tm = self.text_state.text_matrix;
         let origin = tm.translation();
 
-        inner(&mut self.backend, &mut self.text_state, &mut self.graphics_state, &mut span);
+        // hidden text still has to advance the text matrix
+        if self.hidden() {
+            let mode = std::mem::replace(&mut self.text_state.mode, TextMode::Invisible);
+            inner(&mut self.backend, &mut self.text_state, &mut self.graphics_state, &mut span, &mut self.knockout);
+            self.text_state.mode = mode;
+            return;
+        }
+        inner(&mut self.backend, &mut self.text_state, &mut self.graphics_state, &mut span, &mut self.knockout);
 
         let transform = self.graphics_state.transform * tm * Transform2F::from_scale(Vector2F::new(1.0, -1.0));
         let p1 = origin;
         let p2 = (tm * Transform2F::from_translation(Vector2F::new(span.width, self.text_state.font_size))).translation();
         let clip = self.graphics_state.clip_path_id;
+        let effective_font_size = (transform.matrix * Vector2F::new(0.0, self.text_state.font_size)).length();
+        let rise = self.text_state.rise;
+        let effective_rise = (transform.matrix * Vector2F::new(0.0, rise)).length().copysign(rise);
+        if rise == 0.0 {
+            self.line_font_size = effective_font_size;
+        }
+        let redacted = !self.redactions.is_empty() && redact_span(&mut span, transform, self.text_state.font_size, self.redactions);
 
+        let rect = self.graphics_state.transform * RectF::from_points(p1.min(p2), p1.max(p2));
+        if !matches!(self.text_state.mode, TextMode::Invisible) && span.width != 0.0 {
+            self.bbox.add(rect);
+        }
+
+        // nothing is left of a fully redacted span
+        if redacted && span.chars.is_empty() {
+            return;
+        }
         debug!("text {}", span.text);
         self.backend.add_text(TextSpan {
-            rect: self.graphics_state.transform * RectF::from_points(p1.min(p2), p1.max(p2)),
+            rect,
             width: span.width,
             bbox: span.bbox.rect(),
             text: span.text,
             chars: span.chars,
             font: self.text_state.font_entry.clone(),
             font_size: self.text_state.font_size,
+            effective_font_size,
             color: self.graphics_state.fill_color,
             alpha: self.graphics_state.fill_color_alpha,
             mode: self.text_state.mode,
             transform,
-            op_nr
+            op_nr,
+            rise: self.text_state.rise,
+            script: Script::from_rise(effective_rise, effective_font_size, self.line_font_size),
         }, clip);
     }
 
-    fn color_space(&self, name: &str) -> Result<&'a ColorSpace> {
+    // colors that can't be converted are black, unless the options are strict
+    fn recover_color(&mut self, color: Result<Fill>) -> Result<Fill> {
+        match color {
+            Err(e) if self.options.strictness.recover_colors() => {
+                warn!("failed to convert color: {:?}", e);
+                self.report(DiagnosticKind::from_error(&e, Feature::ColorSpace, |error| DiagnosticKind::ColorConversion { error }));
+                Ok(Fill::Solid(0.0, 0.0, 0.0))
+            }
+            r => r
+        }
+    }
+    fn prepare_pattern(&mut self, fill: Fill) {
+        if let Fill::Pattern(pattern) = fill {
+            // the content of the pattern counts towards the limits of the page
+            let mut budget = self.budget;
+            self.backend.prepare_pattern(pattern, self.graphics_state.pattern_transform, self.resolve, self.options, &mut budget);
+            self.budget = budget;
+        }
+    }
+    fn color_space(&mut self, name: &str) -> Result<&'a ColorSpace> {
         match name {
             "DeviceGray" => return Ok(&ColorSpace::DeviceGray),
             "DeviceRGB" => return Ok(&ColorSpace::DeviceRGB),
@@ -433,21 +853,49 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
         }
         match self.resources.color_spaces.get(name) {
             Some(cs) => Ok(cs),
+            None if self.options.strictness.recover_colors() => {
+                warn!("color space {:?} not present, using DeviceGray", name);
+                self.report(DiagnosticKind::MissingColorSpace { name: name.into() });
+                Ok(&ColorSpace::DeviceGray)
+            }
             None => Err(PdfError::Other { msg: format!("color space {:?} not present", name) })
         }
     }
     fn flush(&mut self) {
         if !self.current_contour.is_empty() {
-            self.current_outline.push_contour(self.current_contour.clone());
-            self.current_contour.clear();
+            self.current_outline.push_contour(std::mem::replace(&mut self.current_contour, Contour::new()));
+        }
+    }
+    // stops rendering if another nested content stream would exceed `Limits::max_form_depth`
+    fn too_deep(&mut self) -> bool {
+        if self.options.limits.max_form_depth.map_or(false, |max| self.budget.form_depth >= max) {
+            warn!("forms nested too deeply at op {}, stopping", self.op_nr);
+            self.partial = Some(PartialRender { limit: Limit::FormDepth, op_nr: self.op_nr });
+            return true;
         }
+        false
+    }
+    /// Draw the appearance stream `form` of an annotation, `transform` maps form space to user space of the page.
+    pub fn draw_appearance(&mut self, form: &FormXObject, transform: Transform2F) -> Result<()> {
+        let ctm = self.graphics_state.transform;
+        self.graphics_state.transform = ctm * transform;
+        let result = self.draw_form(form);
+        self.graphics_state.transform = ctm;
+        result
     }
     fn draw_form(&mut self, form: &FormXObject) -> Result<()> {
+        if self.too_deep() {
+            return Ok(());
+        }
         let graphics_state = GraphicsState {
+            // patterns used in the form are in the space of the form
+            pattern_transform: self.graphics_state.transform,
             stroke_alpha: self.graphics_state.stroke_color_alpha,
             fill_alpha: self.graphics_state.fill_color_alpha,
             clip_path_id: self.graphics_state.clip_path_id,
             clip_path: self.graphics_state.clip_path.clone(),
+            soft_mask: self.graphics_state.soft_mask.clone(),
+            transfer: self.graphics_state.transfer.clone(),
             .. self.graphics_state
         };
         let resources = match form.dict().resources {
@@ -455,8 +903,9 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             None => self.resources
         };
 
+        let root = (graphics_state.clone(), self.text_state.clone());
         let mut inner = RenderState {
-            graphics_state: graphics_state,
+            graphics_state,
             text_state: self.text_state.clone(),
             resources,
             stack: vec![],
@@ -464,16 +913,344 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             current_contour: Contour::new(),
             backend: self.backend,
             resolve: self.resolve,
+            options: self.options,
+            marked_content: vec![],
+            bbox: BBox::empty(),
+            functions: FunctionLuts::new(self.functions.cache()),
+            knockout: KnockoutGroup::new(),
+            diagnostics: RenderDiagnostics::new(),
+            op_nr: 0,
+            budget: Budget { form_depth: self.budget.form_depth + 1, .. self.budget },
+            partial: None,
+            path_points: 0,
+            path_overflow: false,
+            root,
+            in_text: false,
+            redactions: self.redactions,
         };
         
+        // pattern space is different inside of the form
+        inner.prepare_pattern(inner.graphics_state.fill_color);
+        inner.prepare_pattern(inner.graphics_state.stroke_color);
+
         let ops = t!(form.operations(self.resolve));
         for (i, op) in ops.iter().enumerate() {
             debug!(" form op {}: {:?}", i, op);
             inner.draw_op(op, i)?;
+            if inner.partial.is_some() {
+                break;
+            }
+        }
+        inner.finish();
+        self.budget = Budget { form_depth: self.budget.form_depth, .. inner.budget };
+        if let Some(p) = inner.partial {
+            self.partial = Some(PartialRender { op_nr: self.op_nr, .. p });
         }
+        let diagnostics = inner.take_diagnostics();
+        self.diagnostics.append_nested(diagnostics, self.op_nr);
+        // a soft mask or transfer function set inside of the form ends with it
+        inner.set_soft_mask(self.graphics_state.soft_mask.clone());
+        inner.set_transfer(self.graphics_state.transfer.clone());
+        let bbox = inner.bbox;
+        self.bbox.add_bbox(bbox);
 
         Ok(())
     }
+    fn set_soft_mask(&mut self, mask: Option<Arc<SoftMask>>) {
+        if !same_arc(&self.graphics_state.soft_mask, &mask) {
+            self.backend.set_soft_mask(mask.clone());
+        }
+        self.graphics_state.soft_mask = mask;
+    }
+    fn set_transfer(&mut self, transfer: Option<Arc<Transfer>>) {
+        if !same_arc(&self.graphics_state.transfer, &transfer) {
+            self.backend.set_transfer(transfer.clone());
+        }
+        self.graphics_state.transfer = transfer;
+    }
+    fn set_line_join(&mut self, join: content::LineJoin) {
+        self.graphics_state.stroke_style.line_join = match join {
+            content::LineJoin::Miter => LineJoin::Miter(self.graphics_state.miter_limit),
+            content::LineJoin::Round => LineJoin::Round,
+            content::LineJoin::Bevel => LineJoin::Bevel,
+        };
+    }
+    fn set_miter_limit(&mut self, limit: f32) {
+        self.graphics_state.miter_limit = limit;
+        if let LineJoin::Miter(ref mut l) = self.graphics_state.stroke_style.line_join {
+            *l = limit;
+        }
+    }
+    // an empty dash array is a solid line, as is one of zeros, which would never get anywhere
+    fn set_dash(&mut self, pattern: Cow<'a, [f32]>, phase: f32) {
+        self.graphics_state.dash_pattern = if pattern.iter().all(|&v| v == 0.0) { None } else { Some((pattern, phase)) };
+    }
+    /// Render the mask group of an /SMask entry.
+    /// None for `/None`, alpha masks and backends that can't render masks.
+    fn render_soft_mask(&mut self, smask: &Primitive) -> Result<Option<Arc<SoftMask>>> {
+        let dict = match *smask {
+            Primitive::Name(ref name) if name.as_str() == "None" => return Ok(None),
+            Primitive::Reference(r) => self.resolve.resolve(r)?.into_dictionary()?,
+            ref p => p.clone().into_dictionary()?,
+        };
+        match dict.get("S").map(|s| s.as_name()).transpose()? {
+            Some("Luminosity") => {}
+            s => {
+                info!("soft mask type {:?} is not supported", s);
+                self.report(DiagnosticKind::Unsupported { feature: Feature::SoftMask, detail: format!("soft mask of type {:?}", s) });
+                return Ok(None);
+            }
+        }
+        let group = match dict.get("G") {
+            Some(&Primitive::Reference(r)) => self.resolve.get(Ref::<XObject>::new(r))?,
+            _ => return Err(PdfError::MissingEntry { typ: "SMask", field: "G".into() })
+        };
+        let form = match *group {
+            XObject::Form(ref form) => form,
+            _ => return Err(PdfError::Other { msg: "soft mask group is not a form".into() })
+        };
+        // the backdrop is given in the color space of the group
+        let backdrop = match dict.get("BC") {
+            Some(bc) => {
+                let c = bc.as_array()?.iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?;
+                match c[..] {
+                    [g] => (g, g, g),
+                    [r, g, b] => (r, g, b),
+                    [c, m, y, k] => match cmyk2rgb((c, m, y, k), BlendMode::Overlay) {
+                        Fill::Solid(r, g, b) => (r, g, b),
+                        _ => (0., 0., 0.)
+                    }
+                    _ => (0., 0., 0.)
+                }
+            }
+            None => (0., 0., 0.)
+        };
+
+        if !self.backend.begin_soft_mask() {
+            self.report(DiagnosticKind::Unsupported { feature: Feature::SoftMask, detail: "soft masks in this backend".into() });
+            return Ok(None);
+        }
+        let result = self.draw_mask_group(form);
+        let mask = self.backend.end_soft_mask(backdrop);
+        result?;
+        Ok(mask)
+    }
+    // draws the shading `name` of the resources (the `sh` operator)
+    fn draw_shading(&mut self, name: &str) -> Result<()> {
+        // the pdf crate keeps the /Shading resources only in the dictionary
+        let raw_resources = self.resources.to_primitive(&mut NoUpdate)?.into_dictionary()?;
+        let shadings = match raw_resources.get("Shading") {
+            Some(p) => resolve_primitive(p.clone(), self.resolve)?.into_dictionary()?,
+            None => return Err(PdfError::MissingEntry { typ: "Resources", field: "Shading".into() })
+        };
+        let obj = match shadings.get(name) {
+            Some(p) => resolve_primitive(p.clone(), self.resolve)?,
+            None => return Err(PdfError::NotFound { word: name.into() })
+        };
+        self.paint_shading(obj, name)
+    }
+    /// Paint a shading pattern (`Pattern::Dict`) in the current clip path, like `sh` does.
+    /// The shading is in pattern space with the /Matrix of the pattern applied.
+    pub fn paint_shading_pattern(&mut self, pattern: &Dictionary) -> Result<()> {
+        let shading = match pattern.get("Shading") {
+            Some(p) => resolve_primitive(p.clone(), self.resolve)?,
+            None => return Err(PdfError::MissingEntry { typ: "Pattern", field: "Shading".into() })
+        };
+        let matrix = match pattern.get("Matrix") {
+            Some(m) => match m.as_array()?.iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?[..] {
+                [a, b, c, d, e, f] => Transform2F::row_major(a, c, e, b, d, f),
+                _ => return Err(PdfError::Other { msg: "pattern /Matrix needs 6 numbers".into() })
+            },
+            None => Transform2F::default()
+        };
+        let transform = std::mem::replace(&mut self.graphics_state.transform, self.graphics_state.pattern_transform * matrix);
+        let result = self.paint_shading(shading, "pattern");
+        self.graphics_state.transform = transform;
+        result
+    }
+    // the pattern of `fill` if it is a shading pattern
+    fn shading_pattern(&self, fill: Fill) -> Result<Option<Dictionary>> {
+        match fill {
+            Fill::Pattern(pattern) => match *self.resolve.get(pattern)? {
+                Pattern::Dict(ref dict) => Ok(Some(dict.clone())),
+                Pattern::Stream(..) => Ok(None)
+            },
+            Fill::Solid(..) => Ok(None)
+        }
+    }
+    // fill the current path with a shading pattern, by painting the shading clipped to the path
+    fn fill_shading(&mut self, pattern: &Dictionary, fill_rule: FillRule) -> Result<()> {
+        self.flush();
+        let overflow = self.path_overflow;
+        self.reset_path_points();
+        let path = std::mem::replace(&mut self.current_outline, Outline::new()).transformed(&self.graphics_state.transform);
+        if self.hidden() || overflow || path.is_empty() {
+            return Ok(());
+        }
+        let graphics_state = self.graphics_state.clone();
+        let id = self.backend.create_clip_path(path.clone(), fill_rule, self.graphics_state.clip_path_id);
+        let mut clip = ClipPath::new(path);
+        clip.set_fill_rule(fill_rule);
+        self.graphics_state.clip_path_id = Some(id);
+        self.graphics_state.clip_path = Some(clip);
+        let result = self.paint_shading_pattern(pattern);
+        self.graphics_state = graphics_state;
+        result
+    }
+    // paints the shading dictionary or stream `obj`, `name` is only used in diagnostics
+    fn paint_shading(&mut self, obj: Primitive, name: &str) -> Result<()> {
+        let dict = match obj {
+            Primitive::Stream(ref s) => s.info.clone(),
+            Primitive::Dictionary(ref d) => d.clone(),
+            ref p => return Err(PdfError::UnexpectedPrimitive { expected: "Stream or Dictionary", found: p.get_debug_name() })
+        };
+        let shading_type = match dict.get("ShadingType") {
+            Some(t) => t.as_integer()?,
+            None => return Err(PdfError::MissingEntry { typ: "Shading", field: "ShadingType".into() })
+        };
+        // axial and radial shadings are dictionaries, the meshes are streams
+        let gradient = match shading_type {
+            2 | 3 => true,
+            4 ..= 7 => false,
+            _ => {
+                info!("shading type {} is not supported", shading_type);
+                self.report(DiagnosticKind::Unsupported { feature: Feature::Shading, detail: format!("shading {} of type {}", name, shading_type) });
+                return Ok(());
+            }
+        };
+
+        let cs = match dict.get("ColorSpace") {
+            Some(p) => ColorSpace::from_primitive(p.clone(), self.resolve)?,
+            None => return Err(PdfError::MissingEntry { typ: "Shading", field: "ColorSpace".into() })
+        };
+        // either one function with all color components as outputs or one function per component
+        let functions = match dict.get("Function") {
+            Some(Primitive::Array(parts)) => parts.iter()
+                .map(|f| Function::from_primitive(f.clone(), self.resolve))
+                .collect::<Result<Vec<_>>>()?,
+            Some(f) => vec![Function::from_primitive(f.clone(), self.resolve)?],
+            None => vec![],
+        };
+        let components = if functions.is_empty() { None } else { Some(1) };
+        if gradient && components.is_none() {
+            return Err(PdfError::MissingEntry { typ: "Shading", field: "Function".into() });
+        }
+
+        // range of t, from /Domain of gradients and /Decode of meshes
+        let t_domain = match dict.get(if gradient { "Domain" } else { "Decode" }).map(|d| d.as_array()).transpose()? {
+            Some([.., t0, t1]) if components.is_some() => (t0.as_number()?, t1.as_number()?),
+            _ => (0., 1.)
+        };
+        // the functions and the color space are owned by this call, so they get their own addresses
+        let mut luts = FunctionLuts::new(self.functions.cache());
+        let mut cs_luts = FunctionLuts::new(self.functions.cache());
+        let resources = self.resources;
+        let mode = self.blend_mode_fill();
+        let mut color = |c: &[f32]| {
+            let components = match functions.len() {
+                0 => c.to_vec(),
+                _ => {
+                    let mut out = vec![];
+                    for f in &functions {
+                        let mut o = vec![0.0; f.output_dim()];
+                        if luts.apply_in(f, &[t_domain], &c[.. 1], &mut o).is_err() {
+                            return (0., 0., 0.);
+                        }
+                        out.extend_from_slice(&o);
+                    }
+                    out
+                }
+            };
+            let args = components.into_iter().map(Primitive::Number).collect();
+            match convert_color2(&mut &cs, &Color::Other(args), resources, mode, &mut cs_luts) {
+                Ok(Fill::Solid(r, g, b)) => (r, g, b),
+                _ => (0., 0., 0.)
+            }
+        };
+
+        let transform = self.graphics_state.transform;
+        let clip = self.graphics_state.clip_path_id;
+        let alpha = self.graphics_state.fill_color_alpha;
+        let transfer = self.graphics_state.transfer.clone();
+        let apply_transfer = |(r, g, b): (f32, f32, f32)| match transfer {
+            Some(ref t) => match t.apply_fill(Fill::Solid(r, g, b)) {
+                Fill::Solid(r, g, b) => (r, g, b),
+                _ => (r, g, b)
+            },
+            None => (r, g, b)
+        };
+
+        if gradient {
+            let (shape, extend) = shading::gradient_shape(shading_type, &dict)?;
+            let (t0, t1) = t_domain;
+            let stops = (0 ..= GRADIENT_STOPS)
+                .map(|i| apply_transfer(color(&[t0 + (t1 - t0) * i as f32 / GRADIENT_STOPS as f32])))
+                .collect();
+            let gradient = shading::Gradient { shape, stops, extend };
+
+            // sh paints everything inside of the clip path
+            let mut area = match self.graphics_state.clip_path {
+                Some(ref c) => c.outline.bounds(),
+                None => transform * shape.bounds(),
+            };
+            if let Some(bbox) = dict.get("BBox") {
+                let n = bbox.as_array()?.iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?;
+                if let [x0, y0, x1, y1] = n[..] {
+                    let bbox = transform * RectF::from_points(Vector2F::new(x0.min(x1), y0.min(y1)), Vector2F::new(x0.max(x1), y0.max(y1)));
+                    area = area.intersection(bbox).unwrap_or_default();
+                }
+            }
+            if area.width() <= 0.0 || area.height() <= 0.0 {
+                return Ok(());
+            }
+            self.bbox.add(area);
+            self.backend.draw_gradient(&gradient, area, alpha, mode, transform, clip);
+            return Ok(());
+        }
+
+        let data = Stream::<Dictionary>::from_primitive(obj, self.resolve)?.data(self.resolve)?;
+        let triangles = shading::decode_mesh(shading_type, &dict, &data, components, PATCH_STEPS)?;
+        let backend = &mut *self.backend;
+        let mut emit = |points, colors: [(f32, f32, f32); 3]| {
+            backend.draw_shaded_triangle(points, colors.map(&apply_transfer), alpha, mode, transform, clip);
+        };
+        for tri in &triangles {
+            self.bbox.add(transform * shading::triangle_bounds(tri));
+            shading::subdivide(tri, &mut color, &mut emit);
+        }
+        Ok(())
+    }
+    // the group is drawn with the default graphics state in the coordinate system at the time the mask is set
+    fn draw_mask_group(&mut self, form: &FormXObject) -> Result<()> {
+        if self.too_deep() {
+            return Ok(());
+        }
+        let resources = match form.dict().resources {
+            Some(ref r) => &*r,
+            None => self.resources
+        };
+        let mut inner = RenderState::new(&mut *self.backend, self.resolve, resources, self.graphics_state.transform, self.options);
+        inner.budget = Budget { form_depth: self.budget.form_depth + 1, .. self.budget };
+        inner.redactions = self.redactions;
+        inner.functions = FunctionLuts::new(self.functions.cache());
+
+        let ops = t!(form.operations(self.resolve));
+        for (i, op) in ops.iter().enumerate() {
+            debug!(" soft mask op {}: {:?}", i, op);
+            inner.draw_op(op, i)?;
+            if inner.partial.is_some() {
+                break;
+            }
+        }
+        inner.finish();
+        self.budget = Budget { form_depth: self.budget.form_depth, .. inner.budget };
+        if let Some(p) = inner.partial {
+            self.partial = Some(PartialRender { op_nr: self.op_nr, .. p });
+        }
+        let diagnostics = inner.take_diagnostics();
+        self.diagnostics.append_nested(diagnostics, self.op_nr);
+        Ok(())
+    }
     #[allow(dead_code)]
     fn get_properties<'b>(&'b self, p: &'b Primitive) -> Result<&'b Dictionary> {
         match p {
@@ -491,18 +1268,35 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
     }
 }
 
-fn convert_color<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> Result<Fill> {
-    match convert_color2(cs, color, resources, mode) {
-        Ok(color) => Ok(color),
-        Err(e) if resolve.options().allow_error_in_option => {
-            warn!("failed to convert color: {:?}", e);
-            Ok(Fill::Solid(0.0, 0.0, 0.0))
-        }
-        Err(e) => Err(e)
+// grid size for the patches of mesh shadings
+const PATCH_STEPS: usize = 12;
+// colors sampled from the function of axial and radial shadings (plus one for the end)
+const GRADIENT_STOPS: usize = 64;
+
+fn resolve_primitive(p: Primitive, resolve: &impl Resolve) -> Result<Primitive> {
+    match p {
+        Primitive::Reference(r) => resolve.resolve(r),
+        p => Ok(p)
+    }
+}
+
+fn same_arc<T>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false
     }
 }
+
+fn filter_color(options: &RenderOptions, fill: Fill) -> Fill {
+    match options.color_filter {
+        Some(filter) => filter.apply(fill),
+        None => fill
+    }
+}
+
 #[allow(unused_variables)]
-fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, mode: BlendMode) -> Result<Fill> {
+fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, mode: BlendMode, functions: &mut FunctionLuts) -> Result<Fill> {
     match *color {
         Color::Gray(g) => {
             *cs = &ColorSpace::DeviceGray;
@@ -568,24 +1362,34 @@ fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resour
                     Ok(cmyk2rgb((c, m, y, k), mode))
                 }
                 ColorSpace::DeviceN { ref names, ref alt, ref tint, ref attr } => {
-                    assert_eq!(args.len(), tint.input_dim());
+                    check_eq!(args.len(), tint.input_dim());
                     let mut input = vec![0.; args.len()];
                     for (i, a) in input.iter_mut().zip(args.iter()) {
                         *i = a.as_number()?;
                     }
                     let mut out = vec![0.0; tint.output_dim()];
-                    tint.apply(&input, &mut out)?;
+                    functions.apply(tint, &input, &mut out)?;
+                    let components = |n: usize| if out.len() >= n {
+                        Ok(())
+                    } else {
+                        Err(PdfError::Other { msg: format!("DeviceN tint function has {} outputs, expected {}", out.len(), n) })
+                    };
 
                     let alt = match **alt {
                         ColorSpace::Icc(ref icc) => icc.info.alternate.as_ref().map(|b| &**b),
                         ref a => Some(a),
                     };
                     match alt {
-                        Some(ColorSpace::DeviceGray) => Ok(Fill::Solid(out[0], out[0], out[0])),
+                        Some(ColorSpace::DeviceGray) => {
+                            components(1)?;
+                            Ok(Fill::Solid(out[0], out[0], out[0]))
+                        }
                         Some(ColorSpace::DeviceRGB) => {
+                            components(3)?;
                             Ok(Fill::Solid(out[0], out[1], out[2]))
                         }
                         Some(ColorSpace::DeviceCMYK) => {
+                            components(4)?;
                             Ok(cmyk2rgb((out[0], out[1], out[2], out[3]), mode))
                         }
                         _ => unimplemented!("DeviceN colorspace")
@@ -606,21 +1410,21 @@ fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resour
                     match cs {
                         &ColorSpace::DeviceCMYK => {
                             let mut cmyk = [0.0; 4];
-                            f.apply(&[x], &mut cmyk)?;
+                            functions.apply(f, &[x], &mut cmyk)?;
                             let [c, m, y, k] = cmyk;
                             //debug!("c={c}, m={m}, y={y}, k={k}");
                             Ok(cmyk2rgb((c, m, y, k), mode))
                         },
                         &ColorSpace::DeviceRGB => {
                             let mut rgb = [0.0, 0.0, 0.0];
-                            f.apply(&[x], &mut rgb)?;
+                            functions.apply(f, &[x], &mut rgb)?;
                             let [r, g, b] = rgb;
                             //debug!("r={r}, g={g}, b={b}");
                             Ok(Fill::Solid(r, g, b))
                         },
                         &ColorSpace::DeviceGray => {
                             let mut gray = [0.0];
-                            f.apply(&[x], &mut gray)?;
+                            functions.apply(f, &[x], &mut gray)?;
                             let [gray] = gray;
                             //debug!("gray={gray}");
                             Ok(Fill::Solid(gray, gray, gray))
@@ -632,15 +1436,16 @@ fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resour
                     if args.len() != 1 {
                         return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
                     }
-                    let i = args[0].as_integer()?;
+                    let i = args[0].as_integer()?.max(0) as usize;
+                    let entry = |n: usize| lut.get(n * i .. n * (i + 1)).ok_or(PdfError::Bounds { index: n * i, len: lut.len() });
                     match **cs {
                         ColorSpace::DeviceRGB => {
-                            let c = &lut[3 * i as usize ..];
+                            let c = entry(3)?;
                             let cvt = |b: u8| b as f32;
                             Ok(Fill::Solid(cvt(c[0]), cvt(c[1]), cvt(c[2])))
                         }
                         ColorSpace::DeviceCMYK => {
-                            let c = &lut[4 * i as usize ..];
+                            let c = entry(4)?;
                             let cvt = |b: u8| b as f32;
                             Ok(cmyk2rgb((cvt(c[0]), cvt(c[1]), cvt(c[2]), cvt(c[3])), mode))
                         }
@@ -648,7 +1453,7 @@ fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resour
                     }
                 }
                 ColorSpace::Pattern => {
-                    let name = args[0].as_name()?;
+                    let name = args.get(0).ok_or_else(|| PdfError::Other { msg: "missing pattern name".into() })?.as_name()?;
                     if let Some(&pat) = resources.pattern.get(name) {
                         Ok(Fill::Pattern(pat))
                     } else {
@@ -675,6 +1480,33 @@ fn cmyk2rgb((c, m, y, k): (f32, f32, f32, f32), mode: BlendMode) -> Fill {
     )
 }
 
+// the values of /LC and /LJ in an ExtGState, as for the J and j ops
+fn line_cap(n: i32) -> Option<LineCap> {
+    match n {
+        0 => Some(LineCap::Butt),
+        1 => Some(LineCap::Round),
+        2 => Some(LineCap::Square),
+        _ => None
+    }
+}
+fn line_join(n: i32) -> Option<content::LineJoin> {
+    match n {
+        0 => Some(content::LineJoin::Miter),
+        1 => Some(content::LineJoin::Round),
+        2 => Some(content::LineJoin::Bevel),
+        _ => None
+    }
+}
+// the /D of an ExtGState: [[dash array] phase]
+fn dash_pattern(d: &Primitive) -> Result<(Vec<f32>, f32)> {
+    match *d.as_array()? {
+        [ref array, ref phase] => {
+            let pattern = array.as_array()?.iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?;
+            Ok((pattern, phase.as_number()?))
+        }
+        _ => Err(PdfError::Other { msg: "the dash pattern is not [array phase]".into() })
+    }
+}
 
 fn to_rect(o: &Outline) -> Option<RectF> {
     if o.contours().len() != 1 {
@@ -694,4 +1526,78 @@ fn to_rect(o: &Outline) -> Option<RectF> {
     }
 
     Some(c.bounds())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathfinder_content::stroke::OutlineStrokeToFill;
+
+    #[test]
+    fn ext_gstate_line_style() {
+        assert_eq!(line_cap(2), Some(LineCap::Square));
+        assert_eq!(line_cap(3), None);
+        assert!(matches!(line_join(1), Some(content::LineJoin::Round)));
+        assert!(line_join(-1).is_none());
+
+        let d = Primitive::Array(vec![
+            Primitive::Array(vec![Primitive::Integer(3), Primitive::Number(1.5)]),
+            Primitive::Integer(2),
+        ]);
+        assert_eq!(dash_pattern(&d).unwrap(), (vec![3.0, 1.5], 2.0));
+        let solid = Primitive::Array(vec![Primitive::Array(vec![]), Primitive::Integer(0)]);
+        assert_eq!(dash_pattern(&solid).unwrap(), (vec![], 0.0));
+        assert!(dash_pattern(&Primitive::Array(vec![Primitive::Integer(3)])).is_err());
+    }
+
+    // the bounds of a stroked V with a sharp angle at (10, 0)
+    fn stroked_v(line_join: LineJoin) -> RectF {
+        let mut contour = Contour::new();
+        contour.push_endpoint(Vector2F::new(0.0, -1.0));
+        contour.push_endpoint(Vector2F::new(10.0, 0.0));
+        contour.push_endpoint(Vector2F::new(0.0, 1.0));
+        let mut outline = Outline::new();
+        outline.push_contour(contour);
+        let mut stroke = OutlineStrokeToFill::new(&outline, StrokeStyle { line_width: 1.0, line_cap: LineCap::Butt, line_join });
+        stroke.offset();
+        stroke.into_outline().bounds()
+    }
+
+    #[test]
+    fn miter_limit() {
+        // the miter of the V is ten line widths long, above the limit it is cut off like a bevel
+        let long = stroked_v(LineJoin::Miter(30.0));
+        let cut = stroked_v(LineJoin::Miter(3.0));
+        assert!(long.max_x() > 14.0);
+        assert!(cut.max_x() < 11.0);
+        assert_eq!(cut, stroked_v(LineJoin::Bevel));
+    }
+
+    #[test]
+    fn dash_scales_with_ctm() {
+        use crate::{Cache, SceneBackend, backend::FillMode};
+        use pathfinder_renderer::scene::DrawPathId;
+
+        let mut contour = Contour::new();
+        contour.push_endpoint(Vector2F::new(0.0, 0.0));
+        contour.push_endpoint(Vector2F::new(3.5, 0.0));
+        let mut outline = Outline::new();
+        outline.push_contour(contour);
+        let stroke = FillMode { color: Fill::black(), alpha: 1.0, mode: BlendMode::Overlay, pattern_transform: Transform2F::default() };
+        let stroke_mode = crate::backend::Stroke {
+            dash_pattern: Some((vec![1.0, 1.0], 0.0)),
+            style: StrokeStyle { line_width: 0.5, line_cap: LineCap::Butt, line_join: LineJoin::Miter(10.0) },
+        };
+
+        let mut cache = Cache::new();
+        let mut backend = SceneBackend::new(&mut cache);
+        let transform = Transform2F::from_scale(Vector2F::splat(2.0));
+        backend.draw(Cow::Owned(outline), &DrawMode::Stroke { stroke, stroke_mode }, FillRule::Winding, transform, None);
+        let scene = backend.finish();
+        // the dashes from 0 to 1 and 2 to 3 in user space
+        let dashes = scene.get_draw_path(DrawPathId(0)).outline();
+        assert_eq!(dashes.contours().len(), 2);
+        let bounds = dashes.bounds();
+        assert!((bounds.min_x() - 0.0).abs() < 1e-4 && (bounds.max_x() - 6.0).abs() < 1e-4);
+        assert!((bounds.height() - 1.0).abs() < 1e-4);
+    }
+}
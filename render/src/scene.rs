@@ -2,49 +2,374 @@ use pathfinder_color::{ColorF, ColorU};
 use pathfinder_content::{
     fill::FillRule,
     stroke::{OutlineStrokeToFill},
-    outline::Outline,
-    pattern::{Pattern},
+    outline::{Outline, Contour},
+    pattern::{Pattern, Image},
+    gradient::Gradient as PfGradient,
     dash::OutlineDash,
+    effects::BlendMode,
 };
 use pathfinder_renderer::{
-    scene::{DrawPath, ClipPath, ClipPathId, Scene},
+    scene::{DrawPath, DrawPathId, ClipPath, ClipPathId, Scene},
     paint::{PaintId, Paint},
 };
 use pathfinder_geometry::{
-    vector::{Vector2F},
+    vector::{Vector2F, Vector2I},
     rect::RectF, transform2d::Transform2F,
+    line_segment::LineSegment2F,
 };
-use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef};
-use crate::backend;
+use pathfinder_simd::default::F32x2;
+use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, PlainRef, Page, Pattern as PdfPattern};
+use pdf::content::Op;
+use crate::backend::{self, FillMode};
 
-use super::{FontEntry, TextSpan, DrawMode, Backend, Fill, Cache};
+use super::{FontEntry, PositionedGlyph, TextSpan, DrawMode, Backend, Fill, Cache, FunctionCache, RenderOptions, Budget, ColorGlyph, SoftMask, Transfer, Gradient, GradientShape, PagePlacement, shading};
 use pdf::font::Font as PdfFont;
 use pdf::error::PdfError;
 use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+// largest width and height of a rendered pattern tile, in pixels
+#[cfg(any(feature="rasterize", feature="raster"))]
+const MAX_TILE_SIZE: i32 = 1024;
+// patterns that are drawn inside of pattern tiles are only followed this deep
+#[cfg(any(feature="rasterize", feature="raster"))]
+const MAX_PATTERN_DEPTH: usize = 4;
+
+// how many paints of solid colors are remembered to be used again
+const PAINT_CACHE_SIZE: usize = 16;
+
+// pattern tiles, soft masks and masked drawings are rasterized like render_to_image does
+#[cfg(feature="raster")]
+fn rasterize(scene: Scene, background: Option<ColorF>) -> image::RgbaImage {
+    crate::raster::rasterize(&scene, background)
+}
+#[cfg(all(feature="rasterize", not(feature="raster")))]
+fn rasterize(scene: Scene, background: Option<ColorF>) -> image::RgbaImage {
+    pathfinder_rasterize::Rasterizer::new().rasterize(scene, background)
+}
+
+// a tiling pattern in one pattern space
+pub(crate) type PatternKey = (PlainRef, [u32; 6]);
+pub(crate) fn pattern_key(pattern: Ref<PdfPattern>, pattern_transform: Transform2F) -> PatternKey {
+    let m = pattern_transform.matrix;
+    let t = pattern_transform.vector;
+    (pattern.get_inner(), [m.m11(), m.m12(), m.m21(), m.m22(), t.x(), t.y()].map(f32::to_bits))
+}
+
+// consecutive paths with the same state are pushed as one draw path
+struct Batch {
+    outline: Outline,
+    paint: PaintId,
+    clip: Option<ClipPathId>,
+    fill_rule: FillRule,
+    blend_mode: BlendMode,
+}
 
 pub struct SceneBackend<'a> {
     scene: Scene,
     cache: &'a mut Cache,
+    soft_mask: Option<Arc<SoftMask>>,
+    transfer: Option<Arc<Transfer>>,
+    // scenes and masks of the content around the mask groups that are being rendered
+    #[cfg(any(feature="rasterize", feature="raster"))]
+    mask_stack: Vec<(Scene, Option<Arc<SoftMask>>)>,
+    // rendered tiles of tiling patterns, None if the pattern can't be drawn
+    patterns: HashMap<PatternKey, Option<Pattern>>,
+    // how many pattern tiles this backend is nested in
+    #[cfg(any(feature="rasterize", feature="raster"))]
+    pattern_depth: usize,
+    // filled into the view box before anything else is drawn
+    background: Option<ColorU>,
+    // paints of the last solid colors, most recently used last
+    recent_paints: Vec<(ColorU, PaintId)>,
+    batch: Option<Batch>,
+    // only kept if asked for with keep_user_space
+    user_paths: Option<Vec<UserPath>>,
+    spans: Vec<TextSpan>,
+    pages: Vec<PagePlacement>,
+}
+
+/// A path of the scene as it was drawn, before the transform was applied.
+/// Like `tracer::VectorPath`, strokes keep the outline of the line, not the filled area.
+#[derive(Clone, Debug)]
+pub struct UserPath {
+    pub draw_path: DrawPathId,
+    pub outline: Outline,
+    pub transform: Transform2F,
 }
 impl<'a> SceneBackend<'a> {
     pub fn new(cache: &'a mut Cache) -> Self {
         let scene = Scene::new();
         SceneBackend {
             scene,
-            cache
+            cache,
+            soft_mask: None,
+            transfer: None,
+            #[cfg(any(feature="rasterize", feature="raster"))]
+            mask_stack: vec![],
+            patterns: HashMap::new(),
+            #[cfg(any(feature="rasterize", feature="raster"))]
+            pattern_depth: 0,
+            background: None,
+            recent_paints: vec![],
+            batch: None,
+            user_paths: None,
+            spans: vec![],
+            pages: vec![],
         }
     }
-    pub fn finish(self) -> Scene {
+    /// Also keep the outlines in user space with their transform, see `finish_with_user_paths`.
+    pub fn keep_user_space(&mut self, keep: bool) {
+        self.user_paths = if keep { Some(vec![]) } else { None };
+    }
+    /// The color of the page, None (the default) leaves it transparent.
+    /// Unlike `RenderOptions::background` it is part of the scene itself.
+    pub fn set_background(&mut self, color: Option<ColorF>) {
+        self.background = color.map(|c| c.to_u8());
+    }
+    pub fn finish(mut self) -> Scene {
+        self.flush();
         self.scene
     }
-    fn paint(&mut self, fill: Fill, alpha: f32) -> PaintId {
-        let paint = match fill {
+    /// The pages drawn into the scene, in order.
+    pub fn pages(&self) -> &[PagePlacement] {
+        &self.pages
+    }
+    /// The page at the point `p` of the output and `p` in its page space.
+    /// Where pages overlap, the one drawn last wins.
+    pub fn page_at(&self, p: Vector2F) -> Option<(&PagePlacement, Vector2F)> {
+        self.pages.iter().rev().find(|page| page.contains(p)).map(|page| (page, page.to_page(p)))
+    }
+    /// The scene and the text on it, in the order it was drawn.
+    pub fn finish_with_text(mut self) -> (Scene, Vec<TextSpan>) {
+        self.flush();
+        (self.scene, self.spans)
+    }
+    /// The scene and the paths in user space, which are empty unless `keep_user_space` was set.
+    pub fn finish_with_user_paths(mut self) -> (Scene, Vec<UserPath>) {
+        self.flush();
+        (self.scene, self.user_paths.unwrap_or_default())
+    }
+    /// The tile that `prepare_pattern` rendered for `pattern` in `pattern_transform`, if it could be drawn.
+    pub(crate) fn take_pattern_tile(&mut self, pattern: Ref<PdfPattern>, pattern_transform: Transform2F) -> Option<Pattern> {
+        self.patterns.remove(&pattern_key(pattern, pattern_transform)).flatten()
+    }
+    fn records_user_paths(&self) -> bool {
+        // the scenes of soft masks are not part of the output
+        #[cfg(any(feature="rasterize", feature="raster"))]
+        if !self.mask_stack.is_empty() {
+            return false;
+        }
+        self.user_paths.is_some()
+    }
+    // Paths are merged as long as they don't overlap, so neither the fill rule nor transparency
+    // can tell the difference. `user` is the outline and transform that were drawn, for `UserPath`.
+    fn push_path(&mut self, outline: Outline, paint: PaintId, clip: Option<ClipPathId>, fill_rule: FillRule, blend_mode: BlendMode, user: Option<(&Outline, Transform2F)>) {
+        if self.records_user_paths() {
+            // each of them needs its own draw path
+            self.flush();
+            self.batch = Some(Batch { outline, paint, clip, fill_rule, blend_mode });
+            let id = self.flush();
+            if let (Some(draw_path), Some((outline, transform)), Some(paths)) = (id, user, self.user_paths.as_mut()) {
+                paths.push(UserPath { draw_path, outline: outline.clone(), transform });
+            }
+            return;
+        }
+        if let Some(ref mut batch) = self.batch {
+            if batch.paint == paint && batch.clip == clip && batch.fill_rule == fill_rule && batch.blend_mode == blend_mode
+                && !batch.outline.bounds().intersects(outline.bounds())
+            {
+                for contour in outline.into_contours() {
+                    batch.outline.push_contour(contour);
+                }
+                return;
+            }
+        }
+        self.flush();
+        self.batch = Some(Batch { outline, paint, clip, fill_rule, blend_mode });
+    }
+    fn flush(&mut self) -> Option<DrawPathId> {
+        let batch = self.batch.take()?;
+        let mut draw_path = DrawPath::new(batch.outline, batch.paint);
+        draw_path.set_clip_path(batch.clip);
+        draw_path.set_fill_rule(batch.fill_rule);
+        draw_path.set_blend_mode(batch.blend_mode);
+        Some(self.scene.push_draw_path(draw_path))
+    }
+    fn solid_paint(&mut self, color: ColorU) -> PaintId {
+        if let Some(i) = self.recent_paints.iter().position(|&(c, _)| c == color) {
+            let entry = self.recent_paints.remove(i);
+            self.recent_paints.push(entry);
+            return entry.1;
+        }
+        let paint = self.scene.push_paint(&Paint::from_color(color));
+        if self.recent_paints.len() == PAINT_CACHE_SIZE {
+            self.recent_paints.remove(0);
+        }
+        self.recent_paints.push((color, paint));
+        paint
+    }
+    // `transform` maps the unit square to the output, the image is drawn upright into it
+    fn draw_image_unit_square(&mut self, image: Image, transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>) {
+        let size_f = image.size().to_f32();
+        let outline = Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0)));
+        let im_tr = transform
+            * Transform2F::from_scale(Vector2F::new(1.0 / size_f.x(), -1.0 / size_f.y()))
+            * Transform2F::from_translation(Vector2F::new(0.0, -size_f.y()));
+
+        let unit_square = Outline::from_rect(RectF::new(Vector2F::zero(), Vector2F::splat(1.0)));
+        let user = Some((&unit_square, transform));
+        match self.soft_mask.clone() {
+            #[cfg(any(feature="rasterize", feature="raster"))]
+            Some(ref mask) => {
+                let mut pattern = Pattern::from_image(image);
+                pattern.apply_transform(im_tr);
+                self.push_masked(outline, Paint::from_pattern(pattern), mask, clip, FillRule::Winding, blend_mode(mode), user);
+            }
+            // without a rasterizer the mask is applied to the pixels of the image
+            #[cfg(not(any(feature="rasterize", feature="raster")))]
+            Some(ref mask) => {
+                let size = image.size();
+                let pixels = image.pixels().iter().enumerate().map(|(i, &c)| {
+                    let p = Vector2F::new((i as i32 % size.x()) as f32 + 0.5, (i as i32 / size.x()) as f32 + 0.5);
+                    let a = c.a as f32 * mask.alpha_at(im_tr * p);
+                    ColorU::new(c.r, c.g, c.b, a.round() as u8)
+                }).collect();
+                let mut pattern = Pattern::from_image(Image::new(size, Arc::new(pixels)));
+                pattern.apply_transform(im_tr);
+                let paint_id = self.scene.push_paint(&Paint::from_pattern(pattern));
+                self.push_path(outline, paint_id, clip, FillRule::Winding, blend_mode(mode), user);
+            }
+            None => {
+                let mut pattern = Pattern::from_image(image);
+                pattern.apply_transform(im_tr);
+                let paint_id = self.scene.push_paint(&Paint::from_pattern(pattern));
+                self.push_path(outline, paint_id, clip, FillRule::Winding, blend_mode(mode), user);
+            }
+        }
+    }
+    // fills `outline` (in output space) with `fill`, through the soft mask if there is one
+    fn fill_path(&mut self, outline: Outline, fill: &FillMode, clip: Option<ClipPathId>, fill_rule: FillRule, user: Option<(&Outline, Transform2F)>) {
+        match self.soft_mask.clone() {
+            Some(ref mask) => {
+                let paint = self.fill_paint(fill, fill.alpha);
+                self.push_masked(outline, paint, mask, clip, fill_rule, blend_mode(fill.mode), user);
+            }
+            None => {
+                let paint = match fill.color {
+                    Fill::Solid(r, g, b) => self.solid_paint(ColorF::new(r, g, b, fill.alpha).to_u8()),
+                    _ => {
+                        let paint = self.fill_paint(fill, fill.alpha);
+                        self.scene.push_paint(&paint)
+                    }
+                };
+                self.push_path(outline, paint, clip, fill_rule, blend_mode(fill.mode), user);
+            }
+        }
+    }
+    /// Draw `outline` with `paint` into a separate scene, rasterize it and multiply the soft mask
+    /// into the pixels, which are then drawn as an image. Pathfinder has no masks of its own.
+    #[cfg(any(feature="rasterize", feature="raster"))]
+    fn push_masked(&mut self, outline: Outline, paint: Paint, mask: &SoftMask, clip: Option<ClipPathId>, fill_rule: FillRule, blend_mode: BlendMode, user: Option<(&Outline, Transform2F)>) {
+        // the mask is rasterized with one pixel per unit of the view box, the drawing is aligned to those pixels
+        let rect = match outline.bounds().intersection(self.scene.view_box()) {
+            Some(r) => RectF::from_points(r.origin().floor(), r.lower_right().ceil()),
+            None => return
+        };
+        if rect.width() < 1.0 || rect.height() < 1.0 {
+            return;
+        }
+        let mut target = Scene::new();
+        target.set_view_box(rect);
+        let paint = target.push_paint(&paint);
+        let mut draw_path = DrawPath::new(outline, paint);
+        draw_path.set_fill_rule(fill_rule);
+        target.push_draw_path(draw_path);
+
+        let rgba = rasterize(target, None);
+        let width = rgba.width() as usize;
+        let pixels: Vec<ColorU> = rgba.pixels().enumerate().map(|(i, p)| {
+            let center = rect.origin() + Vector2F::new((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+            let a = p[3] as f32 * mask.alpha_at(center);
+            ColorU::new(p[0], p[1], p[2], a.round() as u8)
+        }).collect();
+        let image = Image::new(Vector2I::new(rgba.width() as i32, rgba.height() as i32), Arc::new(pixels));
+        let mut pattern = Pattern::from_image(image);
+        pattern.apply_transform(Transform2F::from_translation(rect.origin()));
+        pattern.set_smoothing_enabled(false);
+        let paint = self.scene.push_paint(&Paint::from_pattern(pattern));
+        self.push_path(Outline::from_rect(rect), paint, clip, FillRule::Winding, blend_mode, user);
+    }
+    // without a rasterizer, the mean of the soft mask over the bounds of `outline` is used as opacity
+    #[cfg(not(any(feature="rasterize", feature="raster")))]
+    fn push_masked(&mut self, outline: Outline, mut paint: Paint, mask: &SoftMask, clip: Option<ClipPathId>, fill_rule: FillRule, blend_mode: BlendMode, user: Option<(&Outline, Transform2F)>) {
+        paint.apply_opacity(mask.mean_alpha(outline.bounds()));
+        let paint = self.scene.push_paint(&paint);
+        self.push_path(outline, paint, clip, fill_rule, blend_mode, user);
+    }
+    fn fill_paint(&self, fill: &FillMode, alpha: f32) -> Paint {
+        match fill.color {
             Fill::Solid(r, g, b) => Paint::from_color(ColorF::new(r, g, b, alpha).to_u8()),
-            Fill::Pattern(_) => {
-                Paint::black()
+            Fill::Pattern(pattern) => match self.patterns.get(&pattern_key(pattern, fill.pattern_transform)) {
+                Some(Some(tile)) => {
+                    let mut paint = Paint::from_pattern(tile.clone());
+                    paint.apply_opacity(alpha);
+                    paint
+                }
+                _ => Paint::black()
             }
+        }
+    }
+    /// Render one cell of the tiling pattern and make a repeating image pattern of it.
+    #[cfg(any(feature="rasterize", feature="raster"))]
+    fn render_tile(&mut self, pattern: &PdfPattern, pattern_transform: Transform2F, resolve: &impl Resolve, options: &RenderOptions, budget: &mut Budget) -> Option<Pattern> {
+        let dict = match *pattern {
+            PdfPattern::Stream(ref dict, _) => dict,
+            PdfPattern::Dict(_) => return None,
         };
-        self.scene.push_paint(&paint)
+        if self.pattern_depth >= MAX_PATTERN_DEPTH {
+            warn!("patterns nested too deeply");
+            return None;
+        }
+        let pdf::content::Matrix { a, b, c, d, e, f } = dict.matrix;
+        let matrix = Transform2F::row_major(a, c, e, b, d, f);
+        let to_output = pattern_transform * matrix;
+        let step = Vector2F::new(dict.x_step.abs(), dict.y_step.abs());
+        if step.x() == 0.0 || step.y() == 0.0 {
+            return None;
+        }
+
+        // about the resolution of the output
+        let scale = to_output.matrix.det().abs().sqrt();
+        let size = (step * scale).ceil().to_i32().max(Vector2I::splat(1)).min(Vector2I::splat(MAX_TILE_SIZE));
+        let size_f = size.to_f32();
+        // maps the pixels of the tile (y down) to pattern space
+        let origin = Vector2F::new(dict.bbox.left, dict.bbox.bottom);
+        let tile_to_pattern = Transform2F::from_translation(origin + Vector2F::new(0.0, step.y()))
+            * Transform2F::from_scale(Vector2F::new(step.x() / size_f.x(), -step.y() / size_f.y()));
+
+        let mut tile = SceneBackend::new(&mut *self.cache);
+        tile.pattern_depth = self.pattern_depth + 1;
+        tile.set_view_box(RectF::new(Vector2F::zero(), size_f));
+        // render_pattern_in applies the pattern matrix on top of the transform
+        if let Err(e) = crate::render_pattern_in(&mut tile, pattern, tile_to_pattern.inverse() * matrix.inverse(), resolve, options, budget) {
+            warn!("can't render pattern: {:?}", e);
+            return None;
+        }
+        let rgba = rasterize(tile.finish(), None);
+        let pixels: Vec<ColorU> = rgba.pixels().map(|p| ColorU::new(p[0], p[1], p[2], p[3])).collect();
+        let image = Image::new(Vector2I::new(rgba.width() as i32, rgba.height() as i32), Arc::new(pixels));
+
+        let mut tile_pattern = Pattern::from_image(image);
+        tile_pattern.apply_transform(to_output * tile_to_pattern);
+        tile_pattern.set_repeat_x(true);
+        tile_pattern.set_repeat_y(true);
+        Some(tile_pattern)
+    }
+    #[cfg(not(any(feature="rasterize", feature="raster")))]
+    fn render_tile(&mut self, _pattern: &PdfPattern, _pattern_transform: Transform2F, _resolve: &impl Resolve, _options: &RenderOptions, _budget: &mut Budget) -> Option<Pattern> {
+        None
     }
 }
 impl<'a> Backend for SceneBackend<'a> {
@@ -58,81 +383,268 @@ impl<'a> Backend for SceneBackend<'a> {
     fn set_view_box(&mut self, view_box: RectF) {
         self.scene.set_view_box(view_box);
 
-        let white = self.scene.push_paint(&Paint::from_color(ColorU::white()));
-        self.scene.push_draw_path(DrawPath::new(Outline::from_rect(view_box), white));
-
-    }
-    fn draw(&mut self, outline: &Outline, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
-        match mode {
-            DrawMode::Fill { fill } | DrawMode::FillStroke {fill, .. } => {
-                let paint = self.paint(fill.color, fill.alpha);
-                let mut draw_path = DrawPath::new(outline.clone().transformed(&transform), paint);
-                draw_path.set_clip_path(clip);
-                draw_path.set_fill_rule(fill_rule);
-                draw_path.set_blend_mode(blend_mode(fill.mode));
-                self.scene.push_draw_path(draw_path);
-            }
-            _ => {}
+        if let Some(color) = self.background {
+            let paint = self.solid_paint(color);
+            self.push_path(Outline::from_rect(view_box), paint, None, FillRule::Winding, BlendMode::SrcOver, None);
         }
-        match mode {
-            DrawMode::Stroke { stroke, stroke_mode }| DrawMode::FillStroke { stroke, stroke_mode, .. } => {
-                let paint = self.paint(stroke.color, stroke.alpha);
+    }
+    fn begin_page(&mut self, placement: &PagePlacement) {
+        self.pages.push(*placement);
+    }
+    fn draw(&mut self, outline: Cow<Outline>, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
+        // the stroke is made from the untransformed outline, before the fill takes it
+        let contour = match mode {
+            DrawMode::Stroke { stroke_mode, .. } | DrawMode::FillStroke { stroke_mode, .. } => {
                 let contour = match stroke_mode.dash_pattern {
                     Some((ref pat, phase)) => {
-                        let dashed = OutlineDash::new(outline, &*pat, phase).into_outline();
+                        let dashed = OutlineDash::new(&outline, &*pat, phase).into_outline();
                         let mut stroke = OutlineStrokeToFill::new(&dashed, stroke_mode.style);
                         stroke.offset();
                         stroke.into_outline()
                     }
                     None => {
-                        let mut stroke = OutlineStrokeToFill::new(outline, stroke_mode.style);
+                        let mut stroke = OutlineStrokeToFill::new(&outline, stroke_mode.style);
                         stroke.offset();
                         stroke.into_outline()
                     }
                 };
-                let mut draw_path = DrawPath::new(contour.transformed(&transform), paint);
-                draw_path.set_clip_path(clip);
-                draw_path.set_fill_rule(fill_rule);
+                Some(contour.transformed(&transform))
+            }
+            DrawMode::Fill { .. } => None
+        };
+        // user paths keep the outline as it was drawn
+        let user = self.records_user_paths().then(|| Outline::clone(&outline));
+        let user = user.as_ref().map(|outline| (outline, transform));
 
-            draw_path.set_blend_mode(blend_mode(stroke.mode));
-                self.scene.push_draw_path(draw_path);
+        if let DrawMode::Fill { fill } | DrawMode::FillStroke { fill, .. } = mode {
+            // transformed in place if the outline is owned
+            let transformed = outline.into_owned().transformed(&transform);
+            self.fill_path(transformed, fill, clip, fill_rule, user);
+        }
+        if let (Some(contour), DrawMode::Stroke { stroke, .. } | DrawMode::FillStroke { stroke, .. }) = (contour, mode) {
+            self.fill_path(contour, stroke, clip, fill_rule, user);
+        }
+    }
+    fn draw_glyph_run(&mut self, _font: &FontEntry, glyphs: &[PositionedGlyph], mode: &DrawMode, base_transform: Transform2F, clip: Option<ClipPathId>) {
+        // strokes are made in glyph space, merging the glyphs would scale the line width by the font size
+        if !matches!(mode, DrawMode::Fill { .. }) {
+            for g in glyphs {
+                self.draw_glyph(&g.glyph, mode, base_transform * g.transform, clip);
             }
-            _ => {}
+            return;
         }
+        // one draw path for the whole run
+        let mut outline = Outline::new();
+        for g in glyphs {
+            for contour in g.glyph.path.contours() {
+                let mut contour = contour.clone();
+                contour.transform(&g.transform);
+                outline.push_contour(contour);
+            }
+        }
+        self.draw(Cow::Owned(outline), mode, FillRule::Winding, base_transform, clip);
     }
-    fn draw_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>,  resolve: &impl Resolve) {
-        if let Ok(ref image) = *self.cache.get_image(xobject_ref, im, resources, resolve, mode).0 {
-            let size = image.size();
-            let size_f = size.to_f32();
-            let outline = Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0)));
-            let im_tr = transform
-                * Transform2F::from_scale(Vector2F::new(1.0 / size_f.x(), -1.0 / size_f.y()))
-                * Transform2F::from_translation(Vector2F::new(0.0, -size_f.y()));
-
-            let mut pattern = Pattern::from_image(image.clone());
-            pattern.apply_transform(im_tr);
-            let paint = Paint::from_pattern(pattern);
-            let paint_id = self.scene.push_paint(&paint);
-            let mut draw_path = DrawPath::new(outline, paint_id);
-            draw_path.set_clip_path(clip);
-            draw_path.set_blend_mode(blend_mode(mode));
-
-            self.scene.push_draw_path(draw_path);
+    fn draw_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>,  resolve: &impl Resolve) -> Result<(), PdfError> {
+        match *self.cache.get_image(xobject_ref, im, resources, resolve, mode).0 {
+            Ok(ref image) => {
+                let image = match self.transfer {
+                    Some(ref t) => t.apply_image(image),
+                    None => image.clone()
+                };
+                self.draw_image_unit_square(image, transform, mode, clip);
+                Ok(())
+            }
+            // the error stays in the cache
+            Err(ref e) => Err(PdfError::Other { msg: e.to_string() })
         }
     }
-    fn draw_inline_image(&mut self, _im: &Arc<ImageXObject>, _resources: &Resources, _transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) {
+    fn draw_color_glyph(&mut self, glyph: &ColorGlyph, mode: &DrawMode, transform: Transform2F, clip: Option<ClipPathId>) {
+        let (png, rect) = match *glyph {
+            ColorGlyph::Bitmap { png, rect } => (png, rect),
+            ColorGlyph::Layers(_) => return backend::draw_color_layers(self, glyph, mode, transform, clip),
+        };
+        let rgba = match image::load_from_memory_with_format(png, image::ImageFormat::Png) {
+            Ok(im) => im.to_rgba8(),
+            Err(e) => {
+                warn!("invalid bitmap glyph: {:?}", e);
+                return;
+            }
+        };
+        let size = Vector2I::new(rgba.width() as i32, rgba.height() as i32);
+        let pixels: Vec<ColorU> = rgba.pixels().map(|p| ColorU::new(p[0], p[1], p[2], p[3])).collect();
+        let image = Image::new(size, Arc::new(pixels));
+        let tr = transform
+            * Transform2F::from_translation(rect.origin())
+            * Transform2F::from_scale(rect.size());
+        self.draw_image_unit_square(image, tr, backend::BlendMode::Overlay, clip);
+    }
+    #[cfg(any(feature="rasterize", feature="raster"))]
+    fn begin_soft_mask(&mut self) -> bool {
+        self.flush();
+        // paint ids belong to the scene they were pushed to
+        self.recent_paints.clear();
+        let mut mask_scene = Scene::new();
+        mask_scene.set_view_box(self.scene.view_box());
+        let scene = std::mem::replace(&mut self.scene, mask_scene);
+        self.mask_stack.push((scene, self.soft_mask.take()));
+        true
+    }
+    #[cfg(any(feature="rasterize", feature="raster"))]
+    fn end_soft_mask(&mut self, backdrop: (f32, f32, f32)) -> Option<Arc<SoftMask>> {
+        let (scene, outer_mask) = self.mask_stack.pop()?;
+        self.flush();
+        self.recent_paints.clear();
+        let mask_scene = std::mem::replace(&mut self.scene, scene);
+        self.soft_mask = outer_mask;
 
+        let rect = mask_scene.view_box();
+        let (r, g, b) = backdrop;
+        let rgba = rasterize(mask_scene, Some(ColorF::new(r, g, b, 1.0)));
+        let size = Vector2I::new(rgba.width() as i32, rgba.height() as i32);
+        Some(Arc::new(SoftMask::from_luminosity(rgba.as_raw(), size, rect, backdrop)))
+    }
+    fn set_soft_mask(&mut self, mask: Option<Arc<SoftMask>>) {
+        self.soft_mask = mask;
+    }
+    fn set_transfer(&mut self, transfer: Option<Arc<Transfer>>) {
+        self.transfer = transfer;
+    }
+    fn prepare_pattern(&mut self, pattern: Ref<PdfPattern>, pattern_transform: Transform2F, resolve: &impl Resolve, options: &RenderOptions, budget: &mut Budget) {
+        let key = pattern_key(pattern, pattern_transform);
+        if self.patterns.contains_key(&key) {
+            return;
+        }
+        let tile = match resolve.get(pattern) {
+            Ok(p) => self.render_tile(&p, pattern_transform, resolve, options, budget),
+            Err(e) => {
+                warn!("can't load pattern: {:?}", e);
+                None
+            }
+        };
+        self.patterns.insert(key, tile);
+    }
+    fn set_pattern_tile(&mut self, pattern: Ref<PdfPattern>, pattern_transform: Transform2F, tile: &Image, transform: Transform2F) {
+        let mut tile_pattern = Pattern::from_image(tile.clone());
+        tile_pattern.apply_transform(transform);
+        tile_pattern.set_repeat_x(true);
+        tile_pattern.set_repeat_y(true);
+        self.patterns.insert(pattern_key(pattern, pattern_transform), Some(tile_pattern));
+    }
+    fn draw_image_pixels(&mut self, image: &Image, transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>) {
+        self.draw_image_unit_square(image.clone(), transform, mode, clip);
+    }
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        match *self.cache.get_inline_image(im, resources, resolve, mode)?.0 {
+            Ok(ref image) => {
+                let image = match self.transfer {
+                    Some(ref t) => t.apply_image(image),
+                    None => image.clone()
+                };
+                self.draw_image_unit_square(image, transform, mode, clip);
+                Ok(())
+            }
+            Err(ref e) => Err(PdfError::Other { msg: e.to_string() })
+        }
     }
 
+    fn draw_gradient(&mut self, gradient: &Gradient, area: RectF, alpha: f32, mode: backend::BlendMode, transform: Transform2F, clip: Option<ClipPathId>) {
+        // pathfinder continues the end colors, so only extended gradients can be painted directly
+        let (mut pf_gradient, outline) = match (gradient.shape, gradient.extend) {
+            (GradientShape::Axial { start, end }, extend) => {
+                let (t_min, t_max, s_min, s_max) = match shading::axial_extent(start, end, transform.inverse() * area) {
+                    Some(e) => e,
+                    None => return
+                };
+                let (t0, t1) = (if extend.0 { t_min } else { 0.0 }, if extend.1 { t_max } else { 1.0 });
+                let d = end - start;
+                let normal = Vector2F::new(-d.y(), d.x());
+                let point = |t: f32, s: f32| transform * (start + d * t + normal * s);
+                let mut contour = Contour::new();
+                for (t, s) in [(t0, s_min), (t1, s_min), (t1, s_max), (t0, s_max)] {
+                    contour.push_endpoint(point(t, s));
+                }
+                contour.close();
+                let mut outline = Outline::new();
+                outline.push_contour(contour);
+                (PfGradient::linear_from_points(transform * start, transform * end), outline)
+            }
+            (GradientShape::Radial { start, r0, end, r1 }, (true, true)) => {
+                let mut radial = PfGradient::radial(LineSegment2F::new(start, end), F32x2::new(r0, r1));
+                radial.apply_transform(transform);
+                (radial, Outline::from_rect(area))
+            }
+            _ => {
+                shading::gradient_triangles(gradient, transform.inverse() * area, &mut |points, colors| {
+                    self.draw_shaded_triangle(points, colors, alpha, mode, transform, clip);
+                });
+                return;
+            }
+        };
+        let last = (gradient.stops.len() - 1).max(1) as f32;
+        for (i, &(r, g, b)) in gradient.stops.iter().enumerate() {
+            pf_gradient.add_color_stop(ColorF::new(r, g, b, 1.0).to_u8(), i as f32 / last);
+        }
+        let mut paint = Paint::from_gradient(pf_gradient);
+        paint.apply_opacity(alpha);
+        // the area to paint only exists in the output
+        let user = Some((&outline, Transform2F::default()));
+        match self.soft_mask.clone() {
+            Some(ref mask) => self.push_masked(outline.clone(), paint, mask, clip, FillRule::Winding, blend_mode(mode), user),
+            None => {
+                let paint = self.scene.push_paint(&paint);
+                self.push_path(outline.clone(), paint, clip, FillRule::Winding, blend_mode(mode), user);
+            }
+        }
+    }
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
         self.cache.get_font(font_ref, resolve)
     }
-    fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>) {}
+    fn function_cache(&mut self) -> Option<FunctionCache> {
+        Some(self.cache.functions())
+    }
+    fn page_ops(&mut self, page: &Page, page_nr: Option<u32>, resolve: &impl Resolve) -> Result<Arc<[Op]>, PdfError> {
+        match page_nr {
+            Some(nr) => self.cache.get_ops(nr, page, resolve),
+            None => crate::page_ops(page, resolve)
+        }
+    }
+    fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>) {
+        // text in soft masks is not visible as such
+        #[cfg(any(feature="rasterize", feature="raster"))]
+        if !self.mask_stack.is_empty() {
+            return;
+        }
+        self.spans.push(span);
+    }
 }
 
-fn blend_mode(mode: backend::BlendMode) -> pathfinder_content::effects::BlendMode {
+fn blend_mode(mode: backend::BlendMode) -> BlendMode {
     match mode {
-        crate::BlendMode::Darken => pathfinder_content::effects::BlendMode::Multiply,
-        crate::BlendMode::Overlay => pathfinder_content::effects::BlendMode::Overlay,
+        crate::BlendMode::Darken => BlendMode::Multiply,
+        crate::BlendMode::Overlay => BlendMode::SrcOver,
+    }
+}
+#[cfg(all(test, feature="raster"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_mask_per_pixel() {
+        let mut cache = Cache::new();
+        let mut backend = SceneBackend::new(&mut cache);
+        let view_box = RectF::new(Vector2F::zero(), Vector2F::splat(4.0));
+        backend.set_view_box(view_box);
+        // black on the left half, white on the right
+        let rgba: Vec<u8> = (0 .. 16).flat_map(|i| if i % 4 < 2 { [0, 0, 0, 255] } else { [255; 4] }).collect();
+        let mask = SoftMask::from_luminosity(&rgba, Vector2I::splat(4), view_box, (0., 0., 0.));
+        backend.set_soft_mask(Some(Arc::new(mask)));
+
+        let fill = FillMode { color: Fill::Solid(1., 0., 0.), alpha: 1., mode: backend::BlendMode::Overlay, pattern_transform: Transform2F::default() };
+        backend.draw(Cow::Owned(Outline::from_rect(view_box)), &DrawMode::Fill { fill }, FillRule::Winding, Transform2F::default(), None);
+        let image = crate::raster::rasterize(&backend.finish(), None);
+        assert_eq!(image.get_pixel(0, 1).0[3], 0);
+        let [r, g, b, a] = image.get_pixel(3, 1).0;
+        assert_eq!((r, g, b), (255, 0, 0));
+        assert!(a >= 254);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,211 @@
+use pdf::object::{Page, Resolve};
+use pdf::error::PdfError;
+use pathfinder_geometry::{
+    rect::RectF,
+    transform2d::Transform2F,
+};
+use crate::{
+    render_page,
+    TextSpan,
+    tracer::{TraceCache, Tracer, DrawItem},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct FindOptions {
+    pub case_insensitive: bool,
+    pub whole_word: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    /// the matched text as it appears on the page
+    pub text: String,
+    /// Rects of the matched characters, one per span the match touches. They are in the output
+    /// space of `render_page` without a transform of its own: mm from the top left of the page.
+    pub rects: Vec<RectF>,
+}
+
+struct SearchChar {
+    c: char,
+    span: usize,
+    // start and end of the char in the text space of the span, None for the spaces between spans
+    range: Option<(f32, f32)>,
+}
+
+/// Find all occurrences of `query` on the page.
+pub fn find_text(cache: &TraceCache, resolve: &impl Resolve, page: &Page, query: &str, options: &FindOptions) -> Result<Vec<Match>, PdfError> {
+    let mut clip_paths = vec![];
+    let mut tracer = Tracer::new(cache, &mut clip_paths);
+    render_page(&mut tracer, resolve, page, Transform2F::default())?;
+
+    let spans: Vec<TextSpan> = tracer.finish().into_iter().filter_map(|item| match item {
+        DrawItem::Text(span, _) => Some(span),
+        _ => None
+    }).collect();
+
+    Ok(find_in_spans(&spans, query, options))
+}
+
+pub fn find_in_spans(spans: &[TextSpan], query: &str, options: &FindOptions) -> Vec<Match> {
+    let fold = |c: char| -> char {
+        if options.case_insensitive {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(l), None) => l,
+                _ => c
+            }
+        } else {
+            c
+        }
+    };
+
+    let mut chars = vec![];
+    for (span_nr, span) in spans.iter().enumerate() {
+        if chars.len() > 0 && !span.text.starts_with(char::is_whitespace) {
+            chars.push(SearchChar { c: ' ', span: span_nr, range: None });
+        }
+        for part in span.parts() {
+            // the chars of a ligature share its width
+            let n = part.text.chars().count() as f32;
+            for (i, c) in part.text.chars().enumerate() {
+                let start = part.pos + part.width * i as f32 / n;
+                chars.push(SearchChar { c, span: span_nr, range: Some((start, start + part.width / n)) });
+            }
+        }
+    }
+
+    let needle: Vec<char> = query.chars().map(fold).collect();
+    if needle.is_empty() {
+        return vec![];
+    }
+
+    let is_word = |i: usize| chars.get(i).map(|sc| sc.c.is_alphanumeric()).unwrap_or(false);
+
+    let mut matches = vec![];
+    let mut start = 0;
+    while start + needle.len() <= chars.len() {
+        let end = start + needle.len();
+        let found = chars[start..end].iter().zip(needle.iter()).all(|(sc, &n)| fold(sc.c) == n);
+        let bounded = !options.whole_word || (
+            (start == 0 || !is_word(start - 1)) && !is_word(end)
+        );
+        if !(found && bounded) {
+            start += 1;
+            continue;
+        }
+
+        let mut ranges: Vec<(usize, f32, f32)> = vec![];
+        for sc in &chars[start..end] {
+            let (a, b) = match sc.range {
+                Some(r) => r,
+                None => continue
+            };
+            match ranges.last_mut() {
+                Some((span, ref mut min, ref mut max)) if *span == sc.span => {
+                    *min = min.min(a);
+                    *max = max.max(b);
+                }
+                _ => ranges.push((sc.span, a, b))
+            }
+        }
+        matches.push(Match {
+            text: chars[start..end].iter().map(|sc| sc.c).collect(),
+            rects: ranges.into_iter().map(|(span, min, max)| spans[span].char_rect(min, max - min)).collect(),
+        });
+        start = end;
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pathfinder_geometry::vector::Vector2F;
+    use pdf::content::TextMode;
+    use crate::{Fill, Script, TextChar};
+
+    // a span at `x` with one unit of width per char and a font size of 10
+    fn span(text: &str, x: f32) -> TextSpan {
+        let chars: Vec<TextChar> = text.char_indices().enumerate()
+            .map(|(i, (offset, _))| TextChar { offset, pos: i as f32, width: 1.0, bbox: None })
+            .collect();
+        let width = chars.len() as f32;
+        TextSpan {
+            rect: RectF::new(Vector2F::new(x, -10.0), Vector2F::new(width, 10.0)),
+            width,
+            bbox: None,
+            font_size: 10.0,
+            effective_font_size: 10.0,
+            font: None,
+            text: text.into(),
+            chars,
+            color: Fill::black(),
+            alpha: 1.0,
+            transform: Transform2F::from_translation(Vector2F::new(x, 0.0)),
+            mode: TextMode::Fill,
+            op_nr: 0,
+            rise: 0.0,
+            script: Script::Normal,
+        }
+    }
+
+    #[test]
+    fn case_folding() {
+        let spans = [span("Hello HELLO hello", 0.0)];
+        let exact = find_in_spans(&spans, "hello", &FindOptions::default());
+        assert_eq!(exact.len(), 1);
+
+        let options = FindOptions { case_insensitive: true, ..FindOptions::default() };
+        let folded = find_in_spans(&spans, "hello", &options);
+        let texts: Vec<&str> = folded.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["Hello", "HELLO", "hello"]);
+    }
+
+    #[test]
+    fn whole_words() {
+        let spans = [span("cat concat cats cat.", 0.0)];
+        assert_eq!(find_in_spans(&spans, "cat", &FindOptions::default()).len(), 4);
+
+        let options = FindOptions { whole_word: true, ..FindOptions::default() };
+        let found = find_in_spans(&spans, "cat", &options);
+        assert_eq!(found.len(), 2);
+        // the first and the last one, which is followed by a period
+        assert_eq!(found[0].rects[0].min_x(), 0.0);
+        assert_eq!(found[1].rects[0].min_x(), 16.0);
+    }
+
+    #[test]
+    fn across_spans() {
+        // spans are joined with a space unless the next one starts with whitespace
+        let spans = [span("the quick", 0.0), span("brown fox", 20.0)];
+        let found = find_in_spans(&spans, "quick brown", &FindOptions::default());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].text, "quick brown");
+        // one rect per span
+        assert_eq!(found[0].rects.len(), 2);
+        assert_eq!(found[0].rects[0], RectF::new(Vector2F::new(4.0, -10.0), Vector2F::new(5.0, 10.0)));
+        assert_eq!(found[0].rects[1], RectF::new(Vector2F::new(20.0, -10.0), Vector2F::new(5.0, 10.0)));
+    }
+
+    #[test]
+    fn inside_ligature() {
+        // "ffi" is one glyph of 1.5 units, each of its chars gets a third of it
+        let mut office = span("office", 0.0);
+        office.chars = vec![
+            TextChar { offset: 0, pos: 0.0, width: 1.0, bbox: None },
+            TextChar { offset: 1, pos: 1.0, width: 1.5, bbox: None },
+            TextChar { offset: 4, pos: 2.5, width: 1.0, bbox: None },
+            TextChar { offset: 5, pos: 3.5, width: 1.0, bbox: None },
+        ];
+        office.width = 4.5;
+        let found = find_in_spans(&[office], "fic", &FindOptions::default());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].rects, [RectF::new(Vector2F::new(1.5, -10.0), Vector2F::new(2.0, 10.0))]);
+    }
+
+    #[test]
+    fn empty_query() {
+        let spans = [span("anything", 0.0)];
+        assert!(find_in_spans(&spans, "", &FindOptions::default()).is_empty());
+    }
+}
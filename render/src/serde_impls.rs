@@ -0,0 +1,183 @@
+//! (De)serialization of the pathfinder and pdf types used in the items of the `Tracer`,
+//! for use with `#[serde(with = "...")]`.
+//!
+//! Outlines are SVG path data with absolute commands (M, L, Q, C and Z),
+//! transforms are `[a, b, c, d, e, f]` like a PDF matrix and rects are `[min_x, min_y, max_x, max_y]`.
+
+use pathfinder_content::{
+    outline::Outline,
+    fill::FillRule,
+    stroke::{StrokeStyle, LineCap, LineJoin},
+};
+use pathfinder_geometry::{
+    rect::RectF,
+    transform2d::Transform2F,
+    vector::Vector2F,
+};
+use pdf::content::TextMode;
+use pdf::object::{Ref, PlainRef};
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de::Error};
+use crate::svg::{outline_to_svg_path, outline_from_svg_path};
+
+pub mod outline {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(outline: &Outline, s: S) -> Result<S::Ok, S::Error> {
+        outline_to_svg_path(outline).serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Outline, D::Error> {
+        outline_from_svg_path(&String::deserialize(d)?).map_err(D::Error::custom)
+    }
+}
+
+pub mod transform {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(tr: &Transform2F, s: S) -> Result<S::Ok, S::Error> {
+        let (m, t) = (tr.matrix, tr.vector);
+        [m.m11(), m.m21(), m.m12(), m.m22(), t.x(), t.y()].serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Transform2F, D::Error> {
+        let [a, b, c, d, e, f] = <[f32; 6]>::deserialize(d)?;
+        Ok(Transform2F::row_major(a, c, e, b, d, f))
+    }
+}
+
+pub mod vector {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(v: &Vector2F, s: S) -> Result<S::Ok, S::Error> {
+        [v.x(), v.y()].serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vector2F, D::Error> {
+        let [x, y] = <[f32; 2]>::deserialize(d)?;
+        Ok(Vector2F::new(x, y))
+    }
+}
+
+pub mod rect {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(r: &RectF, s: S) -> Result<S::Ok, S::Error> {
+        [r.min_x(), r.min_y(), r.max_x(), r.max_y()].serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<RectF, D::Error> {
+        let [x0, y0, x1, y1] = <[f32; 4]>::deserialize(d)?;
+        Ok(RectF::from_points(Vector2F::new(x0, y0), Vector2F::new(x1, y1)))
+    }
+}
+
+pub mod rect_opt {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(r: &Option<RectF>, s: S) -> Result<S::Ok, S::Error> {
+        r.map(|r| [r.min_x(), r.min_y(), r.max_x(), r.max_y()]).serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<RectF>, D::Error> {
+        Ok(Option::<[f32; 4]>::deserialize(d)?.map(|[x0, y0, x1, y1]| RectF::from_points(Vector2F::new(x0, y0), Vector2F::new(x1, y1))))
+    }
+}
+
+pub mod fill_rule {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(rule: &FillRule, s: S) -> Result<S::Ok, S::Error> {
+        match *rule {
+            FillRule::Winding => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        }.serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<FillRule, D::Error> {
+        match &*String::deserialize(d)? {
+            "nonzero" => Ok(FillRule::Winding),
+            "evenodd" => Ok(FillRule::EvenOdd),
+            s => Err(D::Error::unknown_variant(s, &["nonzero", "evenodd"]))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Style {
+    line_width: f32,
+    line_cap: String,
+    line_join: String,
+    miter_limit: Option<f32>,
+}
+
+pub mod stroke_style {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(style: &StrokeStyle, s: S) -> Result<S::Ok, S::Error> {
+        let (line_join, miter_limit) = match style.line_join {
+            LineJoin::Miter(limit) => ("miter", Some(limit)),
+            LineJoin::Bevel => ("bevel", None),
+            LineJoin::Round => ("round", None),
+        };
+        Style {
+            line_width: style.line_width,
+            line_cap: match style.line_cap {
+                LineCap::Butt => "butt",
+                LineCap::Square => "square",
+                LineCap::Round => "round",
+            }.into(),
+            line_join: line_join.into(),
+            miter_limit,
+        }.serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<StrokeStyle, D::Error> {
+        let style = Style::deserialize(d)?;
+        let line_cap = match &*style.line_cap {
+            "butt" => LineCap::Butt,
+            "square" => LineCap::Square,
+            "round" => LineCap::Round,
+            s => return Err(D::Error::unknown_variant(s, &["butt", "square", "round"]))
+        };
+        let line_join = match &*style.line_join {
+            "miter" => LineJoin::Miter(style.miter_limit.unwrap_or(10.0)),
+            "bevel" => LineJoin::Bevel,
+            "round" => LineJoin::Round,
+            s => return Err(D::Error::unknown_variant(s, &["miter", "bevel", "round"]))
+        };
+        Ok(StrokeStyle { line_width: style.line_width, line_cap, line_join })
+    }
+}
+
+pub mod text_mode {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(mode: &TextMode, s: S) -> Result<S::Ok, S::Error> {
+        match *mode {
+            TextMode::Fill => "fill",
+            TextMode::Stroke => "stroke",
+            TextMode::FillThenStroke => "fill_then_stroke",
+            TextMode::Invisible => "invisible",
+            TextMode::FillAndClip => "fill_and_clip",
+            TextMode::StrokeAndClip => "stroke_and_clip",
+        }.serialize(s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<TextMode, D::Error> {
+        match &*String::deserialize(d)? {
+            "fill" => Ok(TextMode::Fill),
+            "stroke" => Ok(TextMode::Stroke),
+            "fill_then_stroke" => Ok(TextMode::FillThenStroke),
+            "invisible" => Ok(TextMode::Invisible),
+            "fill_and_clip" => Ok(TextMode::FillAndClip),
+            "stroke_and_clip" => Ok(TextMode::StrokeAndClip),
+            s => Err(D::Error::unknown_variant(s, &["fill", "stroke", "fill_then_stroke", "invisible", "fill_and_clip", "stroke_and_clip"]))
+        }
+    }
+}
+
+/// references are `[id, gen]`
+pub mod pdf_ref {
+    use super::*;
+
+    pub fn serialize<T, S: Serializer>(r: &Ref<T>, s: S) -> Result<S::Ok, S::Error> {
+        let r = r.get_inner();
+        (r.id, r.gen).serialize(s)
+    }
+    pub fn deserialize<'de, T, D: Deserializer<'de>>(d: D) -> Result<Ref<T>, D::Error> {
+        let (id, gen) = Deserialize::deserialize(d)?;
+        Ok(Ref::new(PlainRef { id, gen }))
+    }
+}
@@ -0,0 +1,628 @@
+//! Mesh shadings: free-form (type 4) and lattice-form (type 5) Gouraud shaded triangles,
+//! Coons patches (type 6) and tensor-product patches (type 7).
+//!
+//! The mesh data is decoded into triangles with the color components (or the parametric
+//! value `t` if the shading has a function) at their corners. Patches are evaluated on a grid.
+//!
+//! Axial (type 2) and radial (type 3) shadings are turned into a `Gradient` with sampled colors.
+
+use pathfinder_geometry::{
+    vector::Vector2F,
+    rect::RectF,
+};
+use pdf::primitive::{Dictionary, Primitive};
+use pdf::error::{PdfError, Result};
+
+/// A corner of a triangle, with the color components in the color space of the shading,
+/// or a single `t` if the shading has a function.
+#[derive(Clone, Debug)]
+pub struct Vertex {
+    pub p: Vector2F,
+    pub c: Vec<f32>,
+}
+impl Vertex {
+    fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        Vertex {
+            p: self.p.lerp(other.p, t),
+            c: self.c.iter().zip(&other.c).map(|(&a, &b)| a + (b - a) * t).collect(),
+        }
+    }
+}
+
+pub type Triangle = [Vertex; 3];
+
+pub fn triangle_bounds(tri: &Triangle) -> RectF {
+    RectF::from_points(tri[0].p.min(tri[1].p).min(tri[2].p), tri[0].p.max(tri[1].p).max(tri[2].p))
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    // in bits
+    pos: usize,
+}
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: usize) -> Option<u32> {
+        let mut value = 0u64;
+        let mut remaining = bits;
+        while remaining > 0 {
+            let byte = *self.data.get(self.pos / 8)?;
+            let avail = 8 - self.pos % 8;
+            let take = avail.min(remaining);
+            let chunk = (byte >> (avail - take)) as u64 & ((1 << take) - 1);
+            value = (value << take) | chunk;
+            self.pos += take;
+            remaining -= take;
+        }
+        Some(value as u32)
+    }
+    fn align(&mut self) {
+        self.pos = (self.pos + 7) & !7;
+    }
+}
+
+struct MeshReader<'a> {
+    bits: BitReader<'a>,
+    bits_per_coordinate: usize,
+    bits_per_component: usize,
+    bits_per_flag: usize,
+    // min and max of x, y and then of every color component
+    decode: Vec<(f32, f32)>,
+}
+impl<'a> MeshReader<'a> {
+    fn new(dict: &Dictionary, data: &'a [u8], components: Option<usize>, flags: bool) -> Result<Self> {
+        let int = |key: &'static str| -> Result<usize> {
+            match dict.get(key) {
+                Some(p) => Ok(p.as_integer()?.max(0) as usize),
+                None => Err(PdfError::MissingEntry { typ: "Shading", field: key.into() })
+            }
+        };
+        let bits_per_coordinate = int("BitsPerCoordinate")?;
+        let bits_per_component = int("BitsPerComponent")?;
+        let bits_per_flag = if flags { int("BitsPerFlag")? } else { 0 };
+        if !matches!(bits_per_coordinate, 1 | 2 | 4 | 8 | 12 | 16 | 24 | 32) || !matches!(bits_per_component, 1 | 2 | 4 | 8 | 12 | 16) || bits_per_flag > 8 {
+            return Err(PdfError::Other { msg: format!("invalid bits per coordinate/component/flag: {}/{}/{}", bits_per_coordinate, bits_per_component, bits_per_flag) });
+        }
+
+        let decode = match dict.get("Decode") {
+            Some(p) => p.as_array()?.iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?,
+            None => return Err(PdfError::MissingEntry { typ: "Shading", field: "Decode".into() })
+        };
+        let components = components.unwrap_or((decode.len() / 2).saturating_sub(2));
+        if components == 0 || decode.len() < 2 * (2 + components) {
+            return Err(PdfError::Other { msg: format!("Decode has {} entries, expected {}", decode.len(), 2 * (2 + components)) });
+        }
+        let decode = decode.chunks_exact(2).take(2 + components).map(|c| (c[0], c[1])).collect();
+
+        Ok(MeshReader {
+            bits: BitReader { data, pos: 0 },
+            bits_per_coordinate,
+            bits_per_component,
+            bits_per_flag,
+            decode,
+        })
+    }
+    fn value(&mut self, bits: usize, (min, max): (f32, f32)) -> Option<f32> {
+        let raw = self.bits.read(bits)?;
+        let scale = ((1u64 << bits) - 1) as f32;
+        Some(min + raw as f32 * (max - min) / scale)
+    }
+    fn flag(&mut self) -> Option<u32> {
+        self.bits.read(self.bits_per_flag)
+    }
+    fn point(&mut self) -> Option<Vector2F> {
+        let x = self.value(self.bits_per_coordinate, self.decode[0])?;
+        let y = self.value(self.bits_per_coordinate, self.decode[1])?;
+        Some(Vector2F::new(x, y))
+    }
+    fn color(&mut self) -> Option<Vec<f32>> {
+        (2 .. self.decode.len()).map(|i| self.value(self.bits_per_component, self.decode[i])).collect()
+    }
+    fn vertex(&mut self) -> Option<Vertex> {
+        Some(Vertex { p: self.point()?, c: self.color()? })
+    }
+}
+
+/// Decode the triangles of a mesh shading of type 4 to 7.
+///
+/// `components` is 1 if the shading has a function. Otherwise it is None and taken from /Decode.
+/// Patches are evaluated on a grid of `patch_steps` × `patch_steps` cells.
+/// Incomplete data at the end of the stream is ignored.
+pub fn decode_mesh(shading_type: i32, dict: &Dictionary, data: &[u8], components: Option<usize>, patch_steps: usize) -> Result<Vec<Triangle>> {
+    match shading_type {
+        4 => Ok(free_form(MeshReader::new(dict, data, components, true)?)),
+        5 => {
+            let per_row = match dict.get("VerticesPerRow") {
+                Some(p) => p.as_integer()?,
+                None => return Err(PdfError::MissingEntry { typ: "Shading", field: "VerticesPerRow".into() })
+            };
+            if per_row < 2 {
+                return Err(PdfError::Other { msg: format!("VerticesPerRow is {}", per_row) });
+            }
+            Ok(lattice(MeshReader::new(dict, data, components, false)?, per_row as usize))
+        }
+        6 | 7 => {
+            let patches = patches(MeshReader::new(dict, data, components, true)?, shading_type == 7);
+            let mut triangles = vec![];
+            for patch in &patches {
+                patch.triangulate(patch_steps.max(1), &mut triangles);
+            }
+            Ok(triangles)
+        }
+        t => Err(PdfError::Other { msg: format!("shading type {} is not a mesh", t) })
+    }
+}
+
+fn free_form(mut r: MeshReader) -> Vec<Triangle> {
+    let mut triangles: Vec<Triangle> = vec![];
+    loop {
+        let flag = match r.flag() {
+            Some(f) => f,
+            None => break
+        };
+        let v = match r.vertex() {
+            Some(v) => v,
+            None => break
+        };
+        r.bits.align();
+        match (flag, triangles.last()) {
+            (1, Some([_, b, c])) => {
+                let t = [b.clone(), c.clone(), v];
+                triangles.push(t);
+            }
+            (2, Some([a, _, c])) => {
+                let t = [a.clone(), c.clone(), v];
+                triangles.push(t);
+            }
+            _ => {
+                // a new triangle; the flags of the other two vertices are ignored
+                let mut next = || {
+                    r.flag()?;
+                    let v = r.vertex();
+                    r.bits.align();
+                    v
+                };
+                match (next(), next()) {
+                    (Some(b), Some(c)) => triangles.push([v, b, c]),
+                    _ => break
+                }
+            }
+        }
+    }
+    triangles
+}
+
+fn lattice(mut r: MeshReader, per_row: usize) -> Vec<Triangle> {
+    let mut vertices = vec![];
+    while let Some(v) = r.vertex() {
+        vertices.push(v);
+    }
+    let mut triangles = vec![];
+    let rows: Vec<&[Vertex]> = vertices.chunks_exact(per_row).collect();
+    for (top, bottom) in rows.iter().zip(rows.iter().skip(1)) {
+        for i in 0 .. per_row - 1 {
+            triangles.push([top[i].clone(), top[i + 1].clone(), bottom[i].clone()]);
+            triangles.push([top[i + 1].clone(), bottom[i + 1].clone(), bottom[i].clone()]);
+        }
+    }
+    triangles
+}
+
+/// A tensor-product patch, `points[i][j]` is the control point p<sub>ij</sub> of the spec.
+struct Patch {
+    points: [[Vector2F; 4]; 4],
+    // colors at p00, p03, p33 and p30
+    colors: [Vec<f32>; 4],
+}
+
+// the order of the points in the stream, the last four only for type 7
+const STREAM_ORDER: [(usize, usize); 16] = [
+    (0, 0), (0, 1), (0, 2), (0, 3), (1, 3), (2, 3), (3, 3), (3, 2),
+    (3, 1), (3, 0), (2, 0), (1, 0), (1, 1), (1, 2), (2, 2), (2, 1),
+];
+
+fn patches(mut r: MeshReader, tensor: bool) -> Vec<Patch> {
+    let mut patches: Vec<Patch> = vec![];
+    let num_points = if tensor { 16 } else { 12 };
+    loop {
+        let flag = match r.flag() {
+            Some(f) => f,
+            None => break
+        };
+        let mut points = [[Vector2F::zero(); 4]; 4];
+        let mut colors: [Vec<f32>; 4] = Default::default();
+
+        // a flag other than 0 continues the previous patch at one of its edges
+        let (first_point, first_color) = match (flag, patches.last()) {
+            (0, _) => (0, 0),
+            (1 ..= 3, Some(prev)) => {
+                let p = &prev.points;
+                let (edge, c) = match flag {
+                    1 => ([p[0][3], p[1][3], p[2][3], p[3][3]], [&prev.colors[1], &prev.colors[2]]),
+                    2 => ([p[3][3], p[3][2], p[3][1], p[3][0]], [&prev.colors[2], &prev.colors[3]]),
+                    _ => ([p[3][0], p[2][0], p[1][0], p[0][0]], [&prev.colors[3], &prev.colors[0]]),
+                };
+                points[0] = edge;
+                colors[0] = c[0].clone();
+                colors[1] = c[1].clone();
+                (4, 2)
+            }
+            _ => break
+        };
+        let mut complete = true;
+        for &(i, j) in &STREAM_ORDER[first_point .. num_points] {
+            match r.point() {
+                Some(p) => points[i][j] = p,
+                None => complete = false
+            }
+        }
+        for c in &mut colors[first_color ..] {
+            match r.color() {
+                Some(v) => *c = v,
+                None => complete = false
+            }
+        }
+        r.bits.align();
+        if !complete {
+            break;
+        }
+        if !tensor {
+            coons_interior(&mut points);
+        }
+        patches.push(Patch { points, colors });
+    }
+    patches
+}
+
+// the interior control points of a Coons patch as a tensor-product patch
+fn coons_interior(p: &mut [[Vector2F; 4]; 4]) {
+    let f = |a: Vector2F, b: Vector2F, c: Vector2F, d: Vector2F, e: Vector2F, g: Vector2F, h: Vector2F, k: Vector2F| {
+        (a * -4.0 + (b + c) * 6.0 - (d + e) * 2.0 + (g + h) * 3.0 - k) * (1.0 / 9.0)
+    };
+    p[1][1] = f(p[0][0], p[0][1], p[1][0], p[0][3], p[3][0], p[3][1], p[1][3], p[3][3]);
+    p[1][2] = f(p[0][3], p[0][2], p[1][3], p[0][0], p[3][3], p[3][2], p[1][0], p[3][0]);
+    p[2][1] = f(p[3][0], p[3][1], p[2][0], p[3][3], p[0][0], p[0][1], p[2][3], p[0][3]);
+    p[2][2] = f(p[3][3], p[3][2], p[2][3], p[3][0], p[0][3], p[0][2], p[2][0], p[0][0]);
+}
+
+fn bernstein(t: f32) -> [f32; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+impl Patch {
+    fn point(&self, u: f32, v: f32) -> Vector2F {
+        let (bu, bv) = (bernstein(u), bernstein(v));
+        let mut p = Vector2F::zero();
+        for i in 0 .. 4 {
+            for j in 0 .. 4 {
+                p = p + self.points[i][j] * (bu[i] * bv[j]);
+            }
+        }
+        p
+    }
+    fn color(&self, u: f32, v: f32) -> Vec<f32> {
+        let [c00, c03, c33, c30] = &self.colors;
+        (0 .. c00.len()).map(|k| {
+            let low = c00[k] * (1.0 - v) + c03[k] * v;
+            let high = c30[k] * (1.0 - v) + c33[k] * v;
+            low * (1.0 - u) + high * u
+        }).collect()
+    }
+    fn triangulate(&self, steps: usize, out: &mut Vec<Triangle>) {
+        let n = steps + 1;
+        let grid: Vec<Vertex> = (0 .. n * n).map(|k| {
+            let u = (k / n) as f32 / steps as f32;
+            let v = (k % n) as f32 / steps as f32;
+            Vertex { p: self.point(u, v), c: self.color(u, v) }
+        }).collect();
+        for i in 0 .. steps {
+            for j in 0 .. steps {
+                let a = &grid[i * n + j];
+                let b = &grid[i * n + j + 1];
+                let c = &grid[(i + 1) * n + j];
+                let d = &grid[(i + 1) * n + j + 1];
+                out.push([a.clone(), b.clone(), c.clone()]);
+                out.push([b.clone(), d.clone(), c.clone()]);
+            }
+        }
+    }
+}
+
+/// Split `tri` until the colors of its corners are close, calling `emit` with the corners and their colors.
+///
+/// `color` converts the components of a vertex to RGB.
+pub fn subdivide(tri: &Triangle, color: &mut impl FnMut(&[f32]) -> (f32, f32, f32), emit: &mut impl FnMut([Vector2F; 3], [(f32, f32, f32); 3])) {
+    let colors = [color(&tri[0].c), color(&tri[1].c), color(&tri[2].c)];
+    subdivide_inner(tri, colors, 0, color, emit)
+}
+
+const MAX_DEPTH: usize = 4;
+const MAX_COLOR_DIFFERENCE: f32 = 1.0 / 64.0;
+
+fn subdivide_inner(tri: &Triangle, colors: [(f32, f32, f32); 3], depth: usize, color: &mut impl FnMut(&[f32]) -> (f32, f32, f32), emit: &mut impl FnMut([Vector2F; 3], [(f32, f32, f32); 3])) {
+    let diff = |(r1, g1, b1): (f32, f32, f32), (r2, g2, b2): (f32, f32, f32)| (r1 - r2).abs().max((g1 - g2).abs()).max((b1 - b2).abs());
+    let max_diff = diff(colors[0], colors[1]).max(diff(colors[1], colors[2])).max(diff(colors[2], colors[0]));
+    if depth >= MAX_DEPTH || max_diff <= MAX_COLOR_DIFFERENCE {
+        emit([tri[0].p, tri[1].p, tri[2].p], colors);
+        return;
+    }
+    let [a, b, c] = tri;
+    let ab = a.lerp(b, 0.5);
+    let bc = b.lerp(c, 0.5);
+    let ca = c.lerp(a, 0.5);
+    let (c_ab, c_bc, c_ca) = (color(&ab.c), color(&bc.c), color(&ca.c));
+    subdivide_inner(&[a.clone(), ab.clone(), ca.clone()], [colors[0], c_ab, c_ca], depth + 1, color, emit);
+    subdivide_inner(&[ab.clone(), b.clone(), bc.clone()], [c_ab, colors[1], c_bc], depth + 1, color, emit);
+    subdivide_inner(&[ca.clone(), bc.clone(), c.clone()], [c_ca, c_bc, colors[2]], depth + 1, color, emit);
+    subdivide_inner(&[ab, bc, ca], [c_ab, c_bc, c_ca], depth + 1, color, emit);
+}
+
+/// The geometry of an axial or radial shading, in shading space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GradientShape {
+    /// type 2, `t` goes from 0 at `start` to 1 at `end`
+    Axial {
+        #[cfg_attr(feature="serde", serde(with="crate::serde_impls::vector"))]
+        start: Vector2F,
+        #[cfg_attr(feature="serde", serde(with="crate::serde_impls::vector"))]
+        end: Vector2F,
+    },
+    /// type 3, from the circle around `start` with radius `r0` to the one around `end` with `r1`
+    Radial {
+        #[cfg_attr(feature="serde", serde(with="crate::serde_impls::vector"))]
+        start: Vector2F,
+        r0: f32,
+        #[cfg_attr(feature="serde", serde(with="crate::serde_impls::vector"))]
+        end: Vector2F,
+        r1: f32,
+    },
+}
+impl GradientShape {
+    /// the area between `t = 0` and `t = 1`
+    pub fn bounds(&self) -> RectF {
+        match *self {
+            GradientShape::Axial { start, end } => {
+                // the band between the two ends is infinitely wide, take a square
+                let center = (start + end) * 0.5;
+                let half = Vector2F::splat((end - start).length() * 0.5);
+                RectF::from_points(center - half, center + half)
+            }
+            GradientShape::Radial { start, r0, end, r1 } => {
+                let a = RectF::from_points(start - Vector2F::splat(r0), start + Vector2F::splat(r0));
+                let b = RectF::from_points(end - Vector2F::splat(r1), end + Vector2F::splat(r1));
+                a.union_rect(b)
+            }
+        }
+    }
+}
+
+/// An axial or radial shading with the colors sampled at evenly spaced `t`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gradient {
+    pub shape: GradientShape,
+    /// the color at `t = i / (stops.len() - 1)`, there are at least two
+    pub stops: Vec<(f32, f32, f32)>,
+    /// whether the colors continue before `t = 0` and after `t = 1` (the /Extend entry)
+    pub extend: (bool, bool),
+}
+
+/// Read /Coords and /Extend of an axial or radial shading.
+pub fn gradient_shape(shading_type: i32, dict: &Dictionary) -> Result<(GradientShape, (bool, bool))> {
+    let coords = match dict.get("Coords") {
+        Some(c) => c.as_array()?.iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?,
+        None => return Err(PdfError::MissingEntry { typ: "Shading", field: "Coords".into() })
+    };
+    let shape = match (shading_type, coords.as_slice()) {
+        (2, &[x0, y0, x1, y1]) => GradientShape::Axial { start: Vector2F::new(x0, y0), end: Vector2F::new(x1, y1) },
+        (3, &[x0, y0, r0, x1, y1, r1]) => GradientShape::Radial { start: Vector2F::new(x0, y0), r0, end: Vector2F::new(x1, y1), r1 },
+        _ => return Err(PdfError::Other { msg: format!("shading type {} with {} coordinates", shading_type, coords.len()) })
+    };
+    let extend = match dict.get("Extend").map(|e| e.as_array()).transpose()? {
+        Some(&[Primitive::Boolean(a), Primitive::Boolean(b)]) => (a, b),
+        _ => (false, false)
+    };
+    Ok((shape, extend))
+}
+
+fn corners(r: RectF) -> [Vector2F; 4] {
+    [r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]
+}
+
+/// The range of `t` (at least 0 to 1) and of the perpendicular `s` that covers `area` for an axial shading.
+/// A point is `start + t * (end - start) + s * n`, where `n` is `end - start` rotated by 90°.
+/// None if `start` and `end` are the same point.
+pub fn axial_extent(start: Vector2F, end: Vector2F, area: RectF) -> Option<(f32, f32, f32, f32)> {
+    let d = end - start;
+    let len2 = d.square_length();
+    if len2 == 0.0 {
+        return None;
+    }
+    let normal = Vector2F::new(-d.y(), d.x());
+    let (mut t_min, mut t_max, mut s_min, mut s_max) = (0f32, 1f32, f32::INFINITY, f32::NEG_INFINITY);
+    for c in corners(area) {
+        let (t, s) = ((c - start).dot(d) / len2, (c - start).dot(normal) / len2);
+        t_min = t_min.min(t);
+        t_max = t_max.max(t);
+        s_min = s_min.min(s);
+        s_max = s_max.max(s);
+    }
+    Some((t_min, t_max, s_min, s_max))
+}
+
+// segments of a full circle for the rings of radial gradients
+const CIRCLE_STEPS: usize = 48;
+
+/// Cover `area` (in shading space) with triangles of the gradient, for backends without gradient paints.
+///
+/// The colors change linearly along each triangle, between the stops.
+pub fn gradient_triangles(gradient: &Gradient, area: RectF, emit: &mut impl FnMut([Vector2F; 3], [(f32, f32, f32); 3])) {
+    let stops = &gradient.stops;
+    if stops.len() < 2 {
+        return;
+    }
+    let last = stops.len() - 1;
+    // (t0, t1, color at t0, color at t1)
+    let mut bands = Vec::with_capacity(stops.len() + 1);
+    match gradient.shape {
+        GradientShape::Axial { start, end } => {
+            let (t_min, t_max, s_min, s_max) = match axial_extent(start, end, area) {
+                Some(e) => e,
+                None => return
+            };
+            let d = end - start;
+            let normal = Vector2F::new(-d.y(), d.x());
+            if gradient.extend.0 && t_min < 0.0 {
+                bands.push((t_min, 0.0, stops[0], stops[0]));
+            }
+            for i in 0 .. last {
+                bands.push((i as f32 / last as f32, (i + 1) as f32 / last as f32, stops[i], stops[i + 1]));
+            }
+            if gradient.extend.1 && t_max > 1.0 {
+                bands.push((1.0, t_max, stops[last], stops[last]));
+            }
+            let point = |t: f32, s: f32| start + d * t + normal * s;
+            for (t0, t1, c0, c1) in bands {
+                let (a, b, c, e) = (point(t0, s_min), point(t1, s_min), point(t1, s_max), point(t0, s_max));
+                emit([a, b, c], [c0, c1, c1]);
+                emit([a, c, e], [c0, c1, c0]);
+            }
+        }
+        GradientShape::Radial { start, r0, end, r1 } => {
+            let circle = |t: f32| (start.lerp(end, t), r0 + (r1 - r0) * t);
+            if gradient.extend.0 && r0 > 0.0 {
+                // the circles before t = 0, while their radius shrinks to 0
+                let t_min = if r1 != r0 { (-r0 / (r1 - r0)).max(-1e3).min(0.0) } else { 0.0 };
+                bands.push((t_min, 0.0, stops[0], stops[0]));
+            }
+            for i in 0 .. last {
+                bands.push((i as f32 / last as f32, (i + 1) as f32 / last as f32, stops[i], stops[i + 1]));
+            }
+            if gradient.extend.1 {
+                // grow the circles until the last one covers the area
+                let far = corners(area).iter().map(|&c| (c - end).length()).fold(0f32, f32::max);
+                let growth = (r1 - r0) - (end - start).length();
+                let t_max = if growth > 0.0 { 1.0 + ((far - r1) / growth).max(0.0) } else { 1.0 + far / r1.abs().max(1.0) };
+                if t_max > 1.0 {
+                    bands.push((1.0, t_max.min(1e3), stops[last], stops[last]));
+                }
+            }
+            let angle = |k: usize| {
+                let a = k as f32 * std::f32::consts::TAU / CIRCLE_STEPS as f32;
+                Vector2F::new(a.cos(), a.sin())
+            };
+            // later circles are drawn over earlier ones
+            for (t0, t1, c0, c1) in bands {
+                let ((p0, q0), (p1, q1)) = (circle(t0), circle(t1));
+                for k in 0 .. CIRCLE_STEPS {
+                    let (u, v) = (angle(k), angle(k + 1));
+                    let (a, b) = (p0 + u * q0.max(0.0), p0 + v * q0.max(0.0));
+                    let (c, e) = (p1 + v * q1.max(0.0), p1 + u * q1.max(0.0));
+                    emit([a, b, c], [c0, c0, c1]);
+                    emit([a, c, e], [c0, c1, c1]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf::primitive::Primitive;
+
+    // 8 bits for everything, coordinates in 0 ..= 255 and one component in 0 ..= 1
+    fn dict(extra: &[(&str, i32)]) -> Dictionary {
+        let mut dict = Dictionary::new();
+        dict.insert("BitsPerCoordinate", Primitive::Integer(8));
+        dict.insert("BitsPerComponent", Primitive::Integer(8));
+        dict.insert("Decode", Primitive::Array([0., 255., 0., 255., 0., 1.].iter().map(|&n| Primitive::Number(n)).collect()));
+        for &(key, value) in extra {
+            dict.insert(key, Primitive::Integer(value));
+        }
+        dict
+    }
+
+    fn points(tri: &Triangle) -> [(f32, f32); 3] {
+        [(tri[0].p.x(), tri[0].p.y()), (tri[1].p.x(), tri[1].p.y()), (tri[2].p.x(), tri[2].p.y())]
+    }
+
+    fn close(a: Vector2F, b: Vector2F) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn free_form_strip() {
+        let dict = dict(&[("BitsPerFlag", 8)]);
+        // flag, x, y, component
+        let data = [
+            0, 0, 0, 0,
+            0, 255, 0, 255,
+            0, 0, 255, 0,
+            1, 255, 255, 255,
+            // an incomplete vertex
+            2, 10,
+        ];
+        let triangles = decode_mesh(4, &dict, &data, None, 1).unwrap();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(points(&triangles[0]), [(0., 0.), (255., 0.), (0., 255.)]);
+        // flag 1 continues with the edge bc
+        assert_eq!(points(&triangles[1]), [(255., 0.), (0., 255.), (255., 255.)]);
+        assert_eq!(triangles[1][2].c, [1.0]);
+    }
+
+    #[test]
+    fn lattice_form() {
+        let dict = dict(&[("VerticesPerRow", 2)]);
+        // x, y, component; the last vertex doesn't complete a row
+        let data = [
+            0, 0, 0,
+            255, 0, 255,
+            0, 255, 0,
+            255, 255, 255,
+            128, 128, 128,
+        ];
+        let triangles = decode_mesh(5, &dict, &data, None, 1).unwrap();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(points(&triangles[0]), [(0., 0.), (255., 0.), (0., 255.)]);
+        assert_eq!(points(&triangles[1]), [(255., 0.), (255., 255.), (0., 255.)]);
+    }
+
+    #[test]
+    fn coons_patch() {
+        let dict = dict(&[("BitsPerFlag", 8)]);
+        // a flat square with p_ij at (3i, 3j), in the order of the stream
+        let mut data = vec![0];
+        for &(i, j) in &STREAM_ORDER[.. 12] {
+            data.extend_from_slice(&[3 * i as u8, 3 * j as u8]);
+        }
+        // the colors at p00, p03, p33 and p30
+        data.extend_from_slice(&[0, 255, 255, 0]);
+
+        let triangles = decode_mesh(6, &dict, &data, None, 2).unwrap();
+        assert_eq!(triangles.len(), 8);
+        let [a, b, c] = &triangles[0];
+        assert!(close(a.p, Vector2F::new(0., 0.)));
+        assert!(close(b.p, Vector2F::new(0., 4.5)));
+        assert!(close(c.p, Vector2F::new(4.5, 0.)));
+        // the color only changes along v
+        assert_eq!(b.c, [0.5]);
+        assert_eq!(c.c, [0.0]);
+        // the interior points of a flat patch keep it flat
+        let center = triangles[0 .. 2].iter().flatten().find(|v| v.c == [0.5] && v.p.x() > 1.0).unwrap();
+        assert!(close(center.p, Vector2F::new(4.5, 4.5)));
+    }
+
+    #[test]
+    fn errors() {
+        let mut no_decode = dict(&[("BitsPerFlag", 8)]);
+        no_decode.remove("Decode");
+        assert!(decode_mesh(4, &no_decode, &[], None, 1).is_err());
+
+        assert!(decode_mesh(4, &dict(&[]), &[], None, 1).is_err(), "type 4 needs BitsPerFlag");
+        assert!(decode_mesh(5, &dict(&[("VerticesPerRow", 1)]), &[], None, 1).is_err());
+        assert!(decode_mesh(4, &dict(&[("BitsPerFlag", 8), ("BitsPerCoordinate", 3)]), &[], None, 1).is_err());
+        assert!(decode_mesh(2, &dict(&[]), &[], None, 1).is_err());
+    }
+}
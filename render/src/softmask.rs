@@ -0,0 +1,87 @@
+//! Luminosity soft masks (`/SMask` in an ExtGState).
+
+use pathfinder_geometry::{
+    vector::{Vector2F, Vector2I},
+    rect::RectF,
+};
+
+/// A soft mask, rasterized in output space.
+pub struct SoftMask {
+    /// alpha of each pixel, row major
+    alpha: Vec<u8>,
+    size: Vector2I,
+    /// the area covered by the pixels, in output space
+    rect: RectF,
+    /// alpha outside of `rect`, the luminosity of the backdrop
+    outside: u8,
+}
+
+/// Luminosity of an RGB color as defined for blend modes in the PDF spec.
+pub fn luminosity(r: f32, g: f32, b: f32) -> f32 {
+    0.3 * r + 0.59 * g + 0.11 * b
+}
+
+impl SoftMask {
+    /// Build the mask from the rendered mask group, `rgba` has to be composited over the backdrop already.
+    pub fn from_luminosity(rgba: &[u8], size: Vector2I, rect: RectF, backdrop: (f32, f32, f32)) -> SoftMask {
+        let alpha = rgba.chunks_exact(4)
+            .map(|p| luminosity(p[0] as f32, p[1] as f32, p[2] as f32) as u8)
+            .collect();
+        let (r, g, b) = backdrop;
+        SoftMask {
+            alpha,
+            size,
+            rect,
+            outside: (luminosity(r, g, b) * 255.) as u8,
+        }
+    }
+    pub fn size(&self) -> Vector2I {
+        self.size
+    }
+    pub fn rect(&self) -> RectF {
+        self.rect
+    }
+    /// Alpha at the point `p` in output space.
+    pub fn alpha_at(&self, p: Vector2F) -> f32 {
+        let scale = self.size.to_f32() / self.rect.size();
+        let px = ((p - self.rect.origin()) * scale).floor().to_i32();
+        let a = if px.x() >= 0 && px.y() >= 0 && px.x() < self.size.x() && px.y() < self.size.y() {
+            self.alpha[(px.y() * self.size.x() + px.x()) as usize]
+        } else {
+            self.outside
+        };
+        a as f32 * (1.0 / 255.)
+    }
+    /// Mean alpha over `rect` in output space.
+    ///
+    /// Backends that can't mask per pixel multiply the opacity of a drawing with this.
+    pub fn mean_alpha(&self, rect: RectF) -> f32 {
+        let area = rect.width() * rect.height();
+        if area <= 0.0 {
+            return self.alpha_at(rect.origin());
+        }
+        let scale = self.size.to_f32() / self.rect.size();
+        let inside = match rect.intersection(self.rect) {
+            Some(r) => r,
+            None => return self.outside as f32 * (1.0 / 255.)
+        };
+        let min = ((inside.origin() - self.rect.origin()) * scale).floor().to_i32().max(Vector2I::zero());
+        let max = ((inside.lower_right() - self.rect.origin()) * scale).ceil().to_i32().min(self.size);
+
+        let mut sum = 0u64;
+        let mut count = 0u64;
+        for y in min.y() .. max.y() {
+            let row = (y * self.size.x()) as usize;
+            for x in min.x() .. max.x() {
+                sum += self.alpha[row + x as usize] as u64;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return self.alpha_at(rect.center());
+        }
+        let inside_area = inside.width() * inside.height();
+        let inside_mean = sum as f32 / count as f32;
+        (inside_mean * inside_area + self.outside as f32 * (area - inside_area)) / (area * 255.)
+    }
+}
@@ -0,0 +1,206 @@
+//! SVG path data of outlines, and an SVG overlay of what the `Tracer` recorded, for debugging.
+
+use std::fmt::Write;
+use pathfinder_content::{
+    outline::{Outline, Contour, ContourIterFlags},
+    segment::SegmentKind,
+};
+use pathfinder_geometry::{
+    rect::RectF,
+    transform2d::Transform2F,
+    vector::Vector2F,
+};
+use pdf::object::{Page, Resolve};
+use pdf::error::PdfError;
+use crate::{RenderOptions, render_page_with, ocr::escape};
+use crate::tracer::{DrawItem, ClipPath, TraceCache, Tracer};
+
+/// SVG path data with absolute commands (M, L, Q, C and Z).
+pub fn outline_to_svg_path(outline: &Outline) -> String {
+    let mut d = String::new();
+    let point = |d: &mut String, cmd: &str, p: Vector2F| {
+        write!(d, "{}{} {} ", cmd, p.x(), p.y()).unwrap();
+    };
+    for contour in outline.contours() {
+        let mut segments = contour.iter(ContourIterFlags::IGNORE_CLOSE_SEGMENT).peekable();
+        match segments.peek() {
+            Some(first) => point(&mut d, "M", first.baseline.from()),
+            None => continue
+        }
+        for segment in segments {
+            match segment.kind {
+                SegmentKind::Quadratic => {
+                    point(&mut d, "Q", segment.ctrl.from());
+                }
+                SegmentKind::Cubic => {
+                    point(&mut d, "C", segment.ctrl.from());
+                    point(&mut d, "", segment.ctrl.to());
+                }
+                _ => d.push('L'),
+            }
+            point(&mut d, "", segment.baseline.to());
+        }
+        if contour.is_closed() {
+            d.push_str("Z ");
+        }
+    }
+    d.truncate(d.trim_end().len());
+    d
+}
+
+/// Parse what `outline_to_svg_path` writes, relative commands are not supported.
+pub fn outline_from_svg_path(d: &str) -> Result<Outline, String> {
+    let mut outline = Outline::new();
+    let mut contour = Contour::new();
+    let mut tokens = Tokens { s: d };
+    while let Some(cmd) = tokens.command() {
+        match cmd {
+            'M' => {
+                if !contour.is_empty() {
+                    outline.push_contour(std::mem::replace(&mut contour, Contour::new()));
+                }
+                contour.push_endpoint(tokens.point()?);
+            }
+            'L' => contour.push_endpoint(tokens.point()?),
+            'Q' => {
+                let c = tokens.point()?;
+                contour.push_quadratic(c, tokens.point()?);
+            }
+            'C' => {
+                let (c1, c2) = (tokens.point()?, tokens.point()?);
+                contour.push_cubic(c1, c2, tokens.point()?);
+            }
+            'Z' => contour.close(),
+            c => return Err(format!("unsupported path command {:?}", c)),
+        }
+    }
+    if !tokens.s.is_empty() {
+        return Err(format!("invalid path data at {:?}", tokens.s));
+    }
+    if !contour.is_empty() {
+        outline.push_contour(contour);
+    }
+    Ok(outline)
+}
+
+struct Tokens<'a> {
+    s: &'a str,
+}
+impl<'a> Tokens<'a> {
+    fn skip_space(&mut self) {
+        self.s = self.s.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    fn command(&mut self) -> Option<char> {
+        self.skip_space();
+        let c = self.s.chars().next().filter(|c| c.is_ascii_alphabetic())?;
+        self.s = &self.s[1..];
+        Some(c)
+    }
+    fn number(&mut self) -> Result<f32, String> {
+        self.skip_space();
+        let end = self.s.find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))).unwrap_or(self.s.len());
+        let (n, rest) = self.s.split_at(end);
+        self.s = rest;
+        n.parse().map_err(|_| format!("invalid number {:?}", n))
+    }
+    fn point(&mut self) -> Result<Vector2F, String> {
+        Ok(Vector2F::new(self.number()?, self.number()?))
+    }
+}
+
+const OVERLAY_STYLE: &str = "\
+path, rect { fill: none; vector-effect: non-scaling-stroke; stroke-width: 1px; }
+.path { stroke: #0060c0; }
+.text { stroke: #d00000; }
+.image { stroke: #00a000; }
+.shading { stroke: #e08000; }
+.clip { stroke: #c000c0; stroke-dasharray: 4 2; }
+";
+
+fn matrix(tr: Transform2F) -> String {
+    let (m, t) = (tr.matrix, tr.vector);
+    format!("matrix({} {} {} {} {} {})", m.m11(), m.m21(), m.m12(), m.m22(), t.x(), t.y())
+}
+
+fn rect(svg: &mut String, class: &str, r: RectF, title: &str) {
+    writeln!(svg, r#"<rect class="{}" x="{}" y="{}" width="{}" height="{}"><title>{}</title></rect>"#,
+        class, r.min_x(), r.min_y(), r.width(), r.height(), escape(title)).unwrap();
+}
+
+/// Draw the outlines of the items and clip paths of a trace into an SVG of `view_box`.
+///
+/// Paths, text spans, images and shadings each have their own color, clip paths are dashed.
+/// Hovering over an item shows its op number.
+pub fn trace_to_svg(items: &[DrawItem], clip_paths: &[ClipPath], view_box: RectF) -> String {
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        view_box.min_x(), view_box.min_y(), view_box.width(), view_box.height()).unwrap();
+    writeln!(svg, "<style>{}</style>", OVERLAY_STYLE).unwrap();
+    for item in items {
+        let op = item.op_nr();
+        match item {
+            DrawItem::Vector(path) | DrawItem::PatternFill(path) => {
+                writeln!(svg, r#"<path class="path" d="{}" transform="{}"><title>op {}</title></path>"#,
+                    outline_to_svg_path(&path.outline), matrix(path.transform), op).unwrap();
+            }
+            DrawItem::Text(span, _) => rect(&mut svg, "text", span.rect, &format!("op {}: {}", op, span.text)),
+            DrawItem::Image(im) => rect(&mut svg, "image", im.rect, &format!("op {}: image {:?}", op, im.id.get_inner())),
+            DrawItem::InlineImage(im) => rect(&mut svg, "image", im.rect, &format!("op {}: inline image", op)),
+            DrawItem::Shading(shading) => rect(&mut svg, "shading", shading.rect, &format!("op {}: shading", op)),
+        }
+    }
+    // clip paths are in output space already
+    for (i, clip) in clip_paths.iter().enumerate() {
+        writeln!(svg, r#"<path class="clip" d="{}"><title>clip {}</title></path>"#, outline_to_svg_path(&clip.path), i).unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Trace `page` and draw the result with `trace_to_svg`.
+pub fn page_trace_svg(cache: &TraceCache, resolve: &impl Resolve, page: &Page, options: &RenderOptions) -> Result<String, PdfError> {
+    let mut clip_paths = vec![];
+    let mut tracer = Tracer::new(cache, &mut clip_paths);
+    render_page_with(&mut tracer, resolve, page, options)?;
+    let view_box = tracer.view_box();
+    let items = tracer.finish();
+    Ok(trace_to_svg(&items, &clip_paths, view_box))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut outline = Outline::from_rect(RectF::new(Vector2F::new(1.0, 2.0), Vector2F::new(3.0, 4.0)));
+        let mut curves = Contour::new();
+        curves.push_endpoint(Vector2F::new(0.0, 0.0));
+        curves.push_quadratic(Vector2F::new(5.0, 10.0), Vector2F::new(10.0, 0.0));
+        curves.push_cubic(Vector2F::new(12.5, -5.0), Vector2F::new(15.0, 5.0), Vector2F::new(20.0, -0.25));
+        outline.push_contour(curves);
+
+        let d = outline_to_svg_path(&outline);
+        assert_eq!(d, "M1 2 L4 2 L4 6 L1 6 Z M0 0 Q5 10 10 0 C12.5 -5 15 5 20 -0.25");
+        let parsed = outline_from_svg_path(&d).unwrap();
+        assert_eq!(outline_to_svg_path(&parsed), d);
+        assert_eq!(parsed.contours().len(), 2);
+        assert!(parsed.contours()[0].is_closed());
+        assert!(!parsed.contours()[1].is_closed());
+    }
+
+    #[test]
+    fn separators() {
+        let outline = outline_from_svg_path("M 0,0 L 1e1,0 L10 , 1.5Z").unwrap();
+        assert_eq!(outline_to_svg_path(&outline), "M0 0 L10 0 L10 1.5 Z");
+        assert_eq!(outline_from_svg_path("").unwrap().contours().len(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(outline_from_svg_path("m0 0 l1 1").is_err());
+        assert!(outline_from_svg_path("M0 0 Lx 1").is_err());
+        assert!(outline_from_svg_path("M0 0 L1").is_err());
+        assert!(outline_from_svg_path("M0 0 #").is_err());
+    }
+}
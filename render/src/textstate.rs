@@ -2,22 +2,117 @@ use pathfinder_geometry::{
     vector::Vector2F,
     transform2d::Transform2F,
 };
+use pathfinder_content::{fill::FillRule, outline::Outline};
 use font::GlyphId;
-use crate::{BlendMode, backend::{FillMode, Stroke}};
+use crate::{BlendMode, backend::{Stroke, FillMode}};
 
 use super::{
     BBox,
     fontentry::{FontEntry},
+    metrics::{StandardMetrics, standard_metrics, win_ansi_char},
     graphicsstate::{GraphicsState},
     DrawMode,
     Backend,
     TextChar,
+    PositionedGlyph,
 };
 use std::convert::TryInto;
 use pdf::content::TextMode;
+use pdf::font::{Font as PdfFont, Widths};
 use std::sync::Arc;
 use itertools::Either;
 use istring::SmallString;
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization;
+
+/// Post-processing applied to the unicode of each glyph before it is added to a `TextSpan`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextOptions {
+    /// apply NFKC normalization
+    pub nfkc: bool,
+    /// split the Latin typographic ligatures U+FB00 to U+FB06, like ﬁ and ﬂ, into their letters.
+    /// Letters like Ĳ and Œ are kept, they are part of the spelling.
+    pub expand_ligatures: bool,
+    pub remove_soft_hyphens: bool,
+    /// drop placeholders in the private use area
+    pub remove_private_use: bool,
+}
+impl TextOptions {
+    pub fn apply<'s>(&self, s: &'s str) -> Cow<'s, str> {
+        if *self == TextOptions::default() {
+            return Cow::Borrowed(s);
+        }
+        let mut out = String::with_capacity(s.len());
+        for c in s.chars() {
+            match c {
+                '\u{AD}' if self.remove_soft_hyphens => {}
+                '\u{E000}' ..= '\u{F8FF}' if self.remove_private_use => {}
+                c => match expand_ligature(c) {
+                    Some(l) if self.expand_ligatures => out.push_str(l),
+                    _ => out.push(c)
+                }
+            }
+        }
+        if self.nfkc {
+            out = out.nfkc().collect();
+        }
+        Cow::Owned(out)
+    }
+}
+
+fn expand_ligature(c: char) -> Option<&'static str> {
+    Some(match c {
+        '\u{FB00}' => "ff",
+        '\u{FB01}' => "fi",
+        '\u{FB02}' => "fl",
+        '\u{FB03}' => "ffi",
+        '\u{FB04}' => "ffl",
+        '\u{FB05}' | '\u{FB06}' => "st",
+        _ => return None
+    })
+}
+
+/// Translucent glyphs of a text object with text knockout (/TK true).
+///
+/// Glyphs with the same fill are merged into one outline, so where they overlap
+/// they are only painted once instead of adding up.
+/// A text object can't change the clip path, so that is the same for all of them.
+pub struct KnockoutGroup<B: Backend> {
+    outline: Outline,
+    fill: Option<FillMode>,
+    clip: Option<B::ClipPathId>,
+}
+impl<B: Backend> KnockoutGroup<B> {
+    pub fn new() -> Self {
+        KnockoutGroup { outline: Outline::new(), fill: None, clip: None }
+    }
+}
+impl<B: Backend> Default for KnockoutGroup<B> {
+    fn default() -> Self {
+        KnockoutGroup::new()
+    }
+}
+impl<B: Backend> KnockoutGroup<B> {
+    fn add(&mut self, backend: &mut B, path: &Outline, fill: &FillMode, transform: Transform2F, clip: Option<B::ClipPathId>) {
+        if self.fill.as_ref() != Some(fill) {
+            self.flush(backend);
+            self.fill = Some(fill.clone());
+        }
+        self.clip = clip;
+        for contour in path.clone().transformed(&transform).into_contours() {
+            self.outline.push_contour(contour);
+        }
+    }
+    /// Draw the glyphs collected so far.
+    pub fn flush(&mut self, backend: &mut B) {
+        if let Some(fill) = self.fill.take() {
+            let outline = std::mem::replace(&mut self.outline, Outline::new());
+            if !outline.is_empty() {
+                backend.draw(Cow::Owned(outline), &DrawMode::Fill { fill }, FillRule::Winding, Transform2F::default(), self.clip);
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct TextState {
@@ -31,7 +126,10 @@ pub struct TextState {
     pub font_size: f32, // Text font size
     pub mode: TextMode, // Text rendering mode
     pub rise: f32, // Text rise
-    pub knockout: f32, //Text knockout
+    pub knockout: bool, // Text knockout
+    pub standard_metrics: Option<&'static StandardMetrics>, // widths of a standard font, if the font is one
+    pub widths: Option<Arc<Widths>>, // /Widths of the PDF font, only set if there is no font entry
+    pub is_cid: bool, // whether the PDF font uses two byte codes
 }
 impl TextState {
     pub fn new() -> TextState {
@@ -46,9 +144,21 @@ impl TextState {
             font_size: 0.,
             mode: TextMode::Fill,
             rise: 0.,
-            knockout: 0.
+            knockout: true,
+            standard_metrics: None,
+            widths: None,
+            is_cid: false,
         }
     }
+    pub fn set_font(&mut self, pdf_font: Option<&PdfFont>, entry: Option<Arc<FontEntry>>, widths: Option<Arc<Widths>>, size: f32) {
+        self.standard_metrics = pdf_font.filter(|f| !f.is_cid())
+            .and_then(|f| f.name.as_ref())
+            .and_then(|name| standard_metrics(name.as_str()));
+        self.is_cid = pdf_font.map_or(false, |f| f.is_cid());
+        self.font_entry = entry;
+        self.widths = widths;
+        self.font_size = size;
+    }
     pub fn reset_matrix(&mut self) {
         self.set_matrix(Transform2F::default());
     }
@@ -66,11 +176,15 @@ impl TextState {
         self.text_matrix = m;
         self.line_matrix = m;
     }
-    pub fn draw_text<B: Backend>(&mut self, backend: &mut B, gs: &GraphicsState<B>, data: &[u8], span: &mut Span, fill_mode: BlendMode, stroke_mode: BlendMode) {
+    pub fn draw_text<B: Backend>(&mut self, backend: &mut B, gs: &GraphicsState<B>, data: &[u8], span: &mut Span, fill_mode: BlendMode, stroke_mode: BlendMode, knockout: &mut KnockoutGroup<B>) {
         let e = match self.font_entry {
             Some(ref e) => e,
             None => {
-                debug!("no font set");
+                if self.widths.is_some() || self.standard_metrics.is_some() {
+                    self.advance_without_font(data, span);
+                } else {
+                    debug!("no font set");
+                }
                 return;
             }
         };
@@ -85,9 +199,10 @@ impl TextState {
             (cid, e.cmap.get(&cid).map(|&(gid, ref uni)| (gid, uni.clone())))
         );
 
-        let fill = FillMode { color: gs.fill_color, alpha: gs.fill_color_alpha, mode: fill_mode };
-        let stroke = FillMode { color: gs.stroke_color, alpha: gs.stroke_color_alpha, mode: stroke_mode };
+        let fill = gs.fill_mode(fill_mode);
+        let stroke = gs.stroke_fill_mode(stroke_mode);
         let stroke_mode = gs.stroke();
+        let text_options = backend.text_options();
 
         let draw_mode = match self.mode {
             TextMode::Fill => Some(DrawMode::Fill { fill }),
@@ -97,12 +212,23 @@ impl TextState {
             TextMode::Stroke => Some(DrawMode::Stroke { stroke, stroke_mode }),
             TextMode::StrokeAndClip => Some(DrawMode::Stroke { stroke, stroke_mode }),
         };
-        let e = self.font_entry.as_ref().expect("no font");
 
         let tr = Transform2F::row_major(
             self.horiz_scale * self.font_size, 0., 0.,
             0., self.font_size, self.rise
         ) * e.font.font_matrix();
+
+        // plain glyphs are collected into a run, relative to the text matrix at its start.
+        // The run is drawn before color and knockout glyphs to keep the painting order.
+        let mut run: Vec<PositionedGlyph> = vec![];
+        let mut run_start = self.text_matrix;
+        let mut run_x = 0.0;
+        let flush = |backend: &mut B, run: &mut Vec<PositionedGlyph>, run_start: Transform2F| {
+            if let (Some(draw_mode), false) = (draw_mode.as_ref(), run.is_empty()) {
+                backend.draw_glyph_run(e, run, draw_mode, gs.transform * run_start, gs.clip_path_id);
+            }
+            run.clear();
+        };
         
         for (cid, t) in glyphs {
             let (gid, unicode, is_space) = match t {
@@ -115,30 +241,64 @@ impl TextState {
             //debug!("cid {} -> gid {:?} {:?}", cid, gid, unicode);
             
             let glyph = e.font.glyph(gid);
-            let width: f32 = e.widths.as_ref().map(|w| w.get(cid as usize) * 0.001 * self.horiz_scale * self.font_size)
+            let standard_width = || self.standard_metrics
+                .and_then(|m| m.glyph_width(cid, unicode.as_ref().and_then(|u| u.chars().next())));
+            let width: f32 = e.widths.as_ref().map(|w| w.get(cid as usize))
+                .or_else(standard_width)
+                .map(|w| w * 0.001 * self.horiz_scale * self.font_size)
                 .or_else(|| glyph.as_ref().map(|g| tr.m11() * g.metrics.advance))
                 .unwrap_or(0.0);
             
             if is_space {
                 let advance = (self.char_space + self.word_space) * self.horiz_scale + width;
                 self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+                run_x += advance;
 
                 let offset = span.text.len();
                 span.text.push(' ');
                 span.chars.push(TextChar {
                     offset,
                     pos: span.width,
-                    width
+                    width,
+                    bbox: None,
                 });
                 span.width += advance;
                 continue;
             }
+            let mut glyph_bbox = None;
             if let Some(glyph) = glyph {
                 let transform = gs.transform * self.text_matrix * tr;
-                if glyph.path.len() != 0 {
-                    span.bbox.add(gs.transform * transform * glyph.path.bounds());
-                    if let Some(ref draw_mode) = draw_mode {
-                        backend.draw_glyph(&glyph, draw_mode, transform, gs.clip_path_id);
+                let color_glyph = e.color.as_ref().and_then(|c| c.glyph(&e.font, gid));
+                if glyph.path.len() != 0 || color_glyph.is_some() {
+                    let bounds = match color_glyph {
+                        Some(ref c) => c.bounds(),
+                        None => glyph.path.bounds()
+                    };
+                    // `transform` already includes the CTM
+                    let rect = transform * bounds;
+                    glyph_bbox = Some(rect);
+                    span.bbox.add(rect);
+                    match (draw_mode.as_ref(), color_glyph) {
+                        (Some(draw_mode), Some(ref c)) => {
+                            flush(backend, &mut run, run_start);
+                            backend.draw_color_glyph(c, draw_mode, transform, gs.clip_path_id);
+                        }
+                        (Some(DrawMode::Fill { fill }), None) if self.knockout && fill.alpha < 1.0 => {
+                            flush(backend, &mut run, run_start);
+                            knockout.add(backend, &glyph.path, fill, transform, gs.clip_path_id);
+                        }
+                        (Some(_), None) => {
+                            if run.is_empty() {
+                                run_start = self.text_matrix;
+                                run_x = 0.0;
+                            }
+                            run.push(PositionedGlyph {
+                                gid,
+                                glyph,
+                                transform: Transform2F::from_translation(Vector2F::new(run_x, 0.)) * tr,
+                            });
+                        }
+                        (None, _) => {}
                     }
                 }
             } else {
@@ -146,15 +306,55 @@ impl TextState {
             }
             let advance = self.char_space * self.horiz_scale + width;
             self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+            run_x += advance;
             
             let offset = span.text.len();
             if let Some(s) = unicode {
-                span.text.push_str(&*s);
+                let s = text_options.apply(&s);
+                if s.len() > 0 {
+                    span.text.push_str(&s);
+                    span.chars.push(TextChar {
+                        offset,
+                        pos: span.width,
+                        width,
+                        bbox: glyph_bbox,
+                    });
+                }
+            }
+            span.width += advance;
+        }
+        flush(backend, &mut run, run_start);
+    }
+    /// No font was loaded, but the widths are known: only advance and record the text.
+    /// The text of two byte codes is unknown without the font.
+    fn advance_without_font(&mut self, data: &[u8], span: &mut Span) {
+        let codes = if self.is_cid {
+            Either::Left(data.chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]])))
+        } else {
+            Either::Right(data.iter().map(|&b| b as u16))
+        };
+        for code in codes {
+            let c = u8::try_from(code).ok().filter(|_| !self.is_cid).and_then(win_ansi_char);
+            let width = match (&self.widths, self.standard_metrics, c) {
+                (Some(widths), _, _) => widths.get(code as usize),
+                (None, Some(metrics), c) => metrics.glyph_width(code, c).unwrap_or(0.0),
+                _ => 0.0
+            } * 0.001 * self.horiz_scale * self.font_size;
+            let mut advance = self.char_space * self.horiz_scale + width;
+            if c == Some(' ') {
+                advance += self.word_space * self.horiz_scale;
+            }
+            self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+
+            // the standard and WinAnsi encodings agree on printable ASCII
+            if let Some(c) = c.filter(|c| c.is_ascii_graphic() || *c == ' ') {
                 span.chars.push(TextChar {
-                    offset,
+                    offset: span.text.len(),
                     pos: span.width,
-                    width
+                    width,
+                    bbox: None,
                 });
+                span.text.push(c);
             }
             span.width += advance;
         }
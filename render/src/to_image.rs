@@ -0,0 +1,106 @@
+use std::path::Path;
+use image::RgbaImage;
+use pdf::file::FileOptions;
+use pdf::object::{Page, Resolve};
+use pdf::error::PdfError;
+use pathfinder_geometry::{
+    rect::{RectF, RectI},
+    transform2d::Transform2F,
+    vector::{Vector2F, vec2i},
+};
+use pathfinder_renderer::scene::Scene;
+use crate::{Cache, SceneBackend, RenderOptions, render_page_with};
+
+pub enum Source<'a> {
+    Path(&'a Path),
+    Bytes(Vec<u8>),
+}
+impl<'a> From<&'a Path> for Source<'a> {
+    fn from(path: &'a Path) -> Self {
+        Source::Path(path)
+    }
+}
+impl<'a> From<Vec<u8>> for Source<'a> {
+    fn from(data: Vec<u8>) -> Self {
+        Source::Bytes(data)
+    }
+}
+
+#[cfg(feature="raster")]
+fn rasterize(scene: Scene, options: &RenderOptions) -> RgbaImage {
+    crate::raster::rasterize_with(&scene, options.background, &options.quality)
+}
+// pathfinder_rasterize has no quality settings
+#[cfg(not(feature="raster"))]
+fn rasterize(scene: Scene, options: &RenderOptions) -> RgbaImage {
+    pathfinder_rasterize::Rasterizer::new().rasterize(scene, options.background)
+}
+
+/// Open the document, render page `page_nr` (0 based) and rasterize it.
+pub fn render_to_image<'a>(source: impl Into<Source<'a>>, page_nr: u32, options: &RenderOptions) -> Result<RgbaImage, PdfError> {
+    let file = match source.into() {
+        Source::Path(path) => FileOptions::cached().open(path)?,
+        Source::Bytes(data) => FileOptions::cached().load(data)?,
+    };
+    let resolver = file.resolver();
+    let page = file.get_page(page_nr)?;
+
+    let mut cache = Cache::new();
+    let mut backend = SceneBackend::new(&mut cache);
+    render_page_with(&mut backend, &resolver, &page, options)?;
+
+    Ok(rasterize(backend.finish(), options))
+}
+
+/// A caller owned RGBA8 pixel buffer, for example the staging buffer of a texture.
+pub struct PixelBuffer<'a> {
+    pub data: &'a mut [u8],
+    pub width: u32,
+    pub height: u32,
+    /// bytes per row, at least `4 * width`
+    pub stride: usize,
+}
+impl<'a> PixelBuffer<'a> {
+    fn bounds(&self) -> RectI {
+        RectI::new(vec2i(0, 0), vec2i(self.width as i32, self.height as i32))
+    }
+}
+
+/// Render the page into `buffer`, where the output of `options` is placed at the origin of the buffer.
+///
+/// Only the pixels inside of `damage` are written; all of the buffer if `damage` is empty.
+/// Every damage rect is rendered separately, so merge them if there are many.
+/// Returns the rects that were written, clipped to the buffer,
+/// for the caller to upload (e.g. with `wgpu::Queue::write_texture`).
+pub fn render_into_buffer(cache: &mut Cache, resolve: &impl Resolve, page: &Page, options: &RenderOptions, buffer: &mut PixelBuffer, damage: &[RectI]) -> Result<Vec<RectI>, PdfError> {
+    if buffer.stride < 4 * buffer.width as usize || buffer.data.len() < buffer.stride * buffer.height as usize {
+        return Err(PdfError::Other { msg: "pixel buffer is too small".into() });
+    }
+    let full = [buffer.bounds()];
+    let damage = if damage.is_empty() { &full[..] } else { damage };
+
+    let mut written = vec![];
+    for rect in damage {
+        let rect = match rect.intersection(buffer.bounds()) {
+            Some(r) if r.width() > 0 && r.height() > 0 => r,
+            _ => continue
+        };
+        let region_options = RenderOptions {
+            transform: Transform2F::from_translation(-rect.origin().to_f32()) * options.transform,
+            ..options.clone()
+        };
+        let mut backend = SceneBackend::new(cache);
+        render_page_with(&mut backend, resolve, page, &region_options)?;
+        let mut scene = backend.finish();
+        scene.set_view_box(RectF::new(Vector2F::zero(), rect.size().to_f32()));
+
+        let image = rasterize(scene, options);
+        let row_len = 4 * rect.width().min(image.width() as i32) as usize;
+        for (y, row) in image.as_raw().chunks(4 * image.width() as usize).take(rect.height() as usize).enumerate() {
+            let start = (rect.min_y() as usize + y) * buffer.stride + 4 * rect.min_x() as usize;
+            buffer.data[start .. start + row_len].copy_from_slice(&row[.. row_len]);
+        }
+        written.push(rect);
+    }
+    Ok(written)
+}
@@ -1,4 +1,4 @@
-use crate::{TextSpan, DrawMode, Backend, FontEntry, Fill, backend::{BlendMode, FillMode}, BBox};
+use crate::{TextSpan, DrawMode, Backend, FontEntry, Fill, TextOptions, CacheOptions, FunctionCache, Gradient, ImageData, load_image, backend::{BlendMode, FillMode, MarkedContent}, BBox};
 use pathfinder_content::{
     outline::Outline,
     fill::FillRule,
@@ -16,18 +16,25 @@ use font::Glyph;
 use pdf::font::Font as PdfFont;
 use pdf::error::PdfError;
 use std::sync::Arc;
+use std::borrow::Cow;
 use std::path::PathBuf;
-use crate::font::{load_font, StandardCache};
+use std::time::Duration;
+use instant::Instant;
+use crate::font::{load_font, font_key, StandardCache, FontResolver, FontDebugSink, EmbeddedFonts};
 use globalcache::sync::SyncCache;
 use crate::backend::Stroke;
 
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClipPath {
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::outline"))]
     pub path: Outline,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::fill_rule"))]
     pub fill_rule: FillRule,
     pub parent: Option<ClipPathId>,
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClipPathId(pub usize);
 
 pub struct Tracer<'a> {
@@ -36,28 +43,59 @@ pub struct Tracer<'a> {
     pub view_box: RectF,
     cache: &'a TraceCache,
     op_nr: usize,
+    text_options: TextOptions,
+    geometry_only: bool,
+    // the open marked content sequences, shared by the items drawn inside of them
+    marked_content: Arc<[MarkedContent]>,
+    // the marked content of each item
+    tags: Vec<Arc<[MarkedContent]>>,
+    // only with set_timing: when the last item was recorded and the time each item took
+    timing: bool,
+    last_item: Option<Instant>,
+    times: Vec<Duration>,
 }
 pub struct TraceCache {
     fonts: Arc<SyncCache<u64, Option<Arc<FontEntry>>>>,
+    embedded_fonts: Arc<EmbeddedFonts>,
+    functions: FunctionCache,
     std: StandardCache,
 }
-fn font_key(font_ref: &MaybeRef<PdfFont>) -> u64 {
-    match font_ref {
-        MaybeRef::Direct(ref shared) => shared.as_ref() as *const PdfFont as _,
-        MaybeRef::Indirect(re) => re.get_ref().get_inner().id as _
-    }
-}
 impl TraceCache {
     pub fn new() -> Self {
         TraceCache {
             fonts: SyncCache::new(),
+            embedded_fonts: SyncCache::new(),
+            functions: FunctionCache::new(),
             std: StandardCache::new(),
         }
     }
+    pub fn with_options(options: CacheOptions) -> Self {
+        let mut std = StandardCache::new();
+        std.set_options(&options);
+        TraceCache {
+            fonts: SyncCache::new(),
+            embedded_fonts: SyncCache::new(),
+            functions: FunctionCache::new(),
+            std,
+        }
+    }
+    /// Change how fonts are loaded. Fonts that are already cached are not reloaded.
+    pub fn set_options(&mut self, options: &CacheOptions) {
+        self.std.set_options(options);
+    }
+    /// Load substitutes for non-embedded fonts from `resolver` instead of STANDARD_FONTS.
+    pub fn with_font_resolver(resolver: impl FontResolver + 'static) -> Self {
+        TraceCache {
+            fonts: SyncCache::new(),
+            embedded_fonts: SyncCache::new(),
+            functions: FunctionCache::new(),
+            std: StandardCache::with_resolver(resolver),
+        }
+    }
     pub fn get_font(&self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
         let mut error = None;
-        let val = self.fonts.get(font_key(font_ref), |_| 
-            match load_font(font_ref, resolve, &self.std) {
+        let mut load = || {
+            match load_font(font_ref, resolve, &self.std, &self.embedded_fonts) {
                 Ok(Some(f)) => Some(Arc::new(f)),
                 Ok(None) => None,
                 Err(e) => {
@@ -65,7 +103,11 @@ impl TraceCache {
                     None
                 }
             }
-        );
+        };
+        let val = match font_key(font_ref) {
+            Some(key) => self.fonts.get(key, |_| load()),
+            None => load()
+        };
         match error {
             None => Ok(val),
             Some(e) => Err(e)
@@ -74,6 +116,10 @@ impl TraceCache {
     pub fn require_unique_unicode(&mut self, require_unique_unicode: bool) {
         self.std.require_unique_unicode(require_unique_unicode);
     }
+    /// Pass embedded fonts to `sink` when they are parsed.
+    pub fn set_font_debug_sink(&mut self, sink: Option<Arc<dyn FontDebugSink>>) {
+        self.std.set_debug_sink(sink);
+    }
 }
 impl<'a> Tracer<'a> {
     pub fn new(cache: &'a TraceCache, clip_paths: &'a mut Vec<ClipPath>) -> Self {
@@ -83,11 +129,51 @@ impl<'a> Tracer<'a> {
             cache,
             op_nr: 0,
             clip_paths,
+            text_options: TextOptions::default(),
+            geometry_only: false,
+            marked_content: Arc::new([]),
+            tags: vec![],
+            timing: false,
+            last_item: None,
+            times: vec![],
         }
     }
+    pub fn set_text_options(&mut self, options: TextOptions) {
+        self.text_options = options;
+    }
+    /// Don't load fonts, which is a lot faster and works without STANDARD_FONTS.
+    /// Text spans are measured with the /Widths of the fonts and have no glyph bounds.
+    /// The text of fonts with two byte codes is unknown, only their spans are recorded.
+    pub fn set_geometry_only(&mut self, geometry_only: bool) {
+        self.geometry_only = geometry_only;
+    }
+    /// Measure how long it takes to interpret the ops of each item, see `item_times`.
+    pub fn set_timing(&mut self, timing: bool) {
+        self.timing = timing;
+    }
+    /// With `set_timing`, the wall-clock time of each item in `items`: from the previous item
+    /// (or the start of the page) until this one was drawn. It includes the ops that build paths,
+    /// change the graphics state and load fonts for it.
+    /// The `Tracer` does not decode images, so their decoding is not part of it.
+    pub fn item_times(&self) -> &[Duration] {
+        &self.times
+    }
     pub fn finish(self) -> Vec<DrawItem> {
         self.items
     }
+    /// The items with the marked content sequences they are in, outermost first.
+    pub fn finish_with_tags(self) -> Vec<(DrawItem, Arc<[MarkedContent]>)> {
+        self.items.into_iter().zip(self.tags).collect()
+    }
+    fn push(&mut self, item: DrawItem) {
+        self.items.push(item);
+        self.tags.push(self.marked_content.clone());
+        if self.timing {
+            let now = Instant::now();
+            self.times.push(self.last_item.map_or(Duration::ZERO, |last| now - last));
+            self.last_item = Some(now);
+        }
+    }
     pub fn view_box(&self) -> RectF {
         self.view_box
     }
@@ -104,64 +190,125 @@ impl<'a> Backend for Tracer<'a> {
         });
         id
     }
-    fn draw(&mut self, outline: &Outline, mode: &DrawMode, _fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
+    fn draw(&mut self, outline: Cow<Outline>, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
         let stroke = match mode {
             DrawMode::FillStroke { stroke, stroke_mode, .. } | DrawMode::Stroke { stroke, stroke_mode } => Some((stroke.clone(), stroke_mode.clone())),
             DrawMode::Fill { .. } => None,
         };
-        self.items.push(DrawItem::Vector(VectorPath {
-            outline: outline.clone(),
+        let path = VectorPath {
+            outline: outline.into_owned(),
             fill: match mode {
                 DrawMode::Fill { fill } | DrawMode::FillStroke { fill, .. } => Some(fill.clone()),
                 _ => None
             },
             stroke,
+            fill_rule,
             transform,
             clip,
             op_nr: self.op_nr,
-        }));
+        };
+        let pattern = path.fill.iter().chain(path.stroke.as_ref().map(|(stroke, _)| stroke))
+            .any(|f| matches!(f.color, Fill::Pattern(_)));
+        self.push(if pattern { DrawItem::PatternFill(path) } else { DrawItem::Vector(path) });
     }
     fn set_view_box(&mut self, r: RectF) {
         self.view_box = r;
+        if self.timing {
+            self.last_item = Some(Instant::now());
+        }
     }
-    fn draw_image(&mut self, xref: Ref<XObject>, _im: &ImageXObject, _resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) {
+    fn draw_image(&mut self, xref: Ref<XObject>, _im: &ImageXObject, _resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
         let rect = transform * RectF::new(
             Vector2F::new(0.0, 0.0), Vector2F::new(1.0, 1.0)
         );
-        self.items.push(DrawItem::Image(ImageObject {
+        self.push(DrawItem::Image(ImageObject {
             rect, id: xref, transform, op_nr: self.op_nr, mode, clip
         }));
+        Ok(())
     }
-    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, _resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) {
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, _resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
         let rect = transform * RectF::new(
             Vector2F::new(0.0, 0.0), Vector2F::new(1.0, 1.0)
         );
 
-        self.items.push(DrawItem::InlineImage(InlineImageObject {
+        self.push(DrawItem::InlineImage(InlineImageObject {
             rect, im: im.clone(), transform, op_nr: self.op_nr, mode, clip
         }));
+        Ok(())
+    }
+    fn draw_gradient(&mut self, gradient: &Gradient, area: RectF, alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<ClipPathId>) {
+        self.push(DrawItem::Shading(ShadingObject {
+            rect: area, gradient: Some(gradient.clone()), triangles: 0, alpha, mode, transform, op_nr: self.op_nr, clip
+        }));
+    }
+    fn draw_shaded_triangle(&mut self, points: [Vector2F; 3], _colors: [(f32, f32, f32); 3], alpha: f32, mode: BlendMode, transform: Transform2F, clip: Option<ClipPathId>) {
+        let [a, b, c] = points.map(|p| transform * p);
+        let rect = RectF::from_points(a.min(b).min(c), a.max(b).max(c));
+        // the triangles of a mesh become one item
+        if let Some(DrawItem::Shading(shading)) = self.items.last_mut() {
+            if shading.op_nr == self.op_nr && shading.gradient.is_none() {
+                shading.rect = shading.rect.union_rect(rect);
+                shading.triangles += 1;
+                return;
+            }
+        }
+        self.push(DrawItem::Shading(ShadingObject {
+            rect, gradient: None, triangles: 1, alpha, mode, transform, op_nr: self.op_nr, clip
+        }));
     }
     fn draw_glyph(&mut self, _glyph: &Glyph, _mode: &DrawMode, _transform: Transform2F, clip: Option<ClipPathId>) {}
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
         self.cache.get_font(font_ref, resolve)
     }
+    fn function_cache(&mut self) -> Option<FunctionCache> {
+        Some(self.cache.functions.clone())
+    }
     fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>) {
-        self.items.push(DrawItem::Text(span, clip));
+        self.push(DrawItem::Text(span, clip));
+    }
+    fn text_options(&self) -> TextOptions {
+        self.text_options
+    }
+    fn load_fonts(&self) -> bool {
+        !self.geometry_only
     }
     fn bug_op(&mut self, op_nr: usize) {
         self.op_nr = op_nr;
     }
+    fn begin_marked_content(&mut self, marked_content: &MarkedContent) {
+        let mut open = self.marked_content.to_vec();
+        open.push(marked_content.clone());
+        self.marked_content = open.into();
+    }
+    fn end_marked_content(&mut self) {
+        let n = self.marked_content.len().saturating_sub(1);
+        self.marked_content = self.marked_content[..n].into();
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImageObject {
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::rect"))]
     pub rect: RectF,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::pdf_ref"))]
     pub id: Ref<XObject>,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::transform"))]
     pub transform: Transform2F,
     pub op_nr: usize,
     pub mode: BlendMode,
     pub clip: Option<ClipPathId>,
 }
+impl ImageObject {
+    /// Decode the image the way it is drawn.
+    /// Image XObjects can't refer to named color spaces, so any `resources` will do, like those of the page.
+    pub fn decode(&self, resolve: &impl Resolve, resources: &Resources) -> Result<ImageData<'static>, PdfError> {
+        match *resolve.get(self.id)? {
+            XObject::Image(ref im) => load_image(im, resources, resolve, self.mode),
+            _ => Err(PdfError::Other { msg: format!("{:?} is not an image", self.id.get_inner()) })
+        }
+    }
+}
 #[derive(Debug)]
 pub struct InlineImageObject {
     pub rect: RectF,
@@ -172,19 +319,172 @@ pub struct InlineImageObject {
     pub clip: Option<ClipPathId>,
 }
 
+/// A shading painted with `sh`.
+#[derive(Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShadingObject {
+    /// bounds of the painted area in output space
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::rect"))]
+    pub rect: RectF,
+    /// the colors of an axial or radial shading, None for meshes
+    pub gradient: Option<Gradient>,
+    /// number of triangles of a mesh, 0 for gradients
+    pub triangles: usize,
+    pub alpha: f32,
+    pub mode: BlendMode,
+    /// maps shading space to the output
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::transform"))]
+    pub transform: Transform2F,
+    pub op_nr: usize,
+    pub clip: Option<ClipPathId>,
+}
+
+/// With the `serde` feature, everything but inline images can be (de)serialized,
+/// serializing an inline image is an error.
 #[derive(Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DrawItem {
     Vector(VectorPath),
     Image(ImageObject),
+    #[cfg_attr(feature="serde", serde(skip))]
     InlineImage(InlineImageObject),
     Text(TextSpan, Option<ClipPathId>),
+    Shading(ShadingObject),
+    /// a path that is filled or stroked with a tiling pattern, see `FillMode::color`
+    PatternFill(VectorPath),
+}
+impl InlineImageObject {
+    /// Decode the image the way it is drawn.
+    /// `resources` are those of the content stream it is in, for named color spaces.
+    pub fn decode(&self, resolve: &impl Resolve, resources: &Resources) -> Result<ImageData<'static>, PdfError> {
+        load_image(&self.im, resources, resolve, self.mode)
+    }
+}
+
+impl DrawItem {
+    /// bounds in output space
+    pub fn bounds(&self) -> RectF {
+        match self {
+            DrawItem::Vector(path) | DrawItem::PatternFill(path) => {
+                let bounds = path.transform * path.outline.bounds();
+                match path.stroke {
+                    Some((_, ref stroke)) => bounds.dilate(0.5 * stroke.style.line_width * path.transform.matrix.m11().abs()),
+                    None => bounds
+                }
+            }
+            DrawItem::Image(im) => im.rect,
+            DrawItem::InlineImage(im) => im.rect,
+            DrawItem::Text(span, _) => span.rect,
+            DrawItem::Shading(shading) => shading.rect,
+        }
+    }
+    pub fn op_nr(&self) -> usize {
+        match self {
+            DrawItem::Vector(path) | DrawItem::PatternFill(path) => path.op_nr,
+            DrawItem::Image(im) => im.op_nr,
+            DrawItem::InlineImage(im) => im.op_nr,
+            DrawItem::Text(span, _) => span.op_nr,
+            DrawItem::Shading(shading) => shading.op_nr,
+        }
+    }
+}
+
+// largest number of grid cells per side of an ItemMap
+const MAX_GRID_SIZE: usize = 64;
+
+/// Maps positions on the page to the items drawn there.
+///
+/// The items are sorted into a grid over their bounds, so lookups only look at the items near a point.
+pub struct ItemMap<'a> {
+    items: &'a [DrawItem],
+    rects: Vec<RectF>,
+    bounds: RectF,
+    grid_size: usize,
+    // row major, the indices of the items that touch each cell in drawing order
+    cells: Vec<Vec<usize>>,
+}
+impl<'a> ItemMap<'a> {
+    pub fn new(items: &'a [DrawItem]) -> Self {
+        let rects: Vec<RectF> = items.iter().map(|item| item.bounds()).collect();
+        let bounds = rects.iter().cloned()
+            .filter(|r| [r.min_x(), r.min_y(), r.max_x(), r.max_y()].iter().all(|v| v.is_finite()))
+            .reduce(|a, b| a.union_rect(b))
+            .unwrap_or_default();
+        // about one item per cell if they were spread evenly
+        let grid_size = ((rects.len() as f32).sqrt().ceil() as usize).clamp(1, MAX_GRID_SIZE);
+        let mut map = ItemMap {
+            items,
+            rects: vec![],
+            bounds,
+            grid_size,
+            cells: vec![vec![]; grid_size * grid_size],
+        };
+        for (i, &r) in rects.iter().enumerate() {
+            if !r.intersects(bounds) {
+                continue;
+            }
+            let ((x0, y0), (x1, y1)) = (map.cell(r.origin()), map.cell(r.lower_right()));
+            for y in y0 ..= y1 {
+                for x in x0 ..= x1 {
+                    map.cells[y * grid_size + x].push(i);
+                }
+            }
+        }
+        map.rects = rects;
+        map
+    }
+    // the cell `p` is in, points outside of the grid are moved to the closest cell
+    fn cell(&self, p: Vector2F) -> (usize, usize) {
+        let rel = (p - self.bounds.origin()) * Vector2F::splat(self.grid_size as f32) / self.bounds.size();
+        let max = self.grid_size - 1;
+        ((rel.x() as usize).min(max), (rel.y() as usize).min(max))
+    }
+    /// the items that cover `p` with their index, topmost first
+    pub fn at(&self, p: Vector2F) -> impl Iterator<Item=(usize, &'a DrawItem)> + '_ {
+        let cell: &[usize] = if self.bounds.contains_point(p) {
+            let (x, y) = self.cell(p);
+            &self.cells[y * self.grid_size + x]
+        } else {
+            &[]
+        };
+        let items = self.items;
+        cell.iter().rev()
+            .filter(move |&&i| self.rects[i].contains_point(p))
+            .map(move |&i| (i, &items[i]))
+    }
+    /// the topmost item at `p`
+    pub fn top(&self, p: Vector2F) -> Option<&'a DrawItem> {
+        self.at(p).next().map(|(_, item)| item)
+    }
+    /// indices of the items whose bounds intersect `rect`, in drawing order
+    pub fn in_rect(&self, rect: RectF) -> Vec<usize> {
+        let rect = match rect.intersection(self.bounds) {
+            Some(r) => r,
+            None => return vec![]
+        };
+        let ((x0, y0), (x1, y1)) = (self.cell(rect.origin()), self.cell(rect.lower_right()));
+        let mut found = vec![];
+        for y in y0 ..= y1 {
+            for x in x0 ..= x1 {
+                found.extend(self.cells[y * self.grid_size + x].iter().filter(|&&i| self.rects[i].intersects(rect)));
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VectorPath {
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::outline"))]
     pub outline: Outline,
     pub fill: Option<FillMode>,
     pub stroke: Option<(FillMode, Stroke)>,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::fill_rule"))]
+    pub fill_rule: FillRule,
+    #[cfg_attr(feature="serde", serde(with="crate::serde_impls::transform"))]
     pub transform: Transform2F,
     pub op_nr: usize,
     pub clip: Option<ClipPathId>,
@@ -0,0 +1,81 @@
+//! Transfer functions (`/TR` and `/TR2` in an ExtGState).
+
+use std::sync::Arc;
+use pathfinder_color::ColorU;
+use pathfinder_content::pattern::Image;
+use pdf::function::Function;
+use pdf::object::{Object, Resolve};
+use pdf::primitive::Primitive;
+use pdf::error::{PdfError, Result};
+use crate::Fill;
+
+const SAMPLES: usize = 256;
+
+/// Transfer functions of the red, green and blue components, sampled at 256 points.
+pub struct Transfer {
+    lut: [[f32; SAMPLES]; 3],
+}
+
+impl Transfer {
+    /// Parse the value of /TR or /TR2.
+    /// None for `/Identity` and `/Default`, which both leave colors unchanged here.
+    pub fn from_primitive(p: &Primitive, resolve: &impl Resolve) -> Result<Option<Transfer>> {
+        let functions = match *p {
+            Primitive::Name(ref name) if name.as_str() == "Identity" || name.as_str() == "Default" => return Ok(None),
+            Primitive::Array(ref parts) => parts.iter()
+                .map(|f| Function::from_primitive(f.clone(), resolve))
+                .collect::<Result<Vec<_>>>()?,
+            ref f => vec![Function::from_primitive(f.clone(), resolve)?],
+        };
+        Transfer::new(&functions).map(Some)
+    }
+
+    /// One function for all components or one per component.
+    /// Of four functions (for C, M, Y, K or R, G, B, gray) the first three are used.
+    pub fn new(functions: &[Function]) -> Result<Transfer> {
+        let mut lut = [[0.0; SAMPLES]; 3];
+        for (c, table) in lut.iter_mut().enumerate() {
+            let f = match *functions {
+                [ref f] => f,
+                [ref r, ref g, ref b, ..] => [r, g, b][c],
+                _ => return Err(PdfError::Other { msg: format!("expected 1 or 4 transfer functions, got {}", functions.len()) })
+            };
+            for (i, v) in table.iter_mut().enumerate() {
+                let mut out = [0.0];
+                f.apply(&[i as f32 / (SAMPLES - 1) as f32], &mut out)?;
+                *v = out[0].clamp(0.0, 1.0);
+            }
+        }
+        Ok(Transfer { lut })
+    }
+
+    fn channel(&self, c: usize, x: f32) -> f32 {
+        let pos = x.clamp(0.0, 1.0) * (SAMPLES - 1) as f32;
+        let i = (pos as usize).min(SAMPLES - 2);
+        let t = pos - i as f32;
+        self.lut[c][i] * (1.0 - t) + self.lut[c][i + 1] * t
+    }
+
+    pub fn apply_fill(&self, fill: Fill) -> Fill {
+        match fill {
+            Fill::Solid(r, g, b) => Fill::Solid(self.channel(0, r), self.channel(1, g), self.channel(2, b)),
+            p => p
+        }
+    }
+
+    /// A copy of `image` with the transfer applied to every pixel.
+    pub fn apply_image(&self, image: &Image) -> Image {
+        let u8_lut = |c: usize| {
+            let mut table = [0u8; SAMPLES];
+            for (t, &v) in table.iter_mut().zip(self.lut[c].iter()) {
+                *t = (v * 255.) as u8;
+            }
+            table
+        };
+        let (r, g, b) = (u8_lut(0), u8_lut(1), u8_lut(2));
+        let pixels = image.pixels().iter()
+            .map(|p| ColorU::new(r[p.r as usize], g[p.g as usize], b[p.b as usize], p.a))
+            .collect();
+        Image::new(image.size(), Arc::new(pixels))
+    }
+}
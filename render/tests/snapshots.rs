@@ -0,0 +1,148 @@
+//! Visual snapshot tests over a corpus of PDF files.
+//!
+//! `cargo test -p pdf_render --features snapshots --test snapshots`
+//!
+//! Every PDF in `$PDF_RENDER_CORPUS` (`tests/corpus` by default) is rendered
+//! with the `SceneBackend` at a fixed resolution and compared to the PNGs in `tests/snapshots`.
+//! Set `PDF_RENDER_BLESS=1` to write the current output as the new reference.
+//! On failure the difference of each page is written to `target/snapshot-diffs`.
+//!
+//! The files of `tests/corpus` only use whole pixels at 75 dpi and no fonts, so that their
+//! references don't depend on anti-aliasing or on the fonts in `STANDARD_FONTS`.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use image::{Rgba, RgbaImage};
+use pathfinder_color::ColorF;
+use pathfinder_rasterize::Rasterizer;
+use pdf::file::FileOptions;
+use pdf_render::{Cache, SceneBackend, RenderOptions, render_page_with};
+
+const DPI: f32 = 75.;
+// only the first pages of each file, to keep the test fast
+const MAX_PAGES: u32 = 3;
+// largest difference of a channel that still counts as equal
+const CHANNEL_TOLERANCE: u8 = 8;
+// fraction of pixels that may differ by more than CHANNEL_TOLERANCE
+const PIXEL_TOLERANCE: f64 = 0.001;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+fn corpus_dir() -> PathBuf {
+    match std::env::var_os("PDF_RENDER_CORPUS") {
+        Some(dir) => dir.into(),
+        None => manifest_dir().join("tests").join("corpus"),
+    }
+}
+fn snapshot_dir() -> PathBuf {
+    manifest_dir().join("tests").join("snapshots")
+}
+fn diff_dir() -> PathBuf {
+    manifest_dir().parent().unwrap().join("target").join("snapshot-diffs")
+}
+fn bless() -> bool {
+    std::env::var_os("PDF_RENDER_BLESS").is_some()
+}
+
+/// The image with the pixels that differ too much in red, None if the images are close enough.
+fn compare(reference: &RgbaImage, actual: &RgbaImage) -> Option<RgbaImage> {
+    if reference.dimensions() != actual.dimensions() {
+        return Some(actual.clone());
+    }
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut differing = 0usize;
+    for ((a, b), d) in reference.pixels().zip(actual.pixels()).zip(diff.pixels_mut()) {
+        let off = a.0.iter().zip(b.0.iter()).any(|(&x, &y)| x.abs_diff(y) > CHANNEL_TOLERANCE);
+        *d = if off {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // the reference, faded
+            let l = ((a[0] as u32 + a[1] as u32 + a[2] as u32) / 3) as u8;
+            Rgba([l / 4 + 191, l / 4 + 191, l / 4 + 191, 255])
+        };
+    }
+    let pixels = actual.width() as usize * actual.height() as usize;
+    if differing as f64 > PIXEL_TOLERANCE * pixels as f64 {
+        Some(diff)
+    } else {
+        None
+    }
+}
+
+fn check_file(path: &Path, failures: &mut String) {
+    let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+    let file = match FileOptions::cached().open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            writeln!(failures, "{}: can't open: {:?}", stem, e).unwrap();
+            return;
+        }
+    };
+    let resolver = file.resolver();
+    let options = RenderOptions::new().dpi(DPI).background(Some(ColorF::white()));
+    let mut cache = Cache::new();
+
+    for page_nr in 0 .. file.num_pages().min(MAX_PAGES) {
+        let name = format!("{}-{}", stem, page_nr);
+        let page = match file.get_page(page_nr) {
+            Ok(p) => p,
+            Err(e) => {
+                writeln!(failures, "{}: can't read page: {:?}", name, e).unwrap();
+                continue;
+            }
+        };
+
+        let mut backend = SceneBackend::new(&mut cache);
+        if let Err(e) = render_page_with(&mut backend, &resolver, &page, &options) {
+            writeln!(failures, "{}: render failed: {:?}", name, e).unwrap();
+            continue;
+        }
+        let image = Rasterizer::new().rasterize(backend.finish(), options.background);
+
+        let png_path = snapshot_dir().join(format!("{}.png", name));
+        if bless() {
+            fs::create_dir_all(snapshot_dir()).unwrap();
+            image.save(&png_path).unwrap();
+            continue;
+        }
+
+        match image::open(&png_path) {
+            Ok(reference) => {
+                if let Some(diff) = compare(&reference.to_rgba8(), &image) {
+                    fs::create_dir_all(diff_dir()).unwrap();
+                    image.save(diff_dir().join(format!("{}.png", name))).unwrap();
+                    diff.save(diff_dir().join(format!("{}-diff.png", name))).unwrap();
+                    writeln!(failures, "{}: image differs, see {}", name, diff_dir().display()).unwrap();
+                }
+            }
+            Err(_) => writeln!(failures, "{}: no reference image", name).unwrap(),
+        }
+    }
+}
+
+#[test]
+fn snapshots() {
+    let corpus = corpus_dir();
+    let mut files: Vec<PathBuf> = match fs::read_dir(&corpus) {
+        Ok(dir) => dir.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map_or(false, |e| e.eq_ignore_ascii_case("pdf")))
+            .collect(),
+        Err(e) => {
+            eprintln!("skipping the snapshots, can't read the corpus at {}: {:?}", corpus.display(), e);
+            return;
+        }
+    };
+    files.sort();
+
+    let mut failures = String::new();
+    for path in &files {
+        check_file(path, &mut failures);
+    }
+    if !failures.is_empty() {
+        panic!("snapshots of {} files differ:\n{}", files.len(), failures);
+    }
+}
@@ -7,6 +7,7 @@ use std::path::PathBuf;
 use pdf_render::{Cache, SceneBackend, render_page};
 use pathfinder_export::{FileFormat, Export};
 use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_color::ColorF;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -34,6 +35,10 @@ struct Opt {
     #[structopt(long = "digits", default_value="1")]
     digits: usize,
 
+    /// Password of an encrypted input file
+    #[structopt(long = "password")]
+    password: Option<String>,
+
     /// Input file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
@@ -62,7 +67,8 @@ fn main() -> Result<(), PdfError> {
     let transform = Transform2F::from_scale(opt.dpi / 25.4);
 
     println!("read: {:?}", opt.input);
-    let file = FileOptions::cached().open(&opt.input)?;
+    let password = opt.password.as_deref().unwrap_or("");
+    let file = FileOptions::cached().password(password.as_bytes()).open(&opt.input)?;
     let resolver = file.resolver();
     
     let mut cache = Cache::new();
@@ -70,6 +76,7 @@ fn main() -> Result<(), PdfError> {
         println!("page {}", i);
         let p: &Page = &*page.unwrap();
         let mut backend = SceneBackend::new(&mut cache);
+        backend.set_background(Some(ColorF::white()));
         render_page(&mut backend, &resolver, p, transform)?;
         let output = if opt.pages > 1 {
             let replacement = format!("{page:0digits$}", page=i, digits=opt.digits);
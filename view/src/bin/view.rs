@@ -3,14 +3,47 @@ use pathfinder_view::{show, Config};
 use pathfinder_resources::embedded::EmbeddedResourceLoader;
 use pathfinder_color::ColorF;
 
-use pdf::file::FileOptions;
+use pdf::file::{FileOptions, File, NoCache, NoLog};
+use pdf::error::PdfError;
 use pdf_view::PdfView;
+use std::io::{self, Write, BufRead};
 
+type UncachedFile = File<Vec<u8>, NoCache, NoCache, NoLog>;
+
+// an encrypted file is opened with the empty password first, as it may only have an owner password.
+// if that is not it, ask on the terminal until the right one is entered.
+fn open(path: &str, mut password: String) -> UncachedFile {
+    loop {
+        match FileOptions::uncached().password(password.as_bytes()).open(path) {
+            Ok(file) => return file,
+            Err(PdfError::InvalidPassword) => {
+                eprint!("password for {}: ", path);
+                io::stderr().flush().unwrap();
+                password.clear();
+                if io::stdin().lock().read_line(&mut password).unwrap() == 0 {
+                    std::process::exit(1);
+                }
+                let len = password.trim_end_matches(&['\r', '\n'][..]).len();
+                password.truncate(len);
+            }
+            Err(e) => panic!("{:?}", e)
+        }
+    }
+}
 
 fn main() {
     env_logger::init();
-    let path = std::env::args().nth(1).unwrap();
-    let file = FileOptions::uncached().open(&path).unwrap();
+    let mut args = std::env::args().skip(1);
+    let mut path = None;
+    let mut password = String::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--password" => password = args.next().expect("--password needs a value"),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.expect("usage: view [--password PASSWORD] FILE");
+    let file = open(&path, password);
     let view = PdfView::new(file);
     let mut config = Config::new(Box::new(EmbeddedResourceLoader));
     config.zoom = true;
@@ -4,12 +4,17 @@ use std::sync::Arc;
 use pathfinder_view::{Config, Interactive, Context, Emitter, view::{ElementState, KeyCode, KeyEvent, ModifiersState}};
 use pathfinder_renderer::scene::Scene;
 use pathfinder_geometry::vector::Vector2F;
+use pathfinder_color::ColorF;
 
 use pdf::file::{File as PdfFile, Cache as PdfCache, Log};
 use pdf::any::AnySync;
 use pdf::PdfError;
 use pdf::backend::Backend;
-use pdf_render::{Cache, SceneBackend, page_bounds, render_page};
+use pdf::object::{Resolve, Ref, OutlineItem, PlainRef};
+use pdf::primitive::Primitive;
+use pdf_render::{Cache, SceneBackend, Unit, TextSpan, Match, page_bounds_in, render_page};
+use pathfinder_geometry::transform2d::Transform2F;
+use pdf::content::TextMode;
 
 #[cfg(target_arch = "wasm32")]
 use pathfinder_view::WasmView;
@@ -18,6 +23,8 @@ pub struct PdfView<B: Backend, OC, SC, L> {
     file: PdfFile<B, OC, SC, L>,
     num_pages: usize,
     cache: Cache,
+    current_page: Option<usize>,
+    on_page_change: Option<Box<dyn Fn(usize)>>,
 }
 impl<B, OC, SC, L> PdfView<B, OC, SC, L>
 where
@@ -31,8 +38,15 @@ where
             num_pages: file.num_pages() as usize,
             file,
             cache: Cache::new(),
+            current_page: None,
+            on_page_change: None,
         }
     }
+    /// called with the (0 based) page number whenever a different page is drawn
+    pub fn on_page_change(mut self, f: impl Fn(usize) + 'static) -> Self {
+        self.on_page_change = Some(Box::new(f));
+        self
+    }
 }
 impl<B, OC, SC, L> Interactive for PdfView<B, OC, SC, L>
 where
@@ -54,11 +68,24 @@ where
     }
     fn scene(&mut self, ctx: &mut Context) -> Scene {
         info!("drawing page {}", ctx.page_nr());
+        if self.current_page != Some(ctx.page_nr()) {
+            self.current_page = Some(ctx.page_nr());
+            if let Some(ref f) = self.on_page_change {
+                f(ctx.page_nr());
+            }
+        }
         let page = self.file.get_page(ctx.page_nr as u32).unwrap();
 
-        ctx.set_bounds(page_bounds(&page));
+        match page_bounds_in(&page, Unit::Millimeters) {
+            Ok(bounds) => ctx.set_bounds(bounds),
+            Err(e) => {
+                warn!("page {} has no bounds: {:?}", ctx.page_nr(), e);
+                return Scene::new();
+            }
+        }
 
         let mut backend = SceneBackend::new(&mut self.cache);
+        backend.set_background(Some(ColorF::white()));
         let resolver = self.file.resolver();
         render_page(&mut backend, &resolver, &page, ctx.view_transform()).unwrap();
         backend.finish()
@@ -87,6 +114,107 @@ where
     }
 }
 
+/// Serialize the spans of a page as a JSON text layer, like pdf.js does.
+///
+/// Each entry is meant to be placed in an absolutely positioned element with
+/// `transform-origin: 0 0`, the given font size (in px) and the CSS `transform`.
+/// `scale` maps mm, the unit of the spans, to CSS pixels.
+pub fn text_layer_json(spans: &[TextSpan], scale: f32) -> String {
+    let items: Vec<_> = spans.iter()
+        .filter(|span| span.text.trim().len() > 0 && span.font_size > 0.0)
+        .map(|span| {
+            // move the top of the em box to the origin, where CSS places the element
+            let tr = Transform2F::from_scale(scale)
+                * span.transform
+                * Transform2F::from_translation(Vector2F::new(0.0, -span.font_size));
+            let m = tr.matrix;
+            let t = tr.vector;
+            serde_json::json!({
+                "text": span.text,
+                "font_size": span.font_size,
+                "width": span.width,
+                "transform": format!("matrix({}, {}, {}, {}, {}, {})", m.m11(), m.m21(), m.m12(), m.m22(), t.x(), t.y()),
+                "invisible": matches!(span.mode, TextMode::Invisible),
+            })
+        })
+        .collect();
+    serde_json::Value::Array(items).to_string()
+}
+
+/// Serialize search results as JSON, with rects as `[x, y, width, height]`
+/// in CSS pixels. `scale` maps mm, the unit of the matches, to CSS pixels.
+pub fn matches_json(matches: &[Match], scale: f32) -> String {
+    let items: Vec<_> = matches.iter().map(|m| {
+        let rects: Vec<_> = m.rects.iter()
+            .map(|r| [r.min_x() * scale, r.min_y() * scale, r.width() * scale, r.height() * scale])
+            .collect();
+        serde_json::json!({
+            "text": m.text,
+            "rects": rects,
+        })
+    }).collect();
+    serde_json::Value::Array(items).to_string()
+}
+
+/// Serialize the document outline (bookmarks) as a JSON tree.
+///
+/// Each entry has a `title`, the (0 based) `page` it points to, or null if the
+/// destination could not be resolved to a page, and its `children`.
+pub fn outline_json<B, OC, SC, L>(file: &PdfFile<B, OC, SC, L>) -> Result<String, PdfError>
+where
+    B: Backend,
+    OC: PdfCache<Result<AnySync, Arc<PdfError>>>,
+    SC: PdfCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log
+{
+    let first = match file.trailer.root.outlines {
+        Some(ref outlines) => outlines.first,
+        None => return Ok("[]".into())
+    };
+    let mut pages = std::collections::HashMap::new();
+    for (nr, page) in file.pages().enumerate() {
+        pages.insert(page?.get_ref().get_inner(), nr);
+    }
+    // broken files can have cycles in the outline, so give up after a while
+    let mut budget = 10_000;
+    let items = outline_items(&file.resolver(), first, &pages, &mut budget)?;
+    Ok(serde_json::Value::Array(items).to_string())
+}
+
+fn outline_items(resolve: &impl Resolve, mut next: Option<Ref<OutlineItem>>, pages: &std::collections::HashMap<PlainRef, usize>, budget: &mut usize) -> Result<Vec<serde_json::Value>, PdfError> {
+    let mut items = vec![];
+    while let Some(r) = next {
+        if *budget == 0 {
+            warn!("outline too long or cyclic");
+            break;
+        }
+        *budget -= 1;
+
+        let item = resolve.get(r)?;
+        let title = item.title.as_ref().map(|t| t.to_string_lossy()).unwrap_or_default();
+        let page = item.dest.as_ref().and_then(|dest| dest_page(dest, pages));
+        let children = outline_items(resolve, item.first, pages, budget)?;
+        items.push(serde_json::json!({
+            "title": title,
+            "page": page,
+            "children": children,
+        }));
+        next = item.next;
+    }
+    Ok(items)
+}
+
+/// explicit destinations are arrays that start with a reference to the page
+fn dest_page(dest: &Primitive, pages: &std::collections::HashMap<PlainRef, usize>) -> Option<usize> {
+    match *dest {
+        Primitive::Array(ref parts) => match parts.get(0) {
+            Some(Primitive::Reference(r)) => pages.get(r).cloned(),
+            _ => None
+        },
+        _ => None
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
@@ -106,14 +234,21 @@ pub fn run() {
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
-pub fn show(canvas: HtmlCanvasElement, context: WebGl2RenderingContext, data: &Uint8Array) -> WasmView {
+pub fn show(canvas: HtmlCanvasElement, context: WebGl2RenderingContext, data: &Uint8Array, on_page_change: Option<js_sys::Function>) -> WasmView {
     use pathfinder_resources::embedded::EmbeddedResourceLoader;
 
     let data: Vec<u8> = data.to_vec();
     info!("got {} bytes of data", data.len());
     let file = PdfFile::from_data(data).expect("failed to parse PDF");
     info!("got the file");
-    let view = PdfView::new(file);
+    let mut view = PdfView::new(file);
+    if let Some(callback) = on_page_change {
+        view = view.on_page_change(move |page_nr| {
+            if let Err(e) = callback.call1(&JsValue::NULL, &JsValue::from(page_nr as u32)) {
+                warn!("page change callback failed: {:?}", e);
+            }
+        });
+    }
 
     let mut config = Config::new(Box::new(EmbeddedResourceLoader));
     config.zoom = false;
@@ -125,3 +260,59 @@ pub fn show(canvas: HtmlCanvasElement, context: WebGl2RenderingContext, data: &U
         Box::new(view) as _
     )
 }
+
+/// Text, search and outline of a document, separate from the rendering `WasmView`.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub struct PdfDocument {
+    file: pdf::file::CachedFile<Vec<u8>>,
+    cache: pdf_render::TraceCache,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl PdfDocument {
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &Uint8Array) -> Result<PdfDocument, JsValue> {
+        let file = PdfFile::from_data(data.to_vec()).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(PdfDocument {
+            file,
+            cache: pdf_render::TraceCache::new(),
+        })
+    }
+    pub fn num_pages(&self) -> u32 {
+        self.file.num_pages()
+    }
+    fn spans(&self, page_nr: u32) -> Result<Vec<TextSpan>, PdfError> {
+        let page = self.file.get_page(page_nr)?;
+        let resolver = self.file.resolver();
+        let mut clip_paths = vec![];
+        let mut tracer = pdf_render::Tracer::new(&self.cache, &mut clip_paths);
+        render_page(&mut tracer, &resolver, &page, Transform2F::default())?;
+
+        Ok(tracer.finish().into_iter().filter_map(|item| match item {
+            pdf_render::DrawItem::Text(span, _) => Some(span),
+            _ => None
+        }).collect())
+    }
+    /// JSON array of the text spans on page `page_nr` (see `text_layer_json`)
+    pub fn text_layer(&self, page_nr: u32, scale: f32) -> Result<String, JsValue> {
+        let spans = self.spans(page_nr).map_err(js_err)?;
+        Ok(text_layer_json(&spans, scale))
+    }
+    /// JSON array of the matches of `query` on page `page_nr` (see `matches_json`)
+    pub fn find(&self, page_nr: u32, query: &str, case_insensitive: bool, scale: f32) -> Result<String, JsValue> {
+        let spans = self.spans(page_nr).map_err(js_err)?;
+        let options = pdf_render::FindOptions { case_insensitive, ..Default::default() };
+        Ok(matches_json(&pdf_render::find_in_spans(&spans, query, &options), scale))
+    }
+    /// JSON tree of the outline (see `outline_json`)
+    pub fn get_outline(&self) -> Result<String, JsValue> {
+        outline_json(&self.file).map_err(js_err)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn js_err(e: PdfError) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
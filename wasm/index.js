@@ -38,7 +38,7 @@ function init_view(data, attempt) {
         return;
     }
 
-    view = wasm_bindgen.show(canvas, context, data);
+    view = wasm_bindgen.show(canvas, context, data, page_changed);
     display();
 
     let requested = false;
@@ -60,6 +60,42 @@ function init_view(data, attempt) {
     canvas.addEventListener("mousedown", e => check(view.mouse_down(e)));
     window.addEventListener("resize", e => check(view.resize(e)));
     view.render();
+
+    pdf_document = new wasm_bindgen.PdfDocument(data);
+}
+
+let pdf_document;
+let current_page = 0;
+// called by the view whenever a different page is drawn
+function page_changed(page_nr) {
+    current_page = page_nr;
+    document.dispatchEvent(new CustomEvent("pagechange", { detail: { page: page_nr } }));
+}
+
+// matches of `query` on page `page_nr` as {text, rects: [[x, y, w, h]]}, scaled like show_text_layer
+function find(page_nr, query, scale) {
+    return JSON.parse(pdf_document.find(page_nr, query, true, scale));
+}
+
+// the bookmarks as a tree of {title, page, children}
+function get_outline() {
+    return JSON.parse(pdf_document.get_outline());
+}
+
+// place selectable text for page `page_nr` over the canvas. `scale` maps mm to CSS pixels.
+function show_text_layer(page_nr, scale) {
+    let layer = document.getElementById("text-layer");
+    layer.innerHTML = "";
+    let canvas = document.getElementById("canvas");
+    layer.style.left = canvas.offsetLeft + "px";
+    layer.style.top = canvas.offsetTop + "px";
+    for (let item of JSON.parse(pdf_document.text_layer(page_nr, scale))) {
+        let span = document.createElement("span");
+        span.textContent = item.text;
+        span.style.fontSize = item.font_size + "px";
+        span.style.transform = item.transform;
+        layer.appendChild(span);
+    }
 }
 
 function show_data(data) {